@@ -0,0 +1,26 @@
+//! Fetches the policy data document that the controller exports, e.g. for OPA.
+
+use anyhow::{bail, Context, Result};
+
+/// Fetches the indexed policy of all namespaces from the controller's admin server.
+///
+/// `admin_addr` is the base URI of the controller's admin server, e.g. `http://127.0.0.1:8080`.
+pub async fn fetch_policy_data(admin_addr: &str) -> Result<serde_json::Value> {
+    let uri = format!("{}/policy", admin_addr.trim_end_matches('/'));
+    let uri = uri
+        .parse::<hyper::Uri>()
+        .with_context(|| format!("invalid admin URI: {}", uri))?;
+
+    let rsp = hyper::Client::new().get(uri).await?;
+    let status = rsp.status();
+    let body = hyper::body::to_bytes(rsp.into_body()).await?;
+    if !status.is_success() {
+        bail!(
+            "policy export failed: {}: {}",
+            status,
+            String::from_utf8_lossy(body.as_ref()).trim()
+        );
+    }
+
+    serde_json::from_slice(body.as_ref()).context("invalid policy data")
+}
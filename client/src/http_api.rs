@@ -1,7 +1,8 @@
-use crate::Inbound;
+use crate::{ClientTls, Inbound};
 use anyhow::Result;
 use bytes::Bytes;
 use hyper::{Body, Request, Response};
+use polixy_controller_core::Reconnects;
 use serde::Deserialize;
 use std::{collections::HashMap, net::IpAddr};
 use tokio::sync::watch;
@@ -15,6 +16,9 @@ struct Spec {
     client_ip: IpAddr,
 
     tls: Option<TlsSpec>,
+
+    /// If set, the request is checked against the server's routes.
+    http: Option<HttpSpec>,
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -23,6 +27,12 @@ struct TlsSpec {
     client_id: Option<String>,
 }
 
+#[derive(Clone, Debug, Deserialize)]
+struct HttpSpec {
+    method: String,
+    path: String,
+}
+
 pub async fn serve(
     ports: &HashMap<u16, watch::Receiver<Inbound>>,
     req: Request<Body>,
@@ -44,6 +54,7 @@ pub async fn serve(
                 server_port,
                 client_ip,
                 tls,
+                http,
             } = {
                 let body = hyper::body::to_bytes(req.into_body()).await?;
                 serde_json::from_slice(body.as_ref())?
@@ -52,11 +63,22 @@ pub async fn serve(
             match ports.get(&server_port) {
                 Some(rx) => {
                     let inbound = rx.borrow();
-                    let labels = match tls {
-                        Some(TlsSpec { client_id }) => {
-                            inbound.check_tls(client_ip, client_id.as_deref())
+                    let tls = match tls {
+                        Some(TlsSpec {
+                            client_id: Some(ref id),
+                        }) => ClientTls::Authenticated(id),
+                        Some(TlsSpec { client_id: None }) => ClientTls::Unauthenticated,
+                        None => ClientTls::Plaintext,
+                    };
+                    let labels = match http {
+                        Some(HttpSpec { method, path }) => {
+                            inbound.check_http(client_ip, tls, &method, &path)
                         }
-                        None => inbound.check_non_tls(client_ip),
+                        None => match tls {
+                            ClientTls::Plaintext => inbound.check_non_tls(client_ip),
+                            ClientTls::Unauthenticated => inbound.check_tls(client_ip, None),
+                            ClientTls::Authenticated(id) => inbound.check_tls(client_ip, Some(id)),
+                        },
                     };
 
                     let rsp = serde_json::json!({
@@ -103,3 +125,22 @@ pub async fn serve(
             .unwrap()),
     }
 }
+
+/// Serves each port watch's reconnection counters in the Prometheus text format.
+pub fn serve_metrics(reconnects: &HashMap<u16, Reconnects>) -> Response<Body> {
+    let mut ports = reconnects.iter().collect::<Vec<_>>();
+    ports.sort_by_key(|(p, _)| **p);
+
+    let mut out = String::new();
+    Reconnects::fmt_prometheus(
+        &mut out,
+        "port",
+        ports.into_iter().map(|(p, r)| (p.to_string(), r)),
+    )
+    .expect("formatting to a string must not fail");
+    Response::builder()
+        .status(hyper::StatusCode::OK)
+        .header(hyper::header::CONTENT_TYPE, "text/plain; version=0.0.4")
+        .body(out.into())
+        .unwrap()
+}
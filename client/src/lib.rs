@@ -1,7 +1,10 @@
 #![deny(warnings, rust_2018_idioms)]
 #![forbid(unsafe_code)]
 
+pub mod export;
 pub mod http_api;
+pub mod output;
+pub mod pods;
 mod watch_ports;
 
 pub use self::watch_ports::{watch_ports, PortWatch};
@@ -11,9 +14,16 @@ use ipnet::IpNet;
 use linkerd2_proxy_api::inbound::{
     self as proto, inbound_server_discovery_client::InboundServerDiscoveryClient,
 };
+pub use polixy_controller_core::ClientTls;
+use polixy_controller_core::{
+    AuthzMatcher, ClientAuthentication, ClientAuthorization, HttpRouteMatch, IdentityMatch,
+    NetworkMatch, SpiffePrefix,
+};
+use serde::{Serialize, Serializer};
 use std::{
-    collections::{HashMap, HashSet},
-    convert::TryInto,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    convert::{TryFrom, TryInto},
+    fmt,
     net::IpAddr,
 };
 use tokio::time;
@@ -24,16 +34,44 @@ pub struct Client {
     client: InboundServerDiscoveryClient<tonic::transport::Channel>,
 }
 
-#[derive(Clone, Debug)]
+/// An inbound port's discovered configuration.
+///
+/// The serialized form is part of the client's stable output schema: maps and sets are written in
+/// sorted order, networks and identity suffixes are written as strings, and durations are written
+/// in milliseconds.
+///
+/// Authorizations are compiled into an index when the configuration is built so that connections
+/// may be checked without scanning all authorizations. When several authorizations permit a
+/// connection, the most specific one applies, as described by [`AuthzMatcher`].
+#[derive(Clone, Debug, Serialize)]
 pub struct Inbound {
-    pub authorizations: Vec<Authz>,
-    pub labels: HashMap<String, String>,
     pub protocol: Protocol,
+    /// The server-wide authorizations. Private so that they can't diverge from `matcher`.
+    authorizations: Vec<Authz>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    routes: Vec<Route>,
+    #[serde(serialize_with = "sorted_map")]
+    pub labels: HashMap<String, String>,
+    #[serde(skip)]
+    matcher: AuthzMatcher,
+    #[serde(skip)]
+    route_matcher: AuthzMatcher,
 }
 
-#[derive(Copy, Clone, Debug)]
+/// An authorization that only applies to HTTP requests that match a route.
+#[derive(Clone, Debug, Serialize)]
+pub struct Route {
+    matches: Vec<HttpRouteMatch>,
+    authorization: Authz,
+}
+
+#[derive(Copy, Clone, Debug, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
 pub enum Protocol {
-    Detect { timeout: time::Duration },
+    Detect {
+        #[serde(rename = "timeoutMs", serialize_with = "duration_ms")]
+        timeout: time::Duration,
+    },
     Http1,
     Http2,
     Grpc,
@@ -41,31 +79,44 @@ pub enum Protocol {
     Tls,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize)]
 pub struct Authz {
     networks: Vec<Network>,
     authn: Authn,
+    #[serde(serialize_with = "sorted_map")]
     labels: HashMap<String, String>,
 }
 
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, Serialize)]
 pub struct Network {
+    #[serde(serialize_with = "display")]
     net: IpNet,
+    #[serde(serialize_with = "display_seq")]
     except: Vec<IpNet>,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
 pub enum Authn {
     Unauthenticated,
     TlsUnauthenticated,
     TlsAuthenticated {
+        #[serde(serialize_with = "sorted_set")]
         identities: HashSet<String>,
+        #[serde(serialize_with = "display_seq")]
         suffixes: Vec<Suffix>,
+        #[serde(
+            rename = "spiffePrefixes",
+            serialize_with = "display_seq",
+            skip_serializing_if = "Vec::is_empty"
+        )]
+        spiffe_prefixes: Vec<SpiffePrefix>,
     },
 }
 
 #[derive(Clone, Debug)]
 pub struct Suffix {
+    parts: Vec<String>,
     ends_with: String,
 }
 
@@ -115,81 +166,106 @@ impl Client {
     }
 }
 
+/// The proxy API does not describe SPIFFE path prefixes, so the controller encodes an
+/// authorization's prefixes in this label as a JSON list.
+const SPIFFE_LABEL: &str = "spiffe";
+
 // === impl Inbound ===
 
 impl Inbound {
-    #[instrument(skip(self))]
-    pub fn check_non_tls(&self, client_ip: IpAddr) -> Option<&HashMap<String, String>> {
-        trace!(authorizations = %self.authorizations.len());
-        for Authz {
-            networks,
-            authn,
+    pub fn new(
+        protocol: Protocol,
+        authorizations: Vec<Authz>,
+        routes: Vec<Route>,
+        labels: HashMap<String, String>,
+    ) -> Self {
+        let matcher = AuthzMatcher::new(authorizations.iter().map(Authz::to_core));
+        let route_matcher = AuthzMatcher::new(routes.iter().map(|r| r.authorization.to_core()));
+        Self {
+            protocol,
+            authorizations,
+            routes,
             labels,
-        } in self.authorizations.iter()
-        {
-            trace!(?authn);
-            trace!(?networks);
-            trace!(?labels);
-            if matches!(authn, Authn::Unauthenticated)
-                && networks.iter().any(|net| net.contains(&client_ip))
-            {
-                trace!("Match found");
-                return Some(labels);
-            }
+            matcher,
+            route_matcher,
         }
+    }
+
+    pub fn authorizations(&self) -> &[Authz] {
+        &self.authorizations
+    }
 
-        trace!("No match found");
-        None
+    pub fn routes(&self) -> &[Route] {
+        &self.routes
     }
 
+    /// Finds the most specific authorization that permits a non-TLS connection from the client.
+    #[instrument(skip(self))]
+    pub fn check_non_tls(&self, client_ip: IpAddr) -> Option<&HashMap<String, String>> {
+        self.check(client_ip, ClientTls::Plaintext)
+    }
+
+    /// Finds the most specific authorization that permits a TLS connection from the client.
     #[instrument(skip(self))]
     pub fn check_tls(
         &self,
         client_ip: IpAddr,
         id: Option<&str>,
     ) -> Option<&HashMap<String, String>> {
+        let tls = match id {
+            Some(id) => ClientTls::Authenticated(id),
+            None => ClientTls::Unauthenticated,
+        };
+        self.check(client_ip, tls)
+    }
+
+    /// Finds the most specific authorization that permits an HTTP request from the client.
+    ///
+    /// If the request matches any route, only the authorizations of the matching routes apply.
+    /// Otherwise, the server-wide authorizations apply.
+    #[instrument(skip(self))]
+    pub fn check_http(
+        &self,
+        client_ip: IpAddr,
+        tls: ClientTls<'_>,
+        method: &str,
+        path: &str,
+    ) -> Option<&HashMap<String, String>> {
+        let matched = self
+            .routes
+            .iter()
+            .map(|r| r.matches.iter().any(|m| m.matches(method, path)))
+            .collect::<Vec<_>>();
+        if !matched.iter().any(|m| *m) {
+            trace!("No routes match");
+            return self.check(client_ip, tls);
+        }
+
+        let idx = self
+            .route_matcher
+            .find_where(client_ip, tls, |i| matched[i])?;
+        let labels = &self.routes[idx].authorization.labels;
+        trace!(?labels, "Route match found");
+        Some(labels)
+    }
+
+    fn check(&self, client_ip: IpAddr, tls: ClientTls<'_>) -> Option<&HashMap<String, String>> {
         trace!(authorizations = %self.authorizations.len());
-        for Authz {
-            networks,
-            authn,
-            labels,
-        } in self.authorizations.iter()
-        {
-            trace!(?networks);
-            if networks.iter().any(|net| net.contains(&client_ip)) {
-                trace!("Matches network");
-                trace!(?authn);
-                match authn {
-                    Authn::Unauthenticated | Authn::TlsUnauthenticated => {
-                        trace!("Match found");
-                        trace!(?labels);
-                        return Some(labels);
-                    }
-                    Authn::TlsAuthenticated {
-                        identities,
-                        suffixes,
-                    } => {
-                        if let Some(id) = id {
-                            trace!(identities = %identities.iter().map(|i| i.to_string()).collect::<Vec<_>>().join(","));
-                            if identities.contains(id)
-                                || suffixes.iter().any(|sfx| sfx.contains(id))
-                            {
-                                trace!("Match found");
-                                trace!(?labels);
-                                return Some(labels);
-                            }
-                        }
-                    }
-                }
+        match self.matcher.find(client_ip, tls) {
+            Some(idx) => {
+                let labels = &self.authorizations.get(idx)?.labels;
+                trace!(?labels, "Match found");
+                Some(labels)
+            }
+            None => {
+                trace!("No match found");
+                None
             }
         }
-
-        trace!("No match found");
-        None
     }
 }
 
-impl std::convert::TryFrom<proto::Server> for Inbound {
+impl TryFrom<proto::Server> for Inbound {
     type Error = Error;
 
     fn try_from(proto: proto::Server) -> Result<Self> {
@@ -217,75 +293,147 @@ impl std::convert::TryFrom<proto::Server> for Inbound {
         let authorizations = proto
             .authorizations
             .into_iter()
-            .map(
-                |proto::Authz {
-                     labels,
-                     authentication,
-                     networks,
-                 }| {
-                    if networks.is_empty() {
-                        bail!("networks missing");
+            .map(Authz::try_from)
+            .collect::<Result<Vec<_>>>()?;
+
+        // The proxy API does not describe routes, so the controller refuses to serve servers
+        // with route-scoped authorizations.
+        Ok(Inbound::new(protocol, authorizations, vec![], proto.labels))
+    }
+}
+
+// === impl Route ===
+
+impl Route {
+    pub fn matches(&self) -> &[HttpRouteMatch] {
+        &self.matches
+    }
+
+    pub fn authorization(&self) -> &Authz {
+        &self.authorization
+    }
+}
+
+// === impl Authz ===
+
+impl TryFrom<proto::Authz> for Authz {
+    type Error = Error;
+
+    fn try_from(
+        proto::Authz {
+            mut labels,
+            authentication,
+            networks,
+        }: proto::Authz,
+    ) -> Result<Self> {
+        if networks.is_empty() {
+            bail!("networks missing");
+        }
+        let networks = networks
+            .into_iter()
+            .map(|proto::Network { net, except }| {
+                let net = net
+                    .ok_or_else(|| anyhow!("network missing"))?
+                    .try_into()
+                    .context("invalid network")?;
+                let except = except
+                    .into_iter()
+                    .map(|net| net.try_into().context("invalid network"))
+                    .collect::<Result<Vec<IpNet>>>()?;
+                Ok(Network { net, except })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let authn = match authentication.and_then(|proto::Authn { permit }| permit) {
+            Some(proto::authn::Permit::Unauthenticated(_)) => Authn::Unauthenticated,
+            Some(proto::authn::Permit::MeshTls(proto::authn::PermitMeshTls { clients })) => {
+                match clients {
+                    Some(proto::authn::permit_mesh_tls::Clients::Unauthenticated(_)) => {
+                        Authn::TlsUnauthenticated
                     }
-                    let networks = networks
-                        .into_iter()
-                        .map(|proto::Network { net, except }| {
-                            let net = net
-                                .ok_or_else(|| anyhow!("network missing"))?
-                                .try_into()
-                                .context("invalid network")?;
-                            let except = except
-                                .into_iter()
-                                .map(|net| net.try_into().context("invalid network"))
-                                .collect::<Result<Vec<IpNet>>>()?;
-                            Ok(Network { net, except })
-                        })
-                        .collect::<Result<Vec<_>>>()?;
-
-                    let authn = match authentication.and_then(|proto::Authn { permit }| permit) {
-                        Some(proto::authn::Permit::Unauthenticated(_)) => Authn::Unauthenticated,
-                        Some(proto::authn::Permit::MeshTls(proto::authn::PermitMeshTls {
-                            clients,
-                        })) => match clients {
-                            Some(proto::authn::permit_mesh_tls::Clients::Unauthenticated(_)) => {
-                                Authn::TlsUnauthenticated
-                            }
-                            Some(proto::authn::permit_mesh_tls::Clients::Identities(
-                                proto::authn::permit_mesh_tls::PermitClientIdentities {
-                                    identities,
-                                    suffixes,
-                                },
-                            )) => Authn::TlsAuthenticated {
-                                identities: identities
-                                    .into_iter()
-                                    .map(|proto::Identity { name }| name)
-                                    .collect(),
-                                suffixes: suffixes
-                                    .into_iter()
-                                    .map(|proto::IdentitySuffix { parts }| Suffix::from(parts))
-                                    .collect(),
-                            },
-                            None => bail!("no clients permitted"),
+                    Some(proto::authn::permit_mesh_tls::Clients::Identities(
+                        proto::authn::permit_mesh_tls::PermitClientIdentities {
+                            identities,
+                            suffixes,
                         },
-                        authn => bail!("no authentication provided: {:?}", authn),
-                    };
-
-                    Ok(Authz {
-                        networks,
-                        authn,
-                        labels,
-                    })
-                },
-            )
-            .collect::<Result<Vec<_>>>()?;
+                    )) => {
+                        let spiffe_prefixes = match labels.remove(SPIFFE_LABEL) {
+                            Some(prefixes) => serde_json::from_str::<Vec<String>>(&prefixes)
+                                .context("invalid SPIFFE prefixes")?
+                                .iter()
+                                .map(|p| p.parse())
+                                .collect::<Result<Vec<SpiffePrefix>>>()?,
+                            None => vec![],
+                        };
+                        Authn::TlsAuthenticated {
+                            identities: identities
+                                .into_iter()
+                                .map(|proto::Identity { name }| name)
+                                .collect(),
+                            suffixes: suffixes
+                                .into_iter()
+                                .map(|proto::IdentitySuffix { parts }| Suffix::from(parts))
+                                .collect(),
+                            spiffe_prefixes,
+                        }
+                    }
+                    None => bail!("no clients permitted"),
+                }
+            }
+            authn => bail!("no authentication provided: {:?}", authn),
+        };
 
-        Ok(Inbound {
-            labels: proto.labels,
-            authorizations,
-            protocol,
+        Ok(Authz {
+            networks,
+            authn,
+            labels,
         })
     }
 }
 
+impl Authz {
+    fn to_core(&self) -> ClientAuthorization {
+        let networks = self
+            .networks
+            .iter()
+            .map(|Network { net, except }| NetworkMatch {
+                net: *net,
+                except: except.clone(),
+            })
+            .collect();
+        let authentication = match &self.authn {
+            Authn::Unauthenticated => ClientAuthentication::Unauthenticated,
+            Authn::TlsUnauthenticated => ClientAuthentication::TlsUnauthenticated,
+            Authn::TlsAuthenticated {
+                identities,
+                suffixes,
+                spiffe_prefixes,
+            } => ClientAuthentication::TlsAuthenticated(
+                identities
+                    .iter()
+                    .cloned()
+                    .map(IdentityMatch::Name)
+                    .chain(
+                        suffixes
+                            .iter()
+                            .map(|s| IdentityMatch::Suffix(s.parts.clone())),
+                    )
+                    .chain(
+                        spiffe_prefixes
+                            .iter()
+                            .cloned()
+                            .map(IdentityMatch::SpiffePrefix),
+                    )
+                    .collect(),
+            ),
+        };
+        ClientAuthorization {
+            networks,
+            authentication,
+        }
+    }
+}
+
 // === impl Network ===
 
 impl Network {
@@ -294,6 +442,16 @@ impl Network {
     }
 }
 
+impl fmt::Display for Network {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.net.fmt(f)?;
+        for net in self.except.iter() {
+            write!(f, " !{}", net)?;
+        }
+        Ok(())
+    }
+}
+
 // === impl Suffix ===
 
 impl From<Vec<String>> for Suffix {
@@ -303,7 +461,7 @@ impl From<Vec<String>> for Suffix {
         } else {
             format!(".{}", parts.join("."))
         };
-        Suffix { ends_with }
+        Suffix { parts, ends_with }
     }
 }
 
@@ -313,6 +471,34 @@ impl Suffix {
     }
 }
 
+impl fmt::Display for Suffix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "*{}", self.ends_with)
+    }
+}
+
+// === serialization helpers ===
+
+fn sorted_map<S: Serializer>(map: &HashMap<String, String>, s: S) -> Result<S::Ok, S::Error> {
+    map.iter().collect::<BTreeMap<_, _>>().serialize(s)
+}
+
+fn sorted_set<S: Serializer>(set: &HashSet<String>, s: S) -> Result<S::Ok, S::Error> {
+    set.iter().collect::<BTreeSet<_>>().serialize(s)
+}
+
+fn display<T: fmt::Display, S: Serializer>(t: &T, s: S) -> Result<S::Ok, S::Error> {
+    s.collect_str(t)
+}
+
+fn display_seq<T: fmt::Display, S: Serializer>(ts: &[T], s: S) -> Result<S::Ok, S::Error> {
+    s.collect_seq(ts.iter().map(|t| t.to_string()))
+}
+
+fn duration_ms<S: Serializer>(d: &time::Duration, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_u64(d.as_millis() as u64)
+}
+
 #[cfg(test)]
 mod network_tests {
     use super::Network;
@@ -356,3 +542,130 @@ mod network_tests {
         }
     }
 }
+
+#[cfg(test)]
+mod check_tests {
+    use super::*;
+
+    fn authz(name: &str, net: &str, authn: Authn) -> Authz {
+        Authz {
+            networks: vec![Network {
+                net: net.parse().unwrap(),
+                except: vec![],
+            }],
+            authn,
+            labels: Some(("name".to_string(), name.to_string()))
+                .into_iter()
+                .collect(),
+        }
+    }
+
+    fn name(labels: Option<&HashMap<String, String>>) -> Option<&str> {
+        labels.and_then(|l| l.get("name")).map(String::as_str)
+    }
+
+    /// The most specific authorization is reported, regardless of the order in which
+    /// authorizations are listed.
+    #[test]
+    fn most_specific() {
+        let ns = Authn::TlsAuthenticated {
+            identities: HashSet::new(),
+            suffixes: vec![Suffix::from(vec!["ns".to_string(), "local".to_string()])],
+            spiffe_prefixes: vec![],
+        };
+        let inbound = Inbound::new(
+            Protocol::Opaque,
+            vec![
+                authz("a-unauthed", "10.0.0.0/24", Authn::Unauthenticated),
+                authz("b-unauthed", "10.0.0.0/8", Authn::Unauthenticated),
+                authz("c-ns", "0.0.0.0/0", ns),
+            ],
+            vec![],
+            HashMap::new(),
+        );
+        let ip = "10.0.0.1".parse().unwrap();
+        assert_eq!(name(inbound.check_non_tls(ip)), Some("a-unauthed"));
+        assert_eq!(
+            name(inbound.check_tls(ip, Some("sa.ns.local"))),
+            Some("c-ns")
+        );
+        assert_eq!(name(inbound.check_tls(ip, None)), Some("a-unauthed"));
+
+        let ip = "10.1.0.1".parse().unwrap();
+        assert_eq!(name(inbound.check_non_tls(ip)), Some("b-unauthed"));
+    }
+
+    /// Requests that match a route are only authorized by that route's authorizations.
+    #[test]
+    fn routes() {
+        let admin = Route {
+            matches: vec![HttpRouteMatch {
+                path: Some(polixy_controller_core::PathMatch::Prefix(
+                    "/admin".to_string(),
+                )),
+                methods: vec![],
+            }],
+            authorization: authz("admin", "10.0.0.0/24", Authn::Unauthenticated),
+        };
+        let inbound = Inbound::new(
+            Protocol::Http1,
+            vec![authz("all", "10.0.0.0/8", Authn::Unauthenticated)],
+            vec![admin],
+            HashMap::new(),
+        );
+
+        let ip = "10.0.0.1".parse().unwrap();
+        let check = |ip, path| name(inbound.check_http(ip, ClientTls::Plaintext, "GET", path));
+        assert_eq!(check(ip, "/"), Some("all"));
+        assert_eq!(check(ip, "/admin/users"), Some("admin"));
+        assert_eq!(check("10.1.0.1".parse().unwrap(), "/"), Some("all"));
+        assert_eq!(check("10.1.0.1".parse().unwrap(), "/admin"), None);
+    }
+
+    /// SPIFFE prefixes are decoded from authorization labels.
+    #[test]
+    fn decode_spiffe_prefixes() {
+        let authz = proto::Authz {
+            networks: vec![proto::Network {
+                net: Some("0.0.0.0/0".parse::<IpNet>().unwrap().into()),
+                except: vec![],
+            }],
+            authentication: Some(proto::Authn {
+                permit: Some(proto::authn::Permit::MeshTls(proto::authn::PermitMeshTls {
+                    clients: Some(proto::authn::permit_mesh_tls::Clients::Identities(
+                        proto::authn::permit_mesh_tls::PermitClientIdentities {
+                            identities: vec![proto::Identity {
+                                name: "spiffe://example.org/ns/web/sa/api".to_string(),
+                            }],
+                            suffixes: vec![],
+                        },
+                    )),
+                })),
+            }),
+            labels: vec![
+                ("name".to_string(), "web".to_string()),
+                (
+                    SPIFFE_LABEL.to_string(),
+                    r#"["spiffe://example.org/ns/web/*"]"#.to_string(),
+                ),
+            ]
+            .into_iter()
+            .collect(),
+        };
+        let authz = Authz::try_from(authz).unwrap();
+        assert!(!authz.labels.contains_key(SPIFFE_LABEL));
+        let inbound = Inbound::new(Protocol::Opaque, vec![authz], vec![], HashMap::new());
+
+        let ip = "10.0.0.1".parse().unwrap();
+        for id in &[
+            "spiffe://example.org/ns/web/sa/api",
+            "spiffe://example.org/ns/web/sa/db",
+        ] {
+            assert_eq!(name(inbound.check_tls(ip, Some(id))), Some("web"), "{}", id);
+        }
+        assert_eq!(
+            name(inbound.check_tls(ip, Some("spiffe://example.org/ns/db/sa/db"))),
+            None
+        );
+    }
+}
@@ -3,7 +3,12 @@
 
 use anyhow::{bail, Result};
 use futures::prelude::*;
-use std::{collections::HashMap, net::SocketAddr, sync::Arc};
+use polixy_client::{
+    output::{Format, Update},
+    pods::{self, Pods},
+};
+use polixy_controller_core::Backoff;
+use std::{collections::HashMap, net::SocketAddr, sync::Arc, time::Duration};
 use structopt::StructOpt;
 use tracing::info;
 
@@ -13,6 +18,10 @@ struct Args {
     #[structopt(long, env, default_value = "http://127.0.0.1:8090")]
     grpc_addr: String,
 
+    /// The controller's admin server, used to discover pod ports.
+    #[structopt(long, env, default_value = "http://127.0.0.1:8080")]
+    admin_addr: String,
+
     #[structopt(subcommand)]
     command: Command,
 }
@@ -22,12 +31,24 @@ enum Command {
     Watch {
         #[structopt(short, long, default_value = "default")]
         namespace: String,
-        pod: String,
-        port: u16,
+        /// Output format: json (newline-delimited), yaml, or table.
+        #[structopt(short, long, default_value = "table")]
+        output: Format,
+        /// Watches all ports of the pods matching a label selector.
+        #[structopt(short = "l", long, conflicts_with = "pod")]
+        selector: Option<String>,
+        /// A pod name. Required unless a selector is specified.
+        #[structopt(required_unless = "selector")]
+        pod: Option<String>,
+        /// A port. If unspecified, all of the pod's discoverable ports are watched.
+        port: Option<u16>,
     },
     Get {
         #[structopt(short, long, default_value = "default")]
         namespace: String,
+        /// Output format: json, yaml, or table.
+        #[structopt(short, long, default_value = "table")]
+        output: Format,
         pod: String,
         port: u16,
     },
@@ -41,45 +62,102 @@ enum Command {
         #[structopt(env = "POD")]
         pod: String,
 
+        /// Ports to serve. If unspecified, all of the pod's discoverable ports are served.
         #[structopt(env = "PORTS")]
         ports: Vec<u16>,
+
+        /// The minimum delay before reconnecting a failed watch. Defaults to 2s.
+        #[structopt(long, env)]
+        backoff_min_ms: Option<u64>,
+
+        /// The maximum delay before reconnecting a failed watch.
+        #[structopt(long, env, default_value = "30000")]
+        backoff_max_ms: u64,
+
+        /// The fraction by which reconnect delays are randomly reduced.
+        #[structopt(long, env, default_value = "0.5")]
+        backoff_jitter: f64,
     },
+    /// Prints the controller's policy data document, e.g. to be loaded by `opa/polixy.rego`.
+    Export,
 }
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> Result<()> {
     tracing_subscriber::fmt::init();
 
-    let Args { grpc_addr, command } = Args::from_args();
+    let Args {
+        grpc_addr,
+        admin_addr,
+        command,
+    } = Args::from_args();
+
+    // Exports are served by the admin server and don't need a discovery client.
+    if let Command::Export = command {
+        let data = polixy_client::export::fetch_policy_data(&admin_addr).await?;
+        serde_json::to_writer_pretty(std::io::stdout(), &data)?;
+        println!();
+        return Ok(());
+    }
 
     let mut client = polixy_client::Client::connect(grpc_addr).await?;
 
     match command {
         Command::Watch {
             namespace,
+            output,
+            selector,
             pod,
             port,
         } => {
-            let workload = format!("{}:{}", namespace, pod);
-            let mut updates = client.watch_port(workload, port).await?;
-            while let Some(res) = updates.next().await {
+            if let (Some(pod), Some(port)) = (pod.as_ref(), port) {
+                let workload = format!("{}:{}", namespace, pod);
+                let mut updates = client.watch_port(workload, port).await?;
+                while let Some(res) = updates.next().await {
+                    match res {
+                        Ok(config) => {
+                            output.write_update(&mut std::io::stdout(), &Update::now(&config))?
+                        }
+                        Err(error) => eprintln!("Update failed: {}", error),
+                    }
+                }
+                eprintln!("Stream closed");
+                return Ok(());
+            }
+
+            let selected = match (selector, pod) {
+                (Some(selector), _) => Pods::Selector(selector),
+                (None, Some(pod)) => Pods::Name(pod),
+                (None, None) => bail!("a pod or selector must be specified"),
+            };
+            let discovered = pods::discover_ports(&admin_addr, &namespace, &selected).await?;
+            if discovered.iter().all(|p| p.ports.is_empty()) {
+                bail!("no ports discovered in ns={} for {:?}", namespace, selected);
+            }
+
+            let mut updates = pods::watch_pods(client, discovered);
+            while let Some((pod, port, res)) = updates.next().await {
                 match res {
-                    Ok(config) => println!("{:#?}", config),
-                    Err(error) => eprintln!("Update failed: {}", error),
+                    Ok(config) => output.write_update(
+                        &mut std::io::stdout(),
+                        &Update::now(&config).with_target(&pod, port),
+                    )?,
+                    Err(error) => eprintln!("Update failed: {}:{}: {}", pod, port, error),
                 }
             }
-            eprintln!("Stream closed");
+            eprintln!("Streams closed");
             Ok(())
         }
 
         Command::Get {
             namespace,
+            output,
             pod,
             port,
         } => {
             let workload = format!("{}:{}", namespace, pod);
             let server = client.get_port(workload, port).await?;
-            println!("{:#?}", server);
+            output.write_inbound(&mut std::io::stdout(), &server)?;
             Ok(())
         }
 
@@ -88,14 +166,34 @@ async fn main() -> Result<()> {
             namespace,
             pod,
             ports,
+            backoff_min_ms,
+            backoff_max_ms,
+            backoff_jitter,
         } => {
+            let backoff = Backoff::new(
+                backoff_min_ms
+                    .map(Duration::from_millis)
+                    .unwrap_or(Backoff::DEFAULT_MIN),
+                Duration::from_millis(backoff_max_ms),
+                backoff_jitter,
+            )?;
+
+            let ports = if ports.is_empty() {
+                pods::discover_ports(&admin_addr, &namespace, &Pods::Name(pod.clone()))
+                    .await?
+                    .into_iter()
+                    .flat_map(|p| p.ports)
+                    .collect()
+            } else {
+                ports
+            };
             if ports.is_empty() {
-                bail!("no ports specified with ns={} and pod={}", namespace, pod);
+                bail!("no ports discovered with ns={} and pod={}", namespace, pod);
             }
 
             let workload = format!("{}:{}", namespace, pod);
 
-            let watches = polixy_client::watch_ports(client, workload, ports)
+            let watches = polixy_client::watch_ports(client, workload, ports, backoff)
                 .await
                 .expect("Failed to watch ports");
 
@@ -105,14 +203,29 @@ async fn main() -> Result<()> {
                     .map(|(p, w)| (*p, w.rx.clone()))
                     .collect::<HashMap<_, _>>(),
             );
+            let reconnects = Arc::new(
+                watches
+                    .iter()
+                    .map(|(p, w)| (*p, w.reconnects.clone()))
+                    .collect::<HashMap<_, _>>(),
+            );
 
             let server = hyper::server::Server::bind(&listen_addr).serve(
                 hyper::service::make_service_fn(move |_conn| {
                     let ports = ports.clone();
+                    let reconnects = reconnects.clone();
                     future::ok::<_, hyper::Error>(hyper::service::service_fn(
                         move |req: hyper::Request<hyper::Body>| {
                             let ports = ports.clone();
-                            async move { polixy_client::http_api::serve(ports.as_ref(), req).await }
+                            let reconnects = reconnects.clone();
+                            async move {
+                                if req.uri().path() == "/metrics" {
+                                    return Ok(polixy_client::http_api::serve_metrics(
+                                        reconnects.as_ref(),
+                                    ));
+                                }
+                                polixy_client::http_api::serve(ports.as_ref(), req).await
+                            }
                         },
                     ))
                 }),
@@ -125,5 +238,7 @@ async fn main() -> Result<()> {
             }
             Ok(())
         }
+
+        Command::Export => unreachable!("exports are handled above"),
     }
 }
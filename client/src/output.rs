@@ -0,0 +1,307 @@
+//! Writes discovered configurations in machine- or human-readable formats.
+
+use crate::{Authn, Authz, Inbound, Protocol};
+use anyhow::{anyhow, Error, Result};
+use serde::Serialize;
+use std::io::Write;
+
+/// Describes how configurations are written.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// Pretty JSON for single configurations and newline-delimited JSON for updates.
+    Json,
+
+    /// A YAML document per configuration.
+    Yaml,
+
+    /// An aligned text table per configuration.
+    Table,
+}
+
+/// A timestamped configuration update, as written when watching a port.
+///
+/// When multiple ports are watched, each update is tagged with its pod and port.
+#[derive(Clone, Debug, Serialize)]
+pub struct Update<'i> {
+    /// An RFC 3339 timestamp in UTC.
+    pub timestamp: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pod: Option<&'i str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    pub inbound: &'i Inbound,
+}
+
+// === impl Format ===
+
+impl Format {
+    /// Writes a single configuration.
+    pub fn write_inbound<W: Write>(&self, out: &mut W, inbound: &Inbound) -> Result<()> {
+        match self {
+            Self::Json => {
+                serde_json::to_writer_pretty(&mut *out, inbound)?;
+                writeln!(out)?;
+            }
+            Self::Yaml => serde_yaml::to_writer(&mut *out, inbound)?,
+            Self::Table => write_table(out, inbound)?,
+        }
+        out.flush()?;
+        Ok(())
+    }
+
+    /// Writes a configuration update.
+    ///
+    /// JSON updates are written on a single line so that a stream of updates may be consumed as
+    /// newline-delimited JSON.
+    pub fn write_update<W: Write>(&self, out: &mut W, update: &Update<'_>) -> Result<()> {
+        match self {
+            Self::Json => {
+                serde_json::to_writer(&mut *out, update)?;
+                writeln!(out)?;
+            }
+            Self::Yaml => serde_yaml::to_writer(&mut *out, update)?,
+            Self::Table => {
+                write!(out, "# {}", update.timestamp)?;
+                if let Some(pod) = update.pod {
+                    write!(out, " pod={}", pod)?;
+                }
+                if let Some(port) = update.port {
+                    write!(out, " port={}", port)?;
+                }
+                writeln!(out)?;
+                write_table(out, update.inbound)?;
+                writeln!(out)?;
+            }
+        }
+        out.flush()?;
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "json" => Ok(Self::Json),
+            "yaml" => Ok(Self::Yaml),
+            "table" => Ok(Self::Table),
+            s => Err(anyhow!("invalid output format: {}", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Json => "json".fmt(f),
+            Self::Yaml => "yaml".fmt(f),
+            Self::Table => "table".fmt(f),
+        }
+    }
+}
+
+// === impl Update ===
+
+impl<'i> Update<'i> {
+    /// Describes an update observed now.
+    pub fn now(inbound: &'i Inbound) -> Self {
+        Self {
+            timestamp: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            pod: None,
+            port: None,
+            inbound,
+        }
+    }
+
+    /// Tags the update with the pod and port that produced it.
+    pub fn with_target(self, pod: &'i str, port: u16) -> Self {
+        Self {
+            pod: Some(pod),
+            port: Some(port),
+            ..self
+        }
+    }
+}
+
+// === table ===
+
+fn write_table<W: Write>(out: &mut W, inbound: &Inbound) -> std::io::Result<()> {
+    let protocol = match inbound.protocol {
+        Protocol::Detect { timeout } => format!("detect (timeout {}ms)", timeout.as_millis()),
+        Protocol::Http1 => "http1".to_string(),
+        Protocol::Http2 => "http2".to_string(),
+        Protocol::Grpc => "grpc".to_string(),
+        Protocol::Opaque => "opaque".to_string(),
+        Protocol::Tls => "tls".to_string(),
+    };
+    writeln!(out, "PROTOCOL: {}", protocol)?;
+
+    let mut rows = vec![[
+        "NAME".to_string(),
+        "ROUTES".to_string(),
+        "AUTHN".to_string(),
+        "NETWORKS".to_string(),
+        "CLIENTS".to_string(),
+    ]];
+    let mut authzs = inbound.authorizations().iter().collect::<Vec<_>>();
+    authzs.sort_by(|a, b| a.labels.get("name").cmp(&b.labels.get("name")));
+    rows.extend(authzs.into_iter().map(|a| mk_row(a, "*".to_string())));
+    rows.extend(inbound.routes().iter().map(|r| {
+        let routes = r
+            .matches()
+            .iter()
+            .map(|m| m.to_string())
+            .collect::<Vec<_>>()
+            .join(";");
+        mk_row(r.authorization(), routes)
+    }));
+
+    let mut widths = [0; 5];
+    for row in rows.iter() {
+        for (w, col) in widths.iter_mut().zip(row.iter()) {
+            *w = (*w).max(col.len());
+        }
+    }
+
+    for row in rows.iter() {
+        let line = row
+            .iter()
+            .zip(widths.iter())
+            .map(|(col, w)| format!("{:w$}", col, w = *w))
+            .collect::<Vec<_>>()
+            .join("  ");
+        writeln!(out, "{}", line.trim_end())?;
+    }
+
+    Ok(())
+}
+
+fn mk_row(authz: &Authz, routes: String) -> [String; 5] {
+    let name = authz
+        .labels
+        .get("name")
+        .cloned()
+        .unwrap_or_else(|| "-".to_string());
+
+    let networks = authz
+        .networks
+        .iter()
+        .map(|n| n.to_string())
+        .collect::<Vec<_>>()
+        .join(",");
+
+    let (authn, clients) = match authz.authn {
+        Authn::Unauthenticated => ("unauthenticated", "-".to_string()),
+        Authn::TlsUnauthenticated => ("tls-unauthenticated", "-".to_string()),
+        Authn::TlsAuthenticated {
+            ref identities,
+            ref suffixes,
+            ref spiffe_prefixes,
+        } => {
+            let mut clients = identities.iter().cloned().collect::<Vec<_>>();
+            clients.sort();
+            clients.extend(suffixes.iter().map(|s| s.to_string()));
+            clients.extend(spiffe_prefixes.iter().map(|p| p.to_string()));
+            ("tls-authenticated", clients.join(","))
+        }
+    };
+
+    [name, routes, authn.to_string(), networks, clients]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Network, Suffix};
+    use std::time::Duration;
+
+    fn mk_inbound() -> Inbound {
+        Inbound::new(
+            Protocol::Detect {
+                timeout: Duration::from_secs(10),
+            },
+            vec![Authz {
+                networks: vec![Network {
+                    net: "10.0.0.0/8".parse().unwrap(),
+                    except: vec!["10.1.0.0/16".parse().unwrap()],
+                }],
+                authn: Authn::TlsAuthenticated {
+                    identities: Some("b.ns.serviceaccount.identity.linkerd.cluster.local".into())
+                        .into_iter()
+                        .chain(Some(
+                            "a.ns.serviceaccount.identity.linkerd.cluster.local".into(),
+                        ))
+                        .collect(),
+                    suffixes: vec![Suffix::from(vec!["cluster".into(), "local".into()])],
+                    spiffe_prefixes: vec![],
+                },
+                labels: Some(("name".to_string(), "authz-0".to_string()))
+                    .into_iter()
+                    .collect(),
+            }],
+            vec![],
+            Default::default(),
+        )
+    }
+
+    #[test]
+    fn json_schema() {
+        let inbound = mk_inbound();
+        let mut out = Vec::new();
+        Format::Json.write_inbound(&mut out, &inbound).unwrap();
+        let value = serde_json::from_slice::<serde_json::Value>(&out).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({
+                "protocol": { "kind": "detect", "timeoutMs": 10000 },
+                "authorizations": [{
+                    "networks": [{ "net": "10.0.0.0/8", "except": ["10.1.0.0/16"] }],
+                    "authn": {
+                        "kind": "tlsAuthenticated",
+                        "identities": [
+                            "a.ns.serviceaccount.identity.linkerd.cluster.local",
+                            "b.ns.serviceaccount.identity.linkerd.cluster.local",
+                        ],
+                        "suffixes": ["*.cluster.local"],
+                    },
+                    "labels": { "name": "authz-0" },
+                }],
+                "labels": {},
+            })
+        );
+    }
+
+    #[test]
+    fn json_updates_are_single_lines() {
+        let inbound = mk_inbound();
+        let mut out = Vec::new();
+        for _ in 0..2 {
+            Format::Json
+                .write_update(&mut out, &Update::now(&inbound))
+                .unwrap();
+        }
+        let out = String::from_utf8(out).unwrap();
+        let lines = out.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        for line in lines {
+            let value = serde_json::from_str::<serde_json::Value>(line).unwrap();
+            assert!(value["timestamp"].is_string());
+            assert!(value.get("pod").is_none());
+            assert!(value.get("port").is_none());
+            assert_eq!(value["inbound"]["protocol"]["kind"], "detect");
+        }
+    }
+
+    #[test]
+    fn json_updates_are_tagged() {
+        let inbound = mk_inbound();
+        let mut out = Vec::new();
+        Format::Json
+            .write_update(&mut out, &Update::now(&inbound).with_target("pod-0", 8080))
+            .unwrap();
+        let value = serde_json::from_slice::<serde_json::Value>(&out).unwrap();
+        assert_eq!(value["pod"], "pod-0");
+        assert_eq!(value["port"], 8080);
+    }
+}
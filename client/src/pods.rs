@@ -0,0 +1,93 @@
+//! Discovers and watches all of the ports that the controller has indexed for pods.
+
+use crate::{Client, Inbound};
+use anyhow::{bail, Context, Result};
+use futures::{future, prelude::*, stream};
+use serde::Deserialize;
+
+/// Selects the pods in a namespace whose ports are discovered.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Pods {
+    /// A single pod, by name.
+    Name(String),
+
+    /// All pods matching a Kubernetes label selector, e.g. `app=web,tier in (a,b)`.
+    Selector(String),
+}
+
+/// A pod's discoverable ports, as listed by the controller's admin server.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+pub struct PodPorts {
+    pub namespace: String,
+    pub name: String,
+    pub ports: Vec<u16>,
+}
+
+/// Lists the ports that the controller has indexed for the selected pods.
+///
+/// `admin_addr` is the base URI of the controller's admin server, e.g. `http://127.0.0.1:8080`.
+pub async fn discover_ports(
+    admin_addr: &str,
+    namespace: &str,
+    pods: &Pods,
+) -> Result<Vec<PodPorts>> {
+    let base = admin_addr.trim_end_matches('/');
+    let uri = match pods {
+        Pods::Name(name) => format!("{}/ports/{}/{}", base, namespace, name),
+        Pods::Selector(selector) => format!(
+            "{}/ports/{}?labelSelector={}",
+            base,
+            namespace,
+            form_urlencoded::byte_serialize(selector.as_bytes()).collect::<String>()
+        ),
+    };
+    let uri = uri
+        .parse::<hyper::Uri>()
+        .with_context(|| format!("invalid admin URI: {}", uri))?;
+
+    let rsp = hyper::Client::new().get(uri).await?;
+    let status = rsp.status();
+    let body = hyper::body::to_bytes(rsp.into_body()).await?;
+    if !status.is_success() {
+        bail!(
+            "port discovery failed: {}: {}",
+            status,
+            String::from_utf8_lossy(body.as_ref()).trim()
+        );
+    }
+
+    serde_json::from_slice(body.as_ref()).context("invalid port discovery response")
+}
+
+/// Watches each pod port, multiplexing all updates onto a single stream.
+///
+/// Each update is tagged with the pod name and port that produced it. Failure to start a watch is
+/// surfaced as an error on the stream and does not interrupt the other watches.
+pub fn watch_pods(
+    client: Client,
+    pods: impl IntoIterator<Item = PodPorts>,
+) -> impl Stream<Item = (String, u16, Result<Inbound>)> {
+    let watches = pods
+        .into_iter()
+        .flat_map(|p| {
+            let PodPorts {
+                namespace, name, ..
+            } = p;
+            p.ports
+                .into_iter()
+                .map(move |port| (namespace.clone(), name.clone(), port))
+        })
+        .map(move |(ns, pod, port)| {
+            let mut client = client.clone();
+            let workload = format!("{}:{}", ns, pod);
+            stream::once(async move { client.watch_port(workload, port).await })
+                .flat_map(|res| match res {
+                    Ok(updates) => updates.left_stream(),
+                    Err(error) => stream::once(future::err(error)).right_stream(),
+                })
+                .map(move |res| (pod.clone(), port, res))
+                .boxed()
+        });
+
+    stream::select_all(watches)
+}
@@ -1,6 +1,7 @@
 use crate::{Client, Inbound};
-use anyhow::{Error, Result};
+use anyhow::{anyhow, Error, Result};
 use futures::{future, prelude::*};
+use polixy_controller_core::{Attempts, Backoff, Reconnects};
 use std::collections::HashMap;
 use tokio::{sync::watch, time};
 
@@ -8,22 +9,62 @@ use tokio::{sync::watch, time};
 pub struct PortWatch {
     pub rx: watch::Receiver<Inbound>,
     pub task: tokio::task::JoinHandle<Result<()>>,
+    pub reconnects: Reconnects,
+}
+
+/// Limits how many consecutive `NotFound` responses are retried.
+///
+/// The controller responds with `NotFound` until a pod has been indexed, so a pod that was just
+/// created may briefly be unknown. A pod that remains unknown has most likely been deleted.
+const MAX_NOT_FOUND: u32 = 5;
+
+/// Describes how a failed watch is handled.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Class {
+    /// The watch is retried after a backoff.
+    Retry,
+
+    /// The watch is retried after a backoff, but only a limited number of times.
+    NotFound,
+
+    /// The watch is abandoned.
+    Fatal,
+}
+
+/// Tracks a port watch's failures so that reconnects back off.
+struct Recover {
+    attempts: Attempts,
+    reconnects: Reconnects,
+    not_found: u32,
 }
 
 pub async fn watch_ports(
     client: Client,
     workload: String,
     ports: Vec<u16>,
+    backoff: Backoff,
 ) -> Result<HashMap<u16, PortWatch>> {
     let futures = ports.into_iter().map(move |port| {
-        watch_port(client.clone(), workload.clone(), port).map_ok(move |pw| (port, pw))
+        watch_port(client.clone(), workload.clone(), port, backoff).map_ok(move |pw| (port, pw))
     });
     let watches = future::try_join_all(futures).await?;
     Ok(watches.into_iter().collect::<HashMap<_, _>>())
 }
 
-async fn watch_port(mut client: Client, workload: String, port: u16) -> Result<PortWatch> {
-    let (inbound, mut updates) = start_watch(&mut client, workload.clone(), port).await?;
+async fn watch_port(
+    mut client: Client,
+    workload: String,
+    port: u16,
+    backoff: Backoff,
+) -> Result<PortWatch> {
+    let reconnects = Reconnects::default();
+    let mut recover = Recover {
+        attempts: backoff.attempts(),
+        reconnects: reconnects.clone(),
+        not_found: 0,
+    };
+    let (inbound, mut updates) =
+        start_watch(&mut client, workload.clone(), port, &mut recover).await?;
     let (tx, rx) = watch::channel(inbound);
 
     let task = tokio::spawn(async move {
@@ -38,67 +79,159 @@ async fn watch_port(mut client: Client, workload: String, port: u16) -> Result<P
             match res {
                 Ok(Some(inbound)) => {
                     let _ = tx.send(inbound);
+                    continue;
                 }
 
-                Ok(None) => {
-                    let (inbound, stream) = tokio::select! {
-                        res = start_watch(&mut client, workload.clone(), port) => res?,
-                        _ = tx.closed() => {
-                            return Ok(());
-                        }
-                    };
-
-                    let _ = tx.send(inbound);
-                    updates = stream;
-                }
+                Ok(None) => {}
 
                 Err(error) => {
-                    tracing::debug!(%error);
-                    let (inbound, stream) = tokio::select! {
-                        res = start_watch(&mut client, workload.clone(), port) => res?,
+                    tokio::select! {
+                        res = recover.recover(error) => res?,
                         _ = tx.closed() => {
                             return Ok(());
                         }
-                    };
-
-                    let _ = tx.send(inbound);
-                    updates = stream;
+                    }
                 }
             }
+
+            let (inbound, stream) = tokio::select! {
+                res = start_watch(&mut client, workload.clone(), port, &mut recover) => res?,
+                _ = tx.closed() => {
+                    return Ok(());
+                }
+            };
+
+            let _ = tx.send(inbound);
+            updates = stream;
         }
     });
 
-    Ok(PortWatch { rx, task })
+    Ok(PortWatch {
+        rx,
+        task,
+        reconnects,
+    })
 }
 
 async fn start_watch(
     client: &mut Client,
     workload: String,
     port: u16,
+    recover: &mut Recover,
 ) -> Result<(Inbound, impl Stream<Item = Result<Inbound>>)> {
     loop {
         match client.watch_port(workload.clone(), port).await {
             Ok(mut updates) => match updates.try_next().await {
-                Ok(Some(inbound)) => return Ok((inbound, updates)),
-                Ok(None) => {}
-                Err(error) => recover(error).await?,
+                Ok(Some(inbound)) => {
+                    recover.reset();
+                    return Ok((inbound, updates));
+                }
+                Ok(None) => recover.recover(anyhow!("stream closed")).await?,
+                Err(error) => recover.recover(error).await?,
             },
-            Err(error) => recover(error).await?,
+            Err(error) => recover.recover(error).await?,
         }
     }
 }
 
-async fn recover(error: Error) -> Result<()> {
-    // Check unrecoverable errors. For now, we assume that InvalidArgument means we're querying
-    // about a workload or port that doesn't exist.
-    if let Some(status) = error.downcast_ref::<tonic::Status>() {
-        if let tonic::Code::InvalidArgument = status.code() {
-            return Err(error);
+// === impl Class ===
+
+impl Class {
+    fn of(error: &Error) -> Self {
+        let status = match error.downcast_ref::<tonic::Status>() {
+            Some(status) => status,
+            // Transport and decoding errors may be resolved by reconnecting.
+            None => return Self::Retry,
+        };
+
+        match status.code() {
+            // The workload or port is malformed, or the server can never answer this client.
+            tonic::Code::InvalidArgument
+            | tonic::Code::Unimplemented
+            | tonic::Code::Unauthenticated
+            | tonic::Code::PermissionDenied => Self::Fatal,
+
+            tonic::Code::NotFound => Self::NotFound,
+
+            // Unavailable, Internal, DeadlineExceeded, ResourceExhausted, etc.
+            _ => Self::Retry,
         }
     }
+}
 
-    // TODO exponential back-off
-    tracing::debug!(%error, "Recovering");
-    time::sleep(time::Duration::from_secs(1)).await;
-    Ok(())
+// === impl Recover ===
+
+impl Recover {
+    /// Returns the error if it cannot be recovered from; otherwise waits before a retry.
+    async fn recover(&mut self, error: Error) -> Result<()> {
+        match Class::of(&error) {
+            Class::Fatal => return Err(error),
+            Class::NotFound => {
+                self.not_found += 1;
+                if self.not_found > MAX_NOT_FOUND {
+                    return Err(error);
+                }
+            }
+            Class::Retry => {}
+        }
+
+        let delay = self.attempts.next_delay();
+        self.reconnects.attempt();
+        tracing::debug!(%error, ?delay, "Recovering");
+        time::sleep(delay).await;
+        Ok(())
+    }
+
+    /// Resets the backoff once a watch has been (re)established.
+    fn reset(&mut self) {
+        if self.attempts.failures() > 0 {
+            self.reconnects.recovered();
+        }
+        self.attempts.reset();
+        self.not_found = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classify() {
+        for (code, class) in &[
+            (tonic::Code::InvalidArgument, Class::Fatal),
+            (tonic::Code::PermissionDenied, Class::Fatal),
+            (tonic::Code::NotFound, Class::NotFound),
+            (tonic::Code::Unavailable, Class::Retry),
+            (tonic::Code::Internal, Class::Retry),
+        ] {
+            let error = Error::new(tonic::Status::new(*code, "test"));
+            assert_eq!(Class::of(&error), *class, "{:?}", code);
+        }
+        assert_eq!(Class::of(&anyhow!("decode failed")), Class::Retry);
+    }
+
+    #[tokio::test(flavor = "current_thread", start_paused = true)]
+    async fn not_found_is_limited() {
+        let mut recover = Recover {
+            attempts: Backoff::default().attempts(),
+            reconnects: Reconnects::default(),
+            not_found: 0,
+        };
+        for _ in 0..MAX_NOT_FOUND {
+            recover
+                .recover(tonic::Status::not_found("unknown server").into())
+                .await
+                .expect("must retry");
+        }
+        assert!(recover
+            .recover(tonic::Status::not_found("unknown server").into())
+            .await
+            .is_err());
+        assert_eq!(recover.reconnects.attempts(), MAX_NOT_FOUND as u64);
+
+        recover.reset();
+        assert_eq!(recover.reconnects.recoveries(), 1);
+        assert_eq!(recover.not_found, 0);
+    }
 }
@@ -0,0 +1,691 @@
+//! Compiles authorizations into indexes so that each connection may be authorized without scanning
+//! every authorization.
+//!
+//! Networks are indexed in a binary prefix trie (one per address family); identity suffixes are
+//! indexed in a trie of reversed DNS labels and SPIFFE path prefixes in a trie of trust domains and
+//! path segments.
+//!
+//! # Precedence
+//!
+//! When multiple authorizations permit a connection, the most specific one is chosen:
+//!
+//! 1. An authorization that names the client's identity explicitly; then one that matches the
+//!    client's identity by suffix or SPIFFE path prefix (longer first, counting a SPIFFE trust
+//!    domain as one label); then one that permits TLS clients without an identity; then one that
+//!    permits unauthenticated clients.
+//! 2. Then, among authorizations of the same kind, the one with the narrowest network that
+//!    contains the client's address.
+//! 3. Then the authorization that was listed first. For an [`InboundServer`], authorizations are
+//!    listed by name.
+//!
+//! The same authorization is therefore reported for a connection regardless of how the other
+//! authorizations that also permit it are named.
+
+use crate::{
+    identity_match::spiffe_labels, ClientAuthentication, ClientAuthorization, IdentityMatch,
+    InboundServer,
+};
+use ipnet::IpNet;
+use std::{borrow::Borrow, cmp::Reverse, collections::HashMap, net::IpAddr};
+
+/// Authorizations compiled for per-connection lookups.
+#[derive(Clone, Debug, Default)]
+pub struct AuthzMatcher {
+    /// The authentication kind of each authorization, by index.
+    kinds: Vec<Kind>,
+
+    networks: NetworkTrie,
+    identities: HashMap<String, Vec<usize>>,
+    suffixes: LabelTrie,
+    spiffe_prefixes: LabelTrie,
+}
+
+/// An [`InboundServer`]'s authorizations and routes, compiled once so that many connections may be
+/// authorized against them.
+#[derive(Clone, Debug)]
+pub struct ServerMatcher<'s> {
+    server: &'s InboundServer,
+    authorizations: AuthzMatcher,
+    routes: AuthzMatcher,
+}
+
+/// Describes the TLS state of a client connection.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ClientTls<'i> {
+    /// The connection does not use mesh TLS.
+    Plaintext,
+
+    /// The connection uses mesh TLS, but the client did not present an identity.
+    Unauthenticated,
+
+    /// The connection uses mesh TLS with the given client identity.
+    Authenticated(&'i str),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Kind {
+    Unauthenticated,
+    TlsUnauthenticated,
+    TlsAuthenticated,
+}
+
+/// Ranks a matching authorization. Greater ranks are more specific.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct Rank {
+    authn: AuthnRank,
+    prefix_len: u8,
+    index: Reverse<usize>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum AuthnRank {
+    Unauthenticated,
+    TlsUnauthenticated,
+
+    /// A suffix or SPIFFE prefix match, by its number of labels.
+    Suffix(usize),
+    Identity,
+}
+
+#[derive(Clone, Debug, Default)]
+struct NetworkTrie {
+    v4: BitTrie,
+    v6: BitTrie,
+
+    /// The authorization index and prefix length of each indexed network, by network ID.
+    networks: Vec<(usize, u8)>,
+}
+
+/// A binary trie over address bits. Nodes are stored in an arena; the root is at index 0.
+#[derive(Clone, Debug)]
+struct BitTrie {
+    nodes: Vec<BitNode>,
+}
+
+#[derive(Clone, Debug, Default)]
+struct BitNode {
+    children: [Option<usize>; 2],
+
+    /// Networks, by ID, that contain every address under this node.
+    includes: Vec<usize>,
+
+    /// Networks, by ID, for which every address under this node is excepted.
+    excludes: Vec<usize>,
+}
+
+/// A trie over labels. Suffixes are stored by reversed DNS labels, so that
+/// `*.ns.serviceaccount.identity.linkerd.cluster.local` is stored under `local` -> `cluster` ->
+/// `linkerd` -> ...; SPIFFE prefixes are stored by trust domain and then path segments.
+#[derive(Clone, Debug)]
+struct LabelTrie {
+    nodes: Vec<LabelNode>,
+}
+
+#[derive(Clone, Debug, Default)]
+struct LabelNode {
+    children: HashMap<String, usize>,
+
+    /// Authorizations that permit all names ending with this node's suffix.
+    authzs: Vec<usize>,
+}
+
+// === impl AuthzMatcher ===
+
+impl AuthzMatcher {
+    /// Compiles authorizations, in precedence order.
+    pub fn new<A: Borrow<ClientAuthorization>>(authzs: impl IntoIterator<Item = A>) -> Self {
+        let mut matcher = Self::default();
+        for (idx, authz) in authzs.into_iter().enumerate() {
+            let authz = authz.borrow();
+            for network in authz.networks.iter() {
+                matcher.networks.insert(idx, network.net, &network.except);
+            }
+
+            let kind = match &authz.authentication {
+                ClientAuthentication::Unauthenticated => Kind::Unauthenticated,
+                ClientAuthentication::TlsUnauthenticated => Kind::TlsUnauthenticated,
+                ClientAuthentication::TlsAuthenticated(ids) => {
+                    for id in ids.iter() {
+                        match id {
+                            IdentityMatch::Name(name) => matcher
+                                .identities
+                                .entry(name.clone())
+                                .or_default()
+                                .push(idx),
+                            IdentityMatch::Suffix(parts) => matcher
+                                .suffixes
+                                .insert(idx, parts.iter().rev().map(String::as_str)),
+                            IdentityMatch::SpiffePrefix(prefix) => matcher.spiffe_prefixes.insert(
+                                idx,
+                                Some(prefix.trust_domain.as_str())
+                                    .into_iter()
+                                    .chain(prefix.path.iter().map(String::as_str)),
+                            ),
+                        }
+                    }
+                    Kind::TlsAuthenticated
+                }
+            };
+            matcher.kinds.push(kind);
+        }
+        matcher
+    }
+
+    /// Returns the index of the most specific authorization that permits a connection.
+    pub fn find(&self, client_ip: IpAddr, tls: ClientTls<'_>) -> Option<usize> {
+        self.find_where(client_ip, tls, |_| true)
+    }
+
+    /// Returns the index of the most specific authorization that permits a connection, considering
+    /// only the authorizations for which `include` returns true.
+    pub fn find_where(
+        &self,
+        client_ip: IpAddr,
+        tls: ClientTls<'_>,
+        include: impl Fn(usize) -> bool,
+    ) -> Option<usize> {
+        let mut networks = self.networks.find(client_ip);
+        networks.retain(|(index, _)| include(*index));
+        if networks.is_empty() {
+            return None;
+        }
+
+        let (identities, prefixes) = match tls {
+            ClientTls::Authenticated(id) => {
+                let mut matches = self.suffixes.find(id.rsplit('.'));
+                if let Some(labels) = spiffe_labels(id) {
+                    for (authz, depth) in self.spiffe_prefixes.find(labels) {
+                        let d = matches.entry(authz).or_default();
+                        *d = (*d).max(depth);
+                    }
+                }
+                (
+                    self.identities.get(id).map(Vec::as_slice).unwrap_or(&[]),
+                    matches,
+                )
+            }
+            _ => (&[][..], HashMap::new()),
+        };
+
+        networks
+            .into_iter()
+            .filter_map(|(index, prefix_len)| {
+                let authn = match self.kinds[index] {
+                    Kind::Unauthenticated => AuthnRank::Unauthenticated,
+                    Kind::TlsUnauthenticated if tls != ClientTls::Plaintext => {
+                        AuthnRank::TlsUnauthenticated
+                    }
+                    Kind::TlsUnauthenticated => return None,
+                    Kind::TlsAuthenticated if identities.contains(&index) => AuthnRank::Identity,
+                    Kind::TlsAuthenticated => AuthnRank::Suffix(*prefixes.get(&index)?),
+                };
+                Some(Rank {
+                    authn,
+                    prefix_len,
+                    index: Reverse(index),
+                })
+            })
+            .max()
+            .map(|Rank { index, .. }| index.0)
+    }
+}
+
+// === impl InboundServer ===
+
+impl InboundServer {
+    /// Compiles the server's authorizations and routes for per-connection lookups.
+    pub fn matcher(&self) -> ServerMatcher<'_> {
+        ServerMatcher {
+            server: self,
+            authorizations: AuthzMatcher::new(self.authorizations.values()),
+            routes: AuthzMatcher::new(self.routes.values().map(|r| &r.authorization)),
+        }
+    }
+}
+
+// === impl ServerMatcher ===
+
+impl<'s> ServerMatcher<'s> {
+    /// Returns the name of the most specific authorization that permits a connection.
+    pub fn authorize(&self, client_ip: IpAddr, tls: ClientTls<'_>) -> Option<&'s str> {
+        let idx = self.authorizations.find(client_ip, tls)?;
+        self.server
+            .authorizations
+            .keys()
+            .nth(idx)
+            .map(String::as_str)
+    }
+
+    /// Returns the name of the most specific authorization that permits an HTTP request.
+    ///
+    /// If the request matches any of the server's routes, only the authorizations of the matching
+    /// routes are considered.
+    pub fn authorize_http(
+        &self,
+        client_ip: IpAddr,
+        tls: ClientTls<'_>,
+        method: &str,
+        path: &str,
+    ) -> Option<&'s str> {
+        let matched = self
+            .server
+            .routes
+            .values()
+            .map(|r| r.matches.iter().any(|m| m.matches(method, path)))
+            .collect::<Vec<_>>();
+        if !matched.iter().any(|m| *m) {
+            return self.authorize(client_ip, tls);
+        }
+
+        let idx = self.routes.find_where(client_ip, tls, |i| matched[i])?;
+        self.server.routes.keys().nth(idx).map(String::as_str)
+    }
+}
+
+// === impl NetworkTrie ===
+
+impl NetworkTrie {
+    fn insert(&mut self, authz: usize, net: IpNet, except: &[IpNet]) {
+        let id = self.networks.len();
+        self.networks.push((authz, net.prefix_len()));
+        self.trie_mut(&net).node_mut(&net).includes.push(id);
+        for ex in except.iter() {
+            // An exception in another address family can never match.
+            if std::mem::discriminant(ex) == std::mem::discriminant(&net) {
+                self.trie_mut(ex).node_mut(ex).excludes.push(id);
+            }
+        }
+    }
+
+    fn trie_mut(&mut self, net: &IpNet) -> &mut BitTrie {
+        match net {
+            IpNet::V4(_) => &mut self.v4,
+            IpNet::V6(_) => &mut self.v6,
+        }
+    }
+
+    /// Returns each authorization with a network that contains the address, along with the prefix
+    /// length of its narrowest such network.
+    fn find(&self, addr: IpAddr) -> Vec<(usize, u8)> {
+        let (trie, bits, len) = match addr {
+            IpAddr::V4(a) => (&self.v4, u128::from(u32::from(a)), 32),
+            IpAddr::V6(a) => (&self.v6, u128::from(a), 128),
+        };
+
+        let mut includes = Vec::new();
+        let mut excludes = Vec::new();
+        trie.walk(bits, len, |node| {
+            includes.extend_from_slice(&node.includes);
+            excludes.extend_from_slice(&node.excludes);
+        });
+
+        let mut authzs = Vec::<(usize, u8)>::with_capacity(includes.len());
+        for id in includes.into_iter().filter(|id| !excludes.contains(id)) {
+            let (authz, prefix_len) = self.networks[id];
+            match authzs.iter_mut().find(|(a, _)| *a == authz) {
+                Some((_, len)) => *len = (*len).max(prefix_len),
+                None => authzs.push((authz, prefix_len)),
+            }
+        }
+        authzs
+    }
+}
+
+// === impl BitTrie ===
+
+impl Default for BitTrie {
+    fn default() -> Self {
+        Self {
+            nodes: vec![BitNode::default()],
+        }
+    }
+}
+
+impl BitTrie {
+    /// Returns the node for the network, creating it (and its ancestors) as needed.
+    fn node_mut(&mut self, net: &IpNet) -> &mut BitNode {
+        let (bits, len) = match net {
+            IpNet::V4(n) => (u128::from(u32::from(n.network())), 32),
+            IpNet::V6(n) => (u128::from(n.network()), 128),
+        };
+
+        let mut idx = 0;
+        for i in 0..net.prefix_len() {
+            let bit = bit(bits, len, i);
+            idx = match self.nodes[idx].children[bit] {
+                Some(child) => child,
+                None => {
+                    let child = self.nodes.len();
+                    self.nodes.push(BitNode::default());
+                    self.nodes[idx].children[bit] = Some(child);
+                    child
+                }
+            };
+        }
+        &mut self.nodes[idx]
+    }
+
+    /// Visits each node on the path to an address, starting at the root.
+    fn walk(&self, bits: u128, len: u8, mut visit: impl FnMut(&BitNode)) {
+        let mut idx = 0;
+        visit(&self.nodes[idx]);
+        for i in 0..len {
+            match self.nodes[idx].children[bit(bits, len, i)] {
+                Some(child) => idx = child,
+                None => return,
+            }
+            visit(&self.nodes[idx]);
+        }
+    }
+}
+
+/// Returns the `i`th most significant bit of a `len`-bit address.
+fn bit(bits: u128, len: u8, i: u8) -> usize {
+    ((bits >> (len - 1 - i)) & 1) as usize
+}
+
+// === impl LabelTrie ===
+
+impl Default for LabelTrie {
+    fn default() -> Self {
+        Self {
+            nodes: vec![LabelNode::default()],
+        }
+    }
+}
+
+impl LabelTrie {
+    fn insert<'l>(&mut self, authz: usize, labels: impl IntoIterator<Item = &'l str>) {
+        let mut idx = 0;
+        for label in labels {
+            idx = match self.nodes[idx].children.get(label) {
+                Some(child) => *child,
+                None => {
+                    let child = self.nodes.len();
+                    self.nodes.push(LabelNode::default());
+                    self.nodes[idx].children.insert(label.to_string(), child);
+                    child
+                }
+            };
+        }
+        self.nodes[idx].authzs.push(authz);
+    }
+
+    /// Returns each authorization with a prefix of the labels, along with the number of labels in
+    /// its longest such prefix.
+    ///
+    /// A prefix only matches if there is at least one more label than the prefix itself.
+    fn find<'l>(&self, labels: impl IntoIterator<Item = &'l str>) -> HashMap<usize, usize> {
+        let mut authzs = HashMap::new();
+        let mut idx = 0;
+        for (depth, label) in labels.into_iter().enumerate() {
+            for authz in self.nodes[idx].authzs.iter() {
+                authzs.insert(*authz, depth);
+            }
+            match self.nodes[idx].children.get(label) {
+                Some(child) => idx = *child,
+                None => break,
+            }
+        }
+        authzs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::NetworkMatch;
+    use std::net::Ipv4Addr;
+
+    fn authz(
+        nets: &[&str],
+        except: &[&str],
+        authentication: ClientAuthentication,
+    ) -> ClientAuthorization {
+        ClientAuthorization {
+            networks: nets
+                .iter()
+                .map(|n| NetworkMatch {
+                    net: n.parse().unwrap(),
+                    except: except.iter().map(|e| e.parse().unwrap()).collect(),
+                })
+                .collect(),
+            authentication,
+        }
+    }
+
+    fn ids(names: &[&str], suffixes: &[&str]) -> ClientAuthentication {
+        ClientAuthentication::TlsAuthenticated(
+            names
+                .iter()
+                .map(|n| IdentityMatch::Name(n.to_string()))
+                .chain(
+                    suffixes
+                        .iter()
+                        .map(|s| IdentityMatch::Suffix(s.split('.').map(String::from).collect())),
+                )
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn networks() {
+        let m = AuthzMatcher::new(vec![
+            authz(&["0.0.0.0/0"], &[], ClientAuthentication::Unauthenticated),
+            authz(
+                &["10.0.0.0/8"],
+                &["10.1.0.0/16"],
+                ClientAuthentication::Unauthenticated,
+            ),
+            authz(&["fd00::/8"], &[], ClientAuthentication::Unauthenticated),
+        ]);
+        let plain = ClientTls::Plaintext;
+        assert_eq!(m.find(Ipv4Addr::new(192, 168, 0, 1).into(), plain), Some(0));
+        assert_eq!(m.find(Ipv4Addr::new(10, 2, 0, 1).into(), plain), Some(1));
+        assert_eq!(m.find(Ipv4Addr::new(10, 1, 0, 1).into(), plain), Some(0));
+        assert_eq!(m.find("fd00::1".parse().unwrap(), plain), Some(2));
+        assert_eq!(m.find("fe00::1".parse().unwrap(), plain), None);
+    }
+
+    #[test]
+    fn authentication() {
+        let m = AuthzMatcher::new(vec![
+            authz(&["0.0.0.0/0"], &[], ClientAuthentication::Unauthenticated),
+            authz(
+                &["0.0.0.0/0"],
+                &[],
+                ClientAuthentication::TlsUnauthenticated,
+            ),
+            authz(&["0.0.0.0/0"], &[], ids(&[], &["cluster.local"])),
+            authz(
+                &["0.0.0.0/0"],
+                &[],
+                ids(&[], &["ns.serviceaccount.identity.linkerd.cluster.local"]),
+            ),
+            authz(
+                &["0.0.0.0/0"],
+                &[],
+                ids(
+                    &["sa.ns.serviceaccount.identity.linkerd.cluster.local"],
+                    &[],
+                ),
+            ),
+        ]);
+        let ip = Ipv4Addr::new(10, 0, 0, 1).into();
+        assert_eq!(m.find(ip, ClientTls::Plaintext), Some(0));
+        assert_eq!(m.find(ip, ClientTls::Unauthenticated), Some(1));
+        assert_eq!(
+            m.find(ip, ClientTls::Authenticated("foo.cluster.local")),
+            Some(2)
+        );
+        assert_eq!(
+            m.find(ip, ClientTls::Authenticated("cluster.local")),
+            Some(1)
+        );
+        assert_eq!(
+            m.find(
+                ip,
+                ClientTls::Authenticated("other.ns.serviceaccount.identity.linkerd.cluster.local")
+            ),
+            Some(3)
+        );
+        assert_eq!(
+            m.find(
+                ip,
+                ClientTls::Authenticated("sa.ns.serviceaccount.identity.linkerd.cluster.local")
+            ),
+            Some(4)
+        );
+    }
+
+    #[test]
+    fn spiffe() {
+        let spiffe = |ids: &[&str]| {
+            ClientAuthentication::TlsAuthenticated(ids.iter().map(|i| i.parse().unwrap()).collect())
+        };
+        let m = AuthzMatcher::new(vec![
+            authz(&["0.0.0.0/0"], &[], spiffe(&["*"])),
+            authz(&["0.0.0.0/0"], &[], spiffe(&["spiffe://example.org/*"])),
+            authz(
+                &["0.0.0.0/0"],
+                &[],
+                spiffe(&["spiffe://example.org/ns/web/*"]),
+            ),
+            authz(
+                &["0.0.0.0/0"],
+                &[],
+                spiffe(&["spiffe://example.org/ns/web/sa/api"]),
+            ),
+        ]);
+        let ip = Ipv4Addr::new(10, 0, 0, 1).into();
+        let find = |id| m.find(ip, ClientTls::Authenticated(id));
+        assert_eq!(find("spiffe://example.org/ns/web/sa/api"), Some(3));
+        assert_eq!(find("spiffe://example.org/ns/web/sa/db"), Some(2));
+        assert_eq!(find("spiffe://example.org/ns/web"), Some(1));
+        assert_eq!(find("spiffe://example.org/ns/webapp/sa/api"), Some(1));
+        assert_eq!(find("spiffe://other.org/ns/web/sa/api"), Some(0));
+        // Invalid SPIFFE IDs are only matched as names.
+        assert_eq!(find("spiffe://example.org/ns/web//api"), Some(0));
+    }
+
+    /// Authentication precedes network specificity.
+    #[test]
+    fn authentication_before_network() {
+        let m = AuthzMatcher::new(vec![
+            authz(&["10.0.0.1/32"], &[], ClientAuthentication::Unauthenticated),
+            authz(&["0.0.0.0/0"], &[], ids(&[], &["cluster.local"])),
+        ]);
+        let ip = Ipv4Addr::new(10, 0, 0, 1).into();
+        assert_eq!(m.find(ip, ClientTls::Plaintext), Some(0));
+        assert_eq!(
+            m.find(ip, ClientTls::Authenticated("a.cluster.local")),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn narrower_network_within_authn() {
+        let m = AuthzMatcher::new(vec![
+            authz(&["10.0.0.0/8"], &[], ClientAuthentication::Unauthenticated),
+            authz(&["10.0.0.0/24"], &[], ClientAuthentication::Unauthenticated),
+            authz(&["10.0.0.0/24"], &[], ClientAuthentication::Unauthenticated),
+        ]);
+        let plain = ClientTls::Plaintext;
+        assert_eq!(m.find(Ipv4Addr::new(10, 0, 0, 1).into(), plain), Some(1));
+        assert_eq!(m.find(Ipv4Addr::new(10, 0, 1, 1).into(), plain), Some(0));
+    }
+
+    #[test]
+    fn tls_required() {
+        let m = AuthzMatcher::new(vec![
+            authz(
+                &["0.0.0.0/0"],
+                &[],
+                ClientAuthentication::TlsUnauthenticated,
+            ),
+            authz(&["0.0.0.0/0"], &[], ids(&["a.b.c"], &[])),
+        ]);
+        let ip = Ipv4Addr::new(10, 0, 0, 1).into();
+        assert_eq!(m.find(ip, ClientTls::Plaintext), None);
+        assert_eq!(m.find(ip, ClientTls::Authenticated("x.y.z")), Some(0));
+    }
+
+    /// The chosen authorization does not depend on how authorizations are named.
+    #[test]
+    fn independent_of_names() {
+        for (broad, narrow) in &[("a", "b"), ("b", "a")] {
+            let server = InboundServer {
+                protocol: crate::ProxyProtocol::Opaque,
+                authorizations: vec![
+                    (
+                        broad.to_string(),
+                        authz(&["0.0.0.0/0"], &[], ids(&[], &["cluster.local"])),
+                    ),
+                    (
+                        narrow.to_string(),
+                        authz(&["0.0.0.0/0"], &[], ids(&[], &["ns.cluster.local"])),
+                    ),
+                ]
+                .into_iter()
+                .collect(),
+                routes: Default::default(),
+                undeclared: false,
+                health_check_source: None,
+            };
+            assert_eq!(
+                server.matcher().authorize(
+                    Ipv4Addr::new(10, 0, 0, 1).into(),
+                    ClientTls::Authenticated("sa.ns.cluster.local")
+                ),
+                Some(*narrow)
+            );
+        }
+    }
+
+    /// Requests that match a route are only authorized by the route's authorizations.
+    #[test]
+    fn routes_override_server_authorizations() {
+        let server = InboundServer {
+            protocol: crate::ProxyProtocol::Http1,
+            authorizations: Some((
+                "open".to_string(),
+                authz(&["0.0.0.0/0"], &[], ClientAuthentication::Unauthenticated),
+            ))
+            .into_iter()
+            .collect(),
+            routes: Some((
+                "admin".to_string(),
+                crate::HttpRoute {
+                    matches: vec![crate::HttpRouteMatch {
+                        path: Some(crate::PathMatch::Prefix("/admin".to_string())),
+                        methods: vec![],
+                    }],
+                    authorization: authz(&["0.0.0.0/0"], &[], ids(&["admin.ns"], &[])),
+                },
+            ))
+            .into_iter()
+            .collect(),
+            undeclared: false,
+            health_check_source: None,
+        };
+
+        let m = server.matcher();
+        let ip = Ipv4Addr::new(10, 0, 0, 1).into();
+        assert_eq!(
+            m.authorize_http(ip, ClientTls::Plaintext, "GET", "/"),
+            Some("open")
+        );
+        assert_eq!(
+            m.authorize_http(ip, ClientTls::Plaintext, "GET", "/admin/users"),
+            None
+        );
+        assert_eq!(
+            m.authorize_http(ip, ClientTls::Authenticated("other.ns"), "GET", "/admin"),
+            None
+        );
+        assert_eq!(
+            m.authorize_http(ip, ClientTls::Authenticated("admin.ns"), "GET", "/admin"),
+            Some("admin")
+        );
+    }
+}
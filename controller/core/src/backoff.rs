@@ -0,0 +1,237 @@
+use anyhow::{bail, Result};
+use rand::Rng;
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+/// Configures an exponential backoff policy for reconnecting to a remote service.
+///
+/// The delay before the `n`th consecutive retry is `min * 2^n`, capped at `max`, and then reduced
+/// by a random fraction of up to `jitter` so that many clients that lose their connection at the
+/// same time do not all reconnect at the same time.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Backoff {
+    min: Duration,
+    max: Duration,
+    jitter: f64,
+}
+
+/// Tracks consecutive failures against a [`Backoff`] policy.
+#[derive(Clone, Debug)]
+pub struct Attempts {
+    backoff: Backoff,
+    failures: u32,
+}
+
+/// Counts reconnection attempts so that they may be exposed as metrics.
+///
+/// Clones share the same counters.
+#[derive(Clone, Debug, Default)]
+pub struct Reconnects(Arc<Counters>);
+
+#[derive(Debug, Default)]
+struct Counters {
+    attempts: AtomicU64,
+    recoveries: AtomicU64,
+}
+
+// === impl Backoff ===
+
+impl Backoff {
+    /// With the default jitter, the first retry is delayed by 1-2s, so that it is never sooner than
+    /// a fixed 1s delay.
+    pub const DEFAULT_MIN: Duration = Duration::from_secs(2);
+    pub const DEFAULT_MAX: Duration = Duration::from_secs(30);
+    pub const DEFAULT_JITTER: f64 = 0.5;
+
+    pub fn new(min: Duration, max: Duration, jitter: f64) -> Result<Self> {
+        if min == Duration::from_secs(0) {
+            bail!("minimum backoff must be greater than zero");
+        }
+        if max < min {
+            bail!(
+                "maximum backoff ({:?}) must not be less than minimum ({:?})",
+                max,
+                min
+            );
+        }
+        if !(0.0..=1.0).contains(&jitter) {
+            bail!("jitter must be between 0.0 and 1.0: {}", jitter);
+        }
+        Ok(Self { min, max, jitter })
+    }
+
+    pub fn attempts(&self) -> Attempts {
+        Attempts {
+            backoff: *self,
+            failures: 0,
+        }
+    }
+
+    /// The delay before the given retry, without jitter.
+    fn base_delay(&self, failures: u32) -> Duration {
+        1u32.checked_shl(failures)
+            .and_then(|factor| self.min.checked_mul(factor))
+            .map(|delay| delay.min(self.max))
+            .unwrap_or(self.max)
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            min: Self::DEFAULT_MIN,
+            max: Self::DEFAULT_MAX,
+            jitter: Self::DEFAULT_JITTER,
+        }
+    }
+}
+
+// === impl Attempts ===
+
+impl Attempts {
+    /// Records a failure and returns how long to wait before retrying.
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.backoff.base_delay(self.failures);
+        self.failures = self.failures.saturating_add(1);
+        if self.backoff.jitter == 0.0 {
+            return delay;
+        }
+        let jitter = rand::thread_rng().gen_range(0.0..=self.backoff.jitter);
+        delay.mul_f64(1.0 - jitter)
+    }
+
+    /// The number of consecutive failures since the last reset.
+    pub fn failures(&self) -> u32 {
+        self.failures
+    }
+
+    /// Resets the backoff after a successful connection.
+    pub fn reset(&mut self) {
+        self.failures = 0;
+    }
+}
+
+// === impl Reconnects ===
+
+impl Reconnects {
+    /// Records that a reconnection is being attempted after a failure.
+    pub fn attempt(&self) {
+        self.0.attempts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records that a connection was reestablished after one or more failures.
+    pub fn recovered(&self) {
+        self.0.recoveries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn attempts(&self) -> u64 {
+        self.0.attempts.load(Ordering::Relaxed)
+    }
+
+    pub fn recoveries(&self) -> u64 {
+        self.0.recoveries.load(Ordering::Relaxed)
+    }
+
+    /// Writes counters in the Prometheus text format, with one series per labeled set of counters.
+    pub fn fmt_prometheus<'r, W: fmt::Write>(
+        out: &mut W,
+        label: &str,
+        reconnects: impl IntoIterator<Item = (String, &'r Reconnects)> + Clone,
+    ) -> fmt::Result {
+        writeln!(
+            out,
+            "# HELP reconnect_attempts_total Reconnection attempts made after a failure."
+        )?;
+        writeln!(out, "# TYPE reconnect_attempts_total counter")?;
+        for (value, r) in reconnects.clone() {
+            writeln!(
+                out,
+                "reconnect_attempts_total{{{}=\"{}\"}} {}",
+                label,
+                value,
+                r.attempts()
+            )?;
+        }
+
+        writeln!(
+            out,
+            "# HELP reconnect_recoveries_total Connections reestablished after a failure."
+        )?;
+        writeln!(out, "# TYPE reconnect_recoveries_total counter")?;
+        for (value, r) in reconnects {
+            writeln!(
+                out,
+                "reconnect_recoveries_total{{{}=\"{}\"}} {}",
+                label,
+                value,
+                r.recoveries()
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exponential_without_jitter() {
+        let backoff =
+            Backoff::new(Duration::from_millis(100), Duration::from_secs(1), 0.0).unwrap();
+        let mut attempts = backoff.attempts();
+        let delays = (0..6).map(|_| attempts.next_delay()).collect::<Vec<_>>();
+        assert_eq!(
+            delays,
+            [100, 200, 400, 800, 1000, 1000]
+                .iter()
+                .map(|ms| Duration::from_millis(*ms))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(attempts.failures(), 6);
+
+        attempts.reset();
+        assert_eq!(attempts.failures(), 0);
+        assert_eq!(attempts.next_delay(), Duration::from_millis(100));
+    }
+
+    #[test]
+    fn jitter_is_bounded() {
+        let backoff =
+            Backoff::new(Duration::from_millis(100), Duration::from_secs(10), 0.5).unwrap();
+        let mut attempts = backoff.attempts();
+        for failures in 0..100 {
+            let base = backoff.base_delay(failures);
+            let delay = attempts.next_delay();
+            assert!(delay <= base, "{:?} > {:?}", delay, base);
+            assert!(delay >= base / 2, "{:?} < {:?}", delay, base / 2);
+        }
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(Backoff::new(Duration::from_secs(0), Duration::from_secs(1), 0.5).is_err());
+        assert!(Backoff::new(Duration::from_secs(2), Duration::from_secs(1), 0.5).is_err());
+        assert!(Backoff::new(Duration::from_secs(1), Duration::from_secs(2), 1.5).is_err());
+    }
+
+    #[test]
+    fn prometheus() {
+        let pods = Reconnects::default();
+        pods.attempt();
+        pods.attempt();
+        pods.clone().recovered();
+        let mut out = String::new();
+        Reconnects::fmt_prometheus(&mut out, "resource", vec![("pods".to_string(), &pods)])
+            .unwrap();
+        assert!(out.contains("reconnect_attempts_total{resource=\"pods\"} 2\n"));
+        assert!(out.contains("reconnect_recoveries_total{resource=\"pods\"} 1\n"));
+    }
+}
@@ -0,0 +1,146 @@
+use crate::ClientAuthorization;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Authorizes clients to a subset of an HTTP or gRPC server's requests.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HttpRoute {
+    /// Requests that match any of these are part of the route.
+    pub matches: Vec<HttpRouteMatch>,
+
+    pub authorization: ClientAuthorization,
+}
+
+/// Matches HTTP requests by path and method.
+///
+/// gRPC requests are matched by their path, `/<package>.<Service>/<Method>`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HttpRouteMatch {
+    /// Matches all paths if unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathMatch>,
+
+    /// Matches all methods if empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub methods: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum PathMatch {
+    Exact(String),
+
+    /// Matches the path and any path beneath it, so that `/admin` matches `/admin` and
+    /// `/admin/users` but not `/administrator`.
+    Prefix(String),
+}
+
+// === impl HttpRouteMatch ===
+
+impl HttpRouteMatch {
+    /// Matches all requests to a gRPC service or, if a method is provided, to a single method.
+    pub fn grpc(service: &str, method: Option<&str>) -> Self {
+        let path = match method {
+            Some(method) => PathMatch::Exact(format!("/{}/{}", service, method)),
+            None => PathMatch::Prefix(format!("/{}", service)),
+        };
+        Self {
+            path: Some(path),
+            methods: vec!["POST".to_string()],
+        }
+    }
+
+    pub fn matches(&self, method: &str, path: &str) -> bool {
+        let method_matches =
+            self.methods.is_empty() || self.methods.iter().any(|m| m.eq_ignore_ascii_case(method));
+        let path_matches = match self.path {
+            None => true,
+            Some(ref p) => p.matches(path),
+        };
+        method_matches && path_matches
+    }
+}
+
+impl fmt::Display for HttpRouteMatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.methods.is_empty() {
+            write!(f, "{} ", self.methods.join(","))?;
+        }
+        match self.path {
+            None => write!(f, "*"),
+            Some(PathMatch::Exact(ref p)) => p.fmt(f),
+            Some(PathMatch::Prefix(ref p)) => write!(f, "{}/*", p.trim_end_matches('/')),
+        }
+    }
+}
+
+// === impl PathMatch ===
+
+impl PathMatch {
+    pub fn matches(&self, path: &str) -> bool {
+        match self {
+            Self::Exact(p) => p == path,
+            Self::Prefix(p) => {
+                let p = p.trim_end_matches('/');
+                match path.strip_prefix(p) {
+                    Some(rest) => rest.is_empty() || rest.starts_with('/'),
+                    None => false,
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefix() {
+        let admin = PathMatch::Prefix("/admin".to_string());
+        assert!(admin.matches("/admin"));
+        assert!(admin.matches("/admin/"));
+        assert!(admin.matches("/admin/users"));
+        assert!(!admin.matches("/administrator"));
+        assert!(!admin.matches("/"));
+
+        let root = PathMatch::Prefix("/".to_string());
+        assert!(root.matches("/"));
+        assert!(root.matches("/foo"));
+    }
+
+    #[test]
+    fn methods() {
+        let m = HttpRouteMatch {
+            path: None,
+            methods: vec!["GET".to_string(), "HEAD".to_string()],
+        };
+        assert!(m.matches("get", "/"));
+        assert!(m.matches("HEAD", "/foo"));
+        assert!(!m.matches("POST", "/"));
+    }
+
+    #[test]
+    fn grpc() {
+        let svc = HttpRouteMatch::grpc("pkg.Svc", None);
+        assert!(svc.matches("POST", "/pkg.Svc/Get"));
+        assert!(!svc.matches("POST", "/pkg.SvcOther/Get"));
+        assert!(!svc.matches("GET", "/pkg.Svc/Get"));
+
+        let method = HttpRouteMatch::grpc("pkg.Svc", Some("Get"));
+        assert!(method.matches("POST", "/pkg.Svc/Get"));
+        assert!(!method.matches("POST", "/pkg.Svc/GetAll"));
+    }
+
+    #[test]
+    fn json() {
+        let m = HttpRouteMatch {
+            path: Some(PathMatch::Prefix("/admin".to_string())),
+            methods: vec![],
+        };
+        let json = serde_json::to_string(&m).unwrap();
+        assert_eq!(json, r#"{"path":{"prefix":"/admin"}}"#);
+        assert_eq!(serde_json::from_str::<HttpRouteMatch>(&json).unwrap(), m);
+    }
+}
@@ -1,6 +1,15 @@
-use std::fmt;
+use anyhow::{anyhow, bail, Error, Result};
+use std::{fmt, str::FromStr};
 
 /// Matches a client's mesh identity.
+///
+/// Parsed from:
+///
+/// - `*`, which matches all identities;
+/// - `*.<suffix>`, which matches DNS-like identities with at least one more label;
+/// - `spiffe://<trust-domain>/*` and `spiffe://<trust-domain>/<path>/*`, which match SPIFFE IDs in
+///   the trust domain whose path has at least one more segment;
+/// - any other name, which must match exactly. SPIFFE IDs are validated.
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum IdentityMatch {
     /// An exact match.
@@ -8,8 +17,22 @@ pub enum IdentityMatch {
 
     /// A suffix match..
     Suffix(Vec<String>),
+
+    /// A SPIFFE trust domain and path prefix match.
+    SpiffePrefix(SpiffePrefix),
+}
+
+/// Matches SPIFFE IDs in a trust domain by path prefix.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SpiffePrefix {
+    pub trust_domain: String,
+
+    /// Path segments. If empty, all IDs in the trust domain match.
+    pub path: Vec<String>,
 }
 
+const SPIFFE_SCHEME: &str = "spiffe://";
+
 // === impl IdentityMatch ===
 
 impl fmt::Display for IdentityMatch {
@@ -23,6 +46,161 @@ impl fmt::Display for IdentityMatch {
                 }
                 Ok(())
             }
+            Self::SpiffePrefix(prefix) => prefix.fmt(f),
+        }
+    }
+}
+
+impl FromStr for IdentityMatch {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s == "*" {
+            return Ok(Self::Suffix(vec![]));
+        }
+
+        if let Some(suffix) = s.strip_prefix("*.") {
+            if suffix.split('.').any(str::is_empty) {
+                bail!("invalid identity suffix: {}", s);
+            }
+            return Ok(Self::Suffix(suffix.split('.').map(String::from).collect()));
+        }
+
+        if s.starts_with(SPIFFE_SCHEME) {
+            if s.ends_with("/*") {
+                return s.parse().map(Self::SpiffePrefix);
+            }
+            if spiffe_labels(s).is_none() {
+                bail!("invalid SPIFFE ID: {}", s);
+            }
+        }
+
+        if s.is_empty() {
+            bail!("empty identity");
+        }
+        Ok(Self::Name(s.to_string()))
+    }
+}
+
+// === impl SpiffePrefix ===
+
+impl fmt::Display for SpiffePrefix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", SPIFFE_SCHEME, self.trust_domain)?;
+        for segment in self.path.iter() {
+            write!(f, "/{}", segment)?;
+        }
+        write!(f, "/*")
+    }
+}
+
+impl FromStr for SpiffePrefix {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let id = s
+            .strip_suffix("/*")
+            .ok_or_else(|| anyhow!("SPIFFE prefixes must end with /*: {}", s))?;
+        let mut labels = split_spiffe(id)
+            .ok_or_else(|| anyhow!("invalid SPIFFE prefix: {}", s))?
+            .into_iter()
+            .map(String::from);
+
+        let trust_domain = labels.next().expect("SPIFFE IDs must have a trust domain");
+        Ok(Self {
+            trust_domain,
+            path: labels.collect(),
+        })
+    }
+}
+
+/// Splits a SPIFFE ID into its trust domain followed by its path segments.
+///
+/// Returns `None` if the name is not a valid SPIFFE ID.
+pub(crate) fn spiffe_labels(id: &str) -> Option<Vec<&str>> {
+    // Workload IDs must have a path.
+    split_spiffe(id).filter(|labels| labels.len() > 1)
+}
+
+/// Splits a SPIFFE ID, which need not have a path, into its trust domain and path segments.
+fn split_spiffe(id: &str) -> Option<Vec<&str>> {
+    let mut parts = id.strip_prefix(SPIFFE_SCHEME)?.split('/');
+
+    let trust_domain = parts.next()?;
+    let valid_td = |c: char| matches!(c, 'a'..='z' | '0'..='9' | '.' | '-' | '_');
+    if trust_domain.is_empty() || !trust_domain.chars().all(valid_td) {
+        return None;
+    }
+
+    let mut labels = vec![trust_domain];
+    for segment in parts {
+        let valid = |c: char| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_');
+        if segment.is_empty() || segment == "." || segment == ".." || !segment.chars().all(valid) {
+            return None;
+        }
+        labels.push(segment);
+    }
+    Some(labels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_roundtrip() {
+        for (s, m) in [
+            ("*", IdentityMatch::Suffix(vec![])),
+            (
+                "*.cluster.local",
+                IdentityMatch::Suffix(vec!["cluster".into(), "local".into()]),
+            ),
+            (
+                "web.ns.serviceaccount.identity.linkerd.cluster.local",
+                IdentityMatch::Name("web.ns.serviceaccount.identity.linkerd.cluster.local".into()),
+            ),
+            (
+                "spiffe://example.org/ns/web/sa/api",
+                IdentityMatch::Name("spiffe://example.org/ns/web/sa/api".into()),
+            ),
+            (
+                "spiffe://example.org/*",
+                IdentityMatch::SpiffePrefix(SpiffePrefix {
+                    trust_domain: "example.org".into(),
+                    path: vec![],
+                }),
+            ),
+            (
+                "spiffe://example.org/ns/web/*",
+                IdentityMatch::SpiffePrefix(SpiffePrefix {
+                    trust_domain: "example.org".into(),
+                    path: vec!["ns".into(), "web".into()],
+                }),
+            ),
+        ] {
+            assert_eq!(s.parse::<IdentityMatch>().unwrap(), m, "{}", s);
+            assert_eq!(m.to_string(), s);
+        }
+    }
+
+    #[test]
+    fn parse_invalid() {
+        for s in &[
+            "",
+            "*.",
+            "*.cluster..local",
+            "spiffe://",
+            "spiffe://example.org",
+            "spiffe://example.org/",
+            "spiffe://Example.org/web",
+            "spiffe://example.org//web",
+            "spiffe://example.org/ns/../web",
+            "spiffe://example.org:8443/web",
+            "spiffe://example.org/web?x=y",
+            "spiffe:///*",
+            "spiffe://example.org/ns//*",
+        ] {
+            assert!(s.parse::<IdentityMatch>().is_err(), "{}", s);
         }
     }
 }
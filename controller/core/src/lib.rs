@@ -1,15 +1,29 @@
 #![deny(warnings, rust_2018_idioms)]
 #![forbid(unsafe_code)]
 
+mod authz_matcher;
+mod backoff;
+mod http_route;
 mod identity_match;
 mod network_match;
+mod port_protocol;
 
-pub use self::{identity_match::IdentityMatch, network_match::NetworkMatch};
+pub use self::{
+    authz_matcher::{AuthzMatcher, ClientTls, ServerMatcher},
+    backoff::{Attempts, Backoff, Reconnects},
+    http_route::{HttpRoute, HttpRouteMatch, PathMatch},
+    identity_match::{IdentityMatch, SpiffePrefix},
+    network_match::NetworkMatch,
+    port_protocol::PortProtocol,
+};
 use anyhow::Result;
 use futures::prelude::*;
 pub use ipnet::{IpNet, Ipv4Net, Ipv6Net};
 use std::{collections::BTreeMap, pin::Pin, time::Duration};
 
+/// Names the authorization that permits health checks from a pod's kubelet.
+pub const HEALTH_CHECK_AUTHZ: &str = "_health_check";
+
 /// Models inbound server configuration discovery.
 #[async_trait::async_trait]
 pub trait DiscoverInboundServer<T> {
@@ -18,13 +32,54 @@ pub trait DiscoverInboundServer<T> {
     async fn watch_inbound_server(&self, target: T) -> Result<Option<InboundServerStream>>;
 }
 
+/// Resolves the mesh identities of discovery targets' workloads, so that discovery clients may be
+/// authorized.
+pub trait IdentifyTarget<T> {
+    /// Returns the identity of the workload that serves a target.
+    fn target_identity(&self, target: &T) -> TargetIdentity;
+}
+
+/// The identity of the workload that serves a discovery target.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TargetIdentity {
+    /// The target's workload is unknown.
+    Unknown,
+
+    /// The target's workload is known, but its identity can't be resolved (e.g. because identity
+    /// domains are not yet configured).
+    Unresolved,
+
+    Identity(String),
+}
+
 pub type InboundServerStream = Pin<Box<dyn Stream<Item = InboundServer> + Send + Sync + 'static>>;
 
 /// Inbound server configuration.
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct InboundServer {
     pub protocol: ProxyProtocol,
+
+    /// Authorizations, by name.
+    ///
+    /// When several authorizations permit a connection, the most specific one applies: explicit
+    /// identities, then identity suffixes, then TLS without an identity, then unauthenticated
+    /// clients; and then the narrowest network. Names only break ties. See [`AuthzMatcher`].
     pub authorizations: BTreeMap<String, ClientAuthorization>,
+
+    /// Route-scoped authorizations for HTTP and gRPC servers, by name.
+    ///
+    /// A request that matches any route is authorized only by the authorizations of the routes it
+    /// matches; server-wide `authorizations` do not apply to it. Requests that match no route are
+    /// authorized by server-wide `authorizations`. Routes are ignored by proxies that do not
+    /// inspect requests (e.g. for opaque servers).
+    pub routes: BTreeMap<String, HttpRoute>,
+
+    /// Indicates that the port is not declared by any of the pod's containers.
+    pub undeclared: bool,
+
+    /// Describes how the networks of the [`HEALTH_CHECK_AUTHZ`] authorization were determined (e.g.
+    /// `pod-cidr`), if it is set.
+    pub health_check_source: Option<String>,
 }
 
 /// Describes how a proxy should handle inbound connections.
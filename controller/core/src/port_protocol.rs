@@ -0,0 +1,46 @@
+use anyhow::{bail, Error, Result};
+use serde::Serialize;
+use std::{fmt, str::FromStr};
+
+/// The transport protocol of a pod port.
+///
+/// Proxies only handle TCP, but servers may describe UDP and SCTP ports for other enforcement
+/// points.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum PortProtocol {
+    Tcp,
+    Udp,
+    Sctp,
+}
+
+// === impl PortProtocol ===
+
+impl Default for PortProtocol {
+    fn default() -> Self {
+        Self::Tcp
+    }
+}
+
+impl fmt::Display for PortProtocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp => "TCP".fmt(f),
+            Self::Udp => "UDP".fmt(f),
+            Self::Sctp => "SCTP".fmt(f),
+        }
+    }
+}
+
+impl FromStr for PortProtocol {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        for p in [Self::Tcp, Self::Udp, Self::Sctp] {
+            if s.eq_ignore_ascii_case(&p.to_string()) {
+                return Ok(p);
+            }
+        }
+        bail!("invalid port protocol: {}", s)
+    }
+}
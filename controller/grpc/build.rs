@@ -0,0 +1,19 @@
+fn main() {
+    // Use ordered maps so that configurations are encoded deterministically.
+    let mut config = prost_build::Config::new();
+    config.btree_map(&["."]);
+
+    tonic_build::configure()
+        .build_client(false)
+        .build_server(true)
+        .compile_with_config(
+            config,
+            &[
+                "proto/envoy/extensions/filters/http/rbac/v3/rbac.proto",
+                "proto/envoy/extensions/filters/network/rbac/v3/rbac.proto",
+                "proto/envoy/service/extension/v3/config_discovery.proto",
+            ],
+            &["proto"],
+        )
+        .expect("failed to compile protobuf definitions");
+}
@@ -0,0 +1,284 @@
+//! Authorizes discovery clients by their mTLS identities.
+//!
+//! When the server is configured with [`TlsConfig`], clients must present a certificate issued by
+//! the configured roots, and may only discover the policies of workloads that share the
+//! certificate's identity. Exempt identities (e.g. of tooling) may discover the policies of all
+//! workloads. Denied requests are logged with the [`AUDIT_TARGET`] target.
+
+use super::Target;
+use polixy_controller_core::{IdentifyTarget, TargetIdentity};
+use std::{net::SocketAddr, sync::Arc};
+use tracing::{info, trace};
+
+/// The tracing target of authorization audit logs.
+pub const AUDIT_TARGET: &str = "polixy::audit";
+
+/// Configures the server to require mTLS and to authorize clients by identity.
+#[derive(Clone, Debug)]
+pub struct TlsConfig {
+    /// The server's PEM-encoded certificate chain.
+    pub cert_pem: Vec<u8>,
+
+    /// The server's PEM-encoded private key.
+    pub key_pem: Vec<u8>,
+
+    /// PEM-encoded roots that issue client certificates.
+    pub client_ca_pem: Vec<u8>,
+
+    /// Identities that may discover the policies of all workloads.
+    pub exempt_identities: Vec<String>,
+}
+
+/// Authorizes clients' requests.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ClientAuthz {
+    exempt_identities: Arc<[String]>,
+}
+
+/// A client's connection metadata.
+#[derive(Clone, Debug)]
+pub(crate) struct Client {
+    addr: Option<SocketAddr>,
+
+    /// The client's DER-encoded end-entity certificate, if one was presented.
+    cert: Option<Vec<u8>>,
+
+    exempt_identities: Arc<[String]>,
+}
+
+// === impl TlsConfig ===
+
+impl TlsConfig {
+    pub(crate) fn server_config(&self) -> tonic::transport::ServerTlsConfig {
+        tonic::transport::ServerTlsConfig::new()
+            .identity(tonic::transport::Identity::from_pem(
+                &self.cert_pem,
+                &self.key_pem,
+            ))
+            .client_ca_root(tonic::transport::Certificate::from_pem(&self.client_ca_pem))
+    }
+
+    pub(crate) fn client_authz(&self) -> ClientAuthz {
+        ClientAuthz {
+            exempt_identities: self.exempt_identities.clone().into(),
+        }
+    }
+}
+
+// === impl ClientAuthz ===
+
+impl ClientAuthz {
+    /// Reads the client's metadata from a request.
+    pub(crate) fn client<M>(&self, req: &tonic::Request<M>) -> Client {
+        let cert = req
+            .peer_certs()
+            .and_then(|certs| certs.first().map(|c| c.get_ref().to_vec()));
+        Client {
+            addr: req.remote_addr(),
+            cert,
+            exempt_identities: self.exempt_identities.clone(),
+        }
+    }
+}
+
+// === impl Client ===
+
+impl Client {
+    /// Checks that the client may discover the policy for a target.
+    ///
+    /// Targets whose workloads are unknown are permitted, so that lookups fail as not found. Targets
+    /// whose workloads are known but whose identities can't be resolved are denied.
+    ///
+    /// A workload may be indexed between this check and its lookup, so targets must be checked
+    /// again with [`Client::check_found`] once their lookups succeed.
+    pub(crate) fn check(
+        &self,
+        identify: &impl IdentifyTarget<Target>,
+        target: &Target,
+    ) -> Result<(), tonic::Status> {
+        self.authorize(identify, target, false)
+    }
+
+    /// Checks that the client may discover the policy for a target whose lookup succeeded. The
+    /// target's workload must be known.
+    pub(crate) fn check_found(
+        &self,
+        identify: &impl IdentifyTarget<Target>,
+        target: &Target,
+    ) -> Result<(), tonic::Status> {
+        self.authorize(identify, target, true)
+    }
+
+    fn authorize(
+        &self,
+        identify: &impl IdentifyTarget<Target>,
+        target: &Target,
+        found: bool,
+    ) -> Result<(), tonic::Status> {
+        let (ns, pod, port, _) = target;
+        let cert = match self.cert.as_deref() {
+            Some(cert) => cert,
+            None => {
+                info!(
+                    target: AUDIT_TARGET,
+                    addr = ?self.addr,
+                    %ns, %pod, %port,
+                    "Denied discovery by client without a certificate",
+                );
+                return Err(tonic::Status::permission_denied(
+                    "client certificate required",
+                ));
+            }
+        };
+
+        if let Some(id) = self
+            .exempt_identities
+            .iter()
+            .find(|id| has_identity(cert, id))
+        {
+            trace!(identity = %id, "Exempt client");
+            return Ok(());
+        }
+
+        let id = match identify.target_identity(target) {
+            TargetIdentity::Identity(id) => id,
+            TargetIdentity::Unknown if !found => return Ok(()),
+            // The workload was removed after it was looked up.
+            TargetIdentity::Unknown => return Err(tonic::Status::not_found("unknown server")),
+            TargetIdentity::Unresolved => {
+                info!(
+                    target: AUDIT_TARGET,
+                    addr = ?self.addr,
+                    %ns, %pod, %port,
+                    "Denied discovery of a workload whose identity can't be resolved",
+                );
+                return Err(tonic::Status::permission_denied(format!(
+                    "identity of {}:{} can't be resolved",
+                    ns, pod
+                )));
+            }
+        };
+        if has_identity(cert, &id) {
+            return Ok(());
+        }
+
+        info!(
+            target: AUDIT_TARGET,
+            addr = ?self.addr,
+            %ns, %pod, %port,
+            identity = %id,
+            "Denied discovery by client with another identity",
+        );
+        Err(tonic::Status::permission_denied(format!(
+            "client is not authorized to discover {}:{}",
+            ns, pod
+        )))
+    }
+}
+
+/// Indicates whether a DER-encoded certificate is valid for an identity's DNS name.
+fn has_identity(cert: &[u8], id: &str) -> bool {
+    let name = match webpki::DNSNameRef::try_from_ascii_str(id) {
+        Ok(name) => name,
+        Err(_) => return false,
+    };
+    webpki::EndEntityCert::from(cert)
+        .and_then(|c| c.verify_is_valid_for_dns_name(name))
+        .is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use polixy_controller_core::PortProtocol;
+
+    /// A certificate for `web.ns-0.serviceaccount.identity.linkerd.cluster.local`.
+    const CLIENT_CERT: &str = include_str!("../testdata/client.pem");
+
+    /// Identifies pods by their names.
+    struct PodIdentities;
+
+    impl IdentifyTarget<Target> for PodIdentities {
+        fn target_identity(&self, (ns, pod, _, _): &Target) -> TargetIdentity {
+            match pod.as_str() {
+                "unknown" => TargetIdentity::Unknown,
+                "unresolved" => TargetIdentity::Unresolved,
+                _ => TargetIdentity::Identity(format!(
+                    "{}.{}.serviceaccount.identity.linkerd.cluster.local",
+                    pod, ns
+                )),
+            }
+        }
+    }
+
+    fn mk_client(cert: Option<&str>, exempt: &[&str]) -> Client {
+        let cert = cert.map(|pem| {
+            let b64 = pem
+                .lines()
+                .filter(|l| !l.starts_with("-----"))
+                .collect::<String>();
+            base64::decode(b64).expect("certificate must be valid base64")
+        });
+        Client {
+            addr: None,
+            cert,
+            exempt_identities: exempt.iter().map(|id| id.to_string()).collect(),
+        }
+    }
+
+    fn target(pod: &str) -> Target {
+        ("ns-0".to_string(), pod.to_string(), 8080, PortProtocol::Tcp)
+    }
+
+    #[test]
+    fn authorizes_workload_identity() {
+        let client = mk_client(Some(CLIENT_CERT), &[]);
+        assert!(client.check(&PodIdentities, &target("web")).is_ok());
+
+        let status = client.check(&PodIdentities, &target("db")).unwrap_err();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+
+        // Lookups of unknown workloads are not denied so that they fail as not found.
+        assert!(client.check(&PodIdentities, &target("unknown")).is_ok());
+
+        // Once a lookup succeeds, the workload must be known and is checked again.
+        assert!(client.check_found(&PodIdentities, &target("web")).is_ok());
+        let status = client
+            .check_found(&PodIdentities, &target("unknown"))
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::NotFound);
+        let status = client
+            .check_found(&PodIdentities, &target("db"))
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+
+        // Lookups of workloads whose identities can't be resolved are denied.
+        let status = client
+            .check(&PodIdentities, &target("unresolved"))
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+    }
+
+    #[test]
+    fn authorizes_exempt_identities() {
+        let client = mk_client(
+            Some(CLIENT_CERT),
+            &["web.ns-0.serviceaccount.identity.linkerd.cluster.local"],
+        );
+        assert!(client.check(&PodIdentities, &target("db")).is_ok());
+
+        let client = mk_client(
+            Some(CLIENT_CERT),
+            &["tool.ns-0.serviceaccount.identity.linkerd.cluster.local"],
+        );
+        let status = client.check(&PodIdentities, &target("db")).unwrap_err();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+    }
+
+    #[test]
+    fn requires_client_certificate() {
+        let client = mk_client(None, &[]);
+        let status = client.check(&PodIdentities, &target("web")).unwrap_err();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+    }
+}
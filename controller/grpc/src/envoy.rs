@@ -0,0 +1,791 @@
+//! Serves inbound server configurations to Envoy as RBAC filter configurations.
+//!
+//! Envoy discovers filter configurations with the extension config discovery service (ECDS). Each
+//! resource name has the form `<filter>:<namespace>:<pod>:<port>`, where `<filter>` is `network`
+//! for an `envoy.filters.network.rbac` filter or `http` for an `envoy.filters.http.rbac` filter.
+//!
+//! Each authorization is a policy whose principals match its networks as `source_ip`s and its
+//! identities as `authenticated.principal_name`s. Network filters can't inspect requests, so they
+//! only apply server-wide authorizations and route-scoped clients are denied. Envoy can't permit TLS
+//! clients without identities, so these authorizations require a client certificate.
+
+use crate::{
+    client::{Client, ClientAuthz},
+    Target,
+};
+use futures::{
+    prelude::*,
+    stream::{AbortHandle, BoxStream, SelectAll},
+};
+use polixy_controller_core::{
+    ClientAuthentication, ClientAuthorization, DiscoverInboundServer, HttpRouteMatch,
+    IdentifyTarget, IdentityMatch, InboundServer, IpNet, NetworkMatch, PathMatch, PortProtocol,
+};
+use prost::Message;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use tokio::sync::mpsc;
+use tracing::{debug, Instrument};
+
+#[allow(clippy::all)]
+pub mod proto {
+    pub mod envoy {
+        pub mod config {
+            pub mod core {
+                pub mod v3 {
+                    tonic::include_proto!("envoy.config.core.v3");
+                }
+            }
+            pub mod rbac {
+                pub mod v3 {
+                    tonic::include_proto!("envoy.config.rbac.v3");
+                }
+            }
+            pub mod route {
+                pub mod v3 {
+                    tonic::include_proto!("envoy.config.route.v3");
+                }
+            }
+        }
+        pub mod extensions {
+            pub mod filters {
+                pub mod http {
+                    pub mod rbac {
+                        pub mod v3 {
+                            tonic::include_proto!("envoy.extensions.filters.http.rbac.v3");
+                        }
+                    }
+                }
+                pub mod network {
+                    pub mod rbac {
+                        pub mod v3 {
+                            tonic::include_proto!("envoy.extensions.filters.network.rbac.v3");
+                        }
+                    }
+                }
+            }
+        }
+        pub mod service {
+            pub mod discovery {
+                pub mod v3 {
+                    tonic::include_proto!("envoy.service.discovery.v3");
+                }
+            }
+            pub mod extension {
+                pub mod v3 {
+                    tonic::include_proto!("envoy.service.extension.v3");
+                }
+            }
+        }
+        pub mod r#type {
+            pub mod matcher {
+                pub mod v3 {
+                    tonic::include_proto!("envoy.r#type.matcher.v3");
+                }
+            }
+        }
+    }
+}
+
+use self::proto::envoy::{
+    config::{
+        core::v3::{CidrRange, TypedExtensionConfig},
+        rbac::v3::{self as rbac, permission, principal},
+        route::v3::{header_matcher, HeaderMatcher},
+    },
+    extensions::filters::{http::rbac::v3 as http_rbac, network::rbac::v3 as network_rbac},
+    r#type::matcher::v3::{path_matcher, string_matcher, PathMatcher, StringMatcher},
+    service::{
+        discovery::v3::{DiscoveryRequest, DiscoveryResponse},
+        extension::v3::extension_config_discovery_service_server::{
+            ExtensionConfigDiscoveryService, ExtensionConfigDiscoveryServiceServer,
+        },
+    },
+};
+
+const EXTENSION_CONFIG_TYPE_URL: &str =
+    "type.googleapis.com/envoy.config.core.v3.TypedExtensionConfig";
+const NETWORK_RBAC_TYPE_URL: &str =
+    "type.googleapis.com/envoy.extensions.filters.network.rbac.v3.RBAC";
+const HTTP_RBAC_TYPE_URL: &str = "type.googleapis.com/envoy.extensions.filters.http.rbac.v3.RBAC";
+
+/// Prefixes the names of route-scoped policies.
+const ROUTE_POLICY_PREFIX: &str = "routes/";
+
+#[derive(Clone, Debug)]
+pub struct Server<T> {
+    discover: T,
+    drain: drain::Watch,
+    authz: Option<ClientAuthz>,
+}
+
+/// The kind of RBAC filter a resource configures.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Filter {
+    Network,
+    Http,
+}
+
+/// The state of a single ECDS stream.
+struct Discovery<T> {
+    discover: T,
+    client: Option<Client>,
+    subscriptions: HashMap<String, AbortHandle>,
+    updates: SelectAll<BoxStream<'static, (String, Filter, InboundServer)>>,
+    configs: BTreeMap<String, prost_types::Any>,
+    version: u64,
+}
+
+type BoxDiscoveryStream =
+    std::pin::Pin<Box<dyn Stream<Item = Result<DiscoveryResponse, tonic::Status>> + Send + Sync>>;
+
+// === impl Server ===
+
+impl<T> Server<T>
+where
+    T: DiscoverInboundServer<Target> + IdentifyTarget<Target> + Clone + Send + Sync + 'static,
+{
+    pub fn new(discover: T, drain: drain::Watch) -> Self {
+        Self {
+            discover,
+            drain,
+            authz: None,
+        }
+    }
+
+    /// Only permits clients to discover the policies of workloads that share their identity.
+    pub(crate) fn with_client_authz(mut self, authz: ClientAuthz) -> Self {
+        self.authz = Some(authz);
+        self
+    }
+
+    pub fn into_service(self) -> ExtensionConfigDiscoveryServiceServer<Self> {
+        ExtensionConfigDiscoveryServiceServer::new(self)
+    }
+}
+
+#[async_trait::async_trait]
+impl<T> ExtensionConfigDiscoveryService for Server<T>
+where
+    T: DiscoverInboundServer<Target> + IdentifyTarget<Target> + Clone + Send + Sync + 'static,
+{
+    type StreamExtensionConfigsStream = BoxDiscoveryStream;
+
+    async fn stream_extension_configs(
+        &self,
+        req: tonic::Request<tonic::Streaming<DiscoveryRequest>>,
+    ) -> Result<tonic::Response<BoxDiscoveryStream>, tonic::Status> {
+        let discovery = Discovery {
+            discover: self.discover.clone(),
+            client: self.authz.as_ref().map(|authz| authz.client(&req)),
+            subscriptions: HashMap::default(),
+            updates: SelectAll::new(),
+            configs: BTreeMap::default(),
+            version: 0,
+        };
+
+        // Subscriptions are managed on a background task that is canceled when the client closes
+        // its request stream, when the response stream is dropped, or when the server shuts down.
+        let (tx, mut rx) = mpsc::channel(1);
+        tokio::spawn(
+            discovery
+                .run(req.into_inner(), tx, self.drain.clone())
+                .in_current_span(),
+        );
+
+        Ok(tonic::Response::new(Box::pin(async_stream::stream! {
+            while let Some(rsp) = rx.recv().await {
+                yield rsp;
+            }
+        })))
+    }
+}
+
+// === impl Discovery ===
+
+impl<T> Discovery<T>
+where
+    T: DiscoverInboundServer<Target> + IdentifyTarget<Target> + Send + Sync + 'static,
+{
+    async fn run(
+        mut self,
+        mut requests: tonic::Streaming<DiscoveryRequest>,
+        tx: mpsc::Sender<Result<DiscoveryResponse, tonic::Status>>,
+        drain: drain::Watch,
+    ) {
+        tokio::pin! {
+            let shutdown = drain.signaled();
+        }
+
+        loop {
+            tokio::select! {
+                res = requests.message() => match res {
+                    Ok(Some(req)) => {
+                        if let Err(status) = self.subscribe(req).await {
+                            let _ = tx.send(Err(status)).await;
+                            return;
+                        }
+                    }
+                    Ok(None) => return,
+                    Err(status) => {
+                        debug!(%status, "Request stream failed");
+                        return;
+                    }
+                },
+
+                Some((name, filter, srv)) = self.updates.next() => {
+                    let rsp = self.update(name, filter, &srv);
+                    if tx.send(Ok(rsp)).await.is_err() {
+                        return;
+                    }
+                },
+
+                _ = (&mut shutdown) => return,
+            }
+        }
+    }
+
+    /// Updates the stream's subscriptions to match the requested resources.
+    ///
+    /// Requests that acknowledge a response list the same resources, so they don't change the
+    /// stream's subscriptions.
+    async fn subscribe(&mut self, req: DiscoveryRequest) -> Result<(), tonic::Status> {
+        if !req.type_url.is_empty() && req.type_url != EXTENSION_CONFIG_TYPE_URL {
+            return Err(tonic::Status::invalid_argument(format!(
+                "unsupported resource type: {}",
+                req.type_url
+            )));
+        }
+        debug!(
+            node = ?req.node.as_ref().map(|n| &n.id),
+            version = %req.version_info,
+            nonce = %req.response_nonce,
+            resources = ?req.resource_names,
+            "Discovery request",
+        );
+
+        let names = req.resource_names.into_iter().collect::<HashSet<_>>();
+        let configs = &mut self.configs;
+        self.subscriptions.retain(|name, handle| {
+            if names.contains(name) {
+                return true;
+            }
+            handle.abort();
+            configs.remove(name);
+            false
+        });
+
+        for name in names.into_iter() {
+            if self.subscriptions.contains_key(&name) {
+                continue;
+            }
+
+            let (filter, target) = parse_resource_name(&name)?;
+            if let Some(client) = self.client.as_ref() {
+                client.check(&self.discover, &target)?;
+            }
+            let rx = self
+                .discover
+                .watch_inbound_server(target.clone())
+                .await
+                .map_err(|e| tonic::Status::internal(format!("lookup failed: {}", e)))?
+                .ok_or_else(|| tonic::Status::not_found(format!("unknown server: {}", name)))?;
+            if let Some(client) = self.client.as_ref() {
+                client.check_found(&self.discover, &target)?;
+            }
+
+            let (rx, handle) = stream::abortable(rx);
+            let resource = name.clone();
+            self.updates
+                .push(rx.map(move |srv| (resource.clone(), filter, srv)).boxed());
+            self.subscriptions.insert(name, handle);
+        }
+
+        Ok(())
+    }
+
+    /// Returns a response with all of the stream's current configurations.
+    fn update(&mut self, name: String, filter: Filter, srv: &InboundServer) -> DiscoveryResponse {
+        let config = to_extension_config(&name, filter, srv);
+        self.configs.insert(name, config);
+        self.version += 1;
+        DiscoveryResponse {
+            version_info: self.version.to_string(),
+            resources: self.configs.values().cloned().collect(),
+            canary: false,
+            type_url: EXTENSION_CONFIG_TYPE_URL.to_string(),
+            nonce: self.version.to_string(),
+        }
+    }
+}
+
+/// Parses a resource name in the form `<filter>:<namespace>:<pod>:<port>`. RBAC filters only
+/// apply to TCP ports.
+fn parse_resource_name(name: &str) -> Result<(Filter, Target), tonic::Status> {
+    let invalid = || tonic::Status::invalid_argument(format!("invalid resource name: {}", name));
+
+    let mut parts = name.splitn(4, ':');
+    let filter = match parts.next() {
+        Some("network") => Filter::Network,
+        Some("http") => Filter::Http,
+        _ => return Err(invalid()),
+    };
+    let ns = parts
+        .next()
+        .filter(|ns| !ns.is_empty())
+        .ok_or_else(invalid)?;
+    let pod = parts.next().filter(|p| !p.is_empty()).ok_or_else(invalid)?;
+    let port = parts
+        .next()
+        .and_then(|p| p.parse::<u16>().ok())
+        .filter(|p| *p != 0)
+        .ok_or_else(invalid)?;
+    Ok((
+        filter,
+        (ns.to_string(), pod.to_string(), port, PortProtocol::Tcp),
+    ))
+}
+
+fn to_extension_config(name: &str, filter: Filter, srv: &InboundServer) -> prost_types::Any {
+    let rules = Some(to_rbac(filter, srv));
+    let typed_config = match filter {
+        Filter::Network => prost_types::Any {
+            type_url: NETWORK_RBAC_TYPE_URL.to_string(),
+            value: network_rbac::Rbac {
+                rules,
+                stat_prefix: "polixy".to_string(),
+                ..Default::default()
+            }
+            .encode_to_vec(),
+        },
+        Filter::Http => prost_types::Any {
+            type_url: HTTP_RBAC_TYPE_URL.to_string(),
+            value: http_rbac::Rbac {
+                rules,
+                ..Default::default()
+            }
+            .encode_to_vec(),
+        },
+    };
+
+    prost_types::Any {
+        type_url: EXTENSION_CONFIG_TYPE_URL.to_string(),
+        value: TypedExtensionConfig {
+            name: name.to_string(),
+            typed_config: Some(typed_config),
+        }
+        .encode_to_vec(),
+    }
+}
+
+/// Builds RBAC rules that permit the server's authorized clients.
+///
+/// A server without authorizations has no policies, so all connections are denied.
+pub fn to_rbac(filter: Filter, srv: &InboundServer) -> rbac::Rbac {
+    let routes = match filter {
+        Filter::Network => Vec::new(),
+        Filter::Http => srv.routes.iter().collect(),
+    };
+
+    // Server-wide authorizations only apply to requests that match no route.
+    let unrouted = if routes.is_empty() {
+        any_permission()
+    } else {
+        let routed = routes
+            .iter()
+            .flat_map(|(_, r)| r.matches.iter().map(to_permission))
+            .collect();
+        permission::Rule::NotRule(Box::new(rbac::Permission {
+            rule: Some(or_rules(routed)),
+        }))
+    };
+
+    let mut policies = srv
+        .authorizations
+        .iter()
+        .map(|(name, authz)| (name.clone(), to_policy(unrouted.clone(), authz)))
+        .collect::<BTreeMap<_, _>>();
+
+    for (name, route) in routes.into_iter() {
+        let permission = or_rules(route.matches.iter().map(to_permission).collect());
+        policies.insert(
+            format!("{}{}", ROUTE_POLICY_PREFIX, name),
+            to_policy(permission, &route.authorization),
+        );
+    }
+
+    rbac::Rbac {
+        action: rbac::rbac::Action::Allow as i32,
+        policies,
+    }
+}
+
+fn to_policy(permission: permission::Rule, authz: &ClientAuthorization) -> rbac::Policy {
+    rbac::Policy {
+        permissions: vec![rbac::Permission {
+            rule: Some(permission),
+        }],
+        principals: vec![to_principal(authz)],
+    }
+}
+
+fn to_principal(
+    ClientAuthorization {
+        networks,
+        authentication,
+    }: &ClientAuthorization,
+) -> rbac::Principal {
+    let networks = if networks.is_empty() {
+        // TODO use cluster networks (from config).
+        vec![
+            IpNet::V4(Default::default()).into(),
+            IpNet::V6(Default::default()).into(),
+        ]
+    } else {
+        networks.clone()
+    };
+    let networks = or_ids(networks.iter().map(to_network_principal).collect());
+
+    let authn = match authentication {
+        ClientAuthentication::Unauthenticated => return networks,
+        ClientAuthentication::TlsUnauthenticated => authenticated(None),
+        ClientAuthentication::TlsAuthenticated(identities) => or_ids(
+            identities
+                .iter()
+                .map(|id| match id {
+                    IdentityMatch::Name(name) => {
+                        authenticated(Some(string_matcher::MatchPattern::Exact(name.clone())))
+                    }
+                    IdentityMatch::Suffix(parts) if parts.is_empty() => authenticated(None),
+                    IdentityMatch::Suffix(parts) => authenticated(Some(
+                        string_matcher::MatchPattern::Suffix(format!(".{}", parts.join("."))),
+                    )),
+                    // SPIFFE IDs are presented as URI SANs, which Envoy uses as principal names.
+                    IdentityMatch::SpiffePrefix(prefix) => {
+                        let id = prefix.to_string();
+                        authenticated(Some(string_matcher::MatchPattern::Prefix(
+                            id.trim_end_matches('*').to_string(),
+                        )))
+                    }
+                })
+                .collect(),
+        ),
+    };
+
+    rbac::Principal {
+        identifier: Some(principal::Identifier::AndIds(principal::Set {
+            ids: vec![networks, authn],
+        })),
+    }
+}
+
+fn to_network_principal(NetworkMatch { net, except }: &NetworkMatch) -> rbac::Principal {
+    let source_ip = |net: &IpNet| rbac::Principal {
+        identifier: Some(principal::Identifier::SourceIp(CidrRange {
+            address_prefix: net.network().to_string(),
+            prefix_len: Some(net.prefix_len().into()),
+        })),
+    };
+
+    if except.is_empty() {
+        return source_ip(net);
+    }
+
+    let except = or_ids(except.iter().map(source_ip).collect());
+    rbac::Principal {
+        identifier: Some(principal::Identifier::AndIds(principal::Set {
+            ids: vec![
+                source_ip(net),
+                rbac::Principal {
+                    identifier: Some(principal::Identifier::NotId(Box::new(except))),
+                },
+            ],
+        })),
+    }
+}
+
+fn authenticated(pattern: Option<string_matcher::MatchPattern>) -> rbac::Principal {
+    rbac::Principal {
+        identifier: Some(principal::Identifier::Authenticated(
+            principal::Authenticated {
+                principal_name: pattern.map(|p| StringMatcher {
+                    match_pattern: Some(p),
+                    ignore_case: false,
+                }),
+            },
+        )),
+    }
+}
+
+/// Matches any of the principals, or none if there are none.
+fn or_ids(mut ids: Vec<rbac::Principal>) -> rbac::Principal {
+    if ids.len() == 1 {
+        return ids.remove(0);
+    }
+    if ids.is_empty() {
+        return rbac::Principal {
+            identifier: Some(principal::Identifier::NotId(Box::new(rbac::Principal {
+                identifier: Some(principal::Identifier::Any(true)),
+            }))),
+        };
+    }
+    rbac::Principal {
+        identifier: Some(principal::Identifier::OrIds(principal::Set { ids })),
+    }
+}
+
+fn to_permission(m: &HttpRouteMatch) -> rbac::Permission {
+    let mut rules = Vec::new();
+
+    match m.path {
+        None => {}
+        Some(PathMatch::Exact(ref p)) => {
+            rules.push(url_path(string_matcher::MatchPattern::Exact(p.clone())))
+        }
+        Some(PathMatch::Prefix(ref p)) => {
+            // Prefixes match whole path segments, so `/admin` matches `/admin` and
+            // `/admin/users` but not `/administrator`.
+            let p = p.trim_end_matches('/');
+            if !p.is_empty() {
+                rules.push(rbac::Permission {
+                    rule: Some(or_rules(vec![
+                        url_path(string_matcher::MatchPattern::Exact(p.to_string())),
+                        url_path(string_matcher::MatchPattern::Prefix(format!("{}/", p))),
+                    ])),
+                });
+            }
+        }
+    }
+
+    if !m.methods.is_empty() {
+        let methods = m
+            .methods
+            .iter()
+            .map(|method| rbac::Permission {
+                rule: Some(permission::Rule::Header(HeaderMatcher {
+                    name: ":method".to_string(),
+                    header_match_specifier: Some(
+                        header_matcher::HeaderMatchSpecifier::StringMatch(StringMatcher {
+                            match_pattern: Some(string_matcher::MatchPattern::Exact(
+                                method.clone(),
+                            )),
+                            ignore_case: true,
+                        }),
+                    ),
+                    invert_match: false,
+                })),
+            })
+            .collect();
+        rules.push(rbac::Permission {
+            rule: Some(or_rules(methods)),
+        });
+    }
+
+    let rule = match rules.len() {
+        0 => any_permission(),
+        1 => rules.remove(0).rule.expect("rule must be set"),
+        _ => permission::Rule::AndRules(permission::Set { rules }),
+    };
+    rbac::Permission { rule: Some(rule) }
+}
+
+fn url_path(pattern: string_matcher::MatchPattern) -> rbac::Permission {
+    rbac::Permission {
+        rule: Some(permission::Rule::UrlPath(PathMatcher {
+            rule: Some(path_matcher::Rule::Path(StringMatcher {
+                match_pattern: Some(pattern),
+                ignore_case: false,
+            })),
+        })),
+    }
+}
+
+/// Matches any of the permissions, or none if there are none.
+fn or_rules(mut rules: Vec<rbac::Permission>) -> permission::Rule {
+    if rules.len() == 1 {
+        return rules.remove(0).rule.expect("rule must be set");
+    }
+    if rules.is_empty() {
+        return permission::Rule::NotRule(Box::new(rbac::Permission {
+            rule: Some(any_permission()),
+        }));
+    }
+    permission::Rule::OrRules(permission::Set { rules })
+}
+
+fn any_permission() -> permission::Rule {
+    permission::Rule::Any(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use polixy_controller_core::{HttpRoute, ProxyProtocol};
+
+    fn mk_server() -> InboundServer {
+        let networks = vec![NetworkMatch {
+            net: "10.0.0.0/8".parse().unwrap(),
+            except: vec!["10.1.0.0/16".parse().unwrap()],
+        }];
+        InboundServer {
+            protocol: ProxyProtocol::Http1,
+            authorizations: Some((
+                "authz-0".to_string(),
+                ClientAuthorization {
+                    networks: networks.clone(),
+                    authentication: ClientAuthentication::Unauthenticated,
+                },
+            ))
+            .into_iter()
+            .collect(),
+            routes: Some((
+                "admin".to_string(),
+                HttpRoute {
+                    matches: vec![HttpRouteMatch {
+                        path: Some(PathMatch::Prefix("/admin".to_string())),
+                        methods: vec!["GET".to_string()],
+                    }],
+                    authorization: ClientAuthorization {
+                        networks,
+                        authentication: ClientAuthentication::TlsAuthenticated(vec![
+                            IdentityMatch::Name("admin.ns.serviceaccount.cluster.local".into()),
+                            IdentityMatch::Suffix(vec!["cluster".into(), "local".into()]),
+                            "spiffe://example.org/ns/web/*".parse().unwrap(),
+                        ]),
+                    },
+                },
+            ))
+            .into_iter()
+            .collect(),
+            undeclared: false,
+            health_check_source: None,
+        }
+    }
+
+    fn source_ip(prefix: &str, len: u32) -> rbac::Principal {
+        rbac::Principal {
+            identifier: Some(principal::Identifier::SourceIp(CidrRange {
+                address_prefix: prefix.to_string(),
+                prefix_len: Some(len),
+            })),
+        }
+    }
+
+    #[test]
+    fn network_principals() {
+        let rbac = to_rbac(Filter::Network, &mk_server());
+        assert_eq!(rbac.action, rbac::rbac::Action::Allow as i32);
+
+        // Network filters can't match routes, so route-scoped clients are denied.
+        assert_eq!(
+            rbac.policies.keys().collect::<Vec<_>>(),
+            vec!["authz-0"],
+            "{:#?}",
+            rbac
+        );
+        let policy = &rbac.policies["authz-0"];
+        assert_eq!(
+            policy.permissions,
+            vec![rbac::Permission {
+                rule: Some(permission::Rule::Any(true))
+            }]
+        );
+        assert_eq!(
+            policy.principals,
+            vec![rbac::Principal {
+                identifier: Some(principal::Identifier::AndIds(principal::Set {
+                    ids: vec![
+                        source_ip("10.0.0.0", 8),
+                        rbac::Principal {
+                            identifier: Some(principal::Identifier::NotId(Box::new(source_ip(
+                                "10.1.0.0", 16
+                            )))),
+                        },
+                    ],
+                })),
+            }]
+        );
+    }
+
+    #[test]
+    fn http_routes() {
+        let rbac = to_rbac(Filter::Http, &mk_server());
+        assert_eq!(
+            rbac.policies.keys().collect::<Vec<_>>(),
+            vec!["authz-0", "routes/admin"]
+        );
+
+        // Server-wide authorizations only apply to requests that match no route.
+        let route = to_permission(&mk_server().routes["admin"].matches[0]);
+        assert_eq!(
+            rbac.policies["authz-0"].permissions,
+            vec![rbac::Permission {
+                rule: Some(permission::Rule::NotRule(Box::new(route.clone()))),
+            }]
+        );
+        assert_eq!(rbac.policies["routes/admin"].permissions, vec![route]);
+
+        let principal = match rbac.policies["routes/admin"].principals[0].identifier {
+            Some(principal::Identifier::AndIds(ref set)) => set.ids[1].clone(),
+            ref id => panic!("unexpected principal: {:?}", id),
+        };
+        assert_eq!(
+            principal,
+            or_ids(vec![
+                authenticated(Some(string_matcher::MatchPattern::Exact(
+                    "admin.ns.serviceaccount.cluster.local".to_string()
+                ))),
+                authenticated(Some(string_matcher::MatchPattern::Suffix(
+                    ".cluster.local".to_string()
+                ))),
+                authenticated(Some(string_matcher::MatchPattern::Prefix(
+                    "spiffe://example.org/ns/web/".to_string()
+                ))),
+            ])
+        );
+    }
+
+    #[test]
+    fn prefix_permissions() {
+        let permission = to_permission(&HttpRouteMatch {
+            path: Some(PathMatch::Prefix("/admin/".to_string())),
+            methods: vec![],
+        });
+        assert_eq!(
+            permission.rule,
+            Some(or_rules(vec![
+                url_path(string_matcher::MatchPattern::Exact("/admin".to_string())),
+                url_path(string_matcher::MatchPattern::Prefix("/admin/".to_string())),
+            ]))
+        );
+
+        let root = to_permission(&HttpRouteMatch {
+            path: Some(PathMatch::Prefix("/".to_string())),
+            methods: vec![],
+        });
+        assert_eq!(root.rule, Some(any_permission()));
+    }
+
+    #[test]
+    fn resource_names() {
+        assert_eq!(
+            parse_resource_name("http:ns-0:pod-0:8080").unwrap(),
+            (
+                Filter::Http,
+                (
+                    "ns-0".to_string(),
+                    "pod-0".to_string(),
+                    8080,
+                    PortProtocol::Tcp
+                )
+            )
+        );
+        for invalid in [
+            "tcp:ns-0:pod-0:8080",
+            "network:ns-0:pod-0",
+            "network::pod-0:8080",
+            "network:ns-0:pod-0:0",
+        ] {
+            assert!(parse_resource_name(invalid).is_err(), "{}", invalid);
+        }
+    }
+}
@@ -1,46 +1,115 @@
 #![deny(warnings, rust_2018_idioms)]
 #![forbid(unsafe_code)]
 
+mod client;
+pub mod envoy;
+
+use self::client::{Client, ClientAuthz};
+pub use self::client::{TlsConfig, AUDIT_TARGET};
 use futures::prelude::*;
 use linkerd2_proxy_api::inbound::{
     self as proto,
     inbound_server_discovery_server::{InboundServerDiscovery, InboundServerDiscoveryServer},
 };
 use polixy_controller_core::{
-    ClientAuthentication, ClientAuthorization, DiscoverInboundServer, IdentityMatch, InboundServer,
-    InboundServerStream, IpNet, NetworkMatch, ProxyProtocol,
+    ClientAuthentication, ClientAuthorization, DiscoverInboundServer, IdentifyTarget,
+    IdentityMatch, InboundServer, InboundServerStream, IpNet, NetworkMatch, PortProtocol,
+    ProxyProtocol, HEALTH_CHECK_AUTHZ,
 };
 use tracing::trace;
 
+/// The proxy API does not describe SPIFFE path prefixes, so they are encoded in an authorization
+/// label as a JSON list, e.g. `["spiffe://example.org/ns/web/*"]`. Proxies that do not understand
+/// this label do not permit these clients. Exact SPIFFE IDs are sent as identities.
+pub const SPIFFE_LABEL: &str = "spiffe";
+
+/// The proxy API's port specs do not describe a transport protocol, so UDP and SCTP ports are
+/// discovered by setting this request header to `UDP` or `SCTP`. Ports are TCP if it is unset.
+pub const PORT_PROTOCOL_HEADER: &str = "polixy-port-protocol";
+
+/// The proxy API does not indicate whether a port is declared by the pod, so servers of undeclared
+/// ports are labeled with this key (with the value `true`). Proxies ignore this label; it only
+/// informs tooling.
+pub const UNDECLARED_LABEL: &str = "undeclared";
+
+/// The health check authorization is labeled with the source of its networks (e.g. `pod-cidr` or
+/// `internal-ip`), so that operators can tell how the kubelet's IPs were determined.
+pub const HEALTH_CHECK_SOURCE_LABEL: &str = "source";
+
+/// Identifies a pod port by namespace, pod name, port, and protocol.
+type Target = (String, String, u16, PortProtocol);
+
 #[derive(Clone, Debug)]
 pub struct Server<T> {
     discover: T,
     drain: drain::Watch,
+    tls: Option<TlsConfig>,
+    authz: Option<ClientAuthz>,
 }
 
 impl<T> Server<T>
 where
-    T: DiscoverInboundServer<(String, String, u16)> + Send + Sync + 'static,
+    T: DiscoverInboundServer<Target> + IdentifyTarget<Target> + Send + Sync + 'static,
 {
     pub fn new(discover: T, drain: drain::Watch) -> Self {
-        Self { discover, drain }
+        Self {
+            discover,
+            drain,
+            tls: None,
+            authz: None,
+        }
     }
 
+    /// Requires clients to authenticate with mTLS, and only permits them to discover the policies
+    /// of workloads that share their identity (unless they are exempt).
+    pub fn with_tls(mut self, tls: TlsConfig) -> Self {
+        self.authz = Some(tls.client_authz());
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Serves inbound server discovery, as well as Envoy RBAC configurations via
+    /// [`envoy::Server`].
     pub async fn serve(
         self,
         addr: std::net::SocketAddr,
         shutdown: impl std::future::Future<Output = ()>,
-    ) -> Result<(), tonic::transport::Error> {
-        tonic::transport::Server::builder()
+    ) -> Result<(), tonic::transport::Error>
+    where
+        T: Clone,
+    {
+        let mut envoy = envoy::Server::new(self.discover.clone(), self.drain.clone());
+        let mut builder = tonic::transport::Server::builder();
+        if let Some(tls) = self.tls.as_ref() {
+            builder = builder.tls_config(tls.server_config())?;
+            envoy = envoy.with_client_authz(tls.client_authz());
+        }
+        builder
             .add_service(InboundServerDiscoveryServer::new(self))
+            .add_service(envoy.into_service())
             .serve_with_shutdown(addr, shutdown)
             .await
     }
 
+    /// Parses and authorizes a request's target, returning the client so that the target may be
+    /// checked again once it is found.
     fn check_target(
         &self,
-        proto::PortSpec { workload, port }: proto::PortSpec,
-    ) -> Result<(String, String, u16), tonic::Status> {
+        req: tonic::Request<proto::PortSpec>,
+    ) -> Result<(Target, Option<Client>), tonic::Status> {
+        let client = self.authz.as_ref().map(|authz| authz.client(&req));
+        let protocol = match req.metadata().get(PORT_PROTOCOL_HEADER) {
+            None => PortProtocol::Tcp,
+            Some(v) => v
+                .to_str()
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .ok_or_else(|| {
+                    tonic::Status::invalid_argument(format!("Invalid port protocol: {:?}", v))
+                })?,
+        };
+        let proto::PortSpec { workload, port } = req.into_inner();
+
         // Parse a workload name in the form namespace:name.
         let (ns, name) = match workload.split_once(':') {
             None => {
@@ -69,29 +138,44 @@ where
             port as u16
         };
 
-        Ok((ns.to_string(), name.to_string(), port))
+        let target = (ns.to_string(), name.to_string(), port, protocol);
+        if let Some(client) = client.as_ref() {
+            client.check(&self.discover, &target)?;
+        }
+        Ok((target, client))
+    }
+
+    /// Checks a found target again, since its workload may have been indexed after it was first
+    /// checked.
+    fn check_found(&self, client: Option<Client>, target: &Target) -> Result<(), tonic::Status> {
+        match client {
+            Some(client) => client.check_found(&self.discover, target),
+            None => Ok(()),
+        }
     }
 }
 
 #[async_trait::async_trait]
 impl<T> InboundServerDiscovery for Server<T>
 where
-    T: DiscoverInboundServer<(String, String, u16)> + Send + Sync + 'static,
+    T: DiscoverInboundServer<Target> + IdentifyTarget<Target> + Send + Sync + 'static,
 {
     async fn get_port(
         &self,
         req: tonic::Request<proto::PortSpec>,
     ) -> Result<tonic::Response<proto::Server>, tonic::Status> {
-        let target = self.check_target(req.into_inner())?;
+        let (target, client) = self.check_target(req)?;
 
         // Lookup the configuration for an inbound port. If the pod hasn't (yet)
         // been indexed, return a Not Found error.
         let s = self
             .discover
-            .get_inbound_server(target)
+            .get_inbound_server(target.clone())
             .await
             .map_err(|e| tonic::Status::internal(format!("lookup failed: {}", e)))?
             .ok_or_else(|| tonic::Status::not_found("unknown server"))?;
+        self.check_found(client, &target)?;
+        check_routes(&s)?;
 
         Ok(tonic::Response::new(to_server(&s)))
     }
@@ -102,14 +186,15 @@ where
         &self,
         req: tonic::Request<proto::PortSpec>,
     ) -> Result<tonic::Response<BoxWatchStream>, tonic::Status> {
-        let target = self.check_target(req.into_inner())?;
+        let (target, client) = self.check_target(req)?;
         let drain = self.drain.clone();
         let rx = self
             .discover
-            .watch_inbound_server(target)
+            .watch_inbound_server(target.clone())
             .await
             .map_err(|e| tonic::Status::internal(format!("lookup failed: {}", e)))?
             .ok_or_else(|| tonic::Status::not_found("unknown server"))?;
+        self.check_found(client, &target)?;
         Ok(tonic::Response::new(response_stream(drain, rx)))
     }
 }
@@ -124,12 +209,10 @@ fn response_stream(drain: drain::Watch, mut rx: InboundServerStream) -> BoxWatch
         }
 
         loop {
-            tokio::select! {
+            let s = tokio::select! {
                 // When the port is updated with a new server, update the server watch.
                 res = rx.next() => match res {
-                    Some(s) => {
-                        yield to_server(&s);
-                    }
+                    Some(s) => s,
                     None => return,
                 },
 
@@ -138,11 +221,27 @@ fn response_stream(drain: drain::Watch, mut rx: InboundServerStream) -> BoxWatch
                 _ = (&mut shutdown) => {
                     return;
                 }
-            }
+            };
+
+            // Routes may be added to a watched server, in which case the watch fails.
+            check_routes(&s)?;
+            yield to_server(&s);
         }
     })
 }
 
+/// The proxy API does not yet describe routes, so servers with route-scoped authorizations are
+/// only served via the Envoy RBAC API. Proxies would otherwise apply the server-wide
+/// authorizations to requests that the server's routes restrict.
+fn check_routes(srv: &InboundServer) -> Result<(), tonic::Status> {
+    if srv.routes.is_empty() {
+        return Ok(());
+    }
+    Err(tonic::Status::failed_precondition(
+        "server has route-scoped authorizations, which the inbound API can't describe",
+    ))
+}
+
 fn to_server(srv: &InboundServer) -> proto::Server {
     // Convert the protocol object into a protobuf response.
     let protocol = proto::ProxyProtocol {
@@ -174,13 +273,29 @@ fn to_server(srv: &InboundServer) -> proto::Server {
     let authorizations = srv
         .authorizations
         .iter()
-        .map(|(n, c)| to_authz(n, c))
-        .collect();
+        .map(|(n, c)| {
+            let mut authz = to_authz(n, c);
+            if n == HEALTH_CHECK_AUTHZ {
+                if let Some(source) = srv.health_check_source.as_ref() {
+                    authz
+                        .labels
+                        .insert(HEALTH_CHECK_SOURCE_LABEL.to_string(), source.clone());
+                }
+            }
+            authz
+        })
+        .collect::<Vec<_>>();
     trace!(?authorizations);
 
+    let mut labels = std::collections::HashMap::new();
+    if srv.undeclared {
+        labels.insert(UNDECLARED_LABEL.to_string(), "true".to_string());
+    }
+
     proto::Server {
         protocol: Some(protocol),
         authorizations,
+        labels,
         ..Default::default()
     }
 }
@@ -257,10 +372,23 @@ fn to_authz(
         // Authenticated connections must have TLS and apply to all
         // networks.
         ClientAuthentication::TlsAuthenticated(identities) => {
+            let spiffe = identities
+                .iter()
+                .filter_map(|i| match i {
+                    IdentityMatch::SpiffePrefix(p) => Some(p.to_string()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
             let labels = Some(("authn".to_string(), "true".to_string()))
                 .into_iter()
                 .chain(Some(("tls".to_string(), "true".to_string())))
                 .chain(Some(("name".to_string(), name.to_string())))
+                .chain(if spiffe.is_empty() {
+                    None
+                } else {
+                    let prefixes = serde_json::to_string(&spiffe).expect("prefixes must serialize");
+                    Some((SPIFFE_LABEL.to_string(), prefixes))
+                })
                 .collect();
 
             let authn = {
@@ -304,3 +432,60 @@ fn to_authz(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use polixy_controller_core::{HttpRoute, HttpRouteMatch, PathMatch};
+
+    /// All of a server's authorizations are sent, and servers with routes are refused rather
+    /// than described without their routes.
+    #[test]
+    fn routes_are_refused() {
+        let authz = |authentication| ClientAuthorization {
+            networks: vec![],
+            authentication,
+        };
+        let mut srv = InboundServer {
+            protocol: ProxyProtocol::Http1,
+            authorizations: vec![
+                (
+                    "open".to_string(),
+                    authz(ClientAuthentication::Unauthenticated),
+                ),
+                (
+                    HEALTH_CHECK_AUTHZ.to_string(),
+                    authz(ClientAuthentication::Unauthenticated),
+                ),
+            ]
+            .into_iter()
+            .collect(),
+            routes: Default::default(),
+            undeclared: false,
+            health_check_source: None,
+        };
+
+        assert!(check_routes(&srv).is_ok());
+        let server = to_server(&srv);
+        let names = server
+            .authorizations
+            .iter()
+            .map(|a| a.labels["name"].clone())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec![HEALTH_CHECK_AUTHZ, "open"]);
+        assert!(server.labels.is_empty());
+
+        srv.routes.insert(
+            "admin".to_string(),
+            HttpRoute {
+                matches: vec![HttpRouteMatch {
+                    path: Some(PathMatch::Prefix("/admin".to_string())),
+                    methods: vec![],
+                }],
+                authorization: authz(ClientAuthentication::TlsUnauthenticated),
+            },
+        );
+        let status = check_routes(&srv).expect_err("servers with routes must be refused");
+        assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+    }
+}
@@ -1,3 +1,4 @@
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{LabelSelector, LabelSelectorRequirement};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::{
@@ -69,6 +70,115 @@ impl Selector {
     }
 }
 
+/// Parses a label selector in the form accepted by `kubectl --selector`.
+///
+/// Equality (`k=v`, `k==v`, `k!=v`) and set-based (`k in (a,b)`, `k notin (a,b)`) requirements are
+/// supported; existence requirements are not.
+impl std::str::FromStr for Selector {
+    type Err = InvalidSelector;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut match_labels = Map::new();
+        let mut match_expressions = Expressions::new();
+
+        for req in split_requirements(s)? {
+            if let Some((k, v)) = req.split_once("!=") {
+                match_expressions.push(Expression {
+                    key: parse_key(k, req)?,
+                    operator: Operator::NotIn,
+                    values: Some(parse_value(v, req)?).into_iter().collect(),
+                });
+            } else if let Some((k, v)) = req.split_once('=') {
+                let v = v.strip_prefix('=').unwrap_or(v);
+                match_labels.insert(parse_key(k, req)?, parse_value(v, req)?);
+            } else if let Some((k, vs)) = req.split_once(" notin ") {
+                match_expressions.push(Expression {
+                    key: parse_key(k, req)?,
+                    operator: Operator::NotIn,
+                    values: parse_set(vs, req)?,
+                });
+            } else if let Some((k, vs)) = req.split_once(" in ") {
+                match_expressions.push(Expression {
+                    key: parse_key(k, req)?,
+                    operator: Operator::In,
+                    values: parse_set(vs, req)?,
+                });
+            } else {
+                return Err(InvalidSelector(req.to_string()));
+            }
+        }
+
+        Ok(Self {
+            match_labels: Some(match_labels).filter(|m| !m.is_empty()),
+            match_expressions: Some(match_expressions).filter(|e| !e.is_empty()),
+        })
+    }
+}
+
+/// Indicates that a label selector could not be parsed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InvalidSelector(String);
+
+impl std::fmt::Display for InvalidSelector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid label selector requirement: {:?}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidSelector {}
+
+/// Splits a selector on commas that are not enclosed in a value set.
+fn split_requirements(s: &str) -> Result<Vec<&str>, InvalidSelector> {
+    let mut reqs = Vec::new();
+    let mut depth = 0usize;
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                depth = depth
+                    .checked_sub(1)
+                    .ok_or_else(|| InvalidSelector(s.to_string()))?
+            }
+            ',' if depth == 0 => {
+                reqs.push(s[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if depth != 0 {
+        return Err(InvalidSelector(s.to_string()));
+    }
+    reqs.push(s[start..].trim());
+    Ok(reqs.into_iter().filter(|r| !r.is_empty()).collect())
+}
+
+fn parse_key(k: &str, req: &str) -> Result<String, InvalidSelector> {
+    let k = k.trim();
+    if k.is_empty() || k.contains(|c: char| c.is_whitespace() || "=!(),".contains(c)) {
+        return Err(InvalidSelector(req.to_string()));
+    }
+    Ok(k.to_string())
+}
+
+fn parse_value(v: &str, req: &str) -> Result<String, InvalidSelector> {
+    let v = v.trim();
+    if v.contains(|c: char| c.is_whitespace() || "=!(),".contains(c)) {
+        return Err(InvalidSelector(req.to_string()));
+    }
+    Ok(v.to_string())
+}
+
+fn parse_set(vs: &str, req: &str) -> Result<BTreeSet<String>, InvalidSelector> {
+    let vs = vs
+        .trim()
+        .strip_prefix('(')
+        .and_then(|vs| vs.strip_suffix(')'))
+        .ok_or_else(|| InvalidSelector(req.to_string()))?;
+    vs.split(',').map(|v| parse_value(v, req)).collect()
+}
+
 impl std::iter::FromIterator<(String, String)> for Selector {
     fn from_iter<T: IntoIterator<Item = (String, String)>>(iter: T) -> Self {
         Self::from_map(iter.into_iter().collect())
@@ -91,6 +201,27 @@ impl std::iter::FromIterator<Expression> for Selector {
     }
 }
 
+impl From<&Selector> for LabelSelector {
+    fn from(sel: &Selector) -> Self {
+        Self {
+            match_labels: sel.match_labels.clone().unwrap_or_default(),
+            match_expressions: sel
+                .match_expressions
+                .iter()
+                .flatten()
+                .map(|expr| LabelSelectorRequirement {
+                    key: expr.key.clone(),
+                    operator: match expr.operator {
+                        Operator::In => "In".to_string(),
+                        Operator::NotIn => "NotIn".to_string(),
+                    },
+                    values: expr.values.iter().cloned().collect(),
+                })
+                .collect(),
+        }
+    }
+}
+
 // === Labels ===
 
 impl From<Map> for Labels {
@@ -140,7 +271,7 @@ impl Expression {
             }
             Operator::NotIn => {
                 return match labels.get(&self.key) {
-                    Some(v) => self.values.contains(v),
+                    Some(v) => !self.values.contains(v),
                     None => true,
                 }
             }
@@ -181,8 +312,86 @@ mod tests {
                 true,
                 "expression match",
             ),
+            (
+                Selector::from_iter(Some(Expression {
+                    key: "foo".into(),
+                    operator: Operator::NotIn,
+                    values: Some("bar".to_string()).into_iter().collect(),
+                })),
+                Labels::from_iter(vec![("foo", "bar"), ("bah", "baz")]),
+                false,
+                "excluded expression mismatch",
+            ),
+            (
+                Selector::from_iter(Some(Expression {
+                    key: "foo".into(),
+                    operator: Operator::NotIn,
+                    values: Some("bar".to_string()).into_iter().collect(),
+                })),
+                Labels::from_iter(vec![("foo", "bah")]),
+                true,
+                "excluded expression match",
+            ),
+            (
+                Selector::from_iter(Some(Expression {
+                    key: "foo".into(),
+                    operator: Operator::NotIn,
+                    values: Some("bar".to_string()).into_iter().collect(),
+                })),
+                Labels::from_iter(Some(("bah", "baz"))),
+                true,
+                "excluded expression without label",
+            ),
         ] {
             assert_eq!(selector.matches(labels), *matches, "{}", msg);
         }
     }
+
+    #[test]
+    fn test_parse() {
+        for (input, selector) in &[
+            ("", Selector::default()),
+            ("foo=bar", Selector::from_iter(Some(("foo", "bar")))),
+            (
+                "foo==bar, bah=baz",
+                Selector::from_iter(vec![("foo", "bar"), ("bah", "baz")]),
+            ),
+            (
+                "foo!=bar",
+                Selector::from_iter(Some(Expression {
+                    key: "foo".into(),
+                    operator: Operator::NotIn,
+                    values: Some("bar".to_string()).into_iter().collect(),
+                })),
+            ),
+            (
+                "foo in (bar, baz),bah notin (qux)",
+                Selector::from_iter(vec![
+                    Expression {
+                        key: "foo".into(),
+                        operator: Operator::In,
+                        values: vec!["bar".to_string(), "baz".to_string()]
+                            .into_iter()
+                            .collect(),
+                    },
+                    Expression {
+                        key: "bah".into(),
+                        operator: Operator::NotIn,
+                        values: Some("qux".to_string()).into_iter().collect(),
+                    },
+                ]),
+            ),
+        ] {
+            assert_eq!(
+                input.parse::<Selector>().as_ref(),
+                Ok(selector),
+                "{}",
+                input
+            );
+        }
+
+        for input in &["foo", "!foo", "foo in bar", "foo in (bar", "foo bar=baz"] {
+            assert!(input.parse::<Selector>().is_err(), "{}", input);
+        }
+    }
 }
@@ -2,20 +2,28 @@
 #![forbid(unsafe_code)]
 
 pub mod labels;
+mod pod;
 pub mod polixy;
+pub mod smi;
 mod watch;
 
 pub use self::{
     labels::Labels,
+    pod::Pod,
     watch::{Event, Watch},
 };
 pub use k8s_openapi::api::{
     self,
-    core::v1::{Namespace, Node, NodeSpec, Pod, PodSpec, PodStatus},
+    core::v1::{Namespace, Node, NodeSpec, PodSpec, PodStatus},
+};
+pub use k8s_openapi::apimachinery::pkg::{
+    apis::meta::v1::{LabelSelector, OwnerReference, Time},
+    util::intstr::IntOrString,
 };
 use kube::api::{Api, ListParams};
 pub use kube::api::{ObjectMeta, ResourceExt};
 use kube_runtime::watcher;
+use polixy_controller_core::{Backoff, Reconnects};
 
 /// Resource watches.
 pub struct ResourceWatches {
@@ -23,12 +31,69 @@ pub struct ResourceWatches {
     pub pods_rx: Watch<Pod>,
     pub servers_rx: Watch<polixy::Server>,
     pub authorizations_rx: Watch<polixy::ServerAuthorization>,
+
+    /// Set when authorizations may select client pods.
+    pub client_pods: Option<ClientPodWatches>,
+}
+
+/// Watches all pods, including unmeshed pods, and all namespaces so that authorizations may select
+/// clients by label.
+///
+/// These watches are only needed when client pod selection is enabled. They hold every pod in the
+/// cluster in memory (in addition to the meshed pods that are always watched) and require
+/// permission to watch namespaces.
+pub struct ClientPodWatches {
+    pub client_pods_rx: Watch<Pod>,
+    pub namespaces_rx: Watch<Namespace>,
+}
+
+/// Watches SMI resources, which are only needed when SMI resources are imported.
+pub struct SmiWatches {
+    pub traffic_targets_rx: Watch<smi::TrafficTarget>,
+    pub http_route_groups_rx: Watch<smi::HttpRouteGroup>,
+    pub tcp_routes_rx: Watch<smi::TcpRoute>,
 }
 
 // === impl ResourceWatches ===
 
 impl ResourceWatches {
     const DEFAULT_TIMEOUT_SECS: u32 = 5 * 60;
+
+    /// Enables client pod selection.
+    pub fn with_client_pods(self, client_pods: ClientPodWatches) -> Self {
+        Self {
+            client_pods: Some(client_pods),
+            ..self
+        }
+    }
+
+    /// Sets the policy used to delay polling after any of the watches fail.
+    pub fn with_backoff(self, backoff: Backoff) -> Self {
+        Self {
+            nodes_rx: self.nodes_rx.with_backoff(backoff),
+            pods_rx: self.pods_rx.with_backoff(backoff),
+            servers_rx: self.servers_rx.with_backoff(backoff),
+            authorizations_rx: self.authorizations_rx.with_backoff(backoff),
+            client_pods: self.client_pods.map(|w| w.with_backoff(backoff)),
+        }
+    }
+
+    /// Counts each watch's reconnection attempts, by resource name.
+    pub fn reconnects(&self) -> Vec<(&'static str, Reconnects)> {
+        let mut reconnects = vec![
+            ("nodes", self.nodes_rx.reconnects().clone()),
+            ("pods", self.pods_rx.reconnects().clone()),
+            ("servers", self.servers_rx.reconnects().clone()),
+            (
+                "serverauthorizations",
+                self.authorizations_rx.reconnects().clone(),
+            ),
+        ];
+        if let Some(ref client_pods) = self.client_pods {
+            reconnects.extend(client_pods.reconnects());
+        }
+        reconnects
+    }
 }
 
 impl From<kube::Client> for ResourceWatches {
@@ -43,6 +108,76 @@ impl From<kube::Client> for ResourceWatches {
             .into(),
             servers_rx: watcher(Api::all(client.clone()), params.clone()).into(),
             authorizations_rx: watcher(Api::all(client), params).into(),
+            client_pods: None,
+        }
+    }
+}
+
+// === impl ClientPodWatches ===
+
+impl ClientPodWatches {
+    /// Sets the policy used to delay polling after any of the watches fail.
+    pub fn with_backoff(self, backoff: Backoff) -> Self {
+        Self {
+            client_pods_rx: self.client_pods_rx.with_backoff(backoff),
+            namespaces_rx: self.namespaces_rx.with_backoff(backoff),
+        }
+    }
+
+    /// Counts each watch's reconnection attempts, by resource name.
+    pub fn reconnects(&self) -> Vec<(&'static str, Reconnects)> {
+        vec![
+            ("client-pods", self.client_pods_rx.reconnects().clone()),
+            ("namespaces", self.namespaces_rx.reconnects().clone()),
+        ]
+    }
+}
+
+impl From<kube::Client> for ClientPodWatches {
+    fn from(client: kube::Client) -> Self {
+        let params = ListParams::default().timeout(ResourceWatches::DEFAULT_TIMEOUT_SECS);
+        Self {
+            client_pods_rx: watcher(Api::all(client.clone()), params.clone()).into(),
+            namespaces_rx: watcher(Api::all(client), params).into(),
+        }
+    }
+}
+
+// === impl SmiWatches ===
+
+impl SmiWatches {
+    /// Sets the policy used to delay polling after any of the watches fail.
+    pub fn with_backoff(self, backoff: Backoff) -> Self {
+        Self {
+            traffic_targets_rx: self.traffic_targets_rx.with_backoff(backoff),
+            http_route_groups_rx: self.http_route_groups_rx.with_backoff(backoff),
+            tcp_routes_rx: self.tcp_routes_rx.with_backoff(backoff),
+        }
+    }
+
+    /// Counts each watch's reconnection attempts, by resource name.
+    pub fn reconnects(&self) -> Vec<(&'static str, Reconnects)> {
+        vec![
+            (
+                "traffictargets",
+                self.traffic_targets_rx.reconnects().clone(),
+            ),
+            (
+                "httproutegroups",
+                self.http_route_groups_rx.reconnects().clone(),
+            ),
+            ("tcproutes", self.tcp_routes_rx.reconnects().clone()),
+        ]
+    }
+}
+
+impl From<kube::Client> for SmiWatches {
+    fn from(client: kube::Client) -> Self {
+        let params = ListParams::default().timeout(ResourceWatches::DEFAULT_TIMEOUT_SECS);
+        Self {
+            traffic_targets_rx: watcher(Api::all(client.clone()), params.clone()).into(),
+            http_route_groups_rx: watcher(Api::all(client.clone()), params.clone()).into(),
+            tcp_routes_rx: watcher(Api::all(client), params).into(),
         }
     }
 }
@@ -0,0 +1,99 @@
+use crate::{api::core::v1 as core, ObjectMeta, PodSpec, PodStatus};
+use serde::{de, Deserialize, Deserializer};
+use std::{borrow::Cow, collections::HashSet};
+
+/// A pod, along with the names of its native sidecars.
+///
+/// Native sidecars are init containers with `restartPolicy: Always`, which keep running (and
+/// serving their ports) alongside the pod's containers. The Kubernetes API version we use doesn't
+/// describe init containers' restart policies, so they are read from the raw object.
+#[derive(Clone, Debug, Default)]
+pub struct Pod {
+    pub metadata: ObjectMeta,
+    pub spec: Option<PodSpec>,
+    pub status: Option<PodStatus>,
+
+    /// The names of the pod's init containers that run as sidecars.
+    pub sidecars: HashSet<String>,
+}
+
+impl<'de> Deserialize<'de> for Pod {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let pod = serde_json::Value::deserialize(deserializer)?;
+
+        let sidecars = pod
+            .pointer("/spec/initContainers")
+            .and_then(|c| c.as_array())
+            .into_iter()
+            .flatten()
+            .filter(|c| c.get("restartPolicy").and_then(|p| p.as_str()) == Some("Always"))
+            .filter_map(|c| c.get("name")?.as_str().map(String::from))
+            .collect();
+
+        let core::Pod {
+            metadata,
+            spec,
+            status,
+        } = serde_json::from_value(pod).map_err(de::Error::custom)?;
+        Ok(Self {
+            metadata,
+            spec,
+            status,
+            sidecars,
+        })
+    }
+}
+
+impl kube::Resource for Pod {
+    type DynamicType = ();
+
+    fn kind(dt: &()) -> Cow<'_, str> {
+        <core::Pod as kube::Resource>::kind(dt)
+    }
+
+    fn group(dt: &()) -> Cow<'_, str> {
+        <core::Pod as kube::Resource>::group(dt)
+    }
+
+    fn version(dt: &()) -> Cow<'_, str> {
+        <core::Pod as kube::Resource>::version(dt)
+    }
+
+    fn plural(dt: &()) -> Cow<'_, str> {
+        <core::Pod as kube::Resource>::plural(dt)
+    }
+
+    fn meta(&self) -> &ObjectMeta {
+        &self.metadata
+    }
+
+    fn meta_mut(&mut self) -> &mut ObjectMeta {
+        &mut self.metadata
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn native_sidecars() {
+        let pod = serde_json::from_value::<Pod>(serde_json::json!({
+            "metadata": { "namespace": "ns-0", "name": "pod-0" },
+            "spec": {
+                "initContainers": [
+                    { "name": "init" },
+                    { "name": "proxy", "restartPolicy": "Always" },
+                ],
+                "containers": [{ "name": "app" }],
+            },
+        }))
+        .unwrap();
+        assert_eq!(pod.metadata.name.as_deref(), Some("pod-0"));
+        assert_eq!(pod.spec.unwrap().init_containers.len(), 2);
+        assert_eq!(
+            pod.sidecars,
+            Some("proxy".to_string()).into_iter().collect()
+        );
+    }
+}
@@ -15,8 +15,28 @@ use serde::{Deserialize, Serialize};
 pub struct ServerAuthorizationSpec {
     pub server: Server,
     pub client: Client,
+
+    /// Limits the authorization to matching requests on HTTP and gRPC servers.
+    ///
+    /// Requests that match any authorization's routes are authorized only by the authorizations
+    /// with matching routes. If unset, the authorization applies to all requests that match no
+    /// route.
+    pub routes: Option<Vec<HttpRoute>>,
+
+    /// The time at which the authorization takes effect. If unset, it takes effect immediately.
+    pub not_before: Option<Time>,
+
+    /// The time at which the authorization expires. If unset, it never expires.
+    pub not_after: Option<Time>,
+
+    /// Limits the authorization to a subset of its servers' pods, e.g. to canary a revision of
+    /// another authorization.
+    pub rollout: Option<Rollout>,
 }
 
+/// An RFC 3339 timestamp.
+pub type Time = chrono::DateTime<chrono::Utc>;
+
 #[derive(Default, Deserialize, Serialize, Clone, Debug, JsonSchema)]
 pub struct Server {
     pub name: Option<String>,
@@ -31,6 +51,15 @@ pub struct Server {
 pub struct Client {
     pub networks: Option<Vec<Network>>,
 
+    /// Selects client pods by label, as a NetworkPolicy peer does. Pods are selected in the
+    /// authorization's namespace unless `namespace_selector` is set. May not be combined with
+    /// `networks`.
+    pub pod_selector: Option<labels::Selector>,
+
+    /// Selects the namespaces of client pods by label. All pods in matching namespaces are
+    /// selected unless `pod_selector` is set.
+    pub namespace_selector: Option<labels::Selector>,
+
     #[serde(default)]
     pub unauthenticated: bool,
 
@@ -48,6 +77,10 @@ pub struct MeshTls {
     pub unauthenticated_tls: bool,
 
     /// Indicates a Linkerd identity that is authorized to access a server.
+    ///
+    /// Identities may be exact names (including SPIFFE IDs), `*` or `*.<suffix>` to match
+    /// DNS-like identities by suffix, or `spiffe://<trust-domain>/*` and
+    /// `spiffe://<trust-domain>/<path>/*` to match SPIFFE IDs by trust domain and path prefix.
     pub identities: Vec<String>,
 
     /// Identifies a `ServiceAccount` authorized to access a server.
@@ -62,10 +95,58 @@ pub struct Network {
 
 /// References a Kubernetes `ServiceAccount` instance.
 ///
-/// If no namespace is specified, the `Authorization`'s namespace is used.
+/// If no namespace is specified, the `Authorization`'s namespace is used. If a cluster is
+/// specified, the service account is in that linked cluster's identity domain.
 #[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
 pub struct ServiceAccountRef {
     pub namespace: Option<String>,
     pub name: String,
+    pub cluster: Option<String>,
     // TODO pub selector: labels::Selector,
 }
+
+/// Selects the pods to which an authorization applies.
+///
+/// A pod is selected if it matches both `pod_selector` and `percent`, when set. At least one must
+/// be set.
+#[derive(Default, Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Rollout {
+    /// Selects pods by label.
+    pub pod_selector: Option<labels::Selector>,
+
+    /// Selects a percentage of pods, determined by a hash of each pod's name so that the same pods
+    /// remain selected as the percentage grows.
+    pub percent: Option<u8>,
+
+    /// The name of an authorization in the same namespace that doesn't apply to the selected pods,
+    /// so that they use this authorization in its place while other pods keep it.
+    pub replaces: Option<String>,
+}
+
+/// Matches HTTP requests, or gRPC requests by service and method.
+///
+/// `grpc` may not be combined with `pathPrefix`, `path`, or `methods`.
+#[derive(Default, Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct HttpRoute {
+    /// Matches requests with exactly this path.
+    pub path: Option<String>,
+
+    /// Matches requests with this path or a path beneath it.
+    pub path_prefix: Option<String>,
+
+    /// Matches requests with any of these methods. All methods match if unset.
+    pub methods: Option<Vec<String>>,
+
+    pub grpc: Option<GrpcRoute>,
+}
+
+#[derive(Default, Deserialize, Serialize, Clone, Debug, JsonSchema)]
+pub struct GrpcRoute {
+    /// A fully-qualified service name, e.g. `helloworld.Greeter`.
+    pub service: String,
+
+    /// A method name. All of the service's methods match if unset.
+    pub method: Option<String>,
+}
@@ -1,7 +1,7 @@
 use super::super::labels;
 use kube::CustomResource;
-use schemars::JsonSchema;
-use serde::{Deserialize, Serialize};
+use schemars::{gen::SchemaGenerator, schema::Schema, JsonSchema};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 /// Describes a server interface exposed by a set of pods.
 #[derive(Clone, Debug, CustomResource, Deserialize, Serialize, JsonSchema)]
@@ -15,17 +15,48 @@ use serde::{Deserialize, Serialize};
 pub struct ServerSpec {
     pub pod_selector: labels::Selector,
     pub port: Port,
+
+    /// The port's transport protocol. Defaults to TCP.
+    pub port_protocol: Option<PortProtocol>,
+
+    /// If set, only ports declared by this container are selected.
+    pub container: Option<String>,
+
     pub proxy_protocol: Option<ProxyProtocol>,
+
+    /// Clients admitted in addition to the server's authorizations. Defaults to `deny`.
+    pub default_allow: Option<DefaultAllow>,
 }
 
-/// References a pod spec's port by name or number.
+/// References a pod spec's port by name or number, or a range of port numbers.
 #[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize, JsonSchema)]
 #[serde(untagged)]
 pub enum Port {
     Number(u16),
+    Range(PortRange),
     Name(String),
 }
 
+/// An inclusive range of port numbers, written as `<first>-<last>`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PortRange {
+    pub first: u16,
+    pub last: u16,
+}
+
+/// Indicates that a port range could not be parsed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InvalidPortRange(String);
+
+/// A pod port's transport protocol, as in a pod spec.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum PortProtocol {
+    Tcp,
+    Udp,
+    Sctp,
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
 pub enum ProxyProtocol {
     #[serde(rename = "unknown")]
@@ -41,3 +72,149 @@ pub enum ProxyProtocol {
     #[serde(rename = "TLS")]
     Tls,
 }
+
+/// A default-allow policy that a server applies in addition to its authorizations.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum DefaultAllow {
+    AllAuthenticated,
+    AllUnauthenticated,
+    ClusterAuthenticated,
+    ClusterUnauthenticated,
+    Deny,
+}
+
+// === impl Port ===
+
+impl Port {
+    /// Returns the port numbers that are referenced, unless the port is referenced by name.
+    pub fn numbers(&self) -> Option<PortRange> {
+        match self {
+            Self::Number(port) => Some(PortRange::single(*port)),
+            Self::Range(range) => Some(*range),
+            Self::Name(_) => None,
+        }
+    }
+}
+
+// === impl PortRange ===
+
+impl PortRange {
+    /// A range that only contains a single port.
+    pub fn single(port: u16) -> Self {
+        Self {
+            first: port,
+            last: port,
+        }
+    }
+
+    pub fn contains(&self, port: u16) -> bool {
+        self.first <= port && port <= self.last
+    }
+
+    pub fn overlaps(&self, other: &Self) -> bool {
+        self.first <= other.last && other.first <= self.last
+    }
+
+    pub fn iter(&self) -> std::ops::RangeInclusive<u16> {
+        self.first..=self.last
+    }
+}
+
+impl std::str::FromStr for PortRange {
+    type Err = InvalidPortRange;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidPortRange(s.to_string());
+        let (first, last) = s.split_once('-').ok_or_else(invalid)?;
+        let first = first.parse::<u16>().map_err(|_| invalid())?;
+        let last = last.parse::<u16>().map_err(|_| invalid())?;
+        if first == 0 || last < first {
+            return Err(invalid());
+        }
+        Ok(Self { first, last })
+    }
+}
+
+impl std::fmt::Display for PortRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}-{}", self.first, self.last)
+    }
+}
+
+impl Serialize for PortRange {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for PortRange {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(d)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl JsonSchema for PortRange {
+    fn schema_name() -> String {
+        "PortRange".to_string()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        String::json_schema(gen)
+    }
+
+    fn is_referenceable() -> bool {
+        false
+    }
+}
+
+impl std::fmt::Display for InvalidPortRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid port range: {:?}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidPortRange {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_ports() {
+        for (json, port) in [
+            ("8080", Port::Number(8080)),
+            ("\"http\"", Port::Name("http".to_string())),
+            (
+                "\"30000-30100\"",
+                Port::Range(PortRange {
+                    first: 30000,
+                    last: 30100,
+                }),
+            ),
+            ("\"8080-8080\"", Port::Range(PortRange::single(8080))),
+        ] {
+            assert_eq!(
+                serde_json::from_str::<Port>(json).unwrap(),
+                port,
+                "{}",
+                json
+            );
+            assert_eq!(serde_json::to_string(&port).unwrap(), json);
+        }
+
+        for s in &[
+            "",
+            "8080",
+            "-8080",
+            "8080-",
+            "0-10",
+            "30100-30000",
+            "1-65536",
+            "a-b",
+        ] {
+            assert!(s.parse::<PortRange>().is_err(), "{}", s);
+        }
+    }
+}
@@ -0,0 +1,66 @@
+use kube::CustomResource;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Grants sources access to a destination's routes.
+#[derive(CustomResource, Default, Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[kube(
+    group = "access.smi-spec.io",
+    version = "v1alpha3",
+    kind = "TrafficTarget",
+    namespaced
+)]
+#[serde(rename_all = "camelCase")]
+pub struct TrafficTargetSpec {
+    pub destination: IdentityBindingSubject,
+
+    #[serde(default)]
+    pub sources: Vec<IdentityBindingSubject>,
+
+    #[serde(default)]
+    pub rules: Vec<TrafficTargetRule>,
+}
+
+/// Identifies a workload by its service account.
+#[derive(Default, Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct IdentityBindingSubject {
+    pub kind: String,
+    pub name: String,
+    pub namespace: Option<String>,
+    pub port: Option<u16>,
+}
+
+/// References a route resource in the target's namespace.
+#[derive(Default, Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TrafficTargetRule {
+    pub kind: String,
+    pub name: String,
+
+    /// Names of the route's matches to which the rule applies. If empty, all matches apply.
+    #[serde(default)]
+    pub matches: Vec<String>,
+}
+
+/// Describes how a `TrafficTarget` was imported.
+///
+/// The SMI `TrafficTarget` CRD has no status subresource, so the controller writes this to an
+/// annotation on the target.
+#[derive(Default, Deserialize, Serialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TrafficTargetStatus {
+    pub observed_generation: Option<i64>,
+
+    /// The names of the `ServerAuthorization`s generated for the target.
+    #[serde(default)]
+    pub authorizations: Vec<String>,
+
+    /// Describes the parts of the target that were imported.
+    #[serde(default)]
+    pub translated: Vec<String>,
+
+    /// Describes the parts of the target that could not be imported and were ignored.
+    #[serde(default)]
+    pub untranslated: Vec<String>,
+}
@@ -0,0 +1,9 @@
+//! Service Mesh Interface (SMI) resources that may be imported as authorizations.
+//!
+//! See <https://github.com/servicemeshinterface/smi-spec>.
+
+pub mod access;
+pub mod specs;
+
+pub use self::access::{TrafficTarget, TrafficTargetSpec, TrafficTargetStatus};
+pub use self::specs::{HttpRouteGroup, HttpRouteGroupSpec, TcpRoute, TcpRouteSpec};
@@ -0,0 +1,57 @@
+use kube::CustomResource;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Describes HTTP routes that may be referenced by a `TrafficTarget`.
+#[derive(CustomResource, Default, Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[kube(
+    group = "specs.smi-spec.io",
+    version = "v1alpha4",
+    kind = "HTTPRouteGroup",
+    struct = "HttpRouteGroup",
+    namespaced
+)]
+#[serde(rename_all = "camelCase")]
+pub struct HttpRouteGroupSpec {
+    #[serde(default)]
+    pub matches: Vec<HttpMatch>,
+}
+
+#[derive(Default, Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct HttpMatch {
+    pub name: String,
+
+    /// Matches all methods if unset or if it contains `*`.
+    pub methods: Option<Vec<String>>,
+
+    /// A regular expression that must match the entire request path.
+    pub path_regex: Option<String>,
+
+    pub headers: Option<BTreeMap<String, String>>,
+}
+
+/// Describes TCP traffic that may be referenced by a `TrafficTarget`.
+#[derive(CustomResource, Default, Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[kube(
+    group = "specs.smi-spec.io",
+    version = "v1alpha4",
+    kind = "TCPRoute",
+    struct = "TcpRoute",
+    namespaced
+)]
+#[serde(rename_all = "camelCase")]
+pub struct TcpRouteSpec {
+    pub matches: Option<TcpMatch>,
+}
+
+#[derive(Default, Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TcpMatch {
+    pub name: Option<String>,
+
+    /// Limits the route to these destination ports. If empty, all ports match.
+    #[serde(default)]
+    pub ports: Vec<u16>,
+}
@@ -1,4 +1,5 @@
 use futures::prelude::*;
+use polixy_controller_core::{Attempts, Backoff, Reconnects};
 use std::pin::Pin;
 use tokio::time;
 use tracing::info;
@@ -9,6 +10,8 @@ pub use kube_runtime::watcher::{Event, Result};
 pub struct Watch<T> {
     ready: bool,
     rx: Pin<Box<dyn Stream<Item = Result<Event<T>>> + Send + 'static>>,
+    attempts: Attempts,
+    reconnects: Reconnects,
 }
 
 // === impl Watch ===
@@ -21,18 +24,32 @@ where
         Watch {
             ready: false,
             rx: watch.boxed(),
+            attempts: Backoff::default().attempts(),
+            reconnects: Reconnects::default(),
         }
     }
 }
 
 impl<T> Watch<T> {
+    /// Sets the policy used to delay polling after the stream fails.
+    pub fn with_backoff(mut self, backoff: Backoff) -> Self {
+        self.attempts = backoff.attempts();
+        self
+    }
+
     pub fn ready(&self) -> bool {
         self.ready
     }
 
+    /// Counts the watch's reconnection attempts.
+    pub fn reconnects(&self) -> &Reconnects {
+        &self.reconnects
+    }
+
     /// Receive the next event in the stream.
     ///
-    /// If the stream fails, log the error and sleep for 1s before polling for a reset event.
+    /// If the stream fails, log the error and back off before polling for a reset event. The
+    /// backoff is reset once an event is received.
     pub async fn recv(&mut self) -> Event<T> {
         loop {
             match self
@@ -42,13 +59,19 @@ impl<T> Watch<T> {
                 .expect("watch stream must not terminate")
             {
                 Ok(ev) => {
+                    if self.attempts.failures() > 0 {
+                        self.reconnects.recovered();
+                        self.attempts.reset();
+                    }
                     self.ready = true;
                     return ev;
                 }
                 Err(error) => {
                     self.ready = false;
-                    info!(%error, "Disconnected");
-                    time::sleep(time::Duration::from_secs(1)).await;
+                    let delay = self.attempts.next_delay();
+                    self.reconnects.attempt();
+                    info!(%error, ?delay, "Disconnected");
+                    time::sleep(delay).await;
                 }
             }
         }
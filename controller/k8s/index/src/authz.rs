@@ -1,25 +1,81 @@
-use crate::{Index, ServerSelector, SrvIndex};
+use crate::{
+    client_pods::{ClientPodIndex, ClientPodSelector},
+    export::{AuthorizationData, AuthorizationState, RolloutData, ServerSelectorData},
+    rollout::Rollout,
+    IdentityDomains, Index, ServerSelector, SrvIndex,
+};
 use anyhow::{anyhow, bail, Result};
 use polixy_controller_core::{
-    ClientAuthentication, ClientAuthorization, IdentityMatch, IpNet, NetworkMatch,
+    ClientAuthentication, ClientAuthorization, HttpRouteMatch, IdentityMatch, IpNet, NetworkMatch,
+    PathMatch,
 };
 use polixy_controller_k8s_api::{
     self as k8s,
-    polixy::{self, authz::MeshTls},
+    polixy::{
+        self,
+        authz::{MeshTls, Time},
+    },
     ResourceExt,
 };
-use std::collections::{hash_map::Entry as HashEntry, HashMap, HashSet};
-use tracing::{debug, instrument, trace};
+use std::{
+    collections::{hash_map::Entry as HashEntry, BTreeMap, HashMap, HashSet},
+    sync::Arc,
+    time::SystemTime,
+};
+use tokio::time;
+use tracing::{debug, info, instrument, trace};
 
 #[derive(Debug, Default)]
 pub(crate) struct AuthzIndex {
     index: HashMap<String, Authz>,
+
+    /// The applied resources, so that authorizations may be rebuilt when identity domains change.
+    resources: HashMap<String, polixy::ServerAuthorization>,
+}
+
+/// An authorization that selects a server.
+pub(crate) struct SelectedAuthz<'a> {
+    pub name: String,
+    pub clients: &'a ClientAuthorization,
+    pub routes: &'a [HttpRouteMatch],
+    pub rollout: Option<&'a Arc<Rollout>>,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 struct Authz {
     servers: ServerSelector,
     clients: ClientAuthorization,
+
+    /// If non-empty, the authorization only applies to matching requests.
+    routes: Vec<HttpRouteMatch>,
+
+    /// If set, the clients' networks are the IPs of the selected pods.
+    client_pods: Option<ClientPodSelector>,
+
+    window: Window,
+
+    /// If set, the authorization only applies to the selected pods.
+    rollout: Option<Arc<Rollout>>,
+
+    /// Whether the authorization is in effect, as of when it was last updated. Only authorizations
+    /// in effect are added to servers.
+    state: AuthorizationState,
+}
+
+/// Indicates that an authorization took effect or expired as its time window opened or closed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AuthzTransition {
+    pub namespace: String,
+    pub name: String,
+    pub uid: Option<String>,
+    pub state: AuthorizationState,
+}
+
+/// The period during which an authorization is in effect.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+struct Window {
+    not_before: Option<Time>,
+    not_after: Option<Time>,
 }
 
 // === impl AuthzIndex ===
@@ -30,21 +86,27 @@ impl AuthzIndex {
         &mut self,
         authz: polixy::ServerAuthorization,
         servers: &mut SrvIndex,
-        domain: &str,
+        domains: &IdentityDomains,
+        client_pods: &ClientPodIndex,
+        now: Time,
     ) -> Result<()> {
         let name = authz.name();
-        let authz = mk_authz(authz, domain)?;
+        self.resources.insert(name.clone(), authz.clone());
+        let authz = mk_authz(authz, domains, client_pods, now)?;
 
         match self.index.entry(name) {
             HashEntry::Vacant(entry) => {
-                servers.add_authz(entry.key(), &authz.servers, authz.clients.clone());
+                if authz.state != AuthorizationState::Active {
+                    debug!(state = ?authz.state, "Authorization is not in effect");
+                }
+                authz.update_servers(entry.key(), servers);
                 entry.insert(authz);
             }
 
             HashEntry::Occupied(mut entry) => {
                 // If the authorization changed materially, then update it in all servers.
                 if entry.get() != &authz {
-                    servers.add_authz(entry.key(), &authz.servers, authz.clients.clone());
+                    authz.update_servers(entry.key(), servers);
                     entry.insert(authz);
                 }
             }
@@ -53,18 +115,113 @@ impl AuthzIndex {
         Ok(())
     }
 
+    /// Adds authorizations to (or removes them from) servers as their time windows open and
+    /// close, returning the authorizations that changed.
+    pub fn update_windows(
+        &mut self,
+        ns: &str,
+        servers: &mut SrvIndex,
+        now: Time,
+    ) -> Vec<AuthzTransition> {
+        let mut changed = Vec::new();
+        for (name, authz) in self.index.iter_mut() {
+            let state = authz.window.state(now);
+            if state == authz.state {
+                continue;
+            }
+            match state {
+                AuthorizationState::Active => info!(authz = %name, "Authorization took effect"),
+                AuthorizationState::Expired => info!(authz = %name, "Authorization expired"),
+                AuthorizationState::Pending => debug!(authz = %name, "Authorization is pending"),
+            }
+            authz.state = state;
+            authz.update_servers(name, servers);
+            changed.push(AuthzTransition {
+                namespace: ns.to_string(),
+                name: name.clone(),
+                uid: self
+                    .resources
+                    .get(name)
+                    .and_then(|r| r.metadata.uid.clone()),
+                state,
+            });
+        }
+        changed
+    }
+
+    /// Returns the earliest time at which an authorization takes effect or expires.
+    pub fn next_transition(&self, now: Time) -> Option<Time> {
+        self.index
+            .values()
+            .filter_map(|a| a.window.next_transition(now))
+            .min()
+    }
+
+    /// Updates the networks of authorizations that select client pods, returning whether any
+    /// changed.
+    pub fn resolve_client_pods(
+        &mut self,
+        servers: &mut SrvIndex,
+        client_pods: &ClientPodIndex,
+    ) -> bool {
+        let mut changed = false;
+        for (name, authz) in self.index.iter_mut() {
+            if let Some(ref selector) = authz.client_pods {
+                let networks = client_pods.select(selector);
+                if networks != authz.clients.networks {
+                    debug!(authz = %name, ?networks, "Updating client pod networks");
+                    authz.clients.networks = networks;
+                    authz.update_servers(name, servers);
+                    changed = true;
+                }
+            }
+        }
+        changed
+    }
+
     fn delete(&mut self, name: &str) {
         self.index.remove(name);
+        self.resources.remove(name);
         debug!("Removed authz");
     }
 
+    /// Renders each authorization for export, by name.
+    pub fn export(&self) -> BTreeMap<String, AuthorizationData> {
+        self.index
+            .iter()
+            .map(|(name, authz)| {
+                let server = match authz.servers {
+                    ServerSelector::Name(ref n) => ServerSelectorData::Name(n.clone()),
+                    ServerSelector::Selector(ref s) => ServerSelectorData::Selector((**s).clone()),
+                };
+                let data = AuthorizationData {
+                    server,
+                    client: (&authz.clients).into(),
+                    routes: authz.routes.clone(),
+                    not_before: authz.window.not_before,
+                    not_after: authz.window.not_after,
+                    state: authz.state,
+                    rollout: authz.rollout.as_ref().map(|r| RolloutData {
+                        pod_selector: r.pod_selector.as_deref().cloned(),
+                        percent: r.percent,
+                        replaces: r.replaces.clone(),
+                    }),
+                };
+                (name.clone(), data)
+            })
+            .collect()
+    }
+
     pub fn filter_selected(
         &self,
         name: impl Into<String>,
         labels: k8s::Labels,
-    ) -> impl Iterator<Item = (String, &ClientAuthorization)> {
+    ) -> impl Iterator<Item = SelectedAuthz<'_>> {
         let name = name.into();
         self.index.iter().filter_map(move |(authz_name, a)| {
+            if a.state != AuthorizationState::Active {
+                return None;
+            }
             let matches = match a.servers {
                 ServerSelector::Name(ref n) => {
                     trace!(r#ref = %n, %name);
@@ -77,7 +234,12 @@ impl AuthzIndex {
             };
             debug!(authz = %authz_name, %matches);
             if matches {
-                Some((authz_name.clone(), &a.clients))
+                Some(SelectedAuthz {
+                    name: authz_name.clone(),
+                    clients: &a.clients,
+                    routes: a.routes.as_slice(),
+                    rollout: a.rollout.as_ref(),
+                })
             } else {
                 None
             }
@@ -85,6 +247,49 @@ impl AuthzIndex {
     }
 }
 
+// === impl Authz ===
+
+impl Authz {
+    /// Adds the authorization to the servers it selects if it's in effect, or otherwise removes
+    /// it from all servers.
+    fn update_servers(&self, name: &str, servers: &mut SrvIndex) {
+        if self.state == AuthorizationState::Active {
+            servers.add_authz(
+                name,
+                &self.servers,
+                self.clients.clone(),
+                self.routes.clone(),
+                self.rollout.clone(),
+            );
+        } else {
+            servers.remove_authz(name);
+        }
+    }
+}
+
+// === impl Window ===
+
+impl Window {
+    fn state(&self, now: Time) -> AuthorizationState {
+        if self.not_before.map(|t| now < t).unwrap_or(false) {
+            AuthorizationState::Pending
+        } else if self.not_after.map(|t| t <= now).unwrap_or(false) {
+            AuthorizationState::Expired
+        } else {
+            AuthorizationState::Active
+        }
+    }
+
+    /// Returns the next time after `now` at which the window opens or closes.
+    fn next_transition(&self, now: Time) -> Option<Time> {
+        self.not_before
+            .into_iter()
+            .chain(self.not_after)
+            .filter(|t| now < *t)
+            .min()
+    }
+}
+
 // === impl Index ===
 
 impl Index {
@@ -97,12 +302,71 @@ impl Index {
         )
     )]
     pub(crate) fn apply_authz(&mut self, authz: polixy::ServerAuthorization) -> Result<()> {
-        let ns = self
-            .namespaces
-            .get_or_default(authz.namespace().expect("namespace required"));
+        let ns_name = authz.namespace().expect("namespace required");
+        let now = self.now();
+        let ns = self.namespaces.get_or_default(ns_name.clone());
+
+        let res = ns.authzs.apply(
+            authz,
+            &mut ns.servers,
+            &self.identity_domains,
+            &self.client_pods,
+            now,
+        );
+        ns.relink_rollouts();
+        let next = ns.authzs.next_transition(now);
+        self.schedule_authz_update(next);
+        self.publish_policy(&ns_name);
+        res
+    }
 
-        ns.authzs
-            .apply(authz, &mut ns.servers, &*self.identity_domain)
+    /// Updates the authorizations whose time windows have opened or closed since they were last
+    /// updated, publishing the namespaces whose authorizations changed and reporting the
+    /// authorizations that took effect or expired.
+    pub(crate) fn update_authz_windows(&mut self) {
+        let now = self.now();
+        let mut changed = Vec::new();
+        let mut transitions = Vec::new();
+        let mut next = None;
+        for (name, ns) in self.namespaces.index.iter_mut() {
+            let updated = ns.authzs.update_windows(name, &mut ns.servers, now);
+            if !updated.is_empty() {
+                ns.relink_rollouts();
+                changed.push(name.clone());
+                transitions.extend(updated);
+            }
+            next = earliest(next, ns.authzs.next_transition(now));
+        }
+        self.next_authz_update = next;
+
+        for ns in changed.into_iter() {
+            self.publish_policy(&ns);
+        }
+
+        if let Some(ref tx) = self.authz_transitions {
+            for t in transitions.into_iter() {
+                if t.state != AuthorizationState::Pending {
+                    // The receiver may have stopped; that's not the index's concern.
+                    let _ = tx.send(t);
+                }
+            }
+        }
+    }
+
+    /// Returns the instant at which authorizations' time windows must next be updated, if any
+    /// authorization is pending or has yet to expire.
+    pub(crate) fn authz_update_deadline(&self) -> Option<time::Instant> {
+        let at = SystemTime::from(self.next_authz_update?);
+        let delay = at.duration_since(self.clock.now()).unwrap_or_default();
+        Some(time::Instant::now() + delay)
+    }
+
+    fn schedule_authz_update(&mut self, at: Option<Time>) {
+        self.next_authz_update = earliest(self.next_authz_update, at);
+    }
+
+    fn now(&self) -> Time {
+        self.clock.now().into()
     }
 
     #[instrument(
@@ -121,9 +385,34 @@ impl Index {
             let name = authz.name();
             ns.servers.remove_authz(name.as_str());
             ns.authzs.delete(name.as_str());
+            ns.relink_rollouts();
+            self.publish_policy(authz.namespace().unwrap().as_str());
         }
     }
 
+    /// Sets the identity domains, rebuilding all authorizations if they changed.
+    pub(crate) fn set_identity_domains(&mut self, domains: IdentityDomains) -> Result<()> {
+        if domains == self.identity_domains {
+            return Ok(());
+        }
+        debug!(?domains, "Updating identity domains");
+        self.lookups.set_identity_domains(domains.clone());
+        self.identity_domains = domains;
+
+        let authzs = self
+            .namespaces
+            .iter()
+            .flat_map(|(_, ns)| ns.authzs.resources.values().cloned())
+            .collect::<Vec<_>>();
+        let mut result = Ok(());
+        for authz in authzs.into_iter() {
+            if let Err(e) = self.apply_authz(authz) {
+                result = Err(e);
+            }
+        }
+        result
+    }
+
     #[instrument(skip(self, authzs))]
     pub(crate) fn reset_authzs(&mut self, authzs: Vec<polixy::ServerAuthorization>) -> Result<()> {
         let mut prior = self
@@ -153,6 +442,8 @@ impl Index {
                     ns.servers.remove_authz(&name);
                     ns.authzs.delete(&name);
                 }
+                ns.relink_rollouts();
+                self.publish_policy(&ns_name);
             }
         }
 
@@ -160,7 +451,19 @@ impl Index {
     }
 }
 
-fn mk_authz(srv: polixy::authz::ServerAuthorization, domain: &str) -> Result<Authz> {
+fn earliest(a: Option<Time>, b: Option<Time>) -> Option<Time> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+fn mk_authz(
+    srv: polixy::authz::ServerAuthorization,
+    domains: &IdentityDomains,
+    client_pods: &ClientPodIndex,
+    now: Time,
+) -> Result<Authz> {
     let polixy::authz::ServerAuthorization { metadata, spec, .. } = srv;
 
     let servers = {
@@ -173,7 +476,28 @@ fn mk_authz(srv: polixy::authz::ServerAuthorization, domain: &str) -> Result<Aut
         }
     };
 
-    let networks = if let Some(nets) = spec.client.networks {
+    let window = Window {
+        not_before: spec.not_before,
+        not_after: spec.not_after,
+    };
+    if let (Some(nb), Some(na)) = (window.not_before, window.not_after) {
+        if na <= nb {
+            bail!("authorization expires before it takes effect");
+        }
+    }
+
+    let ns = metadata.namespace.as_deref().unwrap_or_default();
+    let selected_pods = ClientPodSelector::from_client(ns, &spec.client);
+    if selected_pods.is_some() && spec.client.networks.is_some() {
+        bail!("client networks may not be combined with pod or namespace selectors");
+    }
+    if selected_pods.is_some() && !client_pods.is_enabled() {
+        bail!("client pod and namespace selectors are not enabled");
+    }
+
+    let networks = if let Some(ref selector) = selected_pods {
+        client_pods.select(selector)
+    } else if let Some(nets) = spec.client.networks {
         nets.into_iter()
             .map(|polixy::authz::Network { cidr, except }| {
                 let net = cidr.parse::<IpNet>()?;
@@ -200,22 +524,82 @@ fn mk_authz(srv: polixy::authz::ServerAuthorization, domain: &str) -> Result<Aut
             .client
             .mesh_tls
             .ok_or_else(|| anyhow!("client mtls missing"))?;
-        mk_mtls_authn(&metadata, mtls, domain)?
+        mk_mtls_authn(&metadata, mtls, domains)?
     };
 
+    let routes = spec
+        .routes
+        .unwrap_or_default()
+        .into_iter()
+        .map(mk_route)
+        .collect::<Result<Vec<_>>>()?;
+
+    let rollout = spec
+        .rollout
+        .map(Rollout::from_spec)
+        .transpose()?
+        .map(Arc::new);
+
     Ok(Authz {
         servers,
         clients: ClientAuthorization {
             networks,
             authentication,
         },
+        routes,
+        client_pods: selected_pods,
+        window,
+        rollout,
+        state: window.state(now),
     })
 }
 
+fn mk_route(route: polixy::authz::HttpRoute) -> Result<HttpRouteMatch> {
+    let polixy::authz::HttpRoute {
+        path,
+        path_prefix,
+        methods,
+        grpc,
+    } = route;
+
+    if let Some(polixy::authz::GrpcRoute { service, method }) = grpc {
+        if path.is_some() || path_prefix.is_some() || methods.is_some() {
+            bail!("gRPC routes may not specify paths or methods");
+        }
+        if service.is_empty() {
+            bail!("gRPC routes must specify a service");
+        }
+        return Ok(HttpRouteMatch::grpc(&service, method.as_deref()));
+    }
+
+    let path = match (path, path_prefix) {
+        (Some(p), None) => Some(PathMatch::Exact(p)),
+        (None, Some(p)) => Some(PathMatch::Prefix(p)),
+        (None, None) => None,
+        (Some(_), Some(_)) => bail!("routes may not specify both a path and a path prefix"),
+    };
+    if let Some(PathMatch::Exact(ref p)) | Some(PathMatch::Prefix(ref p)) = path {
+        if !p.starts_with('/') {
+            bail!("route paths must be absolute: {}", p);
+        }
+    }
+
+    let methods = methods
+        .unwrap_or_default()
+        .into_iter()
+        .map(|m| m.to_ascii_uppercase())
+        .collect::<Vec<_>>();
+    if path.is_none() && methods.is_empty() {
+        bail!("routes must specify a path, path prefix, methods, or gRPC service");
+    }
+
+    Ok(HttpRouteMatch { path, methods })
+}
+
 fn mk_mtls_authn(
     metadata: &k8s::ObjectMeta,
     mtls: MeshTls,
-    domain: &str,
+    domains: &IdentityDomains,
 ) -> Result<ClientAuthentication> {
     if mtls.unauthenticated_tls {
         return Ok(ClientAuthentication::TlsUnauthenticated);
@@ -224,21 +608,9 @@ fn mk_mtls_authn(
     let mut identities = Vec::new();
 
     for id in mtls.identities.into_iter() {
-        if id == "*" {
-            debug!(suffix = %id, "Authenticated");
-            identities.push(IdentityMatch::Suffix(vec![]));
-        } else if id.starts_with("*.") {
-            debug!(suffix = %id, "Authenticated");
-            let mut parts = id.split('.');
-            let star = parts.next();
-            debug_assert_eq!(star, Some("*"));
-            identities.push(IdentityMatch::Suffix(
-                parts.map(|p| p.to_string()).collect::<Vec<_>>(),
-            ));
-        } else {
-            debug!(%id, "Authenticated");
-            identities.push(IdentityMatch::Name(id));
-        }
+        let id = id.parse::<IdentityMatch>()?;
+        debug!(%id, "Authenticated");
+        identities.push(id);
     }
 
     for sa in mtls.service_accounts.into_iter() {
@@ -246,8 +618,8 @@ fn mk_mtls_authn(
         let ns = sa
             .namespace
             .unwrap_or_else(|| metadata.namespace.clone().unwrap());
-        debug!(ns = %ns, serviceaccount = %name, "Authenticated");
-        let n = format!("{}.{}.serviceaccount.identity.linkerd.{}", name, ns, domain);
+        debug!(ns = %ns, serviceaccount = %name, cluster = ?sa.cluster, "Authenticated");
+        let n = domains.service_account(&ns, &name, sa.cluster.as_deref())?;
         identities.push(IdentityMatch::Name(n));
     }
 
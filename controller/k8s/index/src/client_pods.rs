@@ -0,0 +1,259 @@
+use crate::Index;
+use anyhow::Result;
+use polixy_controller_core::{IpNet, NetworkMatch};
+use polixy_controller_k8s_api::{self as k8s, polixy, ResourceExt};
+use std::{
+    collections::{BTreeSet, HashMap},
+    net::IpAddr,
+    sync::Arc,
+};
+use tracing::{debug, instrument, warn};
+
+/// Indexes the labels and IPs of all pods, including unmeshed pods, and the labels of namespaces so
+/// that authorizations may select clients by label.
+#[derive(Debug, Default)]
+pub(crate) struct ClientPodIndex {
+    /// Pods by namespace and name.
+    pods: HashMap<String, HashMap<String, ClientPod>>,
+    namespaces: HashMap<String, k8s::Labels>,
+
+    /// Set when pods and namespaces aren't watched, so authorizations may not select clients.
+    disabled: bool,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct ClientPod {
+    labels: k8s::Labels,
+    ips: Vec<IpAddr>,
+}
+
+/// Selects an authorization's client pods, as a NetworkPolicy peer does.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct ClientPodSelector {
+    /// The authorization's namespace, in which pods are selected unless namespaces are selected.
+    namespace: String,
+    pods: Option<Arc<k8s::labels::Selector>>,
+    namespaces: Option<Arc<k8s::labels::Selector>>,
+}
+
+// === impl ClientPodIndex ===
+
+impl ClientPodIndex {
+    /// Creates an index for a controller that doesn't watch client pods.
+    pub fn disabled() -> Self {
+        Self {
+            disabled: true,
+            ..Self::default()
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.disabled
+    }
+
+    /// Updates a pod, returning whether its labels or IPs changed.
+    fn apply_pod(&mut self, pod: k8s::Pod) -> bool {
+        let ns = pod.namespace().expect("pod must have a namespace");
+        let name = pod.name();
+        let client = match ClientPod::from_pod(pod) {
+            Some(client) => client,
+            None => return self.delete_pod(&ns, &name),
+        };
+
+        let pods = self.pods.entry(ns).or_default();
+        if pods.get(&name) == Some(&client) {
+            return false;
+        }
+        pods.insert(name, client);
+        true
+    }
+
+    /// Removes a pod, returning whether it was indexed.
+    fn delete_pod(&mut self, ns: &str, name: &str) -> bool {
+        if let Some(pods) = self.pods.get_mut(ns) {
+            let removed = pods.remove(name).is_some();
+            if pods.is_empty() {
+                self.pods.remove(ns);
+            }
+            return removed;
+        }
+        false
+    }
+
+    /// Updates a namespace's labels, returning whether they changed.
+    fn apply_namespace(&mut self, ns: k8s::Namespace) -> bool {
+        let name = ns.name();
+        let labels = k8s::Labels::from(ns.metadata.labels);
+        if self.namespaces.get(&name) == Some(&labels) {
+            return false;
+        }
+        self.namespaces.insert(name, labels);
+        true
+    }
+
+    /// Returns the networks of all selected pods.
+    pub fn select(&self, selector: &ClientPodSelector) -> Vec<NetworkMatch> {
+        let namespaces = match selector.namespaces {
+            None => vec![selector.namespace.as_str()],
+            Some(ref sel) => self
+                .namespaces
+                .iter()
+                .filter(|(_, labels)| sel.matches(labels))
+                .map(|(name, _)| name.as_str())
+                .collect(),
+        };
+
+        namespaces
+            .into_iter()
+            .filter_map(|ns| self.pods.get(ns))
+            .flat_map(|pods| pods.values())
+            .filter(|pod| {
+                selector
+                    .pods
+                    .as_ref()
+                    .map(|sel| sel.matches(&pod.labels))
+                    .unwrap_or(true)
+            })
+            .flat_map(|pod| pod.ips.iter().copied())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .map(|ip| IpNet::from(ip).into())
+            .collect()
+    }
+}
+
+// === impl ClientPod ===
+
+impl ClientPod {
+    /// Reads a pod's labels and IPs, unless its IPs can't identify it.
+    ///
+    /// Host-networked pods share their node's IPs, and the IPs of pods that have terminated may be
+    /// reused by other pods.
+    fn from_pod(pod: k8s::Pod) -> Option<Self> {
+        if pod.spec.as_ref().and_then(|s| s.host_network) == Some(true) {
+            return None;
+        }
+        let status = pod.status?;
+        if matches!(status.phase.as_deref(), Some("Succeeded") | Some("Failed")) {
+            return None;
+        }
+
+        let ips = status
+            .pod_ips
+            .iter()
+            .filter_map(|ip| ip.ip.as_deref())
+            .chain(status.pod_ip.as_deref())
+            .filter_map(|ip| match ip.parse::<IpAddr>() {
+                Ok(ip) => Some(ip),
+                Err(error) => {
+                    warn!(%error, %ip, "Ignoring invalid pod IP");
+                    None
+                }
+            })
+            .collect::<BTreeSet<_>>();
+        if ips.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            labels: pod.metadata.labels.into(),
+            ips: ips.into_iter().collect(),
+        })
+    }
+}
+
+// === impl ClientPodSelector ===
+
+impl ClientPodSelector {
+    /// Reads an authorization's client pod selection, if it selects pods or namespaces.
+    pub fn from_client(namespace: &str, client: &polixy::authz::Client) -> Option<Self> {
+        if client.pod_selector.is_none() && client.namespace_selector.is_none() {
+            return None;
+        }
+        Some(Self {
+            namespace: namespace.to_string(),
+            pods: client.pod_selector.clone().map(Arc::new),
+            namespaces: client.namespace_selector.clone().map(Arc::new),
+        })
+    }
+}
+
+// === impl Index ===
+
+impl Index {
+    #[instrument(
+        skip(self, pod),
+        fields(
+            ns = ?pod.metadata.namespace,
+            name = ?pod.metadata.name,
+        )
+    )]
+    pub(crate) fn apply_client_pod(&mut self, pod: k8s::Pod) -> Result<()> {
+        if self.client_pods.apply_pod(pod) {
+            self.resolve_client_pods();
+        }
+        Ok(())
+    }
+
+    pub(crate) fn delete_client_pod(&mut self, pod: k8s::Pod) -> Result<()> {
+        let ns = pod.namespace().expect("pod must have a namespace");
+        if self.client_pods.delete_pod(&ns, &pod.name()) {
+            self.resolve_client_pods();
+        }
+        Ok(())
+    }
+
+    #[instrument(skip(self, pods))]
+    pub(crate) fn reset_client_pods(&mut self, pods: Vec<k8s::Pod>) -> Result<()> {
+        self.client_pods.pods.clear();
+        for pod in pods.into_iter() {
+            self.client_pods.apply_pod(pod);
+        }
+        self.resolve_client_pods();
+        Ok(())
+    }
+
+    #[instrument(skip(self, ns), fields(name = ?ns.metadata.name))]
+    pub(crate) fn apply_namespace(&mut self, ns: k8s::Namespace) -> Result<()> {
+        if self.client_pods.apply_namespace(ns) {
+            self.resolve_client_pods();
+        }
+        Ok(())
+    }
+
+    pub(crate) fn delete_namespace(&mut self, ns: k8s::Namespace) -> Result<()> {
+        if self.client_pods.namespaces.remove(&ns.name()).is_some() {
+            self.resolve_client_pods();
+        }
+        Ok(())
+    }
+
+    #[instrument(skip(self, namespaces))]
+    pub(crate) fn reset_namespaces(&mut self, namespaces: Vec<k8s::Namespace>) -> Result<()> {
+        self.client_pods.namespaces.clear();
+        for ns in namespaces.into_iter() {
+            self.client_pods.apply_namespace(ns);
+        }
+        self.resolve_client_pods();
+        Ok(())
+    }
+
+    /// Updates the networks of authorizations that select client pods, publishing the namespaces
+    /// whose authorizations changed.
+    fn resolve_client_pods(&mut self) {
+        let mut changed = Vec::new();
+        for (name, ns) in self.namespaces.index.iter_mut() {
+            if ns
+                .authzs
+                .resolve_client_pods(&mut ns.servers, &self.client_pods)
+            {
+                changed.push(name.clone());
+            }
+        }
+
+        for ns in changed.into_iter() {
+            debug!(%ns, "Client pods updated");
+            self.publish_policy(&ns);
+        }
+    }
+}
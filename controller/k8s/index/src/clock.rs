@@ -0,0 +1,19 @@
+use std::time::SystemTime;
+
+/// Provides the current time to the index, so that authorizations' time windows may be tested
+/// without depending on the system clock.
+pub(crate) trait Clock: Send + Sync + 'static {
+    fn now(&self) -> SystemTime;
+}
+
+/// Reads the system clock.
+#[derive(Copy, Clone, Debug, Default)]
+pub(crate) struct SystemClock;
+
+// === impl SystemClock ===
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
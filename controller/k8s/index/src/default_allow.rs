@@ -1,11 +1,13 @@
-use crate::ServerRx;
+use crate::{Index, ServerRx, ServerTx};
 use anyhow::{anyhow, Error, Result};
 use polixy_controller_core::{
     ClientAuthentication, ClientAuthorization, IdentityMatch, InboundServer, IpNet, NetworkMatch,
     ProxyProtocol,
 };
 use polixy_controller_k8s_api as k8s;
+use std::{collections::BTreeMap, sync::Arc};
 use tokio::{sync::watch, time};
+use tracing::debug;
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum DefaultAllow {
@@ -16,14 +18,34 @@ pub enum DefaultAllow {
     Deny,
 }
 
+/// Configures the networks that `cluster-*` default-allow policies permit.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ClusterNetworks {
+    /// A fixed set of networks.
+    Static(Vec<IpNet>),
+
+    /// The union of the service networks and all nodes' pod CIDRs, updated as nodes change.
+    Discover { service_networks: Vec<IpNet> },
+}
+
 /// Default server configs to use when no server matches.
 #[derive(Clone, Debug)]
 pub(crate) struct DefaultAllows {
-    all_authed_rx: ServerRx,
-    all_unauthed_rx: ServerRx,
-    cluster_authed_rx: ServerRx,
-    cluster_unauthed_rx: ServerRx,
-    deny_rx: ServerRx,
+    all_authed: DefaultPolicy,
+    all_unauthed: DefaultPolicy,
+    cluster_authed: DefaultPolicy,
+    cluster_unauthed: DefaultPolicy,
+    deny: DefaultPolicy,
+
+    cluster_nets: Vec<IpNet>,
+    detect_timeout: time::Duration,
+}
+
+/// A default policy's receiver and the sender that updates it when the configuration changes.
+#[derive(Clone, Debug)]
+struct DefaultPolicy {
+    tx: Arc<ServerTx>,
+    rx: ServerRx,
 }
 
 // === impl DefaultAllow ===
@@ -56,6 +78,19 @@ impl std::str::FromStr for DefaultAllow {
     }
 }
 
+impl From<k8s::polixy::server::DefaultAllow> for DefaultAllow {
+    fn from(da: k8s::polixy::server::DefaultAllow) -> Self {
+        use k8s::polixy::server::DefaultAllow as Api;
+        match da {
+            Api::AllAuthenticated => Self::AllAuthenticated,
+            Api::AllUnauthenticated => Self::AllUnauthenticated,
+            Api::ClusterAuthenticated => Self::ClusterAuthenticated,
+            Api::ClusterUnauthenticated => Self::ClusterUnauthenticated,
+            Api::Deny => Self::Deny,
+        }
+    }
+}
+
 impl std::fmt::Display for DefaultAllow {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -73,78 +108,184 @@ impl std::fmt::Display for DefaultAllow {
 impl DefaultAllows {
     /// Create default allow policy receivers.
     ///
-    /// These receivers are never updated. The senders are spawned onto a background task so that
-    /// the receivers continue to be live. The background task completes once all receivers are
-    /// dropped.
+    /// These receivers are updated when the cluster's networks or the detect timeout change. The
+    /// senders are spawned onto a background task so that the receivers continue to be live. The
+    /// background task completes once all receivers are dropped.
     pub fn spawn(cluster_nets: Vec<IpNet>, detect_timeout: time::Duration) -> Self {
-        let any_authenticated =
-            ClientAuthentication::TlsAuthenticated(vec![IdentityMatch::Suffix(vec![])]);
+        let mk = |mode| {
+            let (tx, rx) = watch::channel(mk_config(mode, &cluster_nets, detect_timeout));
+            DefaultPolicy {
+                tx: Arc::new(tx),
+                rx,
+            }
+        };
+        let allows = Self {
+            all_authed: mk(DefaultAllow::AllAuthenticated),
+            all_unauthed: mk(DefaultAllow::AllUnauthenticated),
+            cluster_authed: mk(DefaultAllow::ClusterAuthenticated),
+            cluster_unauthed: mk(DefaultAllow::ClusterUnauthenticated),
+            deny: mk(DefaultAllow::Deny),
+            cluster_nets,
+            detect_timeout,
+        };
+
+        // Ensure the senders are not dropped until all receivers are dropped.
+        let txs = allows
+            .policies()
+            .iter()
+            .map(|(_, p)| p.tx.clone())
+            .collect::<Vec<_>>();
+        tokio::spawn(async move {
+            futures::future::join_all(txs.iter().map(|tx| tx.closed())).await;
+        });
+
+        allows
+    }
 
-        let all_nets = [IpNet::V4(Default::default()), IpNet::V6(Default::default())];
+    /// Updates the networks of the `cluster-*` policies, returning whether they changed.
+    pub fn set_cluster_networks(&mut self, nets: Vec<IpNet>) -> bool {
+        if nets == self.cluster_nets {
+            return false;
+        }
+        debug!(networks = ?nets, "Updating cluster networks");
+        self.cluster_nets = nets;
+        self.publish();
+        true
+    }
+
+    /// Updates the protocol detection timeout of all policies, if it changed.
+    pub fn set_detect_timeout(&mut self, timeout: time::Duration) {
+        if timeout == self.detect_timeout {
+            return;
+        }
+        debug!(?timeout, "Updating detect timeout");
+        self.detect_timeout = timeout;
+        self.publish();
+    }
+
+    pub fn get(&self, mode: DefaultAllow) -> ServerRx {
+        match mode {
+            DefaultAllow::AllAuthenticated => self.all_authed.rx.clone(),
+            DefaultAllow::AllUnauthenticated => self.all_unauthed.rx.clone(),
+            DefaultAllow::ClusterAuthenticated => self.cluster_authed.rx.clone(),
+            DefaultAllow::ClusterUnauthenticated => self.cluster_unauthed.rx.clone(),
+            DefaultAllow::Deny => self.deny.rx.clone(),
+        }
+    }
+
+    /// Returns the authorizations of a default policy, so that servers may include them.
+    pub fn authorizations(&self, mode: DefaultAllow) -> BTreeMap<String, ClientAuthorization> {
+        self.get(mode).borrow().authorizations.clone()
+    }
+
+    fn policies(&self) -> [(DefaultAllow, &DefaultPolicy); 5] {
+        [
+            (DefaultAllow::AllAuthenticated, &self.all_authed),
+            (DefaultAllow::AllUnauthenticated, &self.all_unauthed),
+            (DefaultAllow::ClusterAuthenticated, &self.cluster_authed),
+            (DefaultAllow::ClusterUnauthenticated, &self.cluster_unauthed),
+            (DefaultAllow::Deny, &self.deny),
+        ]
+    }
+
+    /// Sends the policies that differ from the current configuration.
+    fn publish(&self) {
+        for (mode, policy) in self.policies().iter() {
+            let config = mk_config(*mode, &self.cluster_nets, self.detect_timeout);
+            if *policy.rx.borrow() != config {
+                // The index holds receivers, so updates can't fail.
+                let _ = policy.tx.send(config);
+            }
+        }
+    }
+}
 
-        let (all_authed_tx, all_authed_rx) = watch::channel(mk_detect_config(
+// === impl ClusterNetworks ===
+
+impl ClusterNetworks {
+    /// Returns the cluster's networks before any nodes are known.
+    pub(crate) fn initial(&self) -> Vec<IpNet> {
+        match self {
+            Self::Static(nets) => nets.clone(),
+            Self::Discover { service_networks } => IpNet::aggregate(service_networks),
+        }
+    }
+}
+
+// === impl Index ===
+
+impl Index {
+    /// Updates the cluster networks from the nodes' pod networks, if they are discovered.
+    pub(crate) fn discover_cluster_networks(&mut self) {
+        if let ClusterNetworks::Discover {
+            ref service_networks,
+        } = self.cluster_networks
+        {
+            let nets = service_networks
+                .iter()
+                .copied()
+                .chain(self.nodes.pod_networks())
+                .collect::<Vec<_>>();
+            if self
+                .default_allows
+                .set_cluster_networks(IpNet::aggregate(&nets))
+            {
+                self.update_server_default_allows();
+            }
+        }
+    }
+
+    /// Updates the authorizations that servers include from default policies.
+    pub(crate) fn update_server_default_allows(&mut self) {
+        for ns in self.namespaces.index.values_mut() {
+            ns.servers.set_default_allows(&self.default_allows);
+        }
+    }
+}
+
+fn mk_config(
+    mode: DefaultAllow,
+    cluster_nets: &[IpNet],
+    detect_timeout: time::Duration,
+) -> InboundServer {
+    let any_authenticated =
+        ClientAuthentication::TlsAuthenticated(vec![IdentityMatch::Suffix(vec![])]);
+    let all_nets = [IpNet::V4(Default::default()), IpNet::V6(Default::default())];
+
+    match mode {
+        DefaultAllow::AllAuthenticated => mk_detect_config(
             "_all_authed",
             detect_timeout,
             all_nets.iter().cloned(),
-            any_authenticated.clone(),
-        ));
-
-        let (all_unauthed_tx, all_unauthed_rx) = watch::channel(mk_detect_config(
+            any_authenticated,
+        ),
+        DefaultAllow::AllUnauthenticated => mk_detect_config(
             "_all_unauthed",
             detect_timeout,
             all_nets.iter().cloned(),
             ClientAuthentication::Unauthenticated,
-        ));
-
-        let (cluster_authed_tx, cluster_authed_rx) = watch::channel(mk_detect_config(
+        ),
+        DefaultAllow::ClusterAuthenticated => mk_detect_config(
             "_cluster_authed",
             detect_timeout,
             cluster_nets.iter().cloned(),
             any_authenticated,
-        ));
-
-        let (cluster_unauthed_tx, cluster_unauthed_rx) = watch::channel(mk_detect_config(
+        ),
+        DefaultAllow::ClusterUnauthenticated => mk_detect_config(
             "_cluster_unauthed",
             detect_timeout,
-            cluster_nets.into_iter(),
+            cluster_nets.iter().cloned(),
             ClientAuthentication::Unauthenticated,
-        ));
-
-        let (deny_tx, deny_rx) = watch::channel(InboundServer {
+        ),
+        DefaultAllow::Deny => InboundServer {
             protocol: ProxyProtocol::Detect {
                 timeout: detect_timeout,
             },
             authorizations: Default::default(),
-        });
-
-        // Ensure the senders are not dropped until all receivers are dropped.
-        tokio::spawn(async move {
-            tokio::join!(
-                all_authed_tx.closed(),
-                all_unauthed_tx.closed(),
-                cluster_authed_tx.closed(),
-                cluster_unauthed_tx.closed(),
-                deny_tx.closed(),
-            );
-        });
-
-        Self {
-            all_authed_rx,
-            all_unauthed_rx,
-            cluster_authed_rx,
-            cluster_unauthed_rx,
-            deny_rx,
-        }
-    }
-
-    pub fn get(&self, mode: DefaultAllow) -> ServerRx {
-        match mode {
-            DefaultAllow::AllAuthenticated => self.all_authed_rx.clone(),
-            DefaultAllow::AllUnauthenticated => self.all_unauthed_rx.clone(),
-            DefaultAllow::ClusterAuthenticated => self.cluster_authed_rx.clone(),
-            DefaultAllow::ClusterUnauthenticated => self.cluster_unauthed_rx.clone(),
-            DefaultAllow::Deny => self.deny_rx.clone(),
-        }
+            routes: Default::default(),
+            undeclared: false,
+            health_check_source: None,
+        },
     }
 }
 
@@ -169,6 +310,9 @@ fn mk_detect_config(
     InboundServer {
         protocol: ProxyProtocol::Detect { timeout },
         authorizations: Some((name.to_string(), authz)).into_iter().collect(),
+        routes: Default::default(),
+        undeclared: false,
+        health_check_source: None,
     }
 }
 
@@ -0,0 +1,287 @@
+//! Renders the index as a JSON data document, e.g. to be evaluated by OPA.
+//!
+//! All maps are ordered so that the same index state always renders the same document.
+
+use polixy_controller_core::{
+    ClientAuthentication, ClientAuthorization, HttpRouteMatch, IdentityMatch, InboundServer,
+    PortProtocol, ProxyProtocol,
+};
+use polixy_controller_k8s_api::{
+    labels,
+    polixy::{
+        authz::Time,
+        server::{DefaultAllow, Port},
+    },
+};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// The indexed policy of all namespaces.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct PolicyData {
+    pub namespaces: BTreeMap<String, NamespaceData>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct NamespaceData {
+    pub servers: BTreeMap<String, ServerData>,
+    pub authorizations: BTreeMap<String, AuthorizationData>,
+
+    /// The effective configuration of each pod's ports, by pod name.
+    pub pods: BTreeMap<String, PodData>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerData {
+    pub port: Port,
+
+    /// Omitted for TCP servers.
+    #[serde(skip_serializing_if = "is_tcp")]
+    pub port_protocol: PortProtocol,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub container: Option<String>,
+
+    pub pod_selector: labels::Selector,
+    pub protocol: ProtocolData,
+
+    /// The names of the authorizations that select this server, including route-scoped ones.
+    pub authorizations: Vec<String>,
+
+    /// Omitted unless the server admits clients by a default-allow policy.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_allow: Option<DefaultAllow>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthorizationData {
+    pub server: ServerSelectorData,
+    pub client: ClientData,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub routes: Vec<HttpRouteMatch>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub not_before: Option<Time>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub not_after: Option<Time>,
+
+    /// Whether the authorization is in effect. Only authorizations in effect are included in
+    /// servers' policies.
+    #[serde(skip_serializing_if = "AuthorizationState::is_active")]
+    pub state: AuthorizationState,
+
+    /// If set, the authorization only applies to the selected pods.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rollout: Option<RolloutData>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RolloutData {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pod_selector: Option<labels::Selector>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub percent: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub replaces: Option<String>,
+}
+
+/// Describes whether an authorization is in effect, per its `notBefore` and `notAfter` times.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum AuthorizationState {
+    Pending,
+    Active,
+    Expired,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ServerSelectorData {
+    Name(String),
+    Selector(labels::Selector),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PodData {
+    pub labels: BTreeMap<String, String>,
+
+    /// The effective inbound server of each discoverable TCP port.
+    pub ports: BTreeMap<u16, InboundServerData>,
+
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub udp_ports: BTreeMap<u16, InboundServerData>,
+
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub sctp_ports: BTreeMap<u16, InboundServerData>,
+
+    /// The inbound server of ports that are neither declared nor selected by a server.
+    pub undeclared: InboundServerData,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct InboundServerData {
+    pub protocol: ProtocolData,
+    pub authorizations: BTreeMap<String, ClientData>,
+    pub routes: BTreeMap<String, RouteData>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct RouteData {
+    pub matches: Vec<HttpRouteMatch>,
+    pub client: ClientData,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum ProtocolData {
+    Detect {
+        #[serde(rename = "timeoutMs")]
+        timeout_ms: u64,
+    },
+    Http1,
+    Http2,
+    Grpc,
+    Opaque,
+    Tls,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ClientData {
+    /// Networks are rendered as CIDRs. A client that is in no network is not authorized.
+    pub networks: Vec<NetworkData>,
+    pub authentication: AuthenticationData,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct NetworkData {
+    pub net: String,
+    pub except: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum AuthenticationData {
+    Unauthenticated,
+    TlsUnauthenticated,
+
+    /// Identities must match exactly; a suffix, as a list of DNS labels, matches identities that
+    /// have at least one more label. A SPIFFE prefix, as a trust domain followed by path segments,
+    /// matches SPIFFE IDs that have at least one more path segment.
+    TlsAuthenticated {
+        identities: Vec<String>,
+        suffixes: Vec<Vec<String>>,
+        #[serde(rename = "spiffePrefixes")]
+        spiffe_prefixes: Vec<Vec<String>>,
+    },
+}
+
+fn is_tcp(p: &PortProtocol) -> bool {
+    *p == PortProtocol::Tcp
+}
+
+// === impl InboundServerData ===
+
+impl From<&InboundServer> for InboundServerData {
+    fn from(srv: &InboundServer) -> Self {
+        Self {
+            protocol: (&srv.protocol).into(),
+            authorizations: srv
+                .authorizations
+                .iter()
+                .map(|(name, authz)| (name.clone(), authz.into()))
+                .collect(),
+            routes: srv
+                .routes
+                .iter()
+                .map(|(name, route)| {
+                    let data = RouteData {
+                        matches: route.matches.clone(),
+                        client: (&route.authorization).into(),
+                    };
+                    (name.clone(), data)
+                })
+                .collect(),
+        }
+    }
+}
+
+// === impl ProtocolData ===
+
+impl From<&ProxyProtocol> for ProtocolData {
+    fn from(p: &ProxyProtocol) -> Self {
+        match p {
+            ProxyProtocol::Detect { timeout } => Self::Detect {
+                timeout_ms: timeout.as_millis() as u64,
+            },
+            ProxyProtocol::Http1 => Self::Http1,
+            ProxyProtocol::Http2 => Self::Http2,
+            ProxyProtocol::Grpc => Self::Grpc,
+            ProxyProtocol::Opaque => Self::Opaque,
+            ProxyProtocol::Tls => Self::Tls,
+        }
+    }
+}
+
+// === impl AuthorizationState ===
+
+impl AuthorizationState {
+    fn is_active(&self) -> bool {
+        *self == Self::Active
+    }
+}
+
+// === impl ClientData ===
+
+impl From<&ClientAuthorization> for ClientData {
+    fn from(authz: &ClientAuthorization) -> Self {
+        let networks = authz
+            .networks
+            .iter()
+            .map(|n| NetworkData {
+                net: n.net.to_string(),
+                except: n.except.iter().map(ToString::to_string).collect(),
+            })
+            .collect();
+
+        let authentication = match authz.authentication {
+            ClientAuthentication::Unauthenticated => AuthenticationData::Unauthenticated,
+            ClientAuthentication::TlsUnauthenticated => AuthenticationData::TlsUnauthenticated,
+            ClientAuthentication::TlsAuthenticated(ref ids) => {
+                let mut identities = vec![];
+                let mut suffixes = vec![];
+                let mut spiffe_prefixes = vec![];
+                for id in ids.iter() {
+                    match id {
+                        IdentityMatch::Name(n) => identities.push(n.clone()),
+                        IdentityMatch::Suffix(s) => suffixes.push(s.clone()),
+                        IdentityMatch::SpiffePrefix(p) => spiffe_prefixes.push(
+                            Some(p.trust_domain.clone())
+                                .into_iter()
+                                .chain(p.path.iter().cloned())
+                                .collect(),
+                        ),
+                    }
+                }
+                identities.sort();
+                identities.dedup();
+                suffixes.sort();
+                suffixes.dedup();
+                spiffe_prefixes.sort();
+                spiffe_prefixes.dedup();
+                AuthenticationData::TlsAuthenticated {
+                    identities,
+                    suffixes,
+                    spiffe_prefixes,
+                }
+            }
+        };
+
+        Self {
+            networks,
+            authentication,
+        }
+    }
+}
@@ -0,0 +1,111 @@
+use anyhow::{anyhow, bail, Error, Result};
+use std::collections::HashMap;
+
+/// The identity domains of the local cluster and of linked clusters, by cluster name.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IdentityDomains {
+    local: String,
+    clusters: HashMap<String, String>,
+}
+
+/// A linked cluster's name and identity domain, parsed from `<name>=<identity-domain>`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LinkedCluster {
+    pub name: String,
+    pub identity_domain: String,
+}
+
+// === impl IdentityDomains ===
+
+impl IdentityDomains {
+    pub fn new(local: String) -> Self {
+        Self {
+            local,
+            clusters: HashMap::default(),
+        }
+    }
+
+    /// Adds a linked cluster's identity domain. Each cluster may only be linked once.
+    pub fn link(&mut self, cluster: LinkedCluster) -> Result<()> {
+        if self.clusters.contains_key(&cluster.name) {
+            bail!("cluster {} is linked more than once", cluster.name);
+        }
+        self.clusters.insert(cluster.name, cluster.identity_domain);
+        Ok(())
+    }
+
+    /// Returns the identity of a service account, in the local cluster if no cluster is specified.
+    pub(crate) fn service_account(
+        &self,
+        ns: &str,
+        name: &str,
+        cluster: Option<&str>,
+    ) -> Result<String> {
+        let domain = match cluster {
+            None => &self.local,
+            Some(c) => self
+                .clusters
+                .get(c)
+                .ok_or_else(|| anyhow!("unknown cluster: {}", c))?,
+        };
+        Ok(format!(
+            "{}.{}.serviceaccount.identity.linkerd.{}",
+            name, ns, domain
+        ))
+    }
+}
+
+// === impl LinkedCluster ===
+
+impl std::str::FromStr for LinkedCluster {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (name, domain) = s.split_once('=').ok_or_else(|| {
+            anyhow!(
+                "linked clusters must be in the form <name>=<identity-domain>: {}",
+                s
+            )
+        })?;
+        if name.is_empty() || domain.is_empty() {
+            bail!(
+                "linked clusters must have a name and an identity domain: {}",
+                s
+            );
+        }
+        Ok(Self {
+            name: name.to_string(),
+            identity_domain: domain.to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_linked_clusters() {
+        let mut domains = IdentityDomains::new("cluster.local".into());
+        domains
+            .link("east=east.example.com".parse().unwrap())
+            .unwrap();
+        assert!(domains
+            .link("east=west.example.com".parse().unwrap())
+            .is_err());
+
+        assert_eq!(
+            domains.service_account("ns", "web", None).unwrap(),
+            "web.ns.serviceaccount.identity.linkerd.cluster.local"
+        );
+        assert_eq!(
+            domains.service_account("ns", "web", Some("east")).unwrap(),
+            "web.ns.serviceaccount.identity.linkerd.east.example.com"
+        );
+        assert!(domains.service_account("ns", "web", Some("west")).is_err());
+
+        for s in &["east", "=east.example.com", "east="] {
+            assert!(s.parse::<LinkedCluster>().is_err(), "{}", s);
+        }
+    }
+}
@@ -8,27 +8,46 @@
 #![forbid(unsafe_code)]
 
 mod authz;
+mod client_pods;
+mod clock;
 mod default_allow;
+pub mod export;
+mod identity;
 mod lookup;
 mod namespace;
 mod node;
 mod pod;
+mod rollout;
 mod server;
 #[cfg(test)]
 mod tests;
+mod undeclared;
 
-pub use self::{default_allow::DefaultAllow, lookup::Reader};
+pub use self::{
+    authz::AuthzTransition,
+    default_allow::{ClusterNetworks, DefaultAllow},
+    identity::{IdentityDomains, LinkedCluster},
+    lookup::Reader,
+    node::KubeletSource,
+    pod::PortConflict,
+    undeclared::UndeclaredPorts,
+};
 use self::{
+    client_pods::ClientPodIndex,
+    clock::{Clock, SystemClock},
     default_allow::DefaultAllows,
     namespace::{Namespace, NamespaceIndex},
     node::NodeIndex,
     server::SrvIndex,
 };
-use anyhow::{Context, Error};
-use polixy_controller_core::{InboundServer, IpNet};
-use polixy_controller_k8s_api::{self as k8s, ResourceExt};
+use anyhow::{Context, Error, Result};
+use polixy_controller_core::InboundServer;
+use polixy_controller_k8s_api::{self as k8s, polixy::authz::Time, ResourceExt};
 use std::sync::Arc;
-use tokio::{sync::watch, time};
+use tokio::{
+    sync::{mpsc, watch},
+    time,
+};
 use tracing::{debug, instrument, warn};
 
 /// Watches a server's configuration for server/authorization changes.
@@ -42,13 +61,32 @@ type ServerRxRx = watch::Receiver<ServerRx>;
 /// Watches a pod port's for a new `ServerRx`.
 type ServerRxTx = watch::Sender<ServerRx>;
 
+/// Configures the policies that the index serves.
+///
+/// Identity domains, cluster networks, the default-allow policy, and the detect timeout may be
+/// updated while the index runs. Undeclared port handling and the kubelet source are fixed at
+/// startup.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Config {
+    pub cluster_networks: ClusterNetworks,
+    pub identity_domains: IdentityDomains,
+    pub default_allow: DefaultAllow,
+    pub undeclared_ports: UndeclaredPorts,
+    pub kubelet_source: KubeletSource,
+    pub detect_timeout: time::Duration,
+}
+
+/// Indexes resources from the given watches.
+///
+/// Authorizations that take effect or expire as their time windows open and close are reported on
+/// `authz_transitions`, and ports that pods declare in more than one container are reported on
+/// `port_conflicts`.
 pub fn index(
     watches: impl Into<k8s::ResourceWatches>,
     ready: watch::Sender<bool>,
-    cluster_networks: Vec<IpNet>,
-    identity_domain: String,
-    default_mode: DefaultAllow,
-    detect_timeout: time::Duration,
+    config: watch::Receiver<Config>,
+    authz_transitions: mpsc::UnboundedSender<AuthzTransition>,
+    port_conflicts: mpsc::UnboundedSender<PortConflict>,
 ) -> (
     lookup::Reader,
     impl std::future::Future<Output = anyhow::Error>,
@@ -57,14 +95,14 @@ pub fn index(
 
     // Watches Nodes, Pods, Servers, and Authorizations to update the lookup map
     // with an entry for each linkerd-injected pod.
-    let idx = Index::new(
-        writer,
-        cluster_networks,
-        identity_domain,
-        default_mode,
-        detect_timeout,
-    );
-    let task = idx.index(watches.into(), ready);
+    let watches = watches.into();
+    let mut idx = Index::new(writer, config.borrow().clone())
+        .with_authz_transitions(authz_transitions)
+        .with_port_conflicts(port_conflicts);
+    if watches.client_pods.is_none() {
+        idx.client_pods = ClientPodIndex::disabled();
+    }
+    let task = idx.index(watches, ready, config);
 
     (reader, task)
 }
@@ -76,10 +114,31 @@ struct Index {
     /// Cached Node IPs.
     nodes: NodeIndex,
 
-    identity_domain: String,
+    /// Resolves service account references to identities.
+    identity_domains: IdentityDomains,
+
+    /// Resolves authorizations' client pod selectors to networks.
+    client_pods: ClientPodIndex,
 
     default_allows: DefaultAllows,
 
+    /// Configures how the networks of the `cluster-*` default-allow policies are determined.
+    cluster_networks: ClusterNetworks,
+
+    undeclared_ports: UndeclaredPorts,
+
+    /// Determines whether authorizations are in effect.
+    clock: Arc<dyn Clock>,
+
+    /// The earliest time at which an authorization takes effect or expires.
+    next_authz_update: Option<Time>,
+
+    /// Reports authorizations that take effect or expire, if set.
+    authz_transitions: Option<mpsc::UnboundedSender<AuthzTransition>>,
+
+    /// Reports ports that pods declare in more than one container, if set.
+    port_conflicts: Option<mpsc::UnboundedSender<PortConflict>>,
+
     lookups: lookup::Writer,
 }
 
@@ -94,32 +153,66 @@ enum ServerSelector {
 
 impl Index {
     pub(crate) fn new(
-        lookups: lookup::Writer,
-        cluster_nets: Vec<IpNet>,
-        identity_domain: String,
-        default_allow: DefaultAllow,
-        detect_timeout: time::Duration,
+        mut lookups: lookup::Writer,
+        Config {
+            cluster_networks,
+            identity_domains,
+            default_allow,
+            undeclared_ports,
+            kubelet_source,
+            detect_timeout,
+        }: Config,
     ) -> Self {
         // Create a common set of receivers for all supported default policies.
         //
         // XXX We shouldn't spawn in the constructor if we can avoid it. Instead, it seems best if
         // we can avoid having to wire this into the pods at all and lazily bind the default policy
         // at discovery time?
-        let default_allows = DefaultAllows::spawn(cluster_nets, detect_timeout);
+        let default_allows = DefaultAllows::spawn(cluster_networks.initial(), detect_timeout);
 
         // Provide the cluster-wide default-allow policy to the namespace index so that it may be
         // used when a workload-level annotation is not set.
         let namespaces = NamespaceIndex::new(default_allow);
 
+        lookups.set_identity_domains(identity_domains.clone());
+
         Self {
             lookups,
             namespaces,
-            identity_domain,
+            identity_domains,
+            client_pods: ClientPodIndex::default(),
             default_allows,
-            nodes: NodeIndex::default(),
+            cluster_networks,
+            undeclared_ports,
+            nodes: NodeIndex::new(kubelet_source),
+            clock: Arc::new(SystemClock),
+            next_authz_update: None,
+            authz_transitions: None,
+            port_conflicts: None,
         }
     }
 
+    /// Reports authorizations that take effect or expire on the given channel.
+    pub(crate) fn with_authz_transitions(
+        mut self,
+        tx: mpsc::UnboundedSender<AuthzTransition>,
+    ) -> Self {
+        self.authz_transitions = Some(tx);
+        self
+    }
+
+    /// Reports ports that pods declare in more than one container on the given channel.
+    pub(crate) fn with_port_conflicts(mut self, tx: mpsc::UnboundedSender<PortConflict>) -> Self {
+        self.port_conflicts = Some(tx);
+        self
+    }
+
+    #[cfg(test)]
+    pub(crate) fn with_clock(mut self, clock: impl Clock) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
     /// Drives indexing for all resource types.
     ///
     /// This is all driven on a single task, so it's not necessary for any of the indexing logic to
@@ -127,21 +220,29 @@ impl Index {
     ///
     /// All updates are atomically published to the shared `lookups` map after indexing occurs; but
     /// the indexing task is solely responsible for mutating it.
-    #[instrument(skip(self, resources, ready_tx), fields(result))]
+    #[instrument(skip(self, resources, ready_tx, config_rx), fields(result))]
     pub(crate) async fn index(
         mut self,
         resources: k8s::ResourceWatches,
         ready_tx: watch::Sender<bool>,
+        mut config_rx: watch::Receiver<Config>,
     ) -> Error {
         let k8s::ResourceWatches {
             mut nodes_rx,
             mut pods_rx,
             mut servers_rx,
             mut authorizations_rx,
+            client_pods,
         } = resources;
+        let (mut client_pods_rx, mut namespaces_rx) = match client_pods {
+            Some(w) => (Some(w.client_pods_rx), Some(w.namespaces_rx)),
+            None => (None, None),
+        };
 
         let mut ready = false;
+        let mut config_live = true;
         loop {
+            let authz_deadline = self.authz_update_deadline();
             let res = tokio::select! {
                 // Track the kubelet IPs for all nodes.
                 up = nodes_rx.recv() => match up {
@@ -173,6 +274,39 @@ impl Index {
                     }
                     k8s::Event::Restarted(authzs) => self.reset_authzs(authzs).context("resetting authorizations"),
                 },
+
+                // Track the labels and IPs of all pods and namespaces so that authorizations may
+                // select client pods, if enabled.
+                up = async { client_pods_rx.as_mut().unwrap().recv().await }, if client_pods_rx.is_some() => match up {
+                    k8s::Event::Applied(pod) => self.apply_client_pod(pod).context("applying a client pod"),
+                    k8s::Event::Deleted(pod) => self.delete_client_pod(pod).context("deleting a client pod"),
+                    k8s::Event::Restarted(pods) => self.reset_client_pods(pods).context("resetting client pods"),
+                },
+
+                up = async { namespaces_rx.as_mut().unwrap().recv().await }, if namespaces_rx.is_some() => match up {
+                    k8s::Event::Applied(ns) => self.apply_namespace(ns).context("applying a namespace"),
+                    k8s::Event::Deleted(ns) => self.delete_namespace(ns).context("deleting a namespace"),
+                    k8s::Event::Restarted(nss) => self.reset_namespaces(nss).context("resetting namespaces"),
+                },
+
+                // Add and remove authorizations as their time windows open and close.
+                _ = time::sleep_until(authz_deadline.unwrap_or_else(time::Instant::now)), if authz_deadline.is_some() => {
+                    self.update_authz_windows();
+                    Ok(())
+                }
+
+                // Reapply the configuration when it changes. If the configuration's sender is
+                // dropped, the current configuration is retained.
+                res = config_rx.changed(), if config_live => match res {
+                    Ok(()) => {
+                        let config = config_rx.borrow().clone();
+                        self.apply_config(config).context("applying configuration")
+                    }
+                    Err(_) => {
+                        config_live = false;
+                        Ok(())
+                    }
+                },
             };
 
             if let Err(error) = res {
@@ -183,7 +317,9 @@ impl Index {
             let ready_now = nodes_rx.ready()
                 && pods_rx.ready()
                 && servers_rx.ready()
-                && authorizations_rx.ready();
+                && authorizations_rx.ready()
+                && client_pods_rx.iter().all(k8s::Watch::ready)
+                && namespaces_rx.iter().all(k8s::Watch::ready);
             if ready != ready_now {
                 let _ = ready_tx.send(ready_now);
                 ready = ready_now;
@@ -191,4 +327,53 @@ impl Index {
             }
         }
     }
+
+    /// Applies an updated configuration, re-publishing the policies that change.
+    pub(crate) fn apply_config(
+        &mut self,
+        Config {
+            cluster_networks,
+            identity_domains,
+            default_allow,
+            undeclared_ports,
+            kubelet_source,
+            detect_timeout,
+        }: Config,
+    ) -> Result<()> {
+        if undeclared_ports != self.undeclared_ports {
+            warn!(%undeclared_ports, "Undeclared port handling cannot be changed without a restart");
+        }
+        if kubelet_source != *self.nodes.kubelet_source() {
+            warn!(%kubelet_source, "The kubelet source cannot be changed without a restart");
+        }
+
+        self.default_allows.set_detect_timeout(detect_timeout);
+
+        if cluster_networks != self.cluster_networks {
+            self.cluster_networks = cluster_networks;
+            match self.cluster_networks {
+                ClusterNetworks::Static(ref nets) => {
+                    let nets = nets.clone();
+                    if self.default_allows.set_cluster_networks(nets) {
+                        self.update_server_default_allows();
+                    }
+                }
+                ClusterNetworks::Discover { .. } => self.discover_cluster_networks(),
+            }
+        }
+
+        self.set_default_allow(default_allow);
+
+        self.set_identity_domains(identity_domains)
+    }
+
+    /// Publishes a namespace's servers and authorizations so that they may be exported.
+    fn publish_policy(&mut self, ns_name: &str) {
+        match self.namespaces.index.get(ns_name) {
+            Some(ns) => self
+                .lookups
+                .set_policy(ns_name, ns.servers.export(), ns.authzs.export()),
+            None => self.lookups.unset_policy(ns_name),
+        }
+    }
 }
@@ -1,42 +1,99 @@
-use crate::{node::KubeletIps, ServerRxRx};
+use crate::{
+    export::{
+        AuthorizationData, InboundServerData, NamespaceData, PodData, PolicyData, ServerData,
+    },
+    node::{KubeletIps, KubeletRx},
+    undeclared::UndeclaredRx,
+    IdentityDomains, ServerRx, ServerRxRx,
+};
 use anyhow::{anyhow, Result};
 use dashmap::{mapref::entry::Entry, DashMap};
+use futures::FutureExt;
 use polixy_controller_core::{
-    ClientAuthentication, ClientAuthorization, DiscoverInboundServer, InboundServer,
-    InboundServerStream, NetworkMatch,
+    ClientAuthentication, ClientAuthorization, DiscoverInboundServer, IdentifyTarget,
+    InboundServer, InboundServerStream, NetworkMatch, PortProtocol, TargetIdentity,
+    HEALTH_CHECK_AUTHZ,
+};
+use polixy_controller_k8s_api::{self as k8s, labels};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
 };
-use std::{collections::HashMap, net::IpAddr, sync::Arc};
+use tokio::sync::watch;
 
-#[derive(Debug, Default)]
-pub(crate) struct Writer(ByNs);
+#[derive(Debug)]
+pub(crate) struct Writer {
+    pods: ByNs,
+    policies: PoliciesByNs,
+    identity_domains: watch::Sender<Option<IdentityDomains>>,
+}
 
 #[derive(Clone, Debug)]
-pub struct Reader(ByNs);
+pub struct Reader {
+    pods: ByNs,
+    policies: PoliciesByNs,
+
+    /// Resolves pods' service accounts to identities. Unset until the index is configured.
+    identity_domains: watch::Receiver<Option<IdentityDomains>>,
+}
 
 type ByNs = Arc<DashMap<String, ByPod>>;
-type ByPod = DashMap<String, ByPort>;
+type ByPod = DashMap<String, PodPorts>;
 
 // Boxed to enforce immutability.
-type ByPort = Box<HashMap<u16, Rx>>;
+type ByPort = Box<HashMap<(u16, PortProtocol), Rx>>;
+
+/// Each namespace's servers and authorizations, as rendered for export.
+type PoliciesByNs = Arc<DashMap<String, NamespaceData>>;
+
+/// A pod's labels, service account, and discoverable ports.
+#[derive(Debug)]
+struct PodPorts {
+    labels: k8s::Labels,
+    service_account: String,
+    ports: ByPort,
+
+    /// Serves the ports that the pod does not declare.
+    kubelet: KubeletRx,
+    undeclared: UndeclaredRx,
+}
 
 pub(crate) fn pair() -> (Writer, Reader) {
-    let by_ns = ByNs::default();
-    let w = Writer(by_ns.clone());
-    let r = Reader(by_ns);
+    let pods = ByNs::default();
+    let policies = PoliciesByNs::default();
+    let (domains_tx, domains_rx) = watch::channel(None);
+    let w = Writer {
+        pods: pods.clone(),
+        policies: policies.clone(),
+        identity_domains: domains_tx,
+    };
+    let r = Reader {
+        pods,
+        policies,
+        identity_domains: domains_rx,
+    };
     (w, r)
 }
 
 #[derive(Clone, Debug)]
 pub struct Rx {
-    kubelet: KubeletIps,
-    rx: ServerRxRx,
+    kubelet: KubeletRx,
+    rx: PortRx,
+}
+
+#[derive(Clone, Debug)]
+enum PortRx {
+    Declared(ServerRxRx),
+
+    /// A pod's undeclared ports share a receiver, from which each port's server is selected.
+    Undeclared((u16, PortProtocol), UndeclaredRx),
 }
 
 // === impl Writer ===
 
 impl Writer {
     pub(crate) fn contains(&self, ns: impl AsRef<str>, pod: impl AsRef<str>) -> bool {
-        self.0
+        self.pods
             .get(ns.as_ref())
             .map(|ns| ns.contains_key(pod.as_ref()))
             .unwrap_or(false)
@@ -46,16 +103,25 @@ impl Writer {
         &mut self,
         ns: impl ToString,
         pod: impl ToString,
-        ports: impl IntoIterator<Item = (u16, Rx)>,
+        (labels, service_account): (k8s::Labels, String),
+        ports: impl IntoIterator<Item = ((u16, PortProtocol), Rx)>,
+        kubelet: KubeletRx,
+        undeclared: UndeclaredRx,
     ) -> Result<()> {
         match self
-            .0
+            .pods
             .entry(ns.to_string())
             .or_default()
             .entry(pod.to_string())
         {
             Entry::Vacant(entry) => {
-                entry.insert(ports.into_iter().collect::<HashMap<_, _>>().into());
+                entry.insert(PodPorts {
+                    labels,
+                    service_account,
+                    ports: ports.into_iter().collect::<HashMap<_, _>>().into(),
+                    kubelet,
+                    undeclared,
+                });
                 Ok(())
             }
             Entry::Occupied(_) => Err(anyhow!(
@@ -66,81 +132,289 @@ impl Writer {
         }
     }
 
+    /// Updates the labels of a pod that has already been set.
+    pub(crate) fn set_labels(
+        &mut self,
+        ns: impl AsRef<str>,
+        pod: impl AsRef<str>,
+        labels: k8s::Labels,
+    ) -> Result<()> {
+        let pods = self
+            .pods
+            .get(ns.as_ref())
+            .ok_or_else(|| anyhow!("missing namespace {}", ns.as_ref()))?;
+        let mut entry = pods
+            .get_mut(pod.as_ref())
+            .ok_or_else(|| anyhow!("missing pod {} in namespace {}", pod.as_ref(), ns.as_ref()))?;
+        entry.labels = labels;
+        Ok(())
+    }
+
     pub(crate) fn unset(&mut self, ns: impl AsRef<str>, pod: impl AsRef<str>) -> Result<ByPort> {
         let pods = self
-            .0
+            .pods
             .get_mut(ns.as_ref())
             .ok_or_else(|| anyhow!("missing namespace {}", ns.as_ref()))?;
 
-        let (_, ports) = pods
+        let (_, PodPorts { ports, .. }) = pods
             .remove(pod.as_ref())
             .ok_or_else(|| anyhow!("missing pod {} in namespace {}", pod.as_ref(), ns.as_ref()))?;
 
         if (*pods).is_empty() {
             drop(pods);
-            self.0.remove(ns.as_ref()).expect("namespace must exist");
+            self.pods.remove(ns.as_ref()).expect("namespace must exist");
         }
 
         Ok(ports)
     }
+
+    /// Sets the servers and authorizations exported for a namespace.
+    pub(crate) fn set_policy(
+        &mut self,
+        ns: impl ToString,
+        servers: BTreeMap<String, ServerData>,
+        authorizations: BTreeMap<String, AuthorizationData>,
+    ) {
+        if servers.is_empty() && authorizations.is_empty() {
+            self.policies.remove(&ns.to_string());
+            return;
+        }
+
+        let data = NamespaceData {
+            servers,
+            authorizations,
+            pods: BTreeMap::new(),
+        };
+        self.policies.insert(ns.to_string(), data);
+    }
+
+    pub(crate) fn unset_policy(&mut self, ns: impl AsRef<str>) {
+        self.policies.remove(ns.as_ref());
+    }
+
+    /// Sets the identity domains with which pods' identities are resolved.
+    pub(crate) fn set_identity_domains(&mut self, domains: IdentityDomains) {
+        // The reader may have been dropped, e.g. in tests.
+        let _ = self.identity_domains.send(Some(domains));
+    }
 }
 
 // === impl Reader ===
 
 impl Reader {
+    /// Returns the receiver for a pod's port, whether or not the pod declares it.
+    ///
+    /// Returns `None` if the pod has not been indexed.
     #[inline]
-    pub(crate) fn lookup(&self, ns: &str, pod: &str, port: u16) -> Option<Rx> {
-        self.0.get(ns)?.get(pod)?.get(&port).cloned()
+    pub(crate) fn lookup(
+        &self,
+        ns: &str,
+        pod: &str,
+        port: u16,
+        protocol: PortProtocol,
+    ) -> Option<Rx> {
+        let pods = self.pods.get(ns)?;
+        let pod = pods.get(pod)?;
+        if let Some(rx) = pod.ports.get(&(port, protocol)) {
+            return Some(rx.clone());
+        }
+        Some(Rx {
+            kubelet: pod.kubelet.clone(),
+            rx: PortRx::Undeclared((port, protocol), pod.undeclared.clone()),
+        })
+    }
+
+    /// Lists the ports with the given protocol that a pod declares, in ascending order.
+    ///
+    /// Returns `None` if the pod has not been indexed.
+    pub fn pod_ports(&self, ns: &str, pod: &str, protocol: PortProtocol) -> Option<Vec<u16>> {
+        let pods = self.pods.get(ns)?;
+        let pod = pods.get(pod)?;
+        Some(pod.sorted_ports(protocol))
+    }
+
+    /// Lists the pods in a namespace that match a label selector, with their declared ports with
+    /// the given protocol.
+    ///
+    /// Pods are returned in name order.
+    pub fn selected_pod_ports(
+        &self,
+        ns: &str,
+        selector: &labels::Selector,
+        protocol: PortProtocol,
+    ) -> Vec<(String, Vec<u16>)> {
+        let pods = match self.pods.get(ns) {
+            Some(pods) => pods,
+            None => return vec![],
+        };
+
+        let mut selected = pods
+            .iter()
+            .filter(|pod| selector.matches(&pod.labels))
+            .map(|pod| (pod.key().clone(), pod.sorted_ports(protocol)))
+            .collect::<Vec<_>>();
+        selected.sort();
+        selected
+    }
+
+    /// Renders the indexed servers and authorizations, and the effective configuration of every
+    /// pod port, as a data document.
+    pub fn export(&self) -> PolicyData {
+        let mut namespaces = self
+            .policies
+            .iter()
+            .map(|ns| (ns.key().clone(), ns.value().clone()))
+            .collect::<BTreeMap<_, _>>();
+
+        for ns in self.pods.iter() {
+            let data = namespaces.entry(ns.key().clone()).or_default();
+            for pod in ns.value().iter() {
+                let undeclared = pod.undeclared.borrow().clone();
+                let kubelet = pod.kubelet.borrow().clone();
+                let mut pod_data = PodData {
+                    labels: pod.labels.as_ref().clone(),
+                    ports: BTreeMap::new(),
+                    udp_ports: BTreeMap::new(),
+                    sctp_ports: BTreeMap::new(),
+                    undeclared: InboundServerData::from(&Rx::mk_server(
+                        &kubelet,
+                        (*undeclared.default.borrow()).clone(),
+                        true,
+                    )),
+                };
+
+                // Undeclared ports that are selected by servers are exported with the pod's
+                // declared ports, one entry per port of a range. As when ports are looked up, the
+                // first server by name serves ports that are selected by multiple servers.
+                let declared = pod.ports.iter().map(|(port, rx)| (*port, rx.get()));
+                let selected = undeclared.selected.values().flat_map(|s| {
+                    let srv = Rx::mk_server(&kubelet, (*s.rx.borrow()).clone(), true);
+                    s.ports.iter().map(move |p| ((p, s.protocol), srv.clone()))
+                });
+                for ((port, protocol), srv) in declared.chain(selected) {
+                    let ports = match protocol {
+                        PortProtocol::Tcp => &mut pod_data.ports,
+                        PortProtocol::Udp => &mut pod_data.udp_ports,
+                        PortProtocol::Sctp => &mut pod_data.sctp_ports,
+                    };
+                    ports
+                        .entry(port)
+                        .or_insert_with(|| InboundServerData::from(&srv));
+                }
+                data.pods.insert(pod.key().clone(), pod_data);
+            }
+        }
+
+        PolicyData { namespaces }
     }
 }
 
 #[async_trait::async_trait]
-impl DiscoverInboundServer<(String, String, u16)> for Reader {
+impl DiscoverInboundServer<(String, String, u16, PortProtocol)> for Reader {
     async fn get_inbound_server(
         &self,
-        (ns, pod, port): (String, String, u16),
+        (ns, pod, port, protocol): (String, String, u16, PortProtocol),
     ) -> Result<Option<InboundServer>> {
-        Ok(self.lookup(&*ns, &*pod, port).map(|rx| rx.get()))
+        Ok(self.lookup(&*ns, &*pod, port, protocol).map(|rx| rx.get()))
     }
 
     async fn watch_inbound_server(
         &self,
-        (ns, pod, port): (String, String, u16),
+        (ns, pod, port, protocol): (String, String, u16, PortProtocol),
     ) -> Result<Option<InboundServerStream>> {
-        Ok(self.lookup(&*ns, &*pod, port).map(|rx| rx.into_stream()))
+        Ok(self
+            .lookup(&*ns, &*pod, port, protocol)
+            .map(|rx| rx.into_stream()))
+    }
+}
+
+impl IdentifyTarget<(String, String, u16, PortProtocol)> for Reader {
+    fn target_identity(
+        &self,
+        (ns, pod, _, _): &(String, String, u16, PortProtocol),
+    ) -> TargetIdentity {
+        let service_account = match self
+            .pods
+            .get(ns.as_str())
+            .and_then(|pods| pods.get(pod.as_str()).map(|p| p.service_account.clone()))
+        {
+            Some(sa) => sa,
+            None => return TargetIdentity::Unknown,
+        };
+        let domains = self.identity_domains.borrow();
+        match domains
+            .as_ref()
+            .map(|d| d.service_account(ns, &service_account, None))
+        {
+            Some(Ok(id)) => TargetIdentity::Identity(id),
+            Some(Err(_)) | None => TargetIdentity::Unresolved,
+        }
+    }
+}
+
+// === impl PodPorts ===
+
+impl PodPorts {
+    fn sorted_ports(&self, protocol: PortProtocol) -> Vec<u16> {
+        let mut ports = self
+            .ports
+            .keys()
+            .filter(|(_, p)| *p == protocol)
+            .map(|(port, _)| *port)
+            .collect::<Vec<_>>();
+        ports.sort_unstable();
+        ports
     }
 }
 
 // === impl Rx ===
 
 impl Rx {
-    pub(crate) fn new(kubelet: KubeletIps, rx: ServerRxRx) -> Self {
-        Self { kubelet, rx }
+    pub(crate) fn new(kubelet: KubeletRx, rx: ServerRxRx) -> Self {
+        Self {
+            kubelet,
+            rx: PortRx::Declared(rx),
+        }
     }
 
     #[inline]
-    fn mk_server(kubelet: &[IpAddr], mut inner: InboundServer) -> InboundServer {
+    fn mk_server(
+        kubelet: &KubeletIps,
+        mut inner: InboundServer,
+        undeclared: bool,
+    ) -> InboundServer {
         let networks = kubelet.iter().copied().map(NetworkMatch::from).collect();
         let authz = ClientAuthorization {
             networks,
             authentication: ClientAuthentication::Unauthenticated,
         };
 
-        inner.authorizations.insert("_health_check".into(), authz);
+        inner
+            .authorizations
+            .insert(HEALTH_CHECK_AUTHZ.to_string(), authz);
+        inner.health_check_source = Some(kubelet.source().to_string());
+        inner.undeclared = undeclared;
         inner
     }
 
     pub(crate) fn get(&self) -> InboundServer {
-        Self::mk_server(&*self.kubelet, (*(*self.rx.borrow()).borrow()).clone())
+        let undeclared = self.rx.is_undeclared();
+        let kubelet = self.kubelet.borrow().clone();
+        Self::mk_server(&kubelet, (*self.rx.get().borrow()).clone(), undeclared)
     }
 
     pub(crate) fn into_stream(self) -> InboundServerStream {
-        let kubelet = self.kubelet;
+        let mut kubelet = self.kubelet;
         let mut outer = self.rx;
-        let mut inner = (*outer.borrow_and_update()).clone();
+        let undeclared = outer.is_undeclared();
+        let mut inner = outer.get_and_update();
         Box::pin(async_stream::stream! {
+            let mut ips = kubelet.borrow_and_update().clone();
             let mut server = (*inner.borrow_and_update()).clone();
-            yield Self::mk_server(&*kubelet, server.clone());
+            yield Self::mk_server(&ips, server.clone(), undeclared);
+
+            // Once a node is deleted, its kubelet IPs can no longer change.
+            let mut node_exists = true;
 
             loop {
                 tokio::select! {
@@ -148,26 +422,85 @@ impl Rx {
                         Ok(()) => {
                             let s = (*inner.borrow()).clone();
                             if s != server {
-                                yield Self::mk_server(&*kubelet, s.clone());
+                                yield Self::mk_server(&ips, s.clone(), undeclared);
+                                server = s;
+                            }
+                        }
+                        // The server's configuration variant was dropped (e.g. because a rollout
+                        // ended), so the port must have been relinked to another variant. If the
+                        // port's server is still the dropped variant, end the stream so that the
+                        // client re-resolves the port.
+                        Err(_) => {
+                            inner = outer.get_and_update();
+                            if let Some(Err(_)) = inner.clone().changed().now_or_never() {
+                                return;
+                            }
+                            let s = (*inner.borrow_and_update()).clone();
+                            if s != server {
+                                yield Self::mk_server(&ips, s.clone(), undeclared);
                                 server = s;
                             }
                         }
-                        Err(_) => {},
                     },
 
                     res = outer.changed() => match res {
                         Ok(()) => {
-                            inner = (*outer.borrow()).clone();
+                            inner = outer.get_and_update();
                             let s = (*inner.borrow_and_update()).clone();
                             if s != server {
-                                yield Self::mk_server(&*kubelet, s.clone());
+                                yield Self::mk_server(&ips, s.clone(), undeclared);
                                 server = s;
                             }
                         }
                         Err(_) => return,
                     },
+
+                    res = kubelet.changed(), if node_exists => match res {
+                        Ok(()) => {
+                            let i = kubelet.borrow_and_update().clone();
+                            if i != ips {
+                                yield Self::mk_server(&i, server.clone(), undeclared);
+                                ips = i;
+                            }
+                        }
+                        Err(_) => node_exists = false,
+                    },
                 }
             }
         })
     }
 }
+
+// === impl PortRx ===
+
+impl PortRx {
+    fn is_undeclared(&self) -> bool {
+        matches!(self, Self::Undeclared(..))
+    }
+
+    /// Returns the port's current server.
+    fn get(&self) -> ServerRx {
+        match self {
+            Self::Declared(rx) => (*rx.borrow()).clone(),
+            Self::Undeclared(port, rx) => rx.borrow().get(*port),
+        }
+    }
+
+    /// Returns the port's current server, marking it as seen.
+    fn get_and_update(&mut self) -> ServerRx {
+        match self {
+            Self::Declared(rx) => (*rx.borrow_and_update()).clone(),
+            Self::Undeclared(port, rx) => rx.borrow_and_update().get(*port),
+        }
+    }
+
+    /// Waits for the port's server to be replaced.
+    ///
+    /// Undeclared ports are notified whenever any of the pod's undeclared ports change.
+    async fn changed(&mut self) -> Result<(), watch::error::RecvError> {
+        match self {
+            Self::Declared(rx) => rx.changed().await,
+            Self::Undeclared(_, rx) => rx.changed().await,
+        }
+    }
+}
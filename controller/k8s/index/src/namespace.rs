@@ -39,7 +39,31 @@ impl NamespaceIndex {
         })
     }
 
+    pub fn default_allow(&self) -> DefaultAllow {
+        self.default_allow
+    }
+
+    /// Updates the global default-allow policy of all namespaces.
+    pub fn set_default_allow(&mut self, default_allow: DefaultAllow) {
+        self.default_allow = default_allow;
+        for ns in self.index.values_mut() {
+            ns.default_allow = default_allow;
+        }
+    }
+
     pub fn iter(&self) -> impl Iterator<Item = (&String, &Namespace)> {
         self.index.iter()
     }
 }
+
+// === impl Namespace ===
+
+impl Namespace {
+    /// Relinks pods against servers if the servers' rollouts changed, since pods may then use
+    /// different variants of their servers' configurations.
+    pub fn relink_rollouts(&mut self) {
+        if self.servers.take_rollouts_changed() {
+            self.pods.link_servers(&mut self.servers);
+        }
+    }
+}
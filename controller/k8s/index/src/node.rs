@@ -1,7 +1,7 @@
 //! Node->Kubelet IP
 
 use crate::Index;
-use anyhow::{anyhow, Context, Result};
+use anyhow::{anyhow, bail, Context, Error, Result};
 use polixy_controller_core::IpNet;
 use polixy_controller_k8s_api::{self as k8s, ResourceExt};
 use std::{
@@ -9,30 +9,82 @@ use std::{
     net::IpAddr,
     sync::Arc,
 };
+use tokio::sync::watch;
 use tracing::{debug, instrument, trace, warn};
 
 #[derive(Debug, Default)]
 pub(crate) struct NodeIndex {
     index: HashMap<String, State>,
+    source: KubeletSource,
+
+    /// Each node's pod networks, from which the cluster's networks may be discovered.
+    pod_networks: HashMap<String, Vec<IpNet>>,
+}
+
+/// Configures how the IPs from which a node's kubelet sends health checks are determined.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum KubeletSource {
+    /// The first host address of each of the node's pod CIDRs.
+    PodCidr,
+
+    /// The node's `InternalIP` addresses.
+    InternalIp,
+
+    /// The IPs or networks listed in the node's `polixy.linkerd.io/kubelet-ips` annotation.
+    Annotation,
+
+    /// A static list of networks, used for all nodes.
+    Static(Vec<IpNet>),
 }
 
 #[derive(Debug)]
 enum State {
     Pending(HashMap<String, HashMap<String, k8s::Pod>>),
-    Known(KubeletIps),
+    Known(Kubelet),
 }
 
+/// Publishes a node's kubelet IPs, which change if the node's pod CIDRs are updated.
+#[derive(Debug)]
+struct Kubelet {
+    tx: watch::Sender<KubeletIps>,
+    rx: KubeletRx,
+}
+
+/// The IPs (or networks) from which a node's kubelet sends health checks, and their source.
 #[derive(Clone, Debug, Hash, PartialEq, Eq)]
-pub(crate) struct KubeletIps(Arc<[IpAddr]>);
+pub(crate) struct KubeletIps {
+    networks: Arc<[IpNet]>,
+    source: &'static str,
+}
+
+/// Watches a node's kubelet IPs.
+pub(crate) type KubeletRx = watch::Receiver<KubeletIps>;
 
 // === impl NodeIndex ===
 
 impl NodeIndex {
-    pub fn get_or_push_pending(&mut self, pod: k8s::Pod) -> Option<(k8s::Pod, KubeletIps)> {
+    pub fn new(source: KubeletSource) -> Self {
+        Self {
+            index: HashMap::default(),
+            source,
+            pod_networks: HashMap::default(),
+        }
+    }
+
+    /// Returns the pod networks of all nodes.
+    pub fn kubelet_source(&self) -> &KubeletSource {
+        &self.source
+    }
+
+    pub fn pod_networks(&self) -> impl Iterator<Item = IpNet> + '_ {
+        self.pod_networks.values().flatten().copied()
+    }
+
+    pub fn get_or_push_pending(&mut self, pod: k8s::Pod) -> Option<(k8s::Pod, KubeletRx)> {
         let node_name = pod.spec.as_ref()?.node_name.clone()?;
         match self.index.entry(node_name) {
             HashEntry::Occupied(mut entry) => match entry.get_mut() {
-                State::Known(ips) => Some((pod, ips.clone())),
+                State::Known(kubelet) => Some((pod, kubelet.rx.clone())),
                 State::Pending(pods) => {
                     pods.entry(pod.namespace()?)
                         .or_default()
@@ -89,34 +141,56 @@ impl Index {
     /// Tracks the kubelet IP for each node.
     ///
     /// As pods are we created, we refer to the node->kubelet index to automatically allow traffic
-    /// from the kubelet.
+    /// from the kubelet. Pods watch their node's kubelet IPs, so updates to a node's pod CIDRs are
+    /// reflected in the policies of all of its pods.
     #[instrument(
         skip(self, node),
         fields(name = ?node.metadata.name)
     )]
     pub fn apply_node(&mut self, node: k8s::Node) -> Result<()> {
+        let result = self.index_node(node);
+        self.discover_cluster_networks();
+        result
+    }
+
+    fn index_node(&mut self, node: k8s::Node) -> Result<()> {
+        let pod_networks = pod_networks(&node);
+        self.nodes.pod_networks.insert(node.name(), pod_networks);
+
         match self.nodes.index.entry(node.name()) {
             HashEntry::Vacant(entry) => {
-                let ips = KubeletIps::try_from_node(node)
-                    .with_context(|| format!("failed to load kubelet IPs for {}", entry.key()))?;
+                let ips =
+                    self.nodes.source.kubelet_ips(node).with_context(|| {
+                        format!("failed to load kubelet IPs for {}", entry.key())
+                    })?;
                 debug!(?ips, "Adding");
-                entry.insert(State::Known(ips));
+                entry.insert(State::Known(Kubelet::new(ips)));
                 Ok(())
             }
 
             HashEntry::Occupied(mut entry) => {
-                // If the node is already configured, ignore the update.
-                if let State::Known(_) = entry.get() {
-                    trace!("Already existed");
+                let ips =
+                    self.nodes.source.kubelet_ips(node).with_context(|| {
+                        format!("failed to load kubelet IPs for {}", entry.key())
+                    })?;
+
+                // If the node is already configured, update its pods' kubelet IPs if they changed.
+                if let State::Known(kubelet) = entry.get() {
+                    if *kubelet.rx.borrow() == ips {
+                        trace!("Already existed");
+                        return Ok(());
+                    }
+                    debug!(?ips, "Updating");
+                    // The index holds a receiver, so the update can't fail.
+                    let _ = kubelet.tx.send(ips);
                     return Ok(());
                 }
 
                 // Otherwise, the update is replacing a set of pending pods. Update the state to the
                 // known set of IPs and then apply all of the pending pods.
-                let ips = KubeletIps::try_from_node(node)
-                    .with_context(|| format!("failed to load kubelet IPs for {}", entry.key()))?;
                 debug!(?ips, "Adding");
-                let pods = match std::mem::replace(entry.get_mut(), State::Known(ips)) {
+                let pods = match std::mem::replace(entry.get_mut(), State::Known(Kubelet::new(ips)))
+                {
                     State::Pending(pods) => pods,
                     State::Known(_) => unreachable!("the node state must have been pending"),
                 };
@@ -136,6 +210,9 @@ impl Index {
 
     #[instrument(skip(self))]
     pub fn delete_node(&mut self, name: &str) -> Result<()> {
+        if self.nodes.pod_networks.remove(name).is_some() {
+            self.discover_cluster_networks();
+        }
         self.nodes
             .index
             .remove(name)
@@ -146,7 +223,7 @@ impl Index {
 
     #[instrument(skip(self, nodes))]
     pub fn reset_nodes(&mut self, nodes: Vec<k8s::Node>) -> Result<()> {
-        // Avoid rebuilding data for nodes that have not changed.
+        // Nodes that already existed are updated in place, so their pods need not be rebuilt.
         let mut prior = self
             .nodes
             .index
@@ -160,9 +237,8 @@ impl Index {
         let mut result = Ok(());
         for node in nodes.into_iter() {
             let name = node.name();
-            if prior.remove(&name) {
-                trace!(%name, "Already existed");
-            } else if let Err(error) = self.apply_node(node) {
+            prior.remove(&name);
+            if let Err(error) = self.index_node(node) {
                 warn!(%name, %error, "Failed to apply node");
                 result = Err(error);
             }
@@ -170,6 +246,7 @@ impl Index {
 
         for name in prior.into_iter() {
             debug!(?name, "Removing defunct node");
+            self.nodes.pod_networks.remove(&name);
             let removed = self.nodes.index.remove(&name).is_some();
             debug_assert!(removed, "node must be removable");
             if !removed {
@@ -177,45 +254,264 @@ impl Index {
             }
         }
 
+        self.discover_cluster_networks();
         result
     }
 }
 
-// === impl KubeletIps ===
+/// Returns a node's pod CIDRs. Invalid CIDRs are ignored.
+fn pod_networks(node: &k8s::Node) -> Vec<IpNet> {
+    let spec = match node.spec.as_ref() {
+        Some(spec) => spec,
+        None => return vec![],
+    };
+    let cidrs = if spec.pod_cidrs.is_empty() {
+        spec.pod_cidr.iter().collect::<Vec<_>>()
+    } else {
+        spec.pod_cidrs.iter().collect()
+    };
+    cidrs
+        .into_iter()
+        .filter_map(|cidr| match cidr.parse::<IpNet>() {
+            Ok(net) => Some(net.trunc()),
+            Err(error) => {
+                warn!(%cidr, %error, "Invalid pod CIDR");
+                None
+            }
+        })
+        .collect()
+}
 
-impl std::ops::Deref for KubeletIps {
-    type Target = [IpAddr];
+// === impl Kubelet ===
 
-    fn deref(&self) -> &[IpAddr] {
-        &*self.0
+impl Kubelet {
+    fn new(ips: KubeletIps) -> Self {
+        let (tx, rx) = watch::channel(ips);
+        Self { tx, rx }
     }
 }
 
-impl KubeletIps {
-    fn try_from_cidr(cidr: String) -> Result<IpAddr> {
-        cidr.parse::<IpNet>()
-            .with_context(|| format!("invalid CIDR {}", cidr))?
-            .hosts()
-            .next()
-            .ok_or_else(|| anyhow!("pod CIDR network is empty"))
+// === impl KubeletSource ===
+
+impl KubeletSource {
+    /// Lists a node's kubelet IPs or networks, separated by commas, for the `annotation` source.
+    pub const ANNOTATION: &'static str = "polixy.linkerd.io/kubelet-ips";
+
+    /// Names the source, e.g. to describe the health check authorization.
+    fn name(&self) -> &'static str {
+        match self {
+            Self::PodCidr => "pod-cidr",
+            Self::InternalIp => "internal-ip",
+            Self::Annotation => "annotation",
+            Self::Static(_) => "static",
+        }
     }
 
-    fn try_from_node(node: k8s::Node) -> Result<Self> {
+    fn kubelet_ips(&self, node: k8s::Node) -> Result<KubeletIps> {
+        let networks = match self {
+            Self::PodCidr => Self::pod_cidr_hosts(node)?,
+            Self::InternalIp => Self::internal_ips(node)?,
+            Self::Annotation => Self::annotated_ips(node)?,
+            Self::Static(nets) => nets.clone(),
+        };
+        Ok(KubeletIps {
+            networks: networks.into(),
+            source: self.name(),
+        })
+    }
+
+    /// Assumes that the kubelet uses the first host address of each of the node's pod CIDRs.
+    fn pod_cidr_hosts(node: k8s::Node) -> Result<Vec<IpNet>> {
         let spec = node.spec.ok_or_else(|| anyhow!("node missing spec"))?;
 
-        let addrs = if spec.pod_cidrs.is_empty() {
+        let cidrs = if spec.pod_cidrs.is_empty() {
             let cidr = spec
                 .pod_cidr
                 .ok_or_else(|| anyhow!("node missing pod_cidr"))?;
-            let ip = Self::try_from_cidr(cidr)?;
-            vec![ip]
+            vec![cidr]
         } else {
             spec.pod_cidrs
+        };
+
+        cidrs
+            .into_iter()
+            .map(|cidr| {
+                let ip = cidr
+                    .parse::<IpNet>()
+                    .with_context(|| format!("invalid CIDR {}", cidr))?
+                    .hosts()
+                    .next()
+                    .ok_or_else(|| anyhow!("pod CIDR network is empty"))?;
+                Ok(ip.into())
+            })
+            .collect()
+    }
+
+    fn internal_ips(node: k8s::Node) -> Result<Vec<IpNet>> {
+        let status = node.status.ok_or_else(|| anyhow!("node missing status"))?;
+        let ips = status
+            .addresses
+            .into_iter()
+            .filter(|a| a.type_ == "InternalIP")
+            .map(|a| {
+                a.address
+                    .parse::<IpAddr>()
+                    .map(IpNet::from)
+                    .with_context(|| format!("invalid InternalIP address {}", a.address))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if ips.is_empty() {
+            bail!("node has no InternalIP addresses");
+        }
+        Ok(ips)
+    }
+
+    fn annotated_ips(node: k8s::Node) -> Result<Vec<IpNet>> {
+        let value = node
+            .metadata
+            .annotations
+            .get(Self::ANNOTATION)
+            .ok_or_else(|| anyhow!("node missing {} annotation", Self::ANNOTATION))?;
+        value
+            .split(',')
+            .map(|s| parse_ip_or_net(s.trim()))
+            .collect()
+    }
+}
+
+impl Default for KubeletSource {
+    fn default() -> Self {
+        Self::PodCidr
+    }
+}
+
+impl std::str::FromStr for KubeletSource {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "pod-cidr" => Ok(Self::PodCidr),
+            "internal-ip" => Ok(Self::InternalIp),
+            "annotation" => Ok(Self::Annotation),
+            s => match s.strip_prefix("static=") {
+                Some(nets) => {
+                    let nets = nets
+                        .split(',')
+                        .map(|n| parse_ip_or_net(n.trim()))
+                        .collect::<Result<Vec<_>>>()?;
+                    Ok(Self::Static(nets))
+                }
+                None => Err(anyhow!("invalid kubelet source: {}", s)),
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for KubeletSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Static(nets) => {
+                write!(f, "static=")?;
+                for (i, net) in nets.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    net.fmt(f)?;
+                }
+                Ok(())
+            }
+            source => source.name().fmt(f),
+        }
+    }
+}
+
+fn parse_ip_or_net(s: &str) -> Result<IpNet> {
+    if let Ok(net) = s.parse::<IpNet>() {
+        return Ok(net);
+    }
+    s.parse::<IpAddr>()
+        .map(IpNet::from)
+        .with_context(|| format!("invalid IP address or network {}", s))
+}
+
+// === impl KubeletIps ===
+
+impl std::ops::Deref for KubeletIps {
+    type Target = [IpNet];
+
+    fn deref(&self) -> &[IpNet] {
+        &*self.networks
+    }
+}
+
+impl KubeletIps {
+    /// Names the source from which the IPs were determined.
+    pub fn source(&self) -> &'static str {
+        self.source
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kubelet_sources() {
+        let node = k8s::Node {
+            metadata: k8s::ObjectMeta {
+                name: Some("node-0".to_string()),
+                annotations: Some((
+                    KubeletSource::ANNOTATION.to_string(),
+                    "192.0.2.3, 2001:db8::/64".to_string(),
+                ))
                 .into_iter()
-                .map(Self::try_from_cidr)
-                .collect::<Result<Vec<_>>>()?
+                .collect(),
+                ..Default::default()
+            },
+            spec: Some(k8s::api::core::v1::NodeSpec {
+                pod_cidr: Some("10.0.1.0/24".to_string()),
+                pod_cidrs: vec!["10.0.1.0/24".to_string(), "fd00:1::/64".to_string()],
+                ..Default::default()
+            }),
+            status: Some(k8s::api::core::v1::NodeStatus {
+                addresses: vec![
+                    k8s::api::core::v1::NodeAddress {
+                        address: "node-0.example.com".to_string(),
+                        type_: "Hostname".to_string(),
+                    },
+                    k8s::api::core::v1::NodeAddress {
+                        address: "172.16.0.5".to_string(),
+                        type_: "InternalIP".to_string(),
+                    },
+                ],
+            }),
         };
 
-        Ok(Self(addrs.into()))
+        let net = |s: &str| s.parse::<IpNet>().unwrap();
+        for (source, nets) in [
+            // The first "host" of an IPv6 network is its subnet-router anycast address.
+            ("pod-cidr", vec![net("10.0.1.1/32"), net("fd00:1::/128")]),
+            ("internal-ip", vec![net("172.16.0.5/32")]),
+            (
+                "annotation",
+                vec![net("192.0.2.3/32"), net("2001:db8::/64")],
+            ),
+            (
+                "static=10.0.0.0/8,192.0.2.1",
+                vec![net("10.0.0.0/8"), net("192.0.2.1/32")],
+            ),
+        ] {
+            let source = source.parse::<KubeletSource>().unwrap();
+            let ips = source.kubelet_ips(node.clone()).unwrap();
+            assert_eq!(&*ips, &*nets, "{}", source);
+            assert_eq!(ips.source(), source.to_string().split('=').next().unwrap());
+        }
+
+        for source in &["annotation", "internal-ip"] {
+            let source = source.parse::<KubeletSource>().unwrap();
+            assert!(source.kubelet_ips(k8s::Node::default()).is_err());
+        }
+        assert!("static=".parse::<KubeletSource>().is_err());
+        assert!("first-host".parse::<KubeletSource>().is_err());
     }
 }
@@ -1,13 +1,39 @@
 use crate::{
-    lookup, node::KubeletIps, DefaultAllow, Index, Namespace, NodeIndex, ServerRx, ServerRxTx,
-    SrvIndex,
+    lookup,
+    node::KubeletRx,
+    rollout::RolloutSet,
+    server::PortMatch,
+    undeclared::{UndeclaredSelection, UndeclaredServers, UndeclaredTx},
+    DefaultAllow, Index, Namespace, NodeIndex, ServerRx, ServerRxTx, SrvIndex, UndeclaredPorts,
 };
 use anyhow::{anyhow, Result};
-use polixy_controller_k8s_api::{self as k8s, polixy, ResourceExt};
-use std::collections::{hash_map::Entry as HashEntry, HashMap, HashSet};
+use polixy_controller_core::PortProtocol;
+use polixy_controller_k8s_api::{
+    self as k8s,
+    polixy::{self, server::PortRange},
+    ResourceExt,
+};
+use std::collections::{hash_map::Entry as HashEntry, BTreeMap, HashMap, HashSet};
 use tokio::sync::watch;
 use tracing::{debug, instrument, trace, warn};
 
+/// Indicates that a pod declares a port in more than one container. Only the first declaration is
+/// indexed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PortConflict {
+    pub namespace: String,
+    pub pod: String,
+    pub uid: Option<String>,
+    pub port: u16,
+    pub protocol: PortProtocol,
+
+    /// The container whose declaration is ignored.
+    pub container: String,
+
+    /// The container whose declaration is indexed.
+    pub declared_by: String,
+}
+
 #[derive(Debug, Default)]
 pub(crate) struct PodIndex {
     index: HashMap<String, Pod>,
@@ -17,18 +43,35 @@ pub(crate) struct PodIndex {
 struct Pod {
     ports: PodPorts,
     labels: k8s::Labels,
+
+    /// The pod's default-allow annotation, which overrides the global default-allow policy.
+    default_allow: Option<DefaultAllow>,
     default_allow_rx: ServerRx,
+
+    /// Serves the ports that the pod doesn't declare.
+    undeclared_default_rx: ServerRx,
+
+    /// The servers that select ports the pod doesn't declare, by name.
+    undeclared_servers: BTreeMap<String, UndeclaredSelection>,
+
+    undeclared_tx: UndeclaredTx,
 }
 
 #[derive(Debug, Default)]
 struct PodPorts {
-    by_port: HashMap<u16, Port>,
-    by_name: HashMap<String, Vec<u16>>,
+    by_port: HashMap<(u16, PortProtocol), Port>,
+    by_name: HashMap<String, Vec<(u16, PortProtocol)>>,
 }
 
 #[derive(Debug)]
 struct Port {
+    /// The name of the container that declares the port.
+    container: String,
     server_name: Option<String>,
+
+    /// The rollouts of the port's server that select the pod.
+    server_rollouts: RolloutSet,
+
     server_tx: ServerRxTx,
 }
 
@@ -58,13 +101,37 @@ impl Index {
         let mk_default_allow =
             move |da: Option<DefaultAllow>| allows.get(da.unwrap_or(default_allow));
 
-        pods.apply(
+        let conflicts = pods.apply(
             pod,
             &mut self.nodes,
             servers,
             &mut self.lookups,
             mk_default_allow,
-        )
+            self.undeclared_ports,
+        )?;
+
+        if let Some(ref tx) = self.port_conflicts {
+            for c in conflicts.into_iter() {
+                // The receiver may have stopped; that's not the index's concern.
+                let _ = tx.send(c);
+            }
+        }
+        Ok(())
+    }
+
+    /// Updates the global default-allow policy, re-publishing the ports of pods that don't override
+    /// it and that aren't selected by a server.
+    pub(crate) fn set_default_allow(&mut self, default_allow: DefaultAllow) {
+        if default_allow == self.namespaces.default_allow() {
+            return;
+        }
+        debug!(%default_allow, "Updating default-allow policy");
+        self.namespaces.set_default_allow(default_allow);
+
+        let rx = self.default_allows.get(default_allow);
+        for ns in self.namespaces.index.values_mut() {
+            ns.pods.set_default_allow_rx(&rx, self.undeclared_ports);
+        }
     }
 
     #[instrument(
@@ -147,10 +214,11 @@ impl PodIndex {
         &mut self,
         pod: k8s::Pod,
         nodes: &mut NodeIndex,
-        servers: &SrvIndex,
+        servers: &mut SrvIndex,
         lookups: &mut lookup::Writer,
         get_default_allow_rx: impl Fn(Option<DefaultAllow>) -> ServerRx,
-    ) -> Result<()> {
+        undeclared_ports: UndeclaredPorts,
+    ) -> Result<Vec<PortConflict>> {
         let ns_name = pod.namespace().expect("pod must have a namespace");
         let pod_name = pod.name();
         match self.index.entry(pod_name) {
@@ -162,7 +230,7 @@ impl PodIndex {
                     Some((pod, ips)) => (pod, ips),
                     None => {
                         debug!("Pod cannot yet assigned to a Node");
-                        return Ok(());
+                        return Ok(vec![]);
                     }
                 };
 
@@ -171,38 +239,72 @@ impl PodIndex {
                 // Check the pod for a default-allow annotation. If it's set, use it; otherwise use
                 // the default policy from the namespace or cluster. We retain this value (and not
                 // only the policy) so that we can more conveniently de-duplicate changes
-                let default_allow_rx = match DefaultAllow::from_annotation(&pod.metadata) {
-                    Ok(allow) => get_default_allow_rx(allow),
+                let default_allow = match DefaultAllow::from_annotation(&pod.metadata) {
+                    Ok(allow) => allow,
                     Err(error) => {
                         warn!(%error, "Ignoring invalid default-allow annotation");
-                        get_default_allow_rx(None)
+                        None
                     }
                 };
+                let default_allow_rx = get_default_allow_rx(default_allow);
+
+                // The pod's identity is derived from its service account.
+                let service_account = spec
+                    .service_account_name
+                    .clone()
+                    .unwrap_or_else(|| "default".to_string());
 
                 // Read the pod's ports and extract:
                 // - `ServerTx`s to be linkerd against the server index; and
                 // - lookup receivers to be returned to API clients.
-                let (ports, pod_lookups) =
-                    Self::extract_ports(spec, default_allow_rx.clone(), kubelet);
+                let (ports, pod_lookups, conflicts) = Self::extract_ports(
+                    &pod.metadata,
+                    spec,
+                    &pod.sidecars,
+                    default_allow_rx.clone(),
+                    kubelet.clone(),
+                );
+
+                // Ports that the pod doesn't declare are served by a single receiver, unless they
+                // are selected by servers.
+                let undeclared_default_rx = match undeclared_ports {
+                    UndeclaredPorts::DefaultAllow => default_allow_rx.clone(),
+                    UndeclaredPorts::Deny => get_default_allow_rx(Some(DefaultAllow::Deny)),
+                };
+                let (undeclared_tx, undeclared_rx) = watch::channel(UndeclaredServers {
+                    default: undeclared_default_rx.clone(),
+                    selected: BTreeMap::new(),
+                });
 
                 // Start tracking the pod's metadata so it can be linked against servers as they are
                 // created. Immediately link the pod against the server index.
                 let mut pod = Pod {
+                    default_allow,
                     default_allow_rx,
                     labels: pod.metadata.labels.into(),
                     ports,
+                    undeclared_default_rx,
+                    undeclared_servers: BTreeMap::new(),
+                    undeclared_tx,
                 };
-                pod.link_servers(&servers);
+                pod.link_servers(pod_entry.key(), servers);
 
                 // The pod has been linked against servers and is registered for subsequent updates,
                 // so make it discoverable to API clients.
                 lookups
-                    .set(ns_name, pod_entry.key(), pod_lookups)
+                    .set(
+                        ns_name,
+                        pod_entry.key(),
+                        (pod.labels.clone(), service_account),
+                        pod_lookups,
+                        kubelet,
+                        undeclared_rx,
+                    )
                     .expect("pod must not already exist");
 
                 pod_entry.insert(pod);
 
-                Ok(())
+                Ok(conflicts)
             }
 
             HashEntry::Occupied(mut entry) => {
@@ -214,81 +316,149 @@ impl PodIndex {
                 // Labels can be updated at runtime (even though that's kind of weird). If the
                 // labels have changed, then we relink servers to pods in case label selections have
                 // changed.
+                let name = entry.key().clone();
                 let p = entry.get_mut();
                 if p.labels.as_ref() != &pod.metadata.labels {
                     p.labels = pod.metadata.labels.into();
-                    p.link_servers(&servers);
+                    p.link_servers(&name, servers);
+                    lookups.set_labels(&ns_name, entry.key(), entry.get().labels.clone())?;
                 }
 
-                // Note that the default-allow annotation may not be changed at runtime.
-                Ok(())
+                // Note that neither the default-allow annotation nor the pod's containers may be
+                // changed at runtime, so port conflicts are only reported when the pod is indexed.
+                Ok(vec![])
             }
         }
     }
 
     /// Extracts port information from a pod spec.
+    ///
+    /// Ports are read from containers and then from sidecar init containers. If a port is declared
+    /// by more than one container, only the first declaration is indexed and the conflict is
+    /// returned.
     fn extract_ports(
+        meta: &k8s::ObjectMeta,
         spec: k8s::PodSpec,
+        sidecars: &HashSet<String>,
         server_rx: ServerRx,
-        kubelet: KubeletIps,
-    ) -> (PodPorts, HashMap<u16, lookup::Rx>) {
+        kubelet: KubeletRx,
+    ) -> (
+        PodPorts,
+        HashMap<(u16, PortProtocol), lookup::Rx>,
+        Vec<PortConflict>,
+    ) {
         let mut ports = PodPorts::default();
         let mut lookups = HashMap::new();
-
-        for container in spec.containers.into_iter() {
-            for p in container.ports.into_iter() {
-                if p.protocol.map(|p| p == "TCP").unwrap_or(true) {
-                    let port = p.container_port as u16;
-                    if ports.by_port.contains_key(&port) {
-                        debug!(port, "Port duplicated");
+        let mut conflicts = Vec::new();
+
+        let containers = spec
+            .containers
+            .into_iter()
+            .chain(
+                spec.init_containers
+                    .into_iter()
+                    .filter(|c| sidecars.contains(&c.name)),
+            )
+            .map(|c| (c.name, c.ports));
+        for (container, container_ports) in containers {
+            for p in container_ports.into_iter() {
+                let protocol = match p
+                    .protocol
+                    .as_deref()
+                    .map(str::parse::<PortProtocol>)
+                    .transpose()
+                {
+                    Ok(protocol) => protocol.unwrap_or_default(),
+                    Err(error) => {
+                        warn!(%error, %container, port = p.container_port, "Ignoring port");
                         continue;
                     }
+                };
+                let port = (p.container_port as u16, protocol);
+                if let Some(existing) = ports.by_port.get(&port) {
+                    warn!(
+                        port = port.0,
+                        %protocol,
+                        %container,
+                        declared_by = %existing.container,
+                        "Ignoring port that is already declared by another container"
+                    );
+                    conflicts.push(PortConflict {
+                        namespace: meta.namespace.clone().unwrap_or_default(),
+                        pod: meta.name.clone().unwrap_or_default(),
+                        uid: meta.uid.clone(),
+                        port: port.0,
+                        protocol,
+                        container: container.clone(),
+                        declared_by: existing.container.clone(),
+                    });
+                    continue;
+                }
 
-                    let (server_tx, rx) = watch::channel(server_rx.clone());
-                    let pod_port = Port {
-                        server_name: None,
-                        server_tx,
-                    };
-
-                    trace!(%port, name = ?p.name, "Adding port");
-                    if let Some(name) = p.name {
-                        ports.by_name.entry(name).or_default().push(port);
-                    }
+                let (server_tx, rx) = watch::channel(server_rx.clone());
+                let pod_port = Port {
+                    container: container.clone(),
+                    server_name: None,
+                    server_rollouts: RolloutSet::new(),
+                    server_tx,
+                };
 
-                    ports.by_port.insert(port, pod_port);
-                    lookups.insert(port, lookup::Rx::new(kubelet.clone(), rx));
+                trace!(port = port.0, %protocol, %container, name = ?p.name, "Adding port");
+                if let Some(name) = p.name {
+                    ports.by_name.entry(name).or_default().push(port);
                 }
+
+                ports.by_port.insert(port, pod_port);
+                lookups.insert(port, lookup::Rx::new(kubelet.clone(), rx));
             }
         }
 
-        (ports, lookups)
+        (ports, lookups, conflicts)
     }
 
-    pub(crate) fn link_servers(&mut self, servers: &SrvIndex) {
+    pub(crate) fn link_servers(&mut self, servers: &mut SrvIndex) {
+        for (name, pod) in self.index.iter_mut() {
+            pod.link_servers(name, servers)
+        }
+        servers.prune_variants();
+    }
+
+    /// Updates the default-allow policy of pods without a default-allow annotation.
+    fn set_default_allow_rx(&mut self, rx: &ServerRx, undeclared_ports: UndeclaredPorts) {
         for pod in self.index.values_mut() {
-            pod.link_servers(&servers)
+            if pod.default_allow.is_none() {
+                pod.set_default_allow_rx(rx.clone(), undeclared_ports);
+            }
         }
     }
 
     pub(crate) fn reset_server(&mut self, name: &str) {
         for (pod_name, pod) in self.index.iter_mut() {
             let rx = pod.default_allow_rx.clone();
-            for (p, port) in pod.ports.by_port.iter_mut() {
+            for ((p, protocol), port) in pod.ports.by_port.iter_mut() {
                 if port
                     .server_name
                     .as_ref()
                     .map(|n| n == name)
                     .unwrap_or(false)
                 {
-                    debug!(pod = %pod_name, port = %p, "Removing server from pod");
+                    debug!(pod = %pod_name, port = %p, %protocol, "Removing server from pod");
                     port.server_name = None;
+                    port.server_rollouts.clear();
                     port.server_tx
                         .send(rx.clone())
                         .expect("pod config receiver must still be held");
                 } else {
-                    trace!(pod = %pod_name, port = %p, server = ?port.server_name, "Server does not match");
+                    trace!(pod = %pod_name, port = %p, %protocol, server = ?port.server_name, "Server does not match");
                 }
             }
+
+            if pod.undeclared_servers.contains_key(name) {
+                debug!(pod = %pod_name, "Removing server from undeclared pod ports");
+                let mut undeclared = pod.undeclared_servers.clone();
+                undeclared.remove(name);
+                pod.set_undeclared_servers(undeclared);
+            }
         }
     }
 }
@@ -299,48 +469,140 @@ impl Pod {
     /// Links this pods to server (by label selector).
     //
     // XXX This doesn't properly reset a policy when a server is removed or de-selects a pod.
-    fn link_servers(&mut self, servers: &SrvIndex) {
-        let mut remaining_ports = self.ports.by_port.keys().copied().collect::<HashSet<u16>>();
-
-        // Get all servers that match this pod.
-        let matching = servers.iter_matching(self.labels.clone());
-        for (name, port_match, rx) in matching {
+    fn link_servers(&mut self, name: &str, servers: &mut SrvIndex) {
+        let mut remaining_ports = self
+            .ports
+            .by_port
+            .keys()
+            .copied()
+            .collect::<HashSet<(u16, PortProtocol)>>();
+
+        // Get all servers that match this pod. Servers are linked in name order so that, when
+        // several servers select the same port, the first server by name applies.
+        let mut undeclared = BTreeMap::new();
+        let mut matching = servers
+            .iter_matching(name, self.labels.clone())
+            .collect::<Vec<_>>();
+        matching.sort_by_key(|(name, ..)| *name);
+        let mut linked = HashMap::<(u16, PortProtocol), &str>::new();
+        for (name, port_match, rollouts, rx) in matching {
             // Get all pod ports that match this server.
-            for p in self.ports.collect_port(&port_match).into_iter().flatten() {
-                self.link_server_port(p, name, rx);
+            for p in self.ports.collect_port(port_match) {
+                if !self.ports.by_port.contains_key(&p) {
+                    continue;
+                }
+                if let Some(other) = linked.get(&p) {
+                    warn!(
+                        port = p.0,
+                        protocol = %p.1,
+                        server = %name,
+                        %other,
+                        "Ignoring server that selects a port that another server selects"
+                    );
+                    continue;
+                }
+                self.link_server_port(p, name, &rollouts, &rx);
+                linked.insert(p, name);
                 remaining_ports.remove(&p);
             }
+
+            // Servers may also select ports that the pod doesn't declare by number or range.
+            if let Some(ports) = self.ports.undeclared_ports(port_match) {
+                let selection = UndeclaredSelection {
+                    ports,
+                    protocol: port_match.protocol,
+                    rollouts,
+                    rx,
+                };
+                undeclared.insert(name.to_string(), selection);
+            }
         }
+        self.set_undeclared_servers(undeclared);
 
         // Iterate through the ports that have not been matched to clear them.
         for p in remaining_ports.into_iter() {
             let port = self.ports.by_port.get_mut(&p).unwrap();
             port.server_name = None;
+            port.server_rollouts.clear();
             port.server_tx
                 .send(self.default_allow_rx.clone())
                 .expect("pod config receiver must still be held");
         }
     }
 
-    fn link_server_port(&mut self, port: u16, name: &str, rx: &ServerRx) {
+    /// Replaces the pod's default-allow policy on ports that aren't selected by a server.
+    fn set_default_allow_rx(&mut self, rx: ServerRx, undeclared_ports: UndeclaredPorts) {
+        for port in self.ports.by_port.values_mut() {
+            if port.server_name.is_none() {
+                port.server_tx
+                    .send(rx.clone())
+                    .expect("pod config receiver must still be held");
+            }
+        }
+
+        if let UndeclaredPorts::DefaultAllow = undeclared_ports {
+            self.undeclared_default_rx = rx.clone();
+            self.undeclared_tx
+                .send(UndeclaredServers {
+                    default: self.undeclared_default_rx.clone(),
+                    selected: self.undeclared_servers.clone(),
+                })
+                .expect("pod config receiver must still be held");
+        }
+
+        self.default_allow_rx = rx;
+    }
+
+    /// Updates the servers of undeclared ports, if they have changed.
+    fn set_undeclared_servers(&mut self, servers: BTreeMap<String, UndeclaredSelection>) {
+        let selections = |s: &BTreeMap<String, UndeclaredSelection>| {
+            s.iter()
+                .map(|(n, s)| (n.clone(), s.ports, s.protocol, s.rollouts.clone()))
+                .collect::<Vec<_>>()
+        };
+        if selections(&servers) == selections(&self.undeclared_servers) {
+            return;
+        }
+
+        // Ports that are selected by multiple servers use the first server by name.
+        for (i, (n0, s0)) in servers.iter().enumerate() {
+            for (n1, s1) in servers.iter().skip(i + 1) {
+                if s0.protocol == s1.protocol && s0.ports.overlaps(&s1.ports) {
+                    warn!("Pod port matches multiple servers: {} and {}", n0, n1);
+                }
+            }
+        }
+
+        self.undeclared_servers = servers;
+        self.undeclared_tx
+            .send(UndeclaredServers {
+                default: self.undeclared_default_rx.clone(),
+                selected: self.undeclared_servers.clone(),
+            })
+            .expect("pod config receiver must still be held");
+        debug!(servers = ?self.undeclared_servers.keys(), "Undeclared pod ports updated");
+    }
+
+    fn link_server_port(
+        &mut self,
+        port: (u16, PortProtocol),
+        name: &str,
+        rollouts: &RolloutSet,
+        rx: &ServerRx,
+    ) {
         let port = match self.ports.by_port.get_mut(&port) {
             Some(p) => p,
             None => return,
         };
 
-        // Either this port is using a default allow policy, and the server name is unset,
-        // or multiple servers select this pod. If there's a conflict, we panic if the proxy
-        // is running in debug mode. In release mode, we log a warning and ignore the
-        // conflicting server.
-        if let Some(sn) = port.server_name.as_ref() {
-            if sn != name {
-                debug_assert!(false, "Pod port must not match multiple servers");
-                tracing::warn!("Pod port matches multiple servers: {} and {}", sn, name);
-            }
-            // If the name matched there's no use in proceeding with a redundant update. If the
+        // If the name and rollouts matched there's no use in proceeding with a redundant update.
+        // If the rollouts that select the pod changed, the port uses another variant of the
+        // server's configuration.
+        if port.server_name.as_deref() == Some(name) && port.server_rollouts == *rollouts {
             return;
         }
         port.server_name = Some(name.to_string());
+        port.server_rollouts = rollouts.clone();
 
         port.server_tx
             .send(rx.clone())
@@ -352,14 +614,55 @@ impl Pod {
 // === impl PodPorts ===
 
 impl PodPorts {
-    /// Finds all ports on this pod that match a server's port reference.
+    /// Finds all ports on this pod that match a server's port reference, protocol, and container.
     ///
     /// Numeric port matches will only return a single server, generally, while named port
-    /// references may select an arbitrary number of server ports.
-    fn collect_port(&self, port_match: &polixy::server::Port) -> Option<Vec<u16>> {
-        match port_match {
-            polixy::server::Port::Number(ref port) => Some(vec![*port]),
-            polixy::server::Port::Name(ref name) => self.by_name.get(name).cloned(),
+    /// references and port ranges may select an arbitrary number of server ports.
+    fn collect_port(&self, port_match: &PortMatch) -> Vec<(u16, PortProtocol)> {
+        let ports = match port_match.port {
+            polixy::server::Port::Number(port) => vec![(port, port_match.protocol)],
+            polixy::server::Port::Range(range) => self
+                .by_port
+                .keys()
+                .copied()
+                .filter(|(p, proto)| range.contains(*p) && *proto == port_match.protocol)
+                .collect(),
+            polixy::server::Port::Name(ref name) => self
+                .by_name
+                .get(name)
+                .into_iter()
+                .flatten()
+                .copied()
+                .filter(|(_, p)| *p == port_match.protocol)
+                .collect(),
+        };
+
+        match port_match.container {
+            None => ports,
+            Some(ref container) => ports
+                .into_iter()
+                .filter(|p| {
+                    self.by_port
+                        .get(p)
+                        .map(|port| port.container == *container)
+                        .unwrap_or(false)
+                })
+                .collect(),
         }
     }
+
+    /// Returns the ports that a server selects by number or range, unless the pod declares all of
+    /// them.
+    ///
+    /// Servers that are scoped to a container only select declared ports.
+    fn undeclared_ports(&self, port_match: &PortMatch) -> Option<PortRange> {
+        if port_match.container.is_some() {
+            return None;
+        }
+        let ports = port_match.port.numbers()?;
+        let declared = ports
+            .iter()
+            .all(|p| self.by_port.contains_key(&(p, port_match.protocol)));
+        Some(ports).filter(|_| !declared)
+    }
 }
@@ -0,0 +1,68 @@
+use anyhow::{bail, Result};
+use polixy_controller_k8s_api::{self as k8s, polixy};
+use std::{collections::BTreeSet, sync::Arc};
+
+/// Selects the pods to which an authorization applies.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Rollout {
+    pub pod_selector: Option<Arc<k8s::labels::Selector>>,
+    pub percent: Option<u8>,
+
+    /// The authorization that doesn't apply to the selected pods.
+    pub replaces: Option<String>,
+}
+
+/// The names of the rollouts that select a pod. Pods that are selected by the same rollouts share
+/// a variant of each server's configuration.
+pub(crate) type RolloutSet = BTreeSet<String>;
+
+// === impl Rollout ===
+
+impl Rollout {
+    pub fn from_spec(spec: polixy::authz::Rollout) -> Result<Self> {
+        let polixy::authz::Rollout {
+            pod_selector,
+            percent,
+            replaces,
+        } = spec;
+        if pod_selector.is_none() && percent.is_none() {
+            bail!("rollout must specify a pod selector or percentage");
+        }
+        if let Some(p) = percent {
+            if p > 100 {
+                bail!("rollout percentage must not exceed 100: {}", p);
+            }
+        }
+        Ok(Self {
+            pod_selector: pod_selector.map(Arc::new),
+            percent,
+            replaces,
+        })
+    }
+
+    /// Indicates whether the rollout selects a pod.
+    pub fn selects(&self, pod_name: &str, labels: &k8s::Labels) -> bool {
+        if let Some(ref sel) = self.pod_selector {
+            if !sel.matches(labels) {
+                return false;
+            }
+        }
+        match self.percent {
+            Some(p) => bucket(pod_name) < p,
+            None => true,
+        }
+    }
+}
+
+/// Assigns a pod to one of 100 buckets by its name.
+///
+/// FNV-1a is used (rather than the standard library's hasher, which may change between releases)
+/// so that pods remain in the same bucket across controller versions.
+fn bucket(pod_name: &str) -> u8 {
+    let mut hash = 0xcbf29ce484222325u64;
+    for b in pod_name.bytes() {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    (hash % 100) as u8
+}
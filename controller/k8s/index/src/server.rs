@@ -1,149 +1,295 @@
-use crate::{authz::AuthzIndex, Index, Namespace, ServerRx, ServerSelector, ServerTx};
+use crate::{
+    authz::AuthzIndex,
+    default_allow::DefaultAllows,
+    export::ServerData,
+    rollout::{Rollout, RolloutSet},
+    Index, Namespace, ServerRx, ServerSelector, ServerTx,
+};
 use anyhow::{anyhow, bail, Result};
-use polixy_controller_core::{ClientAuthorization, InboundServer, ProxyProtocol};
+use polixy_controller_core::{
+    ClientAuthorization, HttpRoute, HttpRouteMatch, InboundServer, PortProtocol, ProxyProtocol,
+};
 use polixy_controller_k8s_api::{self as k8s, polixy, ResourceExt};
 use std::{
-    collections::{hash_map::Entry as HashEntry, BTreeMap, HashMap, HashSet},
+    collections::{hash_map::Entry as HashEntry, BTreeMap, BTreeSet, HashMap, HashSet},
     sync::Arc,
 };
 use tokio::{sync::watch, time};
-use tracing::{debug, instrument, trace};
+use tracing::{debug, instrument, trace, warn};
 
 #[derive(Debug, Default)]
 pub(crate) struct SrvIndex {
     index: HashMap<String, Server>,
+
+    /// Set when a server's rollouts change, so that pods must be relinked to select the variants
+    /// of their servers' configurations.
+    rollouts_changed: bool,
 }
 
 #[derive(Debug)]
 struct Server {
     meta: ServerMeta,
     authorizations: BTreeMap<String, ClientAuthorization>,
+    routes: BTreeMap<String, HttpRoute>,
+
+    /// The authorizations of the server's default-allow policy, if any.
+    default_authzs: BTreeMap<String, ClientAuthorization>,
+
+    /// The rollouts of authorizations that only apply to some of the server's pods, by
+    /// authorization name.
+    rollouts: BTreeMap<String, Arc<Rollout>>,
+
+    /// The configuration of pods that aren't selected by any rollout.
     rx: ServerRx,
     tx: ServerTx,
+
+    /// The configurations of pods that are selected by rollouts.
+    variants: HashMap<RolloutSet, (ServerTx, ServerRx)>,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 struct ServerMeta {
     labels: k8s::Labels,
-    port: polixy::server::Port,
+    port: PortMatch,
     pod_selector: Arc<k8s::labels::Selector>,
     protocol: ProxyProtocol,
+    default_allow: Option<polixy::server::DefaultAllow>,
+}
+
+/// Selects the ports of a pod that a server applies to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct PortMatch {
+    pub port: polixy::server::Port,
+    pub protocol: PortProtocol,
+
+    /// If set, only ports declared by this container are selected.
+    pub container: Option<String>,
+}
+
+// === impl PortMatch ===
+
+impl PortMatch {
+    /// Indicates whether both matches may select the same pod port.
+    ///
+    /// Named ports are only known to overlap other references to the same name.
+    fn overlaps(&self, other: &Self) -> bool {
+        if self.protocol != other.protocol {
+            return false;
+        }
+        if let (Some(c0), Some(c1)) = (self.container.as_ref(), other.container.as_ref()) {
+            if c0 != c1 {
+                return false;
+            }
+        }
+        match (self.port.numbers(), other.port.numbers()) {
+            (Some(r0), Some(r1)) => r0.overlaps(&r1),
+            _ => self.port == other.port,
+        }
+    }
 }
 
 // === impl SrvIndex ===
 
 impl SrvIndex {
-    pub fn add_authz(&mut self, name: &str, selector: &ServerSelector, authz: ClientAuthorization) {
+    pub fn add_authz(
+        &mut self,
+        name: &str,
+        selector: &ServerSelector,
+        authz: ClientAuthorization,
+        routes: Vec<HttpRouteMatch>,
+        rollout: Option<Arc<Rollout>>,
+    ) {
         for (srv_name, srv) in self.index.iter_mut() {
             let matches = match selector {
                 ServerSelector::Name(ref n) => n == srv_name,
                 ServerSelector::Selector(ref s) => s.matches(&srv.meta.labels),
             };
-            if matches {
+            let rollouts_changed = if matches {
                 debug!(server = %srv_name, authz = %name, "Adding authz to server");
-                srv.add_authz(name.to_string(), authz.clone());
+                srv.add_authz(
+                    name.to_string(),
+                    authz.clone(),
+                    routes.clone(),
+                    rollout.clone(),
+                )
             } else {
                 debug!(server = %srv_name, authz = %name, "Removing authz from server");
-                srv.remove_authz(name);
-            }
+                srv.remove_authz(name)
+            };
+            self.rollouts_changed |= rollouts_changed;
         }
     }
 
     pub fn remove_authz(&mut self, name: &str) {
         for srv in self.index.values_mut() {
-            srv.remove_authz(name);
+            self.rollouts_changed |= srv.remove_authz(name);
         }
     }
 
-    pub fn iter_matching(
-        &self,
+    /// Returns whether any server's rollouts changed since this was last called.
+    pub fn take_rollouts_changed(&mut self) -> bool {
+        std::mem::take(&mut self.rollouts_changed)
+    }
+
+    /// Returns the servers that select a pod, with the configuration that applies to the pod and
+    /// the rollouts that determine it.
+    pub fn iter_matching<'a>(
+        &'a mut self,
+        pod_name: &'a str,
         labels: k8s::Labels,
-    ) -> impl Iterator<Item = (&str, &polixy::server::Port, &ServerRx)> {
-        self.index.iter().filter_map(move |(srv_name, server)| {
+    ) -> impl Iterator<Item = (&'a str, &'a PortMatch, RolloutSet, ServerRx)> {
+        self.index.iter_mut().filter_map(move |(srv_name, server)| {
             let matches = server.meta.pod_selector.matches(&labels);
             trace!(server = %srv_name, %matches);
             if matches {
-                Some((srv_name.as_str(), &server.meta.port, &server.rx))
+                let (rollouts, rx) = server.pod_rx(pod_name, &labels);
+                Some((srv_name.as_str(), &server.meta.port, rollouts, rx))
             } else {
                 None
             }
         })
     }
 
+    /// Drops configuration variants for rollouts that no longer apply to their servers.
+    ///
+    /// Pods must be relinked first, so that no pod uses a dropped variant.
+    pub fn prune_variants(&mut self) {
+        for srv in self.index.values_mut() {
+            let rollouts = &srv.rollouts;
+            srv.variants
+                .retain(|set, _| set.iter().all(|n| rollouts.contains_key(n)));
+        }
+    }
+
+    /// Updates the authorizations that servers include from default-allow policies.
+    pub fn set_default_allows(&mut self, allows: &DefaultAllows) {
+        for (name, srv) in self.index.iter_mut() {
+            let authzs = mk_default_authzs(srv.meta.default_allow, allows);
+            if authzs != srv.default_authzs {
+                debug!(server = %name, "Updating default authorizations");
+                srv.default_authzs = authzs;
+                srv.send_config();
+            }
+        }
+    }
+
+    /// Renders each server for export, by name.
+    pub fn export(&self) -> BTreeMap<String, ServerData> {
+        self.index
+            .iter()
+            .map(|(name, srv)| {
+                let authorizations = srv
+                    .authorizations
+                    .keys()
+                    .chain(srv.routes.keys())
+                    .cloned()
+                    .collect::<BTreeSet<_>>();
+                let data = ServerData {
+                    port: srv.meta.port.port.clone(),
+                    port_protocol: srv.meta.port.protocol,
+                    container: srv.meta.port.container.clone(),
+                    pod_selector: (*srv.meta.pod_selector).clone(),
+                    protocol: (&srv.meta.protocol).into(),
+                    authorizations: authorizations.into_iter().collect(),
+                    default_allow: srv.meta.default_allow,
+                };
+                (name.clone(), data)
+            })
+            .collect()
+    }
+
     /// Update the index with a server instance.
-    fn apply(&mut self, srv: polixy::Server, ns_authzs: &AuthzIndex) {
+    fn apply(&mut self, srv: polixy::Server, ns_authzs: &AuthzIndex, allows: &DefaultAllows) {
         let srv_name = srv.name();
-        let port = srv.spec.port;
+        let port = PortMatch {
+            port: srv.spec.port,
+            protocol: mk_port_protocol(srv.spec.port_protocol),
+            container: srv.spec.container,
+        };
         let protocol = mk_protocol(srv.spec.proxy_protocol.as_ref());
+        let default_allow = srv.spec.default_allow;
+
+        // When servers select overlapping ports of a pod, the first server by name applies. We
+        // can't detect overlapping label selectors in general, so only identical selectors are
+        // reported.
+        for (name, other) in self.index.iter() {
+            if *name != srv_name
+                && *other.meta.pod_selector == srv.spec.pod_selector
+                && other.meta.port.overlaps(&port)
+            {
+                warn!(server = %srv_name, other = %name, "Servers select overlapping ports");
+            }
+        }
 
         match self.index.entry(srv_name) {
             HashEntry::Vacant(entry) => {
-                let labels = k8s::Labels::from(srv.metadata.labels);
-                let authzs = ns_authzs
-                    .filter_selected(entry.key(), labels.clone())
-                    .map(|(n, a)| (n, a.clone()))
-                    .collect::<BTreeMap<_, _>>();
                 let meta = ServerMeta {
-                    labels,
+                    labels: srv.metadata.labels.into(),
                     port,
                     pod_selector: srv.spec.pod_selector.into(),
                     protocol: protocol.clone(),
+                    default_allow,
                 };
-                debug!(authzs = ?authzs.keys());
+                let default_authzs = mk_default_authzs(default_allow, allows);
                 let (tx, rx) = watch::channel(InboundServer {
                     protocol,
-                    authorizations: authzs.clone(),
+                    authorizations: default_authzs.clone(),
+                    routes: BTreeMap::new(),
+                    undeclared: false,
+                    health_check_source: None,
                 });
-                entry.insert(Server {
+                let mut server = Server {
                     meta,
                     rx,
                     tx,
-                    authorizations: authzs,
-                });
+                    authorizations: BTreeMap::new(),
+                    routes: BTreeMap::new(),
+                    default_authzs,
+                    rollouts: BTreeMap::new(),
+                    variants: HashMap::new(),
+                };
+                server.select_authzs(entry.key(), ns_authzs);
+                debug!(
+                    authzs = ?server.authorizations.keys(),
+                    routes = ?server.routes.keys(),
+                    defaults = ?server.default_authzs.keys(),
+                );
+                server.send_config();
+                entry.insert(server);
             }
 
             HashEntry::Occupied(mut entry) => {
                 // If something about the server changed, we need to update the config to reflect
                 // the change.
-                let new_labels = if entry.get().meta.labels.as_ref() != &srv.metadata.labels {
-                    Some(k8s::Labels::from(srv.metadata.labels))
-                } else {
-                    None
-                };
+                //
+                // NB: Only a single task applies server updates, so it's okay to borrow a version,
+                // modify, and send it. We don't need a lock because serialization is guaranteed.
+                let mut changed = false;
+                if entry.get().meta.labels.as_ref() != &srv.metadata.labels {
+                    let name = entry.key().clone();
+                    let server = entry.get_mut();
+                    server.meta.labels = srv.metadata.labels.into();
+                    server.select_authzs(&name, ns_authzs);
+                    debug!(authzs = ?server.authorizations.keys(), routes = ?server.routes.keys());
+                    changed = true;
+                }
 
-                let new_protocol = if entry.get().meta.protocol == protocol {
-                    Some(protocol)
-                } else {
-                    None
-                };
+                if entry.get().meta.protocol != protocol {
+                    trace!(?protocol);
+                    entry.get_mut().meta.protocol = protocol;
+                    changed = true;
+                }
 
-                trace!(?new_labels, ?new_protocol);
-                if new_labels.is_some() || new_protocol.is_some() {
-                    // NB: Only a single task applies server updates, so it's
-                    // okay to borrow a version, modify, and send it.  We don't
-                    // need a lock because serialization is guaranteed.
-                    let mut config = entry.get().rx.borrow().clone();
-
-                    if let Some(labels) = new_labels {
-                        let authzs = ns_authzs
-                            .filter_selected(entry.key(), labels.clone())
-                            .map(|(n, a)| (n, a.clone()))
-                            .collect::<BTreeMap<_, _>>();
-                        debug!(authzs = ?authzs.keys());
-                        config.authorizations = authzs.clone();
-                        entry.get_mut().meta.labels = labels;
-                        entry.get_mut().authorizations = authzs;
-                    }
-
-                    if let Some(protocol) = new_protocol {
-                        config.protocol = protocol.clone();
-                        entry.get_mut().meta.protocol = protocol;
-                    }
-                    entry
-                        .get()
-                        .tx
-                        .send(config)
-                        .expect("server update must succeed");
+                if entry.get().meta.default_allow != default_allow {
+                    debug!(?default_allow);
+                    let server = entry.get_mut();
+                    server.meta.default_allow = default_allow;
+                    server.default_authzs = mk_default_authzs(default_allow, allows);
+                    changed = true;
+                }
+
+                if changed {
+                    entry.get().send_config();
                 }
 
                 // If the pod/port selector didn't change, we don't need to
@@ -164,22 +310,151 @@ impl SrvIndex {
 // === impl Server ===
 
 impl Server {
-    fn add_authz(&mut self, name: impl Into<String>, authz: ClientAuthorization) {
+    /// Adds an authorization to the server, scoped to routes if any are provided, returning
+    /// whether the server's rollouts changed.
+    fn add_authz(
+        &mut self,
+        name: impl Into<String>,
+        authz: ClientAuthorization,
+        routes: Vec<HttpRouteMatch>,
+        rollout: Option<Arc<Rollout>>,
+    ) -> bool {
         debug!("Adding authorization to server");
-        self.authorizations.insert(name.into(), authz);
-        let mut config = self.rx.borrow().clone();
-        config.authorizations = self.authorizations.clone();
-        self.tx.send(config).expect("config must send")
+        let rollouts_changed = self.insert_authz(name.into(), authz, routes, rollout);
+        self.send_config();
+        rollouts_changed
     }
 
-    fn remove_authz(&mut self, name: &str) {
-        if self.authorizations.remove(name).is_some() {
+    /// Removes an authorization from the server, returning whether the server's rollouts changed.
+    fn remove_authz(&mut self, name: &str) -> bool {
+        let removed = self.authorizations.remove(name).is_some();
+        if self.routes.remove(name).is_some() || removed {
             debug!("Removing authorization from server");
-            let mut config = self.rx.borrow().clone();
-            config.authorizations = self.authorizations.clone();
-            self.tx.send(config).expect("config must send")
+            self.send_config();
+        }
+        self.rollouts.remove(name).is_some()
+    }
+
+    /// Replaces the server's authorizations with those that select it, without publishing them.
+    fn select_authzs(&mut self, name: &str, ns_authzs: &AuthzIndex) {
+        self.authorizations.clear();
+        self.routes.clear();
+        self.rollouts.clear();
+        for authz in ns_authzs.filter_selected(name, self.meta.labels.clone()) {
+            self.insert_authz(
+                authz.name,
+                authz.clients.clone(),
+                authz.routes.to_vec(),
+                authz.rollout.cloned(),
+            );
+        }
+    }
+
+    /// Records an authorization without publishing it, returning whether the server's rollouts
+    /// changed.
+    fn insert_authz(
+        &mut self,
+        name: String,
+        authz: ClientAuthorization,
+        routes: Vec<HttpRouteMatch>,
+        rollout: Option<Arc<Rollout>>,
+    ) -> bool {
+        let rollouts_changed = self.rollouts.get(&name) != rollout.as_ref();
+        match rollout {
+            Some(rollout) => self.rollouts.insert(name.clone(), rollout),
+            None => self.rollouts.remove(&name),
+        };
+
+        if routes.is_empty() {
+            self.routes.remove(&name);
+            self.authorizations.insert(name, authz);
+        } else {
+            self.authorizations.remove(&name);
+            self.routes.insert(
+                name,
+                HttpRoute {
+                    matches: routes,
+                    authorization: authz,
+                },
+            );
+        }
+        rollouts_changed
+    }
+
+    /// Returns the configuration of a pod's ports, as determined by the rollouts that select the
+    /// pod, creating a variant of the server's configuration if necessary.
+    fn pod_rx(&mut self, pod_name: &str, labels: &k8s::Labels) -> (RolloutSet, ServerRx) {
+        let rollouts = self
+            .rollouts
+            .iter()
+            .filter(|(_, r)| r.selects(pod_name, labels))
+            .map(|(n, _)| n.clone())
+            .collect::<RolloutSet>();
+        if rollouts.is_empty() {
+            return (rollouts, self.rx.clone());
+        }
+        if let Some((_, rx)) = self.variants.get(&rollouts) {
+            return (rollouts, rx.clone());
+        }
+
+        debug!(?rollouts, "Creating server variant");
+        let (tx, rx) = watch::channel(self.rx.borrow().clone());
+        self.send_variant(&tx, &rx, &rollouts);
+        self.variants.insert(rollouts.clone(), (tx, rx.clone()));
+        (rollouts, rx)
+    }
+
+    fn send_config(&self) {
+        self.send_variant(&self.tx, &self.rx, &RolloutSet::new());
+        for (rollouts, (tx, rx)) in self.variants.iter() {
+            self.send_variant(tx, rx, rollouts);
         }
     }
+
+    /// Publishes the configuration of pods that are selected by `rollouts`.
+    fn send_variant(&self, tx: &ServerTx, rx: &ServerRx, rollouts: &RolloutSet) {
+        let (authorizations, routes) = self.variant_authzs(rollouts);
+        let mut config = rx.borrow().clone();
+        config.protocol = self.meta.protocol.clone();
+        config.authorizations = merge_authzs(&self.default_authzs, &authorizations);
+        config.routes = routes;
+        tx.send(config).expect("config must send")
+    }
+
+    /// Returns the authorizations that apply to pods selected by `rollouts`.
+    ///
+    /// These pods use the rollouts' authorizations in place of the authorizations that the
+    /// rollouts replace. Other rollouts' authorizations don't apply to them.
+    fn variant_authzs(
+        &self,
+        rollouts: &RolloutSet,
+    ) -> (
+        BTreeMap<String, ClientAuthorization>,
+        BTreeMap<String, HttpRoute>,
+    ) {
+        let replaced = rollouts
+            .iter()
+            .filter_map(|n| self.rollouts.get(n)?.replaces.as_deref())
+            .collect::<HashSet<_>>();
+        let applies = |name: &String| {
+            !replaced.contains(name.as_str())
+                && (!self.rollouts.contains_key(name) || rollouts.contains(name))
+        };
+
+        let authorizations = self
+            .authorizations
+            .iter()
+            .filter(|(n, _)| applies(n))
+            .map(|(n, a)| (n.clone(), a.clone()))
+            .collect();
+        let routes = self
+            .routes
+            .iter()
+            .filter(|(n, _)| applies(n))
+            .map(|(n, r)| (n.clone(), r.clone()))
+            .collect();
+        (authorizations, routes)
+    }
 }
 
 // === impl Index ===
@@ -200,13 +475,15 @@ impl Index {
             ref mut authzs,
             ref mut servers,
             default_allow: _,
-        } = self.namespaces.get_or_default(ns_name);
+        } = self.namespaces.get_or_default(ns_name.clone());
 
-        servers.apply(srv, authzs);
+        servers.apply(srv, authzs, &self.default_allows);
 
         // If we've updated the server->pod selection, then we need to re-index
         // all pods and servers.
         pods.link_servers(servers);
+
+        self.publish_policy(ns_name.as_str());
     }
 
     #[instrument(
@@ -231,9 +508,12 @@ impl Index {
             bail!("removing non-existent server {}", srv_name);
         }
 
-        // Reset the server config for all pods that were using this server.
+        // Reset the server config for all pods that were using this server and relink them, in
+        // case another server selects the same ports.
         ns.pods.reset_server(srv_name);
+        ns.pods.link_servers(&mut ns.servers);
 
+        self.publish_policy(ns_name);
         debug!("Removed server");
         Ok(())
     }
@@ -272,6 +552,37 @@ impl Index {
     }
 }
 
+/// Resolves the authorizations of a server's default-allow policy. Servers deny by default.
+fn mk_default_authzs(
+    default_allow: Option<polixy::server::DefaultAllow>,
+    allows: &DefaultAllows,
+) -> BTreeMap<String, ClientAuthorization> {
+    match default_allow {
+        Some(da) => allows.authorizations(da.into()),
+        None => BTreeMap::new(),
+    }
+}
+
+/// Combines a server's default authorizations with its explicit authorizations.
+fn merge_authzs(
+    defaults: &BTreeMap<String, ClientAuthorization>,
+    authzs: &BTreeMap<String, ClientAuthorization>,
+) -> BTreeMap<String, ClientAuthorization> {
+    defaults
+        .iter()
+        .chain(authzs.iter())
+        .map(|(n, a)| (n.clone(), a.clone()))
+        .collect()
+}
+
+fn mk_port_protocol(p: Option<polixy::server::PortProtocol>) -> PortProtocol {
+    match p {
+        Some(polixy::server::PortProtocol::Tcp) | None => PortProtocol::Tcp,
+        Some(polixy::server::PortProtocol::Udp) => PortProtocol::Udp,
+        Some(polixy::server::PortProtocol::Sctp) => PortProtocol::Sctp,
+    }
+}
+
 fn mk_protocol(p: Option<&polixy::server::ProxyProtocol>) -> ProxyProtocol {
     match p {
         Some(polixy::server::ProxyProtocol::Unknown) | None => ProxyProtocol::Detect {
@@ -1,11 +1,12 @@
 use super::*;
 use futures::prelude::*;
 use polixy_controller_core::{
-    ClientAuthentication, ClientAuthorization, IdentityMatch, IpNet, Ipv4Net, Ipv6Net,
-    NetworkMatch, ProxyProtocol,
+    ClientAuthentication, ClientAuthorization, ClientTls, HttpRoute, HttpRouteMatch,
+    IdentifyTarget, IdentityMatch, IpNet, Ipv4Net, Ipv6Net, NetworkMatch, PathMatch, PortProtocol,
+    ProxyProtocol, TargetIdentity,
 };
 use polixy_controller_k8s_api::polixy::server::Port;
-use std::{collections::BTreeMap, net::IpAddr, str::FromStr};
+use std::{collections::BTreeMap, net::IpAddr, str::FromStr, time::SystemTime};
 use tokio::time;
 
 /// Creates a pod, then a server, then an authorization--then deletes these resources in the reverse
@@ -22,10 +23,14 @@ async fn incrementally_configure_server() {
     let (lookup_tx, lookup_rx) = crate::lookup::pair();
     let mut idx = Index::new(
         lookup_tx,
-        vec![cluster_net],
-        "cluster.example.com".into(),
-        DefaultAllow::ClusterUnauthenticated,
-        detect_timeout,
+        Config {
+            cluster_networks: ClusterNetworks::Static(vec![cluster_net]),
+            identity_domains: IdentityDomains::new("cluster.example.com".into()),
+            default_allow: DefaultAllow::ClusterUnauthenticated,
+            undeclared_ports: UndeclaredPorts::DefaultAllow,
+            kubelet_source: KubeletSource::default(),
+            detect_timeout,
+        },
     );
 
     idx.apply_node(mk_node("node-0", pod_net)).unwrap();
@@ -40,6 +45,7 @@ async fn incrementally_configure_server() {
     idx.apply_pod(pod.clone()).unwrap();
 
     let default_config = InboundServer {
+        routes: Default::default(),
         authorizations: mk_default_allow(
             DefaultAllow::ClusterUnauthenticated,
             cluster_net,
@@ -48,18 +54,33 @@ async fn incrementally_configure_server() {
         protocol: ProxyProtocol::Detect {
             timeout: detect_timeout,
         },
+        undeclared: false,
+        health_check_source: Some("pod-cidr".to_string()),
     };
 
-    // A port that's not exposed by the pod is not found.
-    assert!(lookup_rx.lookup("ns-0", "pod-0", 7000).is_none());
+    // A port that's not exposed by the pod uses the default policy but is marked as undeclared.
+    let port7000 = lookup_rx
+        .lookup("ns-0", "pod-0", 7000, PortProtocol::Tcp)
+        .unwrap();
+    assert_eq!(
+        port7000.get(),
+        InboundServer {
+            undeclared: true,
+            ..default_config.clone()
+        }
+    );
 
     // The default policy applies for all exposed ports.
-    let port2222 = lookup_rx.lookup("ns-0", "pod-0", 2222).unwrap();
+    let port2222 = lookup_rx
+        .lookup("ns-0", "pod-0", 2222, PortProtocol::Tcp)
+        .unwrap();
     assert_eq!(port2222.get(), default_config);
 
     // In fact, both port resolutions should point to the same data structures (rather than being
     // duplicated for each pod).
-    let port9999 = lookup_rx.lookup("ns-0", "pod-0", 9999).unwrap();
+    let port9999 = lookup_rx
+        .lookup("ns-0", "pod-0", 9999, PortProtocol::Tcp)
+        .unwrap();
     assert_eq!(port9999.get(), default_config);
 
     // Update the server on port 2222 to have a configured protocol.
@@ -73,8 +94,11 @@ async fn incrementally_configure_server() {
     // Check that the watch has been updated to reflect the above change and that this change _only_
     // applies to the correct port.
     let basic_config = InboundServer {
+        routes: Default::default(),
         protocol: ProxyProtocol::Http1,
         authorizations: vec![healthcheck_authz(kubelet_ip)].into_iter().collect(),
+        undeclared: false,
+        health_check_source: Some("pod-cidr".to_string()),
     };
     assert_eq!(port2222.get(), basic_config);
     assert_eq!(port9999.get(), default_config);
@@ -98,6 +122,7 @@ async fn incrementally_configure_server() {
     assert_eq!(
         time::timeout(time::Duration::from_secs(1), rx.next()).await,
         Ok(Some(InboundServer {
+            routes: Default::default(),
             protocol: ProxyProtocol::Http1,
             authorizations: vec![
                 (
@@ -111,6 +136,8 @@ async fn incrementally_configure_server() {
             ]
             .into_iter()
             .collect(),
+            undeclared: false,
+            health_check_source: Some("pod-cidr".to_string()),
         }))
     );
 
@@ -148,10 +175,14 @@ async fn server_update_deselects_pod() {
     let (lookup_tx, lookup_rx) = crate::lookup::pair();
     let mut idx = Index::new(
         lookup_tx,
-        vec![cluster_net],
-        "cluster.example.com".into(),
-        DefaultAllow::ClusterUnauthenticated,
-        detect_timeout,
+        Config {
+            cluster_networks: ClusterNetworks::Static(vec![cluster_net]),
+            identity_domains: IdentityDomains::new("cluster.example.com".into()),
+            default_allow: DefaultAllow::ClusterUnauthenticated,
+            undeclared_ports: UndeclaredPorts::DefaultAllow,
+            kubelet_source: KubeletSource::default(),
+            detect_timeout,
+        },
     );
 
     idx.apply_node(mk_node("node-0", pod_net)).unwrap();
@@ -172,12 +203,17 @@ async fn server_update_deselects_pod() {
     idx.apply_server(srv.clone());
 
     // The default policy applies for all exposed ports.
-    let port2222 = lookup_rx.lookup("ns-0", "pod-0", 2222).unwrap();
+    let port2222 = lookup_rx
+        .lookup("ns-0", "pod-0", 2222, PortProtocol::Tcp)
+        .unwrap();
     assert_eq!(
         port2222.get(),
         InboundServer {
+            routes: Default::default(),
             protocol: ProxyProtocol::Http2,
             authorizations: vec![healthcheck_authz(kubelet_ip)].into_iter().collect(),
+            undeclared: false,
+            health_check_source: Some("pod-cidr".to_string()),
         }
     );
 
@@ -189,6 +225,7 @@ async fn server_update_deselects_pod() {
     assert_eq!(
         port2222.get(),
         InboundServer {
+            routes: Default::default(),
             authorizations: mk_default_allow(
                 DefaultAllow::ClusterUnauthenticated,
                 cluster_net,
@@ -197,6 +234,8 @@ async fn server_update_deselects_pod() {
             protocol: ProxyProtocol::Detect {
                 timeout: detect_timeout,
             },
+            undeclared: false,
+            health_check_source: Some("pod-cidr".to_string()),
         }
     );
 }
@@ -224,10 +263,14 @@ async fn default_allow_global() {
         let (lookup_tx, lookup_rx) = crate::lookup::pair();
         let mut idx = Index::new(
             lookup_tx,
-            vec![cluster_net],
-            "cluster.example.com".into(),
-            *default,
-            detect_timeout,
+            Config {
+                cluster_networks: ClusterNetworks::Static(vec![cluster_net]),
+                identity_domains: IdentityDomains::new("cluster.example.com".into()),
+                default_allow: *default,
+                undeclared_ports: UndeclaredPorts::DefaultAllow,
+                kubelet_source: KubeletSource::default(),
+                detect_timeout,
+            },
         );
 
         idx.reset_nodes(vec![mk_node("node-0", pod_net)]).unwrap();
@@ -242,15 +285,18 @@ async fn default_allow_global() {
         idx.reset_pods(vec![p]).unwrap();
 
         let config = InboundServer {
+            routes: Default::default(),
             authorizations: mk_default_allow(*default, cluster_net, kubelet_ip),
             protocol: ProxyProtocol::Detect {
                 timeout: detect_timeout,
             },
+            undeclared: false,
+            health_check_source: Some("pod-cidr".to_string()),
         };
 
         // Lookup port 2222 -> default config.
         let port2222 = lookup_rx
-            .lookup("ns-0", "pod-0", 2222)
+            .lookup("ns-0", "pod-0", 2222, PortProtocol::Tcp)
             .expect("pod must exist in lookups");
         assert_eq!(port2222.get(), config);
     }
@@ -280,13 +326,17 @@ async fn default_allow_annotated() {
         let (lookup_tx, lookup_rx) = crate::lookup::pair();
         let mut idx = Index::new(
             lookup_tx,
-            vec![cluster_net],
-            "cluster.example.com".into(),
-            match *default {
-                DefaultAllow::Deny => DefaultAllow::AllUnauthenticated,
-                _ => DefaultAllow::Deny,
+            Config {
+                cluster_networks: ClusterNetworks::Static(vec![cluster_net]),
+                identity_domains: IdentityDomains::new("cluster.example.com".into()),
+                default_allow: match *default {
+                    DefaultAllow::Deny => DefaultAllow::AllUnauthenticated,
+                    _ => DefaultAllow::Deny,
+                },
+                undeclared_ports: UndeclaredPorts::DefaultAllow,
+                kubelet_source: KubeletSource::default(),
+                detect_timeout,
             },
-            detect_timeout,
         );
 
         idx.reset_nodes(vec![mk_node("node-0", pod_net)]).unwrap();
@@ -303,14 +353,17 @@ async fn default_allow_annotated() {
         idx.reset_pods(vec![p]).unwrap();
 
         let config = InboundServer {
+            routes: Default::default(),
             authorizations: mk_default_allow(*default, cluster_net, kubelet_ip),
             protocol: ProxyProtocol::Detect {
                 timeout: detect_timeout,
             },
+            undeclared: false,
+            health_check_source: Some("pod-cidr".to_string()),
         };
 
         let port2222 = lookup_rx
-            .lookup("ns-0", "pod-0", 2222)
+            .lookup("ns-0", "pod-0", 2222, PortProtocol::Tcp)
             .expect("pod must exist in lookups");
         assert_eq!(port2222.get(), config);
     }
@@ -330,10 +383,14 @@ async fn default_allow_annotated_invalid() {
     let (lookup_tx, lookup_rx) = crate::lookup::pair();
     let mut idx = Index::new(
         lookup_tx,
-        vec![cluster_net],
-        "cluster.example.com".into(),
-        DefaultAllow::AllUnauthenticated,
-        detect_timeout,
+        Config {
+            cluster_networks: ClusterNetworks::Static(vec![cluster_net]),
+            identity_domains: IdentityDomains::new("cluster.example.com".into()),
+            default_allow: DefaultAllow::AllUnauthenticated,
+            undeclared_ports: UndeclaredPorts::DefaultAllow,
+            kubelet_source: KubeletSource::default(),
+            detect_timeout,
+        },
     );
 
     idx.reset_nodes(vec![mk_node("node-0", pod_net)]).unwrap();
@@ -351,11 +408,12 @@ async fn default_allow_annotated_invalid() {
 
     // Lookup port 2222 -> default config.
     let port2222 = lookup_rx
-        .lookup("ns-0", "pod-0", 2222)
+        .lookup("ns-0", "pod-0", 2222, PortProtocol::Tcp)
         .expect("pod must exist in lookups");
     assert_eq!(
         port2222.get(),
         InboundServer {
+            routes: Default::default(),
             authorizations: mk_default_allow(
                 DefaultAllow::AllUnauthenticated,
                 cluster_net,
@@ -364,6 +422,8 @@ async fn default_allow_annotated_invalid() {
             protocol: ProxyProtocol::Detect {
                 timeout: detect_timeout,
             },
+            undeclared: false,
+            health_check_source: Some("pod-cidr".to_string()),
         }
     );
 }
@@ -382,10 +442,14 @@ async fn pod_before_node_reset() {
     let (lookup_tx, lookup_rx) = crate::lookup::pair();
     let mut idx = Index::new(
         lookup_tx,
-        vec![cluster_net],
-        "cluster.example.com".into(),
-        DefaultAllow::Deny,
-        detect_timeout,
+        Config {
+            cluster_networks: ClusterNetworks::Static(vec![cluster_net]),
+            identity_domains: IdentityDomains::new("cluster.example.com".into()),
+            default_allow: DefaultAllow::Deny,
+            undeclared_ports: UndeclaredPorts::DefaultAllow,
+            kubelet_source: KubeletSource::default(),
+            detect_timeout,
+        },
     );
 
     // First we create a pod for which the node has not yet been observed so that it's marked as
@@ -398,7 +462,9 @@ async fn pod_before_node_reset() {
         Some(("container-0", vec![2222])),
     );
     idx.reset_pods(vec![p]).unwrap();
-    assert!(lookup_rx.lookup("ns-0", "pod-0", 2222).is_none());
+    assert!(lookup_rx
+        .lookup("ns-0", "pod-0", 2222, PortProtocol::Tcp)
+        .is_none());
 
     // Then we reset with a new pod which will be pending on the same node.
     let p = mk_pod(
@@ -415,11 +481,13 @@ async fn pod_before_node_reset() {
 
     // Once the node is created, the first pod should not be discoverable but the second pod should be.
     assert!(
-        lookup_rx.lookup("ns-0", "pod-0", 2222).is_none(),
+        lookup_rx
+            .lookup("ns-0", "pod-0", 2222, PortProtocol::Tcp)
+            .is_none(),
         "first pod must not exist"
     );
     lookup_rx
-        .lookup("ns-0", "pod-1", 3333)
+        .lookup("ns-0", "pod-1", 3333, PortProtocol::Tcp)
         .expect("second pod must exist");
 }
 
@@ -437,10 +505,14 @@ async fn pod_before_node_remove() {
     let (lookup_tx, lookup_rx) = crate::lookup::pair();
     let mut idx = Index::new(
         lookup_tx,
-        vec![cluster_net],
-        "cluster.example.com".into(),
-        DefaultAllow::Deny,
-        detect_timeout,
+        Config {
+            cluster_networks: ClusterNetworks::Static(vec![cluster_net]),
+            identity_domains: IdentityDomains::new("cluster.example.com".into()),
+            default_allow: DefaultAllow::Deny,
+            undeclared_ports: UndeclaredPorts::DefaultAllow,
+            kubelet_source: KubeletSource::default(),
+            detect_timeout,
+        },
     );
 
     // First we create a pod for which the node has not yet been observed so that it's marked as
@@ -453,7 +525,9 @@ async fn pod_before_node_remove() {
         Some(("container-0", vec![2222])),
     );
     idx.reset_pods(vec![pod.clone()]).unwrap();
-    assert!(lookup_rx.lookup("ns-0", "pod-0", 2222).is_none());
+    assert!(lookup_rx
+        .lookup("ns-0", "pod-0", 2222, PortProtocol::Tcp)
+        .is_none());
 
     // Then we delete that pod without updating the nodes.
     idx.delete_pod(pod).unwrap();
@@ -462,140 +536,1879 @@ async fn pod_before_node_remove() {
     idx.reset_nodes(vec![mk_node("node-0", pod_net)]).unwrap();
 
     // Once the node is created, the pod must not be discoverable.
-    assert!(lookup_rx.lookup("ns-0", "pod-0", 2222).is_none());
+    assert!(lookup_rx
+        .lookup("ns-0", "pod-0", 2222, PortProtocol::Tcp)
+        .is_none());
 }
 
-// === Helpers ===
-
-fn mk_node(name: impl Into<String>, pod_net: IpNet) -> k8s::Node {
-    k8s::Node {
-        metadata: k8s::ObjectMeta {
-            name: Some(name.into()),
-            ..Default::default()
+/// Tests that updates to a node's pod CIDRs update the kubelet IPs authorized on its pods' ports.
+#[tokio::test]
+async fn node_update_kubelet_ips() {
+    let cluster_net = IpNet::from_str("192.0.2.0/24").unwrap();
+    let pod_net = IpNet::from_str("192.0.2.2/28").unwrap();
+    let (kubelet_ip, pod_ip) = {
+        let mut ips = pod_net.hosts();
+        (ips.next().unwrap(), ips.next().unwrap())
+    };
+    let detect_timeout = time::Duration::from_secs(1);
+    let (lookup_tx, lookup_rx) = crate::lookup::pair();
+    let mut idx = Index::new(
+        lookup_tx,
+        Config {
+            cluster_networks: ClusterNetworks::Static(vec![cluster_net]),
+            identity_domains: IdentityDomains::new("cluster.example.com".into()),
+            default_allow: DefaultAllow::ClusterUnauthenticated,
+            undeclared_ports: UndeclaredPorts::DefaultAllow,
+            kubelet_source: KubeletSource::default(),
+            detect_timeout,
         },
-        spec: Some(k8s::api::core::v1::NodeSpec {
-            pod_cidr: Some(pod_net.to_string()),
-            pod_cidrs: vec![pod_net.to_string()],
-            ..Default::default()
-        }),
-        status: Some(k8s::api::core::v1::NodeStatus::default()),
-    }
+    );
+
+    idx.apply_node(mk_node("node-0", pod_net)).unwrap();
+    idx.apply_pod(mk_pod(
+        "ns-0",
+        "pod-0",
+        "node-0",
+        pod_ip,
+        Some(("container-0", vec![2222])),
+    ))
+    .unwrap();
+
+    let port2222 = lookup_rx
+        .lookup("ns-0", "pod-0", 2222, PortProtocol::Tcp)
+        .unwrap();
+    let port7000 = lookup_rx
+        .lookup("ns-0", "pod-0", 7000, PortProtocol::Tcp)
+        .unwrap();
+    let health_check =
+        |port: &crate::lookup::Rx| port.get().authorizations["_health_check"].clone();
+    assert_eq!(health_check(&port2222), healthcheck_authz(kubelet_ip).1);
+
+    let mut rx = port2222.clone().into_stream();
+    assert_eq!(
+        time::timeout(time::Duration::from_secs(1), rx.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .authorizations["_health_check"],
+        healthcheck_authz(kubelet_ip).1
+    );
+
+    // Changing the node's pod CIDR updates the kubelet IP of declared and undeclared ports.
+    let new_pod_net = IpNet::from_str("192.0.2.16/28").unwrap();
+    let new_kubelet_ip = new_pod_net.hosts().next().unwrap();
+    idx.apply_node(mk_node("node-0", new_pod_net)).unwrap();
+    assert_eq!(health_check(&port2222), healthcheck_authz(new_kubelet_ip).1);
+    assert_eq!(health_check(&port7000), healthcheck_authz(new_kubelet_ip).1);
+    assert_eq!(
+        time::timeout(time::Duration::from_secs(1), rx.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .authorizations["_health_check"],
+        healthcheck_authz(new_kubelet_ip).1
+    );
+
+    // Resetting nodes also applies updates.
+    idx.reset_nodes(vec![mk_node("node-0", pod_net)]).unwrap();
+    assert_eq!(health_check(&port2222), healthcheck_authz(kubelet_ip).1);
 }
 
-fn mk_pod(
-    ns: impl Into<String>,
-    name: impl Into<String>,
-    node: impl Into<String>,
-    pod_ip: IpAddr,
-    containers: impl IntoIterator<Item = (impl Into<String>, impl IntoIterator<Item = u16>)>,
-) -> k8s::Pod {
-    k8s::Pod {
-        metadata: k8s::ObjectMeta {
-            namespace: Some(ns.into()),
-            name: Some(name.into()),
-            ..Default::default()
+/// Tests that cluster networks may be discovered from nodes' pod CIDRs and service networks.
+#[tokio::test]
+async fn discover_cluster_networks() {
+    let service_net = IpNet::from_str("198.51.100.0/24").unwrap();
+    let pod_net = IpNet::from_str("192.0.2.0/28").unwrap();
+    let (kubelet_ip, pod_ip) = {
+        let mut ips = pod_net.hosts();
+        (ips.next().unwrap(), ips.next().unwrap())
+    };
+    let detect_timeout = time::Duration::from_secs(1);
+    let (lookup_tx, lookup_rx) = crate::lookup::pair();
+    let mut idx = Index::new(
+        lookup_tx,
+        Config {
+            cluster_networks: ClusterNetworks::Discover {
+                service_networks: vec![service_net],
+            },
+            identity_domains: IdentityDomains::new("cluster.example.com".into()),
+            default_allow: DefaultAllow::ClusterUnauthenticated,
+            undeclared_ports: UndeclaredPorts::DefaultAllow,
+            kubelet_source: KubeletSource::default(),
+            detect_timeout,
         },
-        spec: Some(k8s::api::core::v1::PodSpec {
-            node_name: Some(node.into()),
-            containers: containers
-                .into_iter()
-                .map(|(name, ports)| k8s::api::core::v1::Container {
-                    name: name.into(),
-                    ports: ports
-                        .into_iter()
-                        .map(|p| k8s::api::core::v1::ContainerPort {
-                            container_port: p as i32,
-                            ..Default::default()
-                        })
-                        .collect(),
-                    ..Default::default()
-                })
-                .collect(),
-            ..Default::default()
-        }),
-        status: Some(k8s::api::core::v1::PodStatus {
-            pod_ips: vec![k8s::api::core::v1::PodIP {
-                ip: Some(pod_ip.to_string()),
-            }],
-            ..Default::default()
-        }),
-    }
+    );
+
+    idx.apply_node(mk_node("node-0", pod_net)).unwrap();
+    idx.apply_pod(mk_pod(
+        "ns-0",
+        "pod-0",
+        "node-0",
+        pod_ip,
+        Some(("container-0", vec![2222])),
+    ))
+    .unwrap();
+
+    let port2222 = lookup_rx
+        .lookup("ns-0", "pod-0", 2222, PortProtocol::Tcp)
+        .unwrap();
+    let cluster_nets = || {
+        port2222.get().authorizations["_cluster_unauthed"]
+            .networks
+            .iter()
+            .map(|n| n.net)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(cluster_nets(), vec![pod_net, service_net]);
+
+    let mut rx = port2222.clone().into_stream();
+    assert_eq!(
+        time::timeout(time::Duration::from_secs(1), rx.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .authorizations["_health_check"],
+        healthcheck_authz(kubelet_ip).1
+    );
+
+    // Adding a node (with an IPv6 pod network) updates the policy, aggregating adjacent networks.
+    let mut node = mk_node("node-1", IpNet::from_str("192.0.2.16/28").unwrap());
+    node.spec
+        .as_mut()
+        .unwrap()
+        .pod_cidrs
+        .push("2001:db8::/64".to_string());
+    idx.apply_node(node).unwrap();
+    let nets = vec![
+        IpNet::from_str("192.0.2.0/27").unwrap(),
+        service_net,
+        IpNet::from_str("2001:db8::/64").unwrap(),
+    ];
+    assert_eq!(cluster_nets(), nets);
+    let update = time::timeout(time::Duration::from_secs(1), rx.next())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        update.authorizations["_cluster_unauthed"].networks,
+        nets.into_iter().map(NetworkMatch::from).collect::<Vec<_>>()
+    );
+
+    // Removing the node restores the prior networks.
+    idx.delete_node("node-1").unwrap();
+    assert_eq!(cluster_nets(), vec![pod_net, service_net]);
 }
 
-fn mk_server(
-    ns: impl Into<String>,
-    name: impl Into<String>,
-    port: Port,
-    srv_labels: impl IntoIterator<Item = (&'static str, &'static str)>,
-    pod_labels: impl IntoIterator<Item = (&'static str, &'static str)>,
-) -> k8s::polixy::Server {
-    k8s::polixy::Server {
-        api_version: "v1alpha1".to_string(),
-        kind: "Server".to_string(),
+/// Tests that authorizations resolve client pod and namespace selectors to the selected pods' IPs.
+#[tokio::test]
+async fn client_pod_selectors() {
+    let cluster_net = IpNet::from_str("192.0.2.0/24").unwrap();
+    let pod_net = IpNet::from_str("192.0.2.2/28").unwrap();
+    let pod_ip = pod_net.hosts().nth(1).unwrap();
+    let detect_timeout = time::Duration::from_secs(1);
+    let (lookup_tx, lookup_rx) = crate::lookup::pair();
+    let mut idx = Index::new(
+        lookup_tx,
+        Config {
+            cluster_networks: ClusterNetworks::Static(vec![cluster_net]),
+            identity_domains: IdentityDomains::new("cluster.example.com".into()),
+            default_allow: DefaultAllow::ClusterUnauthenticated,
+            undeclared_ports: UndeclaredPorts::DefaultAllow,
+            kubelet_source: KubeletSource::default(),
+            detect_timeout,
+        },
+    );
+
+    idx.apply_node(mk_node("node-0", pod_net)).unwrap();
+    idx.apply_pod(mk_pod(
+        "ns-0",
+        "pod-0",
+        "node-0",
+        pod_ip,
+        Some(("container-0", vec![8080])),
+    ))
+    .unwrap();
+    idx.apply_server({
+        let mut srv = mk_server("ns-0", "srv-0", Port::Number(8080), None, None);
+        srv.spec.proxy_protocol = Some(k8s::polixy::server::ProxyProtocol::Http1);
+        srv
+    });
+
+    let mut authz = mk_authz("ns-0", "authz-0", "srv-0");
+    authz.spec.client = k8s::polixy::authz::Client {
+        unauthenticated: true,
+        pod_selector: Some(Some(("app", "vote-bot")).into_iter().collect()),
+        ..Default::default()
+    };
+    idx.apply_authz(authz.clone()).unwrap();
+
+    let port8080 = lookup_rx
+        .lookup("ns-0", "pod-0", 8080, PortProtocol::Tcp)
+        .unwrap();
+    let networks = || {
+        port8080
+            .get()
+            .authorizations
+            .get("authz-0")
+            .map(|a| a.networks.iter().map(|n| n.net).collect::<Vec<_>>())
+    };
+    let mut rx = port8080.clone().into_stream();
+    assert!(rx.next().await.is_some());
+
+    // No clients are authorized until a matching pod is indexed.
+    assert_eq!(networks(), Some(vec![]));
+
+    let mk_client = |ns: &str, name: &str, ip: &str| {
+        let mut pod = mk_pod(
+            ns,
+            name,
+            "node-1",
+            IpAddr::from_str(ip).unwrap(),
+            None::<(String, Vec<u16>)>,
+        );
+        pod.metadata
+            .labels
+            .insert("app".to_string(), "vote-bot".to_string());
+        pod
+    };
+    idx.reset_client_pods(vec![
+        mk_client("ns-0", "vote-bot-0", "192.0.2.20"),
+        mk_client("ns-1", "vote-bot-1", "192.0.2.21"),
+    ])
+    .unwrap();
+    assert_eq!(
+        networks(),
+        Some(vec![IpNet::from_str("192.0.2.20/32").unwrap()])
+    );
+    assert!(time::timeout(time::Duration::from_secs(1), rx.next())
+        .await
+        .unwrap()
+        .is_some());
+
+    // Namespace selectors select pods in other namespaces by their namespace's labels.
+    authz.spec.client.namespace_selector = Some(Some(("env", "test")).into_iter().collect());
+    idx.apply_authz(authz.clone()).unwrap();
+    assert_eq!(networks(), Some(vec![]));
+    idx.apply_namespace(k8s::Namespace {
         metadata: k8s::ObjectMeta {
-            namespace: Some(ns.into()),
-            name: Some(name.into()),
-            labels: srv_labels
+            name: Some("ns-1".to_string()),
+            labels: Some(("env".to_string(), "test".to_string()))
                 .into_iter()
-                .map(|(k, v)| (k.to_string(), v.to_string()))
                 .collect(),
             ..Default::default()
         },
-        spec: k8s::polixy::ServerSpec {
-            port,
-            pod_selector: pod_labels.into_iter().collect(),
-            proxy_protocol: None,
-        },
-    }
-}
+        spec: None,
+        status: None,
+    })
+    .unwrap();
+    assert_eq!(
+        networks(),
+        Some(vec![IpNet::from_str("192.0.2.21/32").unwrap()])
+    );
 
-fn mk_authz(
-    ns: impl Into<String>,
-    name: impl Into<String>,
-    server: impl Into<String>,
-) -> k8s::polixy::ServerAuthorization {
-    k8s::polixy::ServerAuthorization {
-        api_version: "v1alpha1".to_string(),
-        kind: "ServerAuthorization".to_string(),
-        metadata: k8s::ObjectMeta {
-            namespace: Some(ns.into()),
-            name: Some(name.into()),
-            ..Default::default()
-        },
-        spec: k8s::polixy::ServerAuthorizationSpec {
-            server: k8s::polixy::authz::Server {
-                name: Some(server.into()),
-                selector: None,
-            },
-            client: k8s::polixy::authz::Client {
-                // TODO
-                ..Default::default()
-            },
-        },
-    }
+    // Pods that are removed or terminate are no longer authorized.
+    let mut done = mk_client("ns-1", "vote-bot-1", "192.0.2.21");
+    done.status.as_mut().unwrap().phase = Some("Succeeded".to_string());
+    idx.apply_client_pod(done).unwrap();
+    assert_eq!(networks(), Some(vec![]));
+
+    // Selectors may not be combined with networks.
+    authz.spec.client.networks = Some(vec![]);
+    assert!(idx.apply_authz(authz.clone()).is_err());
+
+    // Selectors are rejected when client pods aren't watched.
+    authz.spec.client.networks = None;
+    idx.client_pods = ClientPodIndex::disabled();
+    assert!(idx.apply_authz(authz).is_err());
 }
 
-fn mk_default_allow(
-    da: DefaultAllow,
-    cluster_net: IpNet,
-    kubelet_ip: IpAddr,
-) -> BTreeMap<String, ClientAuthorization> {
-    let all_nets = vec![Ipv4Net::default().into(), Ipv6Net::default().into()];
+/// Tests that a server's default-allow policy is merged with its authorizations.
+#[tokio::test]
+async fn server_default_allow() {
+    let cluster_net = IpNet::from_str("192.0.2.0/24").unwrap();
+    let pod_net = IpNet::from_str("192.0.2.2/28").unwrap();
+    let (kubelet_ip, pod_ip) = {
+        let mut ips = pod_net.hosts();
+        (ips.next().unwrap(), ips.next().unwrap())
+    };
+    let detect_timeout = time::Duration::from_secs(1);
+    let config = Config {
+        cluster_networks: ClusterNetworks::Static(vec![cluster_net]),
+        identity_domains: IdentityDomains::new("cluster.example.com".into()),
+        default_allow: DefaultAllow::AllUnauthenticated,
+        undeclared_ports: UndeclaredPorts::DefaultAllow,
+        kubelet_source: KubeletSource::default(),
+        detect_timeout,
+    };
+    let (lookup_tx, lookup_rx) = crate::lookup::pair();
+    let mut idx = Index::new(lookup_tx, config.clone());
 
-    let cluster_nets = vec![NetworkMatch::from(cluster_net)];
+    idx.apply_node(mk_node("node-0", pod_net)).unwrap();
+    idx.apply_pod(mk_pod(
+        "ns-0",
+        "pod-0",
+        "node-0",
+        pod_ip,
+        Some(("container-0", vec![8080])),
+    ))
+    .unwrap();
 
-    let authed = ClientAuthentication::TlsAuthenticated(vec![IdentityMatch::Suffix(vec![])]);
+    let mut srv = mk_server("ns-0", "srv-0", Port::Number(8080), None, None);
+    srv.spec.proxy_protocol = Some(k8s::polixy::server::ProxyProtocol::Http1);
+    srv.spec.default_allow = Some(k8s::polixy::server::DefaultAllow::ClusterUnauthenticated);
+    idx.apply_server(srv.clone());
 
-    match da {
-        DefaultAllow::Deny => None,
-        DefaultAllow::AllAuthenticated => Some((
-            "_all_authed".into(),
-            ClientAuthorization {
-                authentication: authed,
-                networks: all_nets,
-            },
-        )),
-        DefaultAllow::AllUnauthenticated => Some((
+    let mut authz = mk_authz("ns-0", "authz-0", "srv-0");
+    authz.spec.client = k8s::polixy::authz::Client {
+        mesh_tls: Some(k8s::polixy::authz::MeshTls {
+            unauthenticated_tls: true,
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    idx.apply_authz(authz).unwrap();
+
+    let port8080 = lookup_rx
+        .lookup("ns-0", "pod-0", 8080, PortProtocol::Tcp)
+        .unwrap();
+    let authz_client = ClientAuthorization {
+        authentication: ClientAuthentication::TlsUnauthenticated,
+        networks: vec![Ipv4Net::default().into(), Ipv6Net::default().into()],
+    };
+    let authzs = |cluster_net: IpNet| {
+        let mut authzs = mk_default_allow(
+            DefaultAllow::ClusterUnauthenticated,
+            cluster_net,
+            kubelet_ip,
+        );
+        authzs.insert("authz-0".to_string(), authz_client.clone());
+        authzs
+    };
+    assert_eq!(port8080.get().authorizations, authzs(cluster_net));
+
+    // The server's default authorization follows changes to the cluster networks.
+    let cluster_net = IpNet::from_str("198.51.100.0/24").unwrap();
+    idx.apply_config(Config {
+        cluster_networks: ClusterNetworks::Static(vec![cluster_net]),
+        ..config
+    })
+    .unwrap();
+    assert_eq!(port8080.get().authorizations, authzs(cluster_net));
+
+    // Tightening the server to `deny` leaves only its authorizations.
+    srv.spec.default_allow = Some(k8s::polixy::server::DefaultAllow::Deny);
+    idx.apply_server(srv);
+    assert_eq!(
+        port8080.get().authorizations,
+        vec![
+            ("authz-0".to_string(), authz_client),
+            healthcheck_authz(kubelet_ip)
+        ]
+        .into_iter()
+        .collect()
+    );
+}
+
+/// Tests that authorizations are added to and removed from servers as their time windows open and
+/// close, with time driven by tokio's paused clock.
+#[tokio::test(start_paused = true)]
+async fn authorization_time_windows() {
+    let cluster_net = IpNet::from_str("192.0.2.0/24").unwrap();
+    let pod_net = IpNet::from_str("192.0.2.2/28").unwrap();
+    let (kubelet_ip, pod_ip) = {
+        let mut ips = pod_net.hosts();
+        (ips.next().unwrap(), ips.next().unwrap())
+    };
+    let epoch = SystemTime::UNIX_EPOCH + time::Duration::from_secs(1_600_000_000);
+    let clock = PausedClock {
+        start: time::Instant::now(),
+        epoch,
+    };
+    let (lookup_tx, lookup_rx) = crate::lookup::pair();
+    let (transitions_tx, mut transitions_rx) = tokio::sync::mpsc::unbounded_channel();
+    let mut idx = Index::new(
+        lookup_tx,
+        Config {
+            cluster_networks: ClusterNetworks::Static(vec![cluster_net]),
+            identity_domains: IdentityDomains::new("cluster.example.com".into()),
+            default_allow: DefaultAllow::Deny,
+            undeclared_ports: UndeclaredPorts::DefaultAllow,
+            kubelet_source: KubeletSource::default(),
+            detect_timeout: time::Duration::from_secs(1),
+        },
+    )
+    .with_clock(clock)
+    .with_authz_transitions(transitions_tx);
+
+    idx.apply_node(mk_node("node-0", pod_net)).unwrap();
+    idx.apply_pod(mk_pod(
+        "ns-0",
+        "pod-0",
+        "node-0",
+        pod_ip,
+        Some(("container-0", vec![8080])),
+    ))
+    .unwrap();
+    let mut srv = mk_server("ns-0", "srv-0", Port::Number(8080), None, None);
+    srv.spec.proxy_protocol = Some(k8s::polixy::server::ProxyProtocol::Http1);
+    idx.apply_server(srv);
+
+    let mk_timed_authz = |name: &str, not_before: Option<u64>, not_after: Option<u64>| {
+        let mut authz = mk_authz("ns-0", name, "srv-0");
+        authz.spec.client.unauthenticated = true;
+        authz.spec.not_before = not_before.map(|s| (epoch + time::Duration::from_secs(s)).into());
+        authz.spec.not_after = not_after.map(|s| (epoch + time::Duration::from_secs(s)).into());
+        authz.metadata.uid = Some(format!("uid-{}", name));
+        authz
+    };
+    idx.apply_authz(mk_timed_authz("temporary", None, Some(60)))
+        .unwrap();
+    idx.apply_authz(mk_timed_authz("scheduled", Some(30), None))
+        .unwrap();
+
+    // An authorization that expires before it takes effect is invalid.
+    assert!(idx
+        .apply_authz(mk_timed_authz("invalid", Some(60), Some(30)))
+        .is_err());
+
+    let port8080 = lookup_rx
+        .lookup("ns-0", "pod-0", 8080, PortProtocol::Tcp)
+        .unwrap();
+    let authz_names = || {
+        port8080
+            .get()
+            .authorizations
+            .keys()
+            .filter(|n| *n != &healthcheck_authz(kubelet_ip).0)
+            .cloned()
+            .collect::<Vec<_>>()
+    };
+    let authz_state = |name: &str| lookup_rx.export().namespaces["ns-0"].authorizations[name].state;
+    assert_eq!(authz_names(), vec!["temporary".to_string()]);
+    assert_eq!(
+        authz_state("scheduled"),
+        export::AuthorizationState::Pending
+    );
+
+    // The scheduled authorization takes effect after 30s.
+    let start = time::Instant::now();
+    time::sleep_until(idx.authz_update_deadline().unwrap()).await;
+    assert_eq!(start.elapsed(), time::Duration::from_secs(30));
+    idx.update_authz_windows();
+    assert_eq!(
+        authz_names(),
+        vec!["scheduled".to_string(), "temporary".to_string()]
+    );
+    assert_eq!(authz_state("scheduled"), export::AuthorizationState::Active);
+    assert_eq!(
+        transitions_rx.try_recv().unwrap(),
+        AuthzTransition {
+            namespace: "ns-0".to_string(),
+            name: "scheduled".to_string(),
+            uid: Some("uid-scheduled".to_string()),
+            state: export::AuthorizationState::Active,
+        }
+    );
+    assert!(transitions_rx.try_recv().is_err());
+
+    // The temporary authorization expires after 60s.
+    time::sleep_until(idx.authz_update_deadline().unwrap()).await;
+    assert_eq!(start.elapsed(), time::Duration::from_secs(60));
+    idx.update_authz_windows();
+    assert_eq!(authz_names(), vec!["scheduled".to_string()]);
+    assert_eq!(
+        authz_state("temporary"),
+        export::AuthorizationState::Expired
+    );
+    assert_eq!(
+        transitions_rx.try_recv().unwrap(),
+        AuthzTransition {
+            namespace: "ns-0".to_string(),
+            name: "temporary".to_string(),
+            uid: Some("uid-temporary".to_string()),
+            state: export::AuthorizationState::Expired,
+        }
+    );
+
+    // No further updates are scheduled.
+    assert_eq!(idx.authz_update_deadline(), None);
+}
+
+/// Tests that rollouts apply an authorization to a subset of a server's pods in place of the
+/// authorization it replaces, and that open watches observe variants as they are dropped.
+#[tokio::test(start_paused = true)]
+async fn authorization_rollouts() {
+    let cluster_net = IpNet::from_str("192.0.2.0/24").unwrap();
+    let pod_net = IpNet::from_str("192.0.2.2/28").unwrap();
+    let (kubelet_ip, pod_ips) = {
+        let mut ips = pod_net.hosts();
+        (ips.next().unwrap(), ips.take(3).collect::<Vec<_>>())
+    };
+    let (lookup_tx, lookup_rx) = crate::lookup::pair();
+    let mut idx = Index::new(
+        lookup_tx,
+        Config {
+            cluster_networks: ClusterNetworks::Static(vec![cluster_net]),
+            identity_domains: IdentityDomains::new("cluster.example.com".into()),
+            default_allow: DefaultAllow::Deny,
+            undeclared_ports: UndeclaredPorts::DefaultAllow,
+            kubelet_source: KubeletSource::default(),
+            detect_timeout: time::Duration::from_secs(1),
+        },
+    );
+
+    idx.apply_node(mk_node("node-0", pod_net)).unwrap();
+    let pods = pod_ips
+        .iter()
+        .enumerate()
+        .map(|(i, ip)| {
+            mk_pod(
+                "ns-0",
+                format!("pod-{}", i),
+                "node-0",
+                *ip,
+                Some(("container-0", vec![8080])),
+            )
+        })
+        .collect::<Vec<_>>();
+    let mut canary = pods[0].clone();
+    canary
+        .metadata
+        .labels
+        .insert("track".to_string(), "canary".to_string());
+    idx.apply_pod(canary).unwrap();
+    idx.apply_pod(pods[1].clone()).unwrap();
+    idx.apply_pod(pods[2].clone()).unwrap();
+    let mut srv = mk_server("ns-0", "srv-0", Port::Number(8080), None, None);
+    srv.spec.proxy_protocol = Some(k8s::polixy::server::ProxyProtocol::Http1);
+    idx.apply_server(srv);
+
+    let mk_unauthenticated = |name: &str| {
+        let mut authz = mk_authz("ns-0", name, "srv-0");
+        authz.spec.client.unauthenticated = true;
+        authz
+    };
+    idx.apply_authz(mk_unauthenticated("v1")).unwrap();
+    let mut v2 = mk_unauthenticated("v2");
+    v2.spec.rollout = Some(k8s::polixy::authz::Rollout {
+        pod_selector: Some(Some(("track", "canary")).into_iter().collect()),
+        percent: None,
+        replaces: Some("v1".to_string()),
+    });
+    idx.apply_authz(v2.clone()).unwrap();
+
+    let ports = (0..3)
+        .map(|i| {
+            lookup_rx
+                .lookup("ns-0", &format!("pod-{}", i), 8080, PortProtocol::Tcp)
+                .unwrap()
+        })
+        .collect::<Vec<_>>();
+    let authz_names = || {
+        ports
+            .iter()
+            .map(|port| {
+                port.get()
+                    .authorizations
+                    .keys()
+                    .filter(|n| *n != &healthcheck_authz(kubelet_ip).0)
+                    .cloned()
+                    .collect::<Vec<_>>()
+                    .join(",")
+            })
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(authz_names(), vec!["v2", "v1", "v1"]);
+
+    // Relabeling a pod adds it to the rollout.
+    let mut rx = ports[1].clone().into_stream();
+    assert!(rx.next().await.is_some());
+    let mut relabeled = pods[1].clone();
+    relabeled
+        .metadata
+        .labels
+        .insert("track".to_string(), "canary".to_string());
+    idx.apply_pod(relabeled).unwrap();
+    assert_eq!(authz_names(), vec!["v2", "v2", "v1"]);
+    assert!(time::timeout(time::Duration::from_secs(1), rx.next())
+        .await
+        .unwrap()
+        .is_some());
+
+    // Percentages select pods by name, regardless of their labels.
+    v2.spec.rollout = Some(k8s::polixy::authz::Rollout {
+        pod_selector: None,
+        percent: Some(100),
+        replaces: Some("v1".to_string()),
+    });
+    idx.apply_authz(v2.clone()).unwrap();
+    assert_eq!(authz_names(), vec!["v2", "v2", "v2"]);
+
+    v2.spec.rollout.as_mut().unwrap().percent = Some(0);
+    idx.apply_authz(v2.clone()).unwrap();
+    assert_eq!(authz_names(), vec!["v1", "v1", "v1"]);
+    assert_eq!(next_latest(&mut rx).await, Some(ports[1].get()));
+
+    // Completing the rollout applies the revision to all pods.
+    idx.delete_authz(mk_unauthenticated("v1"));
+    v2.spec.rollout = None;
+    idx.apply_authz(v2).unwrap();
+    assert_eq!(authz_names(), vec!["v2", "v2", "v2"]);
+
+    // The rollout's variants are dropped, and open watches observe the server's configuration.
+    assert_eq!(next_latest(&mut rx).await, Some(ports[1].get()));
+
+    // Rollouts may not select more than all pods.
+    let mut invalid = mk_unauthenticated("v3");
+    invalid.spec.rollout = Some(k8s::polixy::authz::Rollout {
+        percent: Some(101),
+        ..Default::default()
+    });
+    assert!(idx.apply_authz(invalid).is_err());
+}
+
+/// Tests that configuration changes are applied to existing pods' ports.
+#[tokio::test]
+async fn apply_config() {
+    let cluster_net = IpNet::from_str("192.0.2.0/24").unwrap();
+    let pod_net = IpNet::from_str("192.0.2.2/28").unwrap();
+    let (kubelet_ip, pod_ip) = {
+        let mut ips = pod_net.hosts();
+        (ips.next().unwrap(), ips.next().unwrap())
+    };
+    let detect_timeout = time::Duration::from_secs(1);
+    let config = Config {
+        cluster_networks: ClusterNetworks::Static(vec![cluster_net]),
+        identity_domains: IdentityDomains::new("cluster.example.com".into()),
+        default_allow: DefaultAllow::ClusterUnauthenticated,
+        undeclared_ports: UndeclaredPorts::DefaultAllow,
+        kubelet_source: KubeletSource::default(),
+        detect_timeout,
+    };
+    let (lookup_tx, lookup_rx) = crate::lookup::pair();
+    let mut idx = Index::new(lookup_tx, config.clone());
+
+    idx.apply_node(mk_node("node-0", pod_net)).unwrap();
+    idx.apply_pod(mk_pod(
+        "ns-0",
+        "pod-0",
+        "node-0",
+        pod_ip,
+        Some(("container-0", vec![2222, 8080])),
+    ))
+    .unwrap();
+    let mut annotated = mk_pod(
+        "ns-0",
+        "pod-1",
+        "node-0",
+        pod_ip,
+        Some(("container-0", vec![2222])),
+    );
+    annotated
+        .annotations_mut()
+        .insert(DefaultAllow::ANNOTATION.into(), "deny".into());
+    idx.apply_pod(annotated).unwrap();
+
+    idx.apply_server({
+        let mut srv = mk_server("ns-0", "srv-0", Port::Number(8080), None, None);
+        srv.spec.proxy_protocol = Some(k8s::polixy::server::ProxyProtocol::Http1);
+        srv
+    });
+    let mut authz = mk_authz("ns-0", "authz-0", "srv-0");
+    authz.spec.client = k8s::polixy::authz::Client {
+        mesh_tls: Some(k8s::polixy::authz::MeshTls {
+            service_accounts: vec![k8s::polixy::authz::ServiceAccountRef {
+                namespace: Some("payments".to_string()),
+                name: "api".to_string(),
+                cluster: None,
+            }],
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    idx.apply_authz(authz).unwrap();
+
+    let lookup = |pod: &str, port: u16| {
+        lookup_rx
+            .lookup("ns-0", pod, port, PortProtocol::Tcp)
+            .unwrap()
+    };
+    let port2222 = lookup("pod-0", 2222);
+    let port7000 = lookup("pod-0", 7000);
+    let port8080 = lookup("pod-0", 8080);
+    let annotated2222 = lookup("pod-1", 2222);
+
+    // Applying an unchanged configuration doesn't update any policies.
+    let mut rx = port2222.clone().into_stream();
+    assert!(rx.next().await.is_some());
+    idx.apply_config(config.clone()).unwrap();
+    assert!(time::timeout(time::Duration::from_millis(10), rx.next())
+        .await
+        .is_err());
+
+    let cluster_net = IpNet::from_str("198.51.100.0/24").unwrap();
+    let detect_timeout = time::Duration::from_secs(2);
+    idx.apply_config(Config {
+        cluster_networks: ClusterNetworks::Static(vec![cluster_net]),
+        identity_domains: IdentityDomains::new("cluster.example.org".into()),
+        default_allow: DefaultAllow::ClusterAuthenticated,
+        detect_timeout,
+        ..config
+    })
+    .unwrap();
+
+    // Ports that use the global default-allow policy are updated.
+    let default_config = InboundServer {
+        routes: Default::default(),
+        authorizations: mk_default_allow(
+            DefaultAllow::ClusterAuthenticated,
+            cluster_net,
+            kubelet_ip,
+        ),
+        protocol: ProxyProtocol::Detect {
+            timeout: detect_timeout,
+        },
+        undeclared: false,
+        health_check_source: Some("pod-cidr".to_string()),
+    };
+    assert_eq!(port2222.get(), default_config);
+    assert_eq!(
+        port7000.get(),
+        InboundServer {
+            undeclared: true,
+            ..default_config
+        }
+    );
+    assert_eq!(
+        time::timeout(time::Duration::from_secs(1), rx.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .protocol,
+        ProxyProtocol::Detect {
+            timeout: detect_timeout
+        }
+    );
+
+    // Pods that override the default-allow policy only observe the new detect timeout.
+    assert_eq!(
+        annotated2222.get(),
+        InboundServer {
+            routes: Default::default(),
+            authorizations: mk_default_allow(DefaultAllow::Deny, cluster_net, kubelet_ip),
+            protocol: ProxyProtocol::Detect {
+                timeout: detect_timeout,
+            },
+            undeclared: false,
+            health_check_source: Some("pod-cidr".to_string()),
+        }
+    );
+
+    // Service accounts are resolved in the new identity domain.
+    assert_eq!(
+        port8080.get().authorizations["authz-0"].authentication,
+        ClientAuthentication::TlsAuthenticated(vec![IdentityMatch::Name(
+            "api.payments.serviceaccount.identity.linkerd.cluster.example.org".to_string(),
+        )])
+    );
+}
+
+/// Tests that pods' indexed ports may be listed by name or by label selector.
+#[tokio::test]
+async fn discover_pod_ports() {
+    let cluster_net = IpNet::from_str("192.0.2.0/24").unwrap();
+    let pod_net = IpNet::from_str("192.0.2.2/28").unwrap();
+    let pod_ip = pod_net.hosts().nth(1).unwrap();
+    let detect_timeout = time::Duration::from_secs(1);
+
+    let (lookup_tx, lookup_rx) = crate::lookup::pair();
+    let mut idx = Index::new(
+        lookup_tx,
+        Config {
+            cluster_networks: ClusterNetworks::Static(vec![cluster_net]),
+            identity_domains: IdentityDomains::new("cluster.example.com".into()),
+            default_allow: DefaultAllow::Deny,
+            undeclared_ports: UndeclaredPorts::DefaultAllow,
+            kubelet_source: KubeletSource::default(),
+            detect_timeout,
+        },
+    );
+    idx.reset_nodes(vec![mk_node("node-0", pod_net)]).unwrap();
+
+    let mut pod0 = mk_pod(
+        "ns-0",
+        "pod-0",
+        "node-0",
+        pod_ip,
+        vec![
+            ("container-0", vec![9999, 2222]),
+            ("container-1", vec![3333]),
+        ],
+    );
+    pod0.metadata.labels.insert("app".into(), "web".into());
+    let mut pod1 = mk_pod(
+        "ns-0",
+        "pod-1",
+        "node-0",
+        pod_ip,
+        Some(("container-0", vec![4444])),
+    );
+    pod1.metadata.labels.insert("app".into(), "db".into());
+    idx.reset_pods(vec![pod0, pod1.clone()]).unwrap();
+
+    assert_eq!(
+        lookup_rx.pod_ports("ns-0", "pod-0", PortProtocol::Tcp),
+        Some(vec![2222, 3333, 9999])
+    );
+    assert_eq!(
+        lookup_rx.pod_ports("ns-0", "pod-2", PortProtocol::Tcp),
+        None
+    );
+    assert_eq!(
+        lookup_rx.pod_ports("ns-1", "pod-0", PortProtocol::Tcp),
+        None
+    );
+
+    let web = "app=web".parse().unwrap();
+    assert_eq!(
+        lookup_rx.selected_pod_ports("ns-0", &web, PortProtocol::Tcp),
+        vec![("pod-0".to_string(), vec![2222, 3333, 9999])]
+    );
+    assert_eq!(
+        lookup_rx.selected_pod_ports("ns-0", &Default::default(), PortProtocol::Tcp),
+        vec![
+            ("pod-0".to_string(), vec![2222, 3333, 9999]),
+            ("pod-1".to_string(), vec![4444]),
+        ]
+    );
+
+    // Relabeling a pod updates its selection.
+    pod1.metadata.labels.insert("app".into(), "web".into());
+    idx.apply_pod(pod1).unwrap();
+    assert_eq!(
+        lookup_rx.selected_pod_ports("ns-0", &web, PortProtocol::Tcp),
+        vec![
+            ("pod-0".to_string(), vec![2222, 3333, 9999]),
+            ("pod-1".to_string(), vec![4444]),
+        ]
+    );
+}
+
+/// Tests that pods' identities are derived from their service accounts and the identity domains.
+#[tokio::test]
+async fn identify_pods() {
+    let cluster_net = IpNet::from_str("192.0.2.0/24").unwrap();
+    let pod_net = IpNet::from_str("192.0.2.2/28").unwrap();
+    let pod_ip = pod_net.hosts().nth(1).unwrap();
+
+    let (lookup_tx, lookup_rx) = crate::lookup::pair();
+    let mut idx = Index::new(
+        lookup_tx,
+        Config {
+            cluster_networks: ClusterNetworks::Static(vec![cluster_net]),
+            identity_domains: IdentityDomains::new("cluster.example.com".into()),
+            default_allow: DefaultAllow::Deny,
+            undeclared_ports: UndeclaredPorts::DefaultAllow,
+            kubelet_source: KubeletSource::default(),
+            detect_timeout: time::Duration::from_secs(1),
+        },
+    );
+    idx.reset_nodes(vec![mk_node("node-0", pod_net)]).unwrap();
+
+    let mut pod0 = mk_pod(
+        "ns-0",
+        "pod-0",
+        "node-0",
+        pod_ip,
+        Some(("container-0", vec![8080])),
+    );
+    pod0.spec.as_mut().unwrap().service_account_name = Some("web".into());
+    let pod1 = mk_pod(
+        "ns-0",
+        "pod-1",
+        "node-0",
+        pod_ip,
+        Some(("container-0", vec![8080])),
+    );
+    idx.reset_pods(vec![pod0, pod1]).unwrap();
+
+    let target = |pod: &str| ("ns-0".to_string(), pod.to_string(), 8080, PortProtocol::Tcp);
+    assert_eq!(
+        lookup_rx.target_identity(&target("pod-0")),
+        TargetIdentity::Identity(
+            "web.ns-0.serviceaccount.identity.linkerd.cluster.example.com".to_string()
+        )
+    );
+    assert_eq!(
+        lookup_rx.target_identity(&target("pod-1")),
+        TargetIdentity::Identity(
+            "default.ns-0.serviceaccount.identity.linkerd.cluster.example.com".to_string()
+        )
+    );
+    assert_eq!(
+        lookup_rx.target_identity(&target("pod-2")),
+        TargetIdentity::Unknown
+    );
+
+    // Identities follow changes to the identity domains.
+    idx.set_identity_domains(IdentityDomains::new("cluster.local".into()))
+        .unwrap();
+    assert_eq!(
+        lookup_rx.target_identity(&target("pod-0")),
+        TargetIdentity::Identity(
+            "web.ns-0.serviceaccount.identity.linkerd.cluster.local".to_string()
+        )
+    );
+}
+
+/// Authorizations that specify routes are tracked separately from server-wide authorizations and
+/// invalid routes are rejected.
+/// Tests that UDP ports are indexed separately from TCP ports and are only selected by UDP servers.
+#[tokio::test]
+async fn udp_ports() {
+    let cluster_net = IpNet::from_str("192.0.2.0/24").unwrap();
+    let pod_net = IpNet::from_str("192.0.2.2/28").unwrap();
+    let (kubelet_ip, pod_ip) = {
+        let mut ips = pod_net.hosts();
+        (ips.next().unwrap(), ips.next().unwrap())
+    };
+    let detect_timeout = time::Duration::from_secs(1);
+    let (lookup_tx, lookup_rx) = crate::lookup::pair();
+    let mut idx = Index::new(
+        lookup_tx,
+        Config {
+            cluster_networks: ClusterNetworks::Static(vec![cluster_net]),
+            identity_domains: IdentityDomains::new("cluster.example.com".into()),
+            default_allow: DefaultAllow::ClusterUnauthenticated,
+            undeclared_ports: UndeclaredPorts::DefaultAllow,
+            kubelet_source: KubeletSource::default(),
+            detect_timeout,
+        },
+    );
+
+    idx.apply_node(mk_node("node-0", pod_net)).unwrap();
+    let mut pod = mk_pod(
+        "ns-0",
+        "pod-0",
+        "node-0",
+        pod_ip,
+        Some(("container-0", vec![8080])),
+    );
+    pod.spec.as_mut().unwrap().containers[0]
+        .ports
+        .push(k8s::api::core::v1::ContainerPort {
+            container_port: 53,
+            name: Some("dns".to_string()),
+            protocol: Some("UDP".to_string()),
+            ..Default::default()
+        });
+    idx.apply_pod(pod).unwrap();
+
+    assert!(
+        lookup_rx
+            .lookup("ns-0", "pod-0", 53, PortProtocol::Tcp)
+            .unwrap()
+            .get()
+            .undeclared
+    );
+    assert_eq!(
+        lookup_rx.pod_ports("ns-0", "pod-0", PortProtocol::Udp),
+        Some(vec![53])
+    );
+    let port53 = lookup_rx
+        .lookup("ns-0", "pod-0", 53, PortProtocol::Udp)
+        .unwrap();
+
+    // A TCP server doesn't select the UDP port, even by name.
+    let mut srv = mk_server("ns-0", "srv-dns", Port::Name("dns".to_string()), None, None);
+    srv.spec.proxy_protocol = Some(k8s::polixy::server::ProxyProtocol::Opaque);
+    idx.apply_server(srv.clone());
+    assert_eq!(
+        port53.get().protocol,
+        ProxyProtocol::Detect {
+            timeout: detect_timeout
+        }
+    );
+
+    srv.spec.port_protocol = Some(k8s::polixy::server::PortProtocol::Udp);
+    idx.apply_server(srv);
+    assert_eq!(
+        port53.get(),
+        InboundServer {
+            protocol: ProxyProtocol::Opaque,
+            authorizations: vec![healthcheck_authz(kubelet_ip)].into_iter().collect(),
+            routes: Default::default(),
+            undeclared: false,
+            health_check_source: Some("pod-cidr".to_string()),
+        }
+    );
+}
+
+/// Tests that undeclared ports may be denied and that servers may select them by number.
+#[tokio::test]
+async fn undeclared_ports() {
+    let cluster_net = IpNet::from_str("192.0.2.0/24").unwrap();
+    let pod_net = IpNet::from_str("192.0.2.2/28").unwrap();
+    let (kubelet_ip, pod_ip) = {
+        let mut ips = pod_net.hosts();
+        (ips.next().unwrap(), ips.next().unwrap())
+    };
+    let detect_timeout = time::Duration::from_secs(1);
+    let (lookup_tx, lookup_rx) = crate::lookup::pair();
+    let mut idx = Index::new(
+        lookup_tx,
+        Config {
+            cluster_networks: ClusterNetworks::Static(vec![cluster_net]),
+            identity_domains: IdentityDomains::new("cluster.example.com".into()),
+            default_allow: DefaultAllow::ClusterUnauthenticated,
+            undeclared_ports: UndeclaredPorts::Deny,
+            kubelet_source: KubeletSource::default(),
+            detect_timeout,
+        },
+    );
+
+    idx.apply_node(mk_node("node-0", pod_net)).unwrap();
+    idx.apply_pod(mk_pod(
+        "ns-0",
+        "pod-0",
+        "node-0",
+        pod_ip,
+        Some(("container-0", vec![8080])),
+    ))
+    .unwrap();
+
+    let deny = InboundServer {
+        protocol: ProxyProtocol::Detect {
+            timeout: detect_timeout,
+        },
+        authorizations: mk_default_allow(DefaultAllow::Deny, cluster_net, kubelet_ip),
+        routes: Default::default(),
+        undeclared: true,
+        health_check_source: Some("pod-cidr".to_string()),
+    };
+    let port7000 = lookup_rx
+        .lookup("ns-0", "pod-0", 7000, PortProtocol::Tcp)
+        .unwrap();
+    assert_eq!(port7000.get(), deny);
+
+    // Declared ports still use the default-allow policy.
+    assert_eq!(
+        lookup_rx
+            .lookup("ns-0", "pod-0", 8080, PortProtocol::Tcp)
+            .unwrap()
+            .get()
+            .authorizations,
+        mk_default_allow(
+            DefaultAllow::ClusterUnauthenticated,
+            cluster_net,
+            kubelet_ip
+        ),
+    );
+
+    // A server may select the undeclared port by number.
+    let mut srv = mk_server("ns-0", "srv-7000", Port::Number(7000), None, None);
+    srv.spec.proxy_protocol = Some(k8s::polixy::server::ProxyProtocol::Opaque);
+    idx.apply_server(srv.clone());
+    assert_eq!(
+        port7000.get(),
+        InboundServer {
+            protocol: ProxyProtocol::Opaque,
+            authorizations: vec![healthcheck_authz(kubelet_ip)].into_iter().collect(),
+            routes: Default::default(),
+            undeclared: true,
+            health_check_source: Some("pod-cidr".to_string()),
+        }
+    );
+    assert_eq!(
+        lookup_rx
+            .lookup("ns-0", "pod-0", 7001, PortProtocol::Tcp)
+            .unwrap()
+            .get(),
+        deny
+    );
+
+    // When the server is deleted, the port is denied again.
+    idx.delete_server(srv).unwrap();
+    assert_eq!(port7000.get(), deny);
+}
+
+/// Tests that servers may select ranges of declared and undeclared ports.
+#[tokio::test]
+async fn port_ranges() {
+    let cluster_net = IpNet::from_str("192.0.2.0/24").unwrap();
+    let pod_net = IpNet::from_str("192.0.2.2/28").unwrap();
+    let (kubelet_ip, pod_ip) = {
+        let mut ips = pod_net.hosts();
+        (ips.next().unwrap(), ips.next().unwrap())
+    };
+    let detect_timeout = time::Duration::from_secs(1);
+    let (lookup_tx, lookup_rx) = crate::lookup::pair();
+    let mut idx = Index::new(
+        lookup_tx,
+        Config {
+            cluster_networks: ClusterNetworks::Static(vec![cluster_net]),
+            identity_domains: IdentityDomains::new("cluster.example.com".into()),
+            default_allow: DefaultAllow::ClusterUnauthenticated,
+            undeclared_ports: UndeclaredPorts::DefaultAllow,
+            kubelet_source: KubeletSource::default(),
+            detect_timeout,
+        },
+    );
+
+    idx.apply_node(mk_node("node-0", pod_net)).unwrap();
+    idx.apply_pod(mk_pod(
+        "ns-0",
+        "pod-0",
+        "node-0",
+        pod_ip,
+        Some(("container-0", vec![8080, 30000])),
+    ))
+    .unwrap();
+
+    let mut srv = mk_server(
+        "ns-0",
+        "srv-media",
+        Port::Range("30000-30100".parse().unwrap()),
+        None,
+        None,
+    );
+    srv.spec.proxy_protocol = Some(k8s::polixy::server::ProxyProtocol::Opaque);
+    idx.apply_server(srv);
+
+    let media = InboundServer {
+        protocol: ProxyProtocol::Opaque,
+        authorizations: vec![healthcheck_authz(kubelet_ip)].into_iter().collect(),
+        routes: Default::default(),
+        undeclared: false,
+        health_check_source: Some("pod-cidr".to_string()),
+    };
+    let get = |port| {
+        lookup_rx
+            .lookup("ns-0", "pod-0", port, PortProtocol::Tcp)
+            .unwrap()
+            .get()
+    };
+    assert_eq!(get(30000), media);
+    assert_eq!(
+        get(30100),
+        InboundServer {
+            undeclared: true,
+            ..media.clone()
+        }
+    );
+    assert_eq!(
+        get(30101).protocol,
+        ProxyProtocol::Detect {
+            timeout: detect_timeout
+        }
+    );
+    assert_eq!(
+        get(8080).protocol,
+        ProxyProtocol::Detect {
+            timeout: detect_timeout
+        }
+    );
+
+    // Ports selected by overlapping servers use the first server by name.
+    let mut srv = mk_server("ns-0", "srv-a", Port::Number(30050), None, None);
+    srv.spec.proxy_protocol = Some(k8s::polixy::server::ProxyProtocol::Http1);
+    idx.apply_server(srv);
+    assert_eq!(get(30050).protocol, ProxyProtocol::Http1);
+    assert_eq!(get(30051).protocol, ProxyProtocol::Opaque);
+
+    // Declared ports selected by overlapping ranges also use the first server by name, regardless
+    // of the order in which servers are applied.
+    let mut srv = mk_server(
+        "ns-0",
+        "srv-z",
+        Port::Range("29000-30000".parse().unwrap()),
+        None,
+        None,
+    );
+    srv.spec.proxy_protocol = Some(k8s::polixy::server::ProxyProtocol::Http2);
+    idx.apply_server(srv);
+    assert_eq!(get(30000), media);
+
+    let mut srv = mk_server(
+        "ns-0",
+        "srv-b",
+        Port::Range("29000-30000".parse().unwrap()),
+        None,
+        None,
+    );
+    srv.spec.proxy_protocol = Some(k8s::polixy::server::ProxyProtocol::Grpc);
+    idx.apply_server(srv.clone());
+    assert_eq!(get(30000).protocol, ProxyProtocol::Grpc);
+
+    idx.delete_server(srv).unwrap();
+    assert_eq!(get(30000), media);
+}
+
+/// Tests that sidecar init containers' ports are discoverable, that ports declared by more than one
+/// container are reported, and that servers may select ports by container.
+#[tokio::test]
+async fn init_container_ports() {
+    let cluster_net = IpNet::from_str("192.0.2.0/24").unwrap();
+    let pod_net = IpNet::from_str("192.0.2.2/28").unwrap();
+    let (kubelet_ip, pod_ip) = {
+        let mut ips = pod_net.hosts();
+        (ips.next().unwrap(), ips.next().unwrap())
+    };
+    let detect_timeout = time::Duration::from_secs(1);
+    let (lookup_tx, lookup_rx) = crate::lookup::pair();
+    let (conflicts_tx, mut conflicts_rx) = tokio::sync::mpsc::unbounded_channel();
+    let mut idx = Index::new(
+        lookup_tx,
+        Config {
+            cluster_networks: ClusterNetworks::Static(vec![cluster_net]),
+            identity_domains: IdentityDomains::new("cluster.example.com".into()),
+            default_allow: DefaultAllow::ClusterUnauthenticated,
+            undeclared_ports: UndeclaredPorts::DefaultAllow,
+            kubelet_source: KubeletSource::default(),
+            detect_timeout,
+        },
+    )
+    .with_port_conflicts(conflicts_tx);
+
+    idx.apply_node(mk_node("node-0", pod_net)).unwrap();
+    let mut pod = mk_pod(
+        "ns-0",
+        "pod-0",
+        "node-0",
+        pod_ip,
+        Some(("container-0", vec![8080])),
+    );
+    // A sidecar that also declares the app container's port, which is ignored.
+    pod.sidecars.insert("sidecar".to_string());
+    pod.spec
+        .as_mut()
+        .unwrap()
+        .init_containers
+        .push(k8s::api::core::v1::Container {
+            name: "sidecar".to_string(),
+            ports: vec![
+                k8s::api::core::v1::ContainerPort {
+                    container_port: 4191,
+                    ..Default::default()
+                },
+                k8s::api::core::v1::ContainerPort {
+                    container_port: 8080,
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        });
+    // Init containers that aren't sidecars run to completion, so their ports aren't indexed.
+    pod.spec
+        .as_mut()
+        .unwrap()
+        .init_containers
+        .push(k8s::api::core::v1::Container {
+            name: "init".to_string(),
+            ports: vec![k8s::api::core::v1::ContainerPort {
+                container_port: 9090,
+                ..Default::default()
+            }],
+            ..Default::default()
+        });
+    pod.metadata.uid = Some("uid-0".to_string());
+    idx.apply_pod(pod).unwrap();
+
+    assert_eq!(
+        lookup_rx.pod_ports("ns-0", "pod-0", PortProtocol::Tcp),
+        Some(vec![4191, 8080])
+    );
+    assert_eq!(
+        conflicts_rx.try_recv().unwrap(),
+        PortConflict {
+            namespace: "ns-0".to_string(),
+            pod: "pod-0".to_string(),
+            uid: Some("uid-0".to_string()),
+            port: 8080,
+            protocol: PortProtocol::Tcp,
+            container: "sidecar".to_string(),
+            declared_by: "container-0".to_string(),
+        }
+    );
+    assert!(conflicts_rx.try_recv().is_err());
+    let port4191 = lookup_rx
+        .lookup("ns-0", "pod-0", 4191, PortProtocol::Tcp)
+        .unwrap();
+    let port8080 = lookup_rx
+        .lookup("ns-0", "pod-0", 8080, PortProtocol::Tcp)
+        .unwrap();
+
+    let opaque = |name: &str, port: u16| {
+        let mut srv = mk_server("ns-0", name, Port::Number(port), None, None);
+        srv.spec.proxy_protocol = Some(k8s::polixy::server::ProxyProtocol::Opaque);
+        srv.spec.container = Some("sidecar".to_string());
+        srv
+    };
+    idx.apply_server(opaque("srv-admin", 4191));
+    idx.apply_server(opaque("srv-app", 8080));
+
+    assert_eq!(
+        port4191.get(),
+        InboundServer {
+            protocol: ProxyProtocol::Opaque,
+            authorizations: vec![healthcheck_authz(kubelet_ip)].into_iter().collect(),
+            routes: Default::default(),
+            undeclared: false,
+            health_check_source: Some("pod-cidr".to_string()),
+        }
+    );
+    // The app port is declared by the app container, so the sidecar's server doesn't select it.
+    assert_eq!(
+        port8080.get().protocol,
+        ProxyProtocol::Detect {
+            timeout: detect_timeout
+        }
+    );
+}
+
+#[tokio::test]
+async fn route_authorizations() {
+    let cluster_net = IpNet::from_str("192.0.2.0/24").unwrap();
+    let pod_net = IpNet::from_str("192.0.2.2/28").unwrap();
+    let (kubelet_ip, pod_ip) = {
+        let mut ips = pod_net.hosts();
+        (ips.next().unwrap(), ips.next().unwrap())
+    };
+    let detect_timeout = time::Duration::from_secs(1);
+    let (lookup_tx, lookup_rx) = crate::lookup::pair();
+    let mut idx = Index::new(
+        lookup_tx,
+        Config {
+            cluster_networks: ClusterNetworks::Static(vec![cluster_net]),
+            identity_domains: IdentityDomains::new("cluster.example.com".into()),
+            default_allow: DefaultAllow::ClusterUnauthenticated,
+            undeclared_ports: UndeclaredPorts::DefaultAllow,
+            kubelet_source: KubeletSource::default(),
+            detect_timeout,
+        },
+    );
+
+    idx.apply_node(mk_node("node-0", pod_net)).unwrap();
+    idx.apply_pod(mk_pod(
+        "ns-0",
+        "pod-0",
+        "node-0",
+        pod_ip,
+        Some(("container-0", vec![8080])),
+    ))
+    .unwrap();
+    idx.apply_server({
+        let mut srv = mk_server("ns-0", "srv-0", Port::Number(8080), None, None);
+        srv.spec.proxy_protocol = Some(k8s::polixy::server::ProxyProtocol::Http1);
+        srv
+    });
+    let port8080 = lookup_rx
+        .lookup("ns-0", "pod-0", 8080, PortProtocol::Tcp)
+        .unwrap();
+
+    let unauthenticated = k8s::polixy::authz::Client {
+        unauthenticated: true,
+        ..Default::default()
+    };
+    let mut authz = mk_authz("ns-0", "authz-admin", "srv-0");
+    authz.spec.client = unauthenticated.clone();
+    authz.spec.routes = Some(vec![
+        k8s::polixy::authz::HttpRoute {
+            path_prefix: Some("/admin".to_string()),
+            methods: Some(vec!["get".to_string()]),
+            ..Default::default()
+        },
+        k8s::polixy::authz::HttpRoute {
+            grpc: Some(k8s::polixy::authz::GrpcRoute {
+                service: "admin.Admin".to_string(),
+                method: None,
+            }),
+            ..Default::default()
+        },
+    ]);
+    idx.apply_authz(authz.clone()).unwrap();
+
+    let client = ClientAuthorization {
+        authentication: ClientAuthentication::Unauthenticated,
+        networks: vec![Ipv4Net::default().into(), Ipv6Net::default().into()],
+    };
+    assert_eq!(
+        port8080.get(),
+        InboundServer {
+            protocol: ProxyProtocol::Http1,
+            authorizations: vec![healthcheck_authz(kubelet_ip)].into_iter().collect(),
+            routes: Some((
+                "authz-admin".to_string(),
+                HttpRoute {
+                    matches: vec![
+                        HttpRouteMatch {
+                            path: Some(PathMatch::Prefix("/admin".to_string())),
+                            methods: vec!["GET".to_string()],
+                        },
+                        HttpRouteMatch::grpc("admin.Admin", None),
+                    ],
+                    authorization: client.clone(),
+                },
+            ))
+            .into_iter()
+            .collect(),
+            undeclared: false,
+            health_check_source: Some("pod-cidr".to_string()),
+        }
+    );
+
+    // Removing the routes makes the authorization apply to the whole server.
+    authz.spec.routes = None;
+    idx.apply_authz(authz).unwrap();
+    assert_eq!(
+        port8080.get(),
+        InboundServer {
+            protocol: ProxyProtocol::Http1,
+            authorizations: vec![
+                ("authz-admin".to_string(), client),
+                healthcheck_authz(kubelet_ip)
+            ]
+            .into_iter()
+            .collect(),
+            routes: Default::default(),
+            undeclared: false,
+            health_check_source: Some("pod-cidr".to_string()),
+        }
+    );
+
+    for invalid in [
+        k8s::polixy::authz::HttpRoute::default(),
+        k8s::polixy::authz::HttpRoute {
+            path: Some("/a".to_string()),
+            path_prefix: Some("/b".to_string()),
+            ..Default::default()
+        },
+        k8s::polixy::authz::HttpRoute {
+            path: Some("relative".to_string()),
+            ..Default::default()
+        },
+        k8s::polixy::authz::HttpRoute {
+            methods: Some(vec!["GET".to_string()]),
+            grpc: Some(k8s::polixy::authz::GrpcRoute {
+                service: "admin.Admin".to_string(),
+                method: None,
+            }),
+            ..Default::default()
+        },
+    ] {
+        let mut authz = mk_authz("ns-0", "authz-invalid", "srv-0");
+        authz.spec.client = unauthenticated.clone();
+        authz.spec.routes = Some(vec![invalid.clone()]);
+        assert!(idx.apply_authz(authz).is_err(), "{:?}", invalid);
+    }
+}
+
+/// Checks that the exported data document and the Rust authorization matcher agree with the cases
+/// that the reference Rego policy is tested against (see `opa/polixy_test.rego`).
+/// Tests that service accounts in linked clusters are authorized by their cluster's identity domain.
+#[tokio::test]
+async fn linked_cluster_service_accounts() {
+    let cluster_net = IpNet::from_str("192.0.2.0/24").unwrap();
+    let pod_net = IpNet::from_str("192.0.2.2/28").unwrap();
+    let (kubelet_ip, pod_ip) = {
+        let mut ips = pod_net.hosts();
+        (ips.next().unwrap(), ips.next().unwrap())
+    };
+    let detect_timeout = time::Duration::from_secs(1);
+    let (lookup_tx, lookup_rx) = crate::lookup::pair();
+    let mut domains = IdentityDomains::new("cluster.example.com".into());
+    domains
+        .link("east=east.example.com".parse().unwrap())
+        .unwrap();
+    let mut idx = Index::new(
+        lookup_tx,
+        Config {
+            cluster_networks: ClusterNetworks::Static(vec![cluster_net]),
+            identity_domains: domains,
+            default_allow: DefaultAllow::ClusterUnauthenticated,
+            undeclared_ports: UndeclaredPorts::DefaultAllow,
+            kubelet_source: KubeletSource::default(),
+            detect_timeout,
+        },
+    );
+
+    idx.apply_node(mk_node("node-0", pod_net)).unwrap();
+    idx.apply_pod(mk_pod(
+        "ns-0",
+        "pod-0",
+        "node-0",
+        pod_ip,
+        Some(("container-0", vec![8080])),
+    ))
+    .unwrap();
+    idx.apply_server({
+        let mut srv = mk_server("ns-0", "srv-0", Port::Number(8080), None, None);
+        srv.spec.proxy_protocol = Some(k8s::polixy::server::ProxyProtocol::Http1);
+        srv
+    });
+    let port8080 = lookup_rx
+        .lookup("ns-0", "pod-0", 8080, PortProtocol::Tcp)
+        .unwrap();
+
+    let sa_client = |cluster: &str| k8s::polixy::authz::Client {
+        mesh_tls: Some(k8s::polixy::authz::MeshTls {
+            service_accounts: vec![k8s::polixy::authz::ServiceAccountRef {
+                namespace: Some("payments".to_string()),
+                name: "api".to_string(),
+                cluster: Some(cluster.to_string()),
+            }],
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+
+    let mut authz = mk_authz("ns-0", "authz-east", "srv-0");
+    authz.spec.client = sa_client("east");
+    idx.apply_authz(authz).unwrap();
+
+    let client = ClientAuthorization {
+        authentication: ClientAuthentication::TlsAuthenticated(vec![IdentityMatch::Name(
+            "api.payments.serviceaccount.identity.linkerd.east.example.com".to_string(),
+        )]),
+        networks: vec![Ipv4Net::default().into(), Ipv6Net::default().into()],
+    };
+    assert_eq!(
+        port8080.get(),
+        InboundServer {
+            protocol: ProxyProtocol::Http1,
+            authorizations: vec![
+                healthcheck_authz(kubelet_ip),
+                ("authz-east".to_string(), client)
+            ]
+            .into_iter()
+            .collect(),
+            routes: Default::default(),
+            undeclared: false,
+            health_check_source: Some("pod-cidr".to_string()),
+        }
+    );
+
+    // Authorizations that reference unknown clusters are rejected.
+    let mut authz = mk_authz("ns-0", "authz-west", "srv-0");
+    authz.spec.client = sa_client("west");
+    assert!(idx.apply_authz(authz).is_err());
+}
+
+#[tokio::test]
+async fn export_matches_opa_cases() {
+    let cluster_net = IpNet::from_str("192.0.2.0/24").unwrap();
+    let pod_net = IpNet::from_str("192.0.2.0/28").unwrap();
+    let (lookup_tx, lookup_rx) = crate::lookup::pair();
+    let mut idx = Index::new(
+        lookup_tx,
+        Config {
+            cluster_networks: ClusterNetworks::Static(vec![cluster_net]),
+            identity_domains: IdentityDomains::new("cluster.example.com".into()),
+            default_allow: DefaultAllow::ClusterUnauthenticated,
+            undeclared_ports: UndeclaredPorts::DefaultAllow,
+            kubelet_source: KubeletSource::default(),
+            detect_timeout: time::Duration::from_secs(1),
+        },
+    );
+
+    idx.apply_node(mk_node("node-0", pod_net)).unwrap();
+    idx.apply_pod(mk_pod(
+        "ns-0",
+        "pod-0",
+        "node-0",
+        "192.0.2.2".parse().unwrap(),
+        Some(("container-0", vec![8080, 9090])),
+    ))
+    .unwrap();
+    idx.apply_server({
+        let mut srv = mk_server("ns-0", "srv-0", Port::Number(8080), None, None);
+        srv.spec.proxy_protocol = Some(k8s::polixy::server::ProxyProtocol::Http1);
+        srv
+    });
+
+    let network = |cidr: &str, except: &[&str]| k8s::polixy::authz::Network {
+        cidr: cidr.to_string(),
+        except: except.iter().map(|e| e.to_string()).collect(),
+    };
+    let mesh_tls = |identities: &[&str]| k8s::polixy::authz::MeshTls {
+        identities: identities.iter().map(|i| i.to_string()).collect(),
+        ..Default::default()
+    };
+    let clients = vec![
+        (
+            "authz-cluster",
+            k8s::polixy::authz::Client {
+                networks: Some(vec![network("10.0.0.0/8", &["10.1.0.0/16"])]),
+                unauthenticated: true,
+                ..Default::default()
+            },
+        ),
+        (
+            "authz-narrow",
+            k8s::polixy::authz::Client {
+                networks: Some(vec![network("10.2.0.0/16", &[])]),
+                unauthenticated: true,
+                ..Default::default()
+            },
+        ),
+        (
+            "authz-tls",
+            k8s::polixy::authz::Client {
+                mesh_tls: Some(k8s::polixy::authz::MeshTls {
+                    unauthenticated_tls: true,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        ),
+        (
+            "authz-any-id",
+            k8s::polixy::authz::Client {
+                mesh_tls: Some(mesh_tls(&["*"])),
+                ..Default::default()
+            },
+        ),
+        (
+            "authz-suffix",
+            k8s::polixy::authz::Client {
+                mesh_tls: Some(mesh_tls(&[
+                    "*.ns-1.serviceaccount.identity.linkerd.cluster.example.com",
+                ])),
+                ..Default::default()
+            },
+        ),
+        (
+            "authz-spiffe",
+            k8s::polixy::authz::Client {
+                mesh_tls: Some(mesh_tls(&["spiffe://example.org/ns/web/*"])),
+                ..Default::default()
+            },
+        ),
+        (
+            "authz-sa",
+            k8s::polixy::authz::Client {
+                mesh_tls: Some(k8s::polixy::authz::MeshTls {
+                    service_accounts: vec![k8s::polixy::authz::ServiceAccountRef {
+                        namespace: None,
+                        name: "web".to_string(),
+                        cluster: None,
+                    }],
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        ),
+    ];
+    for (name, client) in clients.into_iter() {
+        let mut authz = mk_authz("ns-0", name, "srv-0");
+        authz.spec.client = client;
+        idx.apply_authz(authz).unwrap();
+    }
+    let mut admin = mk_authz("ns-0", "authz-admin", "srv-0");
+    admin.spec.client.unauthenticated = true;
+    admin.spec.routes = Some(vec![k8s::polixy::authz::HttpRoute {
+        path_prefix: Some("/admin".to_string()),
+        ..Default::default()
+    }]);
+    idx.apply_authz(admin).unwrap();
+
+    let fixture = serde_json::from_str::<serde_json::Value>(include_str!(
+        "../../../../opa/testdata/cases.json"
+    ))
+    .expect("fixture must be valid JSON");
+
+    let data = serde_json::to_value(lookup_rx.export()).unwrap();
+    assert_eq!(
+        data,
+        fixture["data"],
+        "exported data differs from the fixture:\n{}",
+        serde_json::to_string_pretty(&data).unwrap()
+    );
+
+    let cases = fixture["cases"].as_array().expect("cases must be a list");
+    assert!(!cases.is_empty());
+    for case in cases.iter() {
+        let input = &case["input"];
+        let client_ip = input["client_ip"].as_str().unwrap().parse().unwrap();
+        let tls = match input["client_id"].as_str() {
+            Some(id) => ClientTls::Authenticated(id),
+            None if input["tls"].as_bool().unwrap_or(false) => ClientTls::Unauthenticated,
+            None => ClientTls::Plaintext,
+        };
+        let server = lookup_rx
+            .lookup(
+                input["namespace"].as_str().unwrap(),
+                input["pod"].as_str().unwrap(),
+                input["port"].as_u64().unwrap() as u16,
+                PortProtocol::Tcp,
+            )
+            .map(|rx| rx.get());
+        let authorization = server
+            .as_ref()
+            .and_then(|s| s.matcher().authorize(client_ip, tls))
+            .map(String::from);
+        assert_eq!(
+            serde_json::json!({
+                "allow": authorization.is_some(),
+                "authorization": authorization,
+            }),
+            case["expected"],
+            "{}",
+            case["name"]
+        );
+    }
+}
+
+// === Helpers ===
+
+/// Returns the last update that a watch yields before it is idle for a second.
+async fn next_latest(
+    rx: &mut polixy_controller_core::InboundServerStream,
+) -> Option<InboundServer> {
+    let mut latest = None;
+    while let Ok(Some(s)) = time::timeout(time::Duration::from_secs(1), rx.next()).await {
+        latest = Some(s);
+    }
+    latest
+}
+
+fn mk_node(name: impl Into<String>, pod_net: IpNet) -> k8s::Node {
+    k8s::Node {
+        metadata: k8s::ObjectMeta {
+            name: Some(name.into()),
+            ..Default::default()
+        },
+        spec: Some(k8s::api::core::v1::NodeSpec {
+            pod_cidr: Some(pod_net.to_string()),
+            pod_cidrs: vec![pod_net.to_string()],
+            ..Default::default()
+        }),
+        status: Some(k8s::api::core::v1::NodeStatus::default()),
+    }
+}
+
+fn mk_pod(
+    ns: impl Into<String>,
+    name: impl Into<String>,
+    node: impl Into<String>,
+    pod_ip: IpAddr,
+    containers: impl IntoIterator<Item = (impl Into<String>, impl IntoIterator<Item = u16>)>,
+) -> k8s::Pod {
+    k8s::Pod {
+        metadata: k8s::ObjectMeta {
+            namespace: Some(ns.into()),
+            name: Some(name.into()),
+            ..Default::default()
+        },
+        spec: Some(k8s::api::core::v1::PodSpec {
+            node_name: Some(node.into()),
+            containers: containers
+                .into_iter()
+                .map(|(name, ports)| k8s::api::core::v1::Container {
+                    name: name.into(),
+                    ports: ports
+                        .into_iter()
+                        .map(|p| k8s::api::core::v1::ContainerPort {
+                            container_port: p as i32,
+                            ..Default::default()
+                        })
+                        .collect(),
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        }),
+        status: Some(k8s::api::core::v1::PodStatus {
+            pod_ips: vec![k8s::api::core::v1::PodIP {
+                ip: Some(pod_ip.to_string()),
+            }],
+            ..Default::default()
+        }),
+        sidecars: Default::default(),
+    }
+}
+
+fn mk_server(
+    ns: impl Into<String>,
+    name: impl Into<String>,
+    port: Port,
+    srv_labels: impl IntoIterator<Item = (&'static str, &'static str)>,
+    pod_labels: impl IntoIterator<Item = (&'static str, &'static str)>,
+) -> k8s::polixy::Server {
+    k8s::polixy::Server {
+        api_version: "v1alpha1".to_string(),
+        kind: "Server".to_string(),
+        metadata: k8s::ObjectMeta {
+            namespace: Some(ns.into()),
+            name: Some(name.into()),
+            labels: srv_labels
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            ..Default::default()
+        },
+        spec: k8s::polixy::ServerSpec {
+            port,
+            pod_selector: pod_labels.into_iter().collect(),
+            port_protocol: None,
+            container: None,
+            proxy_protocol: None,
+            default_allow: None,
+        },
+    }
+}
+
+fn mk_authz(
+    ns: impl Into<String>,
+    name: impl Into<String>,
+    server: impl Into<String>,
+) -> k8s::polixy::ServerAuthorization {
+    k8s::polixy::ServerAuthorization {
+        api_version: "v1alpha1".to_string(),
+        kind: "ServerAuthorization".to_string(),
+        metadata: k8s::ObjectMeta {
+            namespace: Some(ns.into()),
+            name: Some(name.into()),
+            ..Default::default()
+        },
+        spec: k8s::polixy::ServerAuthorizationSpec {
+            server: k8s::polixy::authz::Server {
+                name: Some(server.into()),
+                selector: None,
+            },
+            client: k8s::polixy::authz::Client {
+                // TODO
+                ..Default::default()
+            },
+            routes: None,
+            not_before: None,
+            not_after: None,
+            rollout: None,
+        },
+    }
+}
+
+fn mk_default_allow(
+    da: DefaultAllow,
+    cluster_net: IpNet,
+    kubelet_ip: IpAddr,
+) -> BTreeMap<String, ClientAuthorization> {
+    let all_nets = vec![Ipv4Net::default().into(), Ipv6Net::default().into()];
+
+    let cluster_nets = vec![NetworkMatch::from(cluster_net)];
+
+    let authed = ClientAuthentication::TlsAuthenticated(vec![IdentityMatch::Suffix(vec![])]);
+
+    match da {
+        DefaultAllow::Deny => None,
+        DefaultAllow::AllAuthenticated => Some((
+            "_all_authed".into(),
+            ClientAuthorization {
+                authentication: authed,
+                networks: all_nets,
+            },
+        )),
+        DefaultAllow::AllUnauthenticated => Some((
             "_all_unauthed".into(),
             ClientAuthorization {
                 authentication: ClientAuthentication::Unauthenticated,
@@ -631,3 +2444,15 @@ fn healthcheck_authz(ip: IpAddr) -> (String, ClientAuthorization) {
         },
     )
 }
+
+/// A clock that advances with tokio's clock, so that it may be paused in tests.
+struct PausedClock {
+    start: time::Instant,
+    epoch: SystemTime,
+}
+
+impl Clock for PausedClock {
+    fn now(&self) -> SystemTime {
+        self.epoch + self.start.elapsed()
+    }
+}
@@ -0,0 +1,82 @@
+use crate::{rollout::RolloutSet, ServerRx};
+use anyhow::{anyhow, Error, Result};
+use polixy_controller_core::PortProtocol;
+use polixy_controller_k8s_api::polixy::server::PortRange;
+use std::collections::BTreeMap;
+use tokio::sync::watch;
+
+/// Configures how ports that are not declared by a pod's containers are served.
+///
+/// Servers may select undeclared ports by number; otherwise these ports are denied or use the pod's
+/// default-allow policy.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum UndeclaredPorts {
+    DefaultAllow,
+    Deny,
+}
+
+/// The servers of a pod's undeclared ports.
+#[derive(Clone, Debug)]
+pub(crate) struct UndeclaredServers {
+    /// The server of undeclared ports that no server selects.
+    pub default: ServerRx,
+
+    /// The servers that select undeclared ports, by name.
+    pub selected: BTreeMap<String, UndeclaredSelection>,
+}
+
+/// The undeclared ports that a server selects.
+#[derive(Clone, Debug)]
+pub(crate) struct UndeclaredSelection {
+    pub ports: PortRange,
+    pub protocol: PortProtocol,
+
+    /// The rollouts of the server that select the pod, which determine `rx`.
+    pub rollouts: RolloutSet,
+    pub rx: ServerRx,
+}
+
+/// Watches a pod's undeclared port servers.
+pub(crate) type UndeclaredRx = watch::Receiver<UndeclaredServers>;
+
+/// Publishes updates to a pod's undeclared port servers.
+pub(crate) type UndeclaredTx = watch::Sender<UndeclaredServers>;
+
+// === impl UndeclaredPorts ===
+
+impl std::str::FromStr for UndeclaredPorts {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "default-allow" => Ok(Self::DefaultAllow),
+            "deny" => Ok(Self::Deny),
+            s => Err(anyhow!("invalid undeclared port policy: {}", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for UndeclaredPorts {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::DefaultAllow => "default-allow".fmt(f),
+            Self::Deny => "deny".fmt(f),
+        }
+    }
+}
+
+// === impl UndeclaredServers ===
+
+impl UndeclaredServers {
+    /// Returns the server of an undeclared port.
+    ///
+    /// If servers select overlapping ports, the first server by name is used.
+    pub fn get(&self, (port, protocol): (u16, PortProtocol)) -> ServerRx {
+        self.selected
+            .values()
+            .find(|s| s.protocol == protocol && s.ports.contains(port))
+            .map(|s| &s.rx)
+            .unwrap_or(&self.default)
+            .clone()
+    }
+}
@@ -0,0 +1,648 @@
+//! Generates Kubernetes `NetworkPolicy` resources from Servers and their authorizations.
+//!
+//! Proxies can't enforce policy for traffic that bypasses them, e.g. from unmeshed or
+//! host-networked clients. A `NetworkPolicy` is generated for each Server so that its port only
+//! accepts connections from the networks of the authorizations that select it.
+//!
+//! NetworkPolicies operate at L3/L4, so they can only express an authorization's networks. When an
+//! authorization also requires meshed TLS identities or limits routes, the generated rule permits
+//! any client in its networks and the constraint is reported as unexpressed; proxies continue to
+//! enforce it. Note also that:
+//!
+//! - Once a pod is selected by a NetworkPolicy, it only accepts traffic permitted by a policy, so
+//!   ports without Servers no longer fall back to the default-allow policy;
+//! - Whether `ipBlock` rules apply to pod IPs depends on the network plugin;
+//! - Client pod and namespace selectors are expressed as NetworkPolicy peers, which select pods
+//!   by their IPs as the controller does;
+//! - Kubelet probes are not authorized explicitly, as most network plugins permit traffic from the
+//!   pod's node;
+//! - Servers with a default-allow policy other than `deny` accept connections from all networks,
+//!   since cluster networks and identities can't be expressed;
+//! - Authorizations' `notBefore` and `notAfter` times are reported as unexpressed, so a generated
+//!   policy continues to permit an authorization's clients after it expires;
+//! - Rollouts are reported as unexpressed, so both an authorization and the authorization it
+//!   replaces are permitted on all of their servers' pods.
+
+#![deny(warnings, rust_2018_idioms)]
+#![forbid(unsafe_code)]
+
+use anyhow::{anyhow, Result};
+use polixy_controller_core::IpNet;
+use polixy_controller_k8s_api::{
+    api::networking::v1::{
+        IPBlock, NetworkPolicy, NetworkPolicyIngressRule, NetworkPolicyPeer, NetworkPolicyPort,
+        NetworkPolicySpec,
+    },
+    polixy::{
+        self,
+        server::{DefaultAllow, Port, PortProtocol},
+        Server, ServerAuthorization,
+    },
+    IntOrString, Labels, ObjectMeta, ResourceExt,
+};
+use serde::Deserialize;
+
+/// Labels generated policies with the name of the Server they were generated from.
+pub const SERVER_LABEL: &str = "polixy.linkerd.io/server";
+
+/// Server and authorization resources from which policies are generated.
+#[derive(Clone, Debug, Default)]
+pub struct Resources {
+    pub servers: Vec<Server>,
+    pub authorizations: Vec<ServerAuthorization>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Generated {
+    /// A policy for each Server, ordered by namespace and name.
+    pub policies: Vec<NetworkPolicy>,
+
+    /// Authorization constraints that the policies don't enforce.
+    pub unexpressed: Vec<Unexpressed>,
+}
+
+/// Describes an authorization constraint that can't be expressed as a `NetworkPolicy`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Unexpressed {
+    pub namespace: String,
+    pub authorization: String,
+    pub reason: String,
+}
+
+/// An authorization's L3 constraints.
+struct Authz {
+    namespace: String,
+    server: polixy::authz::Server,
+    from: Vec<NetworkPolicyPeer>,
+}
+
+/// Generates a `NetworkPolicy` for each Server.
+///
+/// Authorizations that are invalid are not included in any policy and are reported as
+/// unexpressed, so their servers' policies may be more restrictive than their proxies.
+pub fn generate(resources: &Resources) -> Generated {
+    let mut unexpressed = Vec::new();
+
+    let mut authzs = resources.authorizations.iter().collect::<Vec<_>>();
+    authzs.sort_by_key(|a| (a.namespace(), a.name()));
+    let authzs = authzs
+        .into_iter()
+        .filter_map(|authz| mk_authz(authz, &mut unexpressed))
+        .collect::<Vec<_>>();
+
+    let mut servers = resources.servers.iter().collect::<Vec<_>>();
+    servers.sort_by_key(|s| (s.namespace(), s.name()));
+    let policies = servers
+        .into_iter()
+        .map(|srv| mk_policy(srv, &authzs))
+        .collect();
+
+    Generated {
+        policies,
+        unexpressed,
+    }
+}
+
+fn mk_policy(srv: &Server, authzs: &[Authz]) -> NetworkPolicy {
+    let ns = srv.namespace().unwrap_or_default();
+    let name = srv.name();
+    let labels = Labels::from(srv.labels().clone());
+
+    let protocol = match srv.spec.port_protocol {
+        None | Some(PortProtocol::Tcp) => "TCP",
+        Some(PortProtocol::Udp) => "UDP",
+        Some(PortProtocol::Sctp) => "SCTP",
+    };
+    // NetworkPolicy ports can't express ranges (before Kubernetes v1.21's `endPort`), so each port
+    // in a range is listed.
+    let ports = match srv.spec.port {
+        Port::Number(n) => vec![IntOrString::Int(n.into())],
+        Port::Range(range) => range.iter().map(|n| IntOrString::Int(n.into())).collect(),
+        Port::Name(ref n) => vec![IntOrString::String(n.clone())],
+    }
+    .into_iter()
+    .map(|port| NetworkPolicyPort {
+        port: Some(port),
+        protocol: Some(protocol.to_string()),
+        ..Default::default()
+    })
+    .collect::<Vec<_>>();
+
+    let default_rule = match srv.spec.default_allow {
+        None | Some(DefaultAllow::Deny) => None,
+        Some(_) => Some(NetworkPolicyIngressRule {
+            from: vec![
+                mk_peer_from_net(IpNet::V4(Default::default()), Vec::new()),
+                mk_peer_from_net(IpNet::V6(Default::default()), Vec::new()),
+            ],
+            ports: ports.clone(),
+        }),
+    };
+
+    let ingress = authzs
+        .iter()
+        .filter(|a| a.namespace == ns)
+        .filter(|a| match a.server {
+            polixy::authz::Server {
+                name: Some(ref n), ..
+            } => *n == name,
+            polixy::authz::Server {
+                selector: Some(ref sel),
+                ..
+            } => sel.matches(&labels),
+            _ => false,
+        })
+        // A rule without peers would permit all clients.
+        .filter(|a| !a.from.is_empty())
+        .map(|a| NetworkPolicyIngressRule {
+            from: a.from.clone(),
+            ports: ports.clone(),
+        })
+        .chain(default_rule)
+        .collect();
+
+    NetworkPolicy {
+        metadata: ObjectMeta {
+            name: Some(format!("polixy-{}", name)),
+            namespace: Some(ns),
+            labels: Some((SERVER_LABEL.to_string(), name)).into_iter().collect(),
+            ..Default::default()
+        },
+        spec: Some(NetworkPolicySpec {
+            pod_selector: (&srv.spec.pod_selector).into(),
+            policy_types: vec!["Ingress".to_string()],
+            ingress,
+            ..Default::default()
+        }),
+    }
+}
+
+fn mk_authz(authz: &ServerAuthorization, unexpressed: &mut Vec<Unexpressed>) -> Option<Authz> {
+    let namespace = authz.namespace().unwrap_or_default();
+    let name = authz.name();
+    let mut report = |reason: String| {
+        unexpressed.push(Unexpressed {
+            namespace: namespace.clone(),
+            authorization: name.clone(),
+            reason,
+        })
+    };
+
+    let spec = &authz.spec;
+    if spec.server.name.is_some() == spec.server.selector.is_some() {
+        report("invalid: the authorization must select servers by name or selector".to_string());
+        return None;
+    }
+
+    let selects_pods =
+        spec.client.pod_selector.is_some() || spec.client.namespace_selector.is_some();
+    let from = match spec.client.networks {
+        Some(_) if selects_pods => {
+            report(
+                "invalid: client networks may not be combined with pod or namespace selectors"
+                    .to_string(),
+            );
+            return None;
+        }
+        None if selects_pods => vec![NetworkPolicyPeer {
+            pod_selector: spec.client.pod_selector.as_ref().map(Into::into),
+            namespace_selector: spec.client.namespace_selector.as_ref().map(Into::into),
+            ..Default::default()
+        }],
+        Some(ref nets) => match nets.iter().map(mk_peer).collect::<Result<Vec<_>>>() {
+            Ok(from) => from,
+            Err(error) => {
+                report(format!("invalid: {}", error));
+                return None;
+            }
+        },
+        None => vec![
+            mk_peer_from_net(IpNet::V4(Default::default()), Vec::new()),
+            mk_peer_from_net(IpNet::V6(Default::default()), Vec::new()),
+        ],
+    };
+
+    if !spec.client.unauthenticated {
+        match spec.client.mesh_tls {
+            None => {
+                report("invalid: client mtls missing".to_string());
+                return None;
+            }
+            Some(ref mtls) if !mtls.unauthenticated_tls => report(
+                "meshTLS client identities are not enforced; clients are permitted by network"
+                    .to_string(),
+            ),
+            Some(_) => {
+                report("meshTLS is not enforced; clients are permitted by network".to_string())
+            }
+        }
+    }
+
+    if spec.routes.iter().flatten().next().is_some() {
+        report(
+            "routes are not enforced; all requests are permitted on the server's port".to_string(),
+        );
+    }
+
+    if spec.not_before.is_some() || spec.not_after.is_some() {
+        report(
+            "notBefore and notAfter are not enforced; clients are permitted at all times"
+                .to_string(),
+        );
+    }
+
+    if spec.rollout.is_some() {
+        report(
+            "rollout is not enforced; clients are permitted on all of the server's pods"
+                .to_string(),
+        );
+    }
+
+    Some(Authz {
+        server: spec.server.clone(),
+        from,
+        namespace,
+    })
+}
+
+fn mk_peer(network: &polixy::authz::Network) -> Result<NetworkPolicyPeer> {
+    let net = network
+        .cidr
+        .parse::<IpNet>()
+        .map_err(|e| anyhow!("invalid network {:?}: {}", network.cidr, e))?;
+    let except = network
+        .except
+        .iter()
+        .map(|cidr| {
+            cidr.parse::<IpNet>()
+                .map_err(|e| anyhow!("invalid network {:?}: {}", cidr, e))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(mk_peer_from_net(net, except))
+}
+
+fn mk_peer_from_net(net: IpNet, except: Vec<IpNet>) -> NetworkPolicyPeer {
+    NetworkPolicyPeer {
+        ip_block: Some(IPBlock {
+            cidr: net.to_string(),
+            // Kubernetes rejects exceptions outside of the block, and they have no effect anyway.
+            except: except
+                .into_iter()
+                .filter(|e| net.contains(e))
+                .map(|e| e.to_string())
+                .collect(),
+        }),
+        ..Default::default()
+    }
+}
+
+// === impl Resources ===
+
+impl Resources {
+    /// Reads Servers and ServerAuthorizations from a stream of YAML documents.
+    ///
+    /// Lists, as written by `kubectl get -o yaml`, are flattened and other resources are ignored.
+    pub fn read_yaml(&mut self, yaml: &str) -> Result<()> {
+        for doc in serde_yaml::Deserializer::from_str(yaml) {
+            let value = serde_yaml::Value::deserialize(doc)?;
+            self.add(value)?;
+        }
+        Ok(())
+    }
+
+    fn add(&mut self, value: serde_yaml::Value) -> Result<()> {
+        let kind = value
+            .get("kind")
+            .and_then(|k| k.as_str())
+            .unwrap_or_default();
+        let polixy = value
+            .get("apiVersion")
+            .and_then(|v| v.as_str())
+            .map(|v| v.starts_with("polixy.linkerd.io/"))
+            .unwrap_or(false);
+        match kind {
+            "List" => {
+                if let Some(items) = value.get("items").and_then(|i| i.as_sequence()) {
+                    for item in items.iter() {
+                        self.add(item.clone())?;
+                    }
+                }
+            }
+            "Server" if polixy => self.servers.push(serde_yaml::from_value(value)?),
+            "ServerAuthorization" if polixy => {
+                self.authorizations.push(serde_yaml::from_value(value)?)
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+// === impl Unexpressed ===
+
+impl std::fmt::Display for Unexpressed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "serverauthorization {}/{}: {}",
+            self.namespace, self.authorization, self.reason
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use polixy_controller_k8s_api::{
+        labels,
+        polixy::{authz, server::ServerSpec, ServerAuthorizationSpec},
+    };
+
+    fn mk_server(name: &str, port: Port, labels: &[(&str, &str)]) -> Server {
+        let mut srv = Server::new(
+            name,
+            ServerSpec {
+                pod_selector: Some(("app", "web")).into_iter().collect(),
+                port,
+                port_protocol: None,
+                container: None,
+                proxy_protocol: None,
+                default_allow: None,
+            },
+        );
+        srv.metadata.namespace = Some("ns-0".to_string());
+        srv.metadata.labels = labels
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        srv
+    }
+
+    fn mk_authz(name: &str, server: authz::Server, client: authz::Client) -> ServerAuthorization {
+        let mut authz = ServerAuthorization::new(
+            name,
+            ServerAuthorizationSpec {
+                server,
+                client,
+                routes: None,
+                not_before: None,
+                not_after: None,
+                rollout: None,
+            },
+        );
+        authz.metadata.namespace = Some("ns-0".to_string());
+        authz
+    }
+
+    fn by_name(name: &str) -> authz::Server {
+        authz::Server {
+            name: Some(name.to_string()),
+            selector: None,
+        }
+    }
+
+    fn ip_blocks(policy: &NetworkPolicy) -> Vec<Vec<(String, Vec<String>)>> {
+        policy
+            .spec
+            .as_ref()
+            .unwrap()
+            .ingress
+            .iter()
+            .map(|rule| {
+                rule.from
+                    .iter()
+                    .map(|p| {
+                        let block = p.ip_block.clone().unwrap();
+                        (block.cidr, block.except)
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn unauthenticated_networks() {
+        let resources = Resources {
+            servers: vec![mk_server("web", Port::Name("http".to_string()), &[])],
+            authorizations: vec![mk_authz(
+                "authz-0",
+                by_name("web"),
+                authz::Client {
+                    unauthenticated: true,
+                    networks: Some(vec![authz::Network {
+                        cidr: "10.0.0.0/8".to_string(),
+                        except: vec!["10.1.0.0/16".to_string(), "192.168.0.0/16".to_string()],
+                    }]),
+                    ..Default::default()
+                },
+            )],
+        };
+
+        let Generated {
+            policies,
+            unexpressed,
+        } = generate(&resources);
+        assert_eq!(unexpressed, vec![]);
+        assert_eq!(policies.len(), 1);
+
+        let policy = &policies[0];
+        assert_eq!(policy.metadata.name.as_deref(), Some("polixy-web"));
+        assert_eq!(policy.metadata.namespace.as_deref(), Some("ns-0"));
+        let spec = policy.spec.as_ref().unwrap();
+        assert_eq!(spec.pod_selector.match_labels.get("app").unwrap(), "web");
+        assert_eq!(spec.policy_types, vec!["Ingress".to_string()]);
+        assert_eq!(
+            spec.ingress[0].ports[0].port,
+            Some(IntOrString::String("http".to_string()))
+        );
+        assert_eq!(
+            ip_blocks(policy),
+            vec![vec![(
+                "10.0.0.0/8".to_string(),
+                vec!["10.1.0.0/16".to_string()]
+            )]]
+        );
+    }
+
+    #[test]
+    fn identities_are_reported() {
+        let resources = Resources {
+            servers: vec![
+                mk_server("web", Port::Number(8080), &[("tier", "front")]),
+                mk_server("admin", Port::Number(9990), &[]),
+            ],
+            authorizations: vec![mk_authz(
+                "authz-0",
+                authz::Server {
+                    name: None,
+                    selector: Some(Some(("tier", "front")).into_iter().collect()),
+                },
+                authz::Client {
+                    mesh_tls: Some(authz::MeshTls {
+                        identities: vec!["*".to_string()],
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                },
+            )],
+        };
+
+        let Generated {
+            policies,
+            unexpressed,
+        } = generate(&resources);
+        assert_eq!(unexpressed.len(), 1);
+        assert_eq!(unexpressed[0].authorization, "authz-0");
+
+        // Policies are ordered by name.
+        assert_eq!(policies[0].metadata.name.as_deref(), Some("polixy-admin"));
+        assert_eq!(ip_blocks(&policies[0]), Vec::<Vec<_>>::new());
+
+        assert_eq!(policies[1].metadata.name.as_deref(), Some("polixy-web"));
+        assert_eq!(
+            ip_blocks(&policies[1]),
+            vec![vec![
+                ("0.0.0.0/0".to_string(), vec![]),
+                ("::/0".to_string(), vec![]),
+            ]]
+        );
+        assert_eq!(
+            policies[1].spec.as_ref().unwrap().ingress[0].ports[0].port,
+            Some(IntOrString::Int(8080))
+        );
+    }
+
+    #[test]
+    fn invalid_and_empty_authorizations() {
+        let resources = Resources {
+            servers: vec![mk_server("web", Port::Number(8080), &[])],
+            authorizations: vec![
+                mk_authz(
+                    "empty",
+                    by_name("web"),
+                    authz::Client {
+                        unauthenticated: true,
+                        networks: Some(vec![]),
+                        ..Default::default()
+                    },
+                ),
+                mk_authz(
+                    "invalid",
+                    by_name("web"),
+                    authz::Client {
+                        unauthenticated: true,
+                        networks: Some(vec![authz::Network {
+                            cidr: "10.0.0.0".to_string(),
+                            except: vec![],
+                        }]),
+                        ..Default::default()
+                    },
+                ),
+            ],
+        };
+
+        let Generated {
+            policies,
+            unexpressed,
+        } = generate(&resources);
+        assert_eq!(ip_blocks(&policies[0]), Vec::<Vec<_>>::new());
+        assert_eq!(unexpressed.len(), 1);
+        assert_eq!(unexpressed[0].authorization, "invalid");
+        assert!(unexpressed[0].reason.starts_with("invalid:"));
+    }
+
+    #[test]
+    fn client_pod_selectors() {
+        let pods = Some(("app", "vote-bot"))
+            .into_iter()
+            .collect::<labels::Selector>();
+        let resources = Resources {
+            servers: vec![mk_server("web", Port::Number(8080), &[])],
+            authorizations: vec![mk_authz(
+                "authz-0",
+                by_name("web"),
+                authz::Client {
+                    unauthenticated: true,
+                    pod_selector: Some(pods.clone()),
+                    ..Default::default()
+                },
+            )],
+        };
+
+        let Generated {
+            policies,
+            unexpressed,
+        } = generate(&resources);
+        assert_eq!(unexpressed, vec![]);
+        assert_eq!(
+            policies[0].spec.as_ref().unwrap().ingress[0].from,
+            vec![NetworkPolicyPeer {
+                pod_selector: Some((&pods).into()),
+                ..Default::default()
+            }]
+        );
+    }
+
+    #[test]
+    fn server_default_allow() {
+        let mut srv = mk_server("web", Port::Number(8080), &[]);
+        srv.spec.default_allow = Some(DefaultAllow::ClusterAuthenticated);
+        let resources = Resources {
+            servers: vec![srv],
+            authorizations: vec![],
+        };
+
+        let Generated {
+            policies,
+            unexpressed,
+        } = generate(&resources);
+        assert_eq!(unexpressed, vec![]);
+        assert_eq!(
+            ip_blocks(&policies[0]),
+            vec![vec![
+                ("0.0.0.0/0".to_string(), vec![]),
+                ("::/0".to_string(), vec![]),
+            ]]
+        );
+    }
+
+    #[test]
+    fn read_yaml() {
+        let yaml = r#"
+apiVersion: v1
+kind: List
+items:
+- apiVersion: polixy.linkerd.io/v1alpha1
+  kind: Server
+  metadata:
+    name: web
+    namespace: ns-0
+  spec:
+    podSelector:
+      matchLabels:
+        app: web
+    port: 8080
+- apiVersion: v1
+  kind: ConfigMap
+  metadata:
+    name: other
+---
+apiVersion: polixy.linkerd.io/v1alpha1
+kind: ServerAuthorization
+metadata:
+  name: authz-0
+  namespace: ns-0
+spec:
+  server:
+    name: web
+  client:
+    unauthenticated: true
+"#;
+        let mut resources = Resources::default();
+        resources.read_yaml(yaml).unwrap();
+        assert_eq!(resources.servers.len(), 1);
+        assert_eq!(resources.servers[0].name(), "web");
+        assert_eq!(resources.authorizations.len(), 1);
+        assert_eq!(resources.authorizations[0].name(), "authz-0");
+    }
+}
@@ -0,0 +1,238 @@
+//! Imports SMI access policies.
+//!
+//! When enabled, the controller watches SMI `TrafficTarget`, `HTTPRouteGroup`, and `TCPRoute`
+//! resources and generates a `ServerAuthorization` for each target's TCP and HTTP rules. Generated
+//! authorizations are owned by their target, so they are deleted when the target is deleted, and
+//! they are indexed like any other authorization. Each target is annotated with a status describing
+//! what was and wasn't imported.
+
+#![deny(warnings, rust_2018_idioms)]
+#![forbid(unsafe_code)]
+
+mod translate;
+
+pub use self::translate::{
+    translate, Translation, SERVICE_ACCOUNT_LABEL, STATUS_ANNOTATION, TARGET_LABEL,
+};
+use anyhow::{Error, Result};
+use kube::api::{Api, DeleteParams, ListParams, Patch, PatchParams};
+use polixy_controller_k8s_api::{
+    self as k8s,
+    polixy::ServerAuthorization,
+    smi::{HttpRouteGroup, TcpRoute, TrafficTarget},
+    ResourceExt,
+};
+use std::collections::{HashMap, HashSet};
+use tracing::{debug, instrument, warn};
+
+/// Identifies the controller's changes to generated authorizations.
+const FIELD_MANAGER: &str = "polixy-smi";
+
+struct Index {
+    client: kube::Client,
+    namespaces: HashMap<String, Namespace>,
+}
+
+#[derive(Debug, Default)]
+struct Namespace {
+    targets: HashMap<String, TrafficTarget>,
+    http_route_groups: HashMap<String, HttpRouteGroup>,
+    tcp_routes: HashMap<String, TcpRoute>,
+
+    /// The last translation successfully written for each target, so that targets are only
+    /// rewritten when their translation changes.
+    written: HashMap<String, serde_json::Value>,
+}
+
+/// Imports SMI resources.
+///
+/// Watches are restarted when they fail, so this never returns; it returns an `Error` so that it
+/// may be supervised like the indexer.
+///
+/// All updates are processed on a single task. When a resource changes, all of the targets in its
+/// namespace are translated again and those with changed translations are written. Failed writes
+/// are retried when the namespace next changes or when the watches are restarted.
+pub async fn run(client: kube::Client, watches: k8s::SmiWatches) -> Error {
+    let k8s::SmiWatches {
+        mut traffic_targets_rx,
+        mut http_route_groups_rx,
+        mut tcp_routes_rx,
+    } = watches;
+
+    let mut index = Index {
+        client,
+        namespaces: HashMap::default(),
+    };
+    loop {
+        let changed = tokio::select! {
+            up = traffic_targets_rx.recv() => match up {
+                k8s::Event::Applied(target) => index.apply(target, |ns| &mut ns.targets),
+                k8s::Event::Deleted(target) => index.delete_target(target).await,
+                k8s::Event::Restarted(targets) => index.reset(targets, |ns| &mut ns.targets),
+            },
+
+            up = http_route_groups_rx.recv() => match up {
+                k8s::Event::Applied(group) => index.apply(group, |ns| &mut ns.http_route_groups),
+                k8s::Event::Deleted(group) => index.delete(group, |ns| &mut ns.http_route_groups),
+                k8s::Event::Restarted(groups) => index.reset(groups, |ns| &mut ns.http_route_groups),
+            },
+
+            up = tcp_routes_rx.recv() => match up {
+                k8s::Event::Applied(route) => index.apply(route, |ns| &mut ns.tcp_routes),
+                k8s::Event::Deleted(route) => index.delete(route, |ns| &mut ns.tcp_routes),
+                k8s::Event::Restarted(routes) => index.reset(routes, |ns| &mut ns.tcp_routes),
+            },
+        };
+
+        for ns in changed.into_iter() {
+            index.write(&ns).await;
+        }
+    }
+}
+
+// === impl Index ===
+
+impl Index {
+    /// Indexes a resource, returning its namespace.
+    fn apply<K: ResourceExt>(
+        &mut self,
+        resource: K,
+        get: impl Fn(&mut Namespace) -> &mut HashMap<String, K>,
+    ) -> Vec<String> {
+        let ns = resource.namespace().expect("resource must be namespaced");
+        let name = resource.name();
+        get(self.namespaces.entry(ns.clone()).or_default()).insert(name, resource);
+        vec![ns]
+    }
+
+    fn delete<K: ResourceExt>(
+        &mut self,
+        resource: K,
+        get: impl Fn(&mut Namespace) -> &mut HashMap<String, K>,
+    ) -> Vec<String> {
+        let ns = resource.namespace().expect("resource must be namespaced");
+        if let Some(namespace) = self.namespaces.get_mut(&ns) {
+            get(namespace).remove(&resource.name());
+        }
+        vec![ns]
+    }
+
+    /// Replaces all resources of a kind, returning the namespaces of all prior and new resources.
+    fn reset<K: ResourceExt>(
+        &mut self,
+        resources: Vec<K>,
+        get: impl Fn(&mut Namespace) -> &mut HashMap<String, K>,
+    ) -> Vec<String> {
+        let mut changed = HashSet::new();
+        for (name, ns) in self.namespaces.iter_mut() {
+            let prior = get(ns);
+            if !prior.is_empty() {
+                prior.clear();
+                changed.insert(name.clone());
+            }
+            // Rewrite all targets in case a prior write failed.
+            ns.written.clear();
+        }
+        for resource in resources.into_iter() {
+            changed.extend(self.apply(resource, &get));
+        }
+        changed.into_iter().collect()
+    }
+
+    /// Deletes the authorizations generated for a target.
+    ///
+    /// Generated authorizations are owned by their target and are eventually garbage-collected,
+    /// but they are deleted immediately so that access is revoked promptly.
+    async fn delete_target(&mut self, target: TrafficTarget) -> Vec<String> {
+        let ns = target.namespace().expect("resource must be namespaced");
+        let name = target.name();
+        if let Some(namespace) = self.namespaces.get_mut(&ns) {
+            namespace.targets.remove(&name);
+            namespace.written.remove(&name);
+        }
+
+        if let Err(error) = self.prune(&ns, &name, &HashSet::new()).await {
+            warn!(%ns, target = %name, %error, "Failed to delete generated authorizations");
+        }
+
+        Vec::new()
+    }
+
+    /// Writes the translations of all targets in a namespace that have changed.
+    async fn write(&mut self, ns: &str) {
+        let namespace = match self.namespaces.get_mut(ns) {
+            Some(namespace) => namespace,
+            None => return,
+        };
+
+        let mut translations = Vec::new();
+        for (name, target) in namespace.targets.iter() {
+            let translation =
+                translate(target, &namespace.http_route_groups, &namespace.tcp_routes);
+            let value = serde_json::json!({
+                "authorizations": &translation.authorizations,
+                "status": &translation.status,
+            });
+            if namespace.written.get(name) != Some(&value) {
+                translations.push((name.clone(), translation, value));
+            }
+        }
+
+        for (name, translation, value) in translations.into_iter() {
+            match self.write_target(ns, &name, translation).await {
+                Ok(()) => {
+                    if let Some(namespace) = self.namespaces.get_mut(ns) {
+                        namespace.written.insert(name, value);
+                    }
+                }
+                Err(error) => warn!(%ns, target = %name, %error, "Failed to import target"),
+            }
+        }
+    }
+
+    #[instrument(skip(self, translation))]
+    async fn write_target(&self, ns: &str, name: &str, translation: Translation) -> Result<()> {
+        let Translation {
+            authorizations,
+            status,
+        } = translation;
+
+        let api = Api::<ServerAuthorization>::namespaced(self.client.clone(), ns);
+        let params = PatchParams::apply(FIELD_MANAGER).force();
+        let mut desired = HashSet::new();
+        for authz in authorizations.into_iter() {
+            let authz_name = authz.name();
+            debug!(authz = %authz_name, "Applying authorization");
+            api.patch(&authz_name, &params, &Patch::Apply(&authz))
+                .await?;
+            desired.insert(authz_name);
+        }
+        self.prune(ns, name, &desired).await?;
+
+        debug!(?status, "Annotating target");
+        let patch = serde_json::json!({
+            "metadata": {
+                "annotations": { STATUS_ANNOTATION: serde_json::to_string(&status)? },
+            },
+        });
+        Api::<TrafficTarget>::namespaced(self.client.clone(), ns)
+            .patch(name, &PatchParams::default(), &Patch::Merge(&patch))
+            .await?;
+
+        Ok(())
+    }
+
+    /// Deletes the authorizations generated for a target, except those that are still desired.
+    async fn prune(&self, ns: &str, name: &str, desired: &HashSet<String>) -> Result<()> {
+        let api = Api::<ServerAuthorization>::namespaced(self.client.clone(), ns);
+        let params = ListParams::default().labels(&format!("{}={}", TARGET_LABEL, name));
+        for authz in api.list(&params).await?.items.into_iter() {
+            let authz_name = authz.name();
+            if !desired.contains(&authz_name) {
+                debug!(authz = %authz_name, "Deleting authorization");
+                api.delete(&authz_name, &DeleteParams::default()).await?;
+            }
+        }
+        Ok(())
+    }
+}
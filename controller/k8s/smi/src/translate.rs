@@ -0,0 +1,573 @@
+use polixy_controller_k8s_api::{
+    labels::Selector,
+    polixy::{
+        authz::{Client, HttpRoute, MeshTls, Server, ServiceAccountRef},
+        ServerAuthorization, ServerAuthorizationSpec,
+    },
+    smi::{
+        access::{IdentityBindingSubject, TrafficTargetRule},
+        HttpRouteGroup, TcpRoute, TrafficTarget, TrafficTargetStatus,
+    },
+    ObjectMeta, OwnerReference, ResourceExt,
+};
+use std::collections::HashMap;
+
+/// Labels each generated `ServerAuthorization` with the name of the `TrafficTarget` it implements.
+pub const TARGET_LABEL: &str = "smi.polixy.linkerd.io/traffic-target";
+
+/// Servers must carry this label, set to the name of a `TrafficTarget`'s destination service
+/// account, to be selected by the authorizations generated for the target.
+///
+/// Servers select pods by label, so the pods' service accounts can't be inferred from them. Rather
+/// than authorizing a target's sources to every server in the namespace, servers must opt in.
+pub const SERVICE_ACCOUNT_LABEL: &str = "smi.polixy.linkerd.io/service-account";
+
+/// Annotates each `TrafficTarget` with a JSON-encoded `TrafficTargetStatus` describing how it was
+/// imported.
+pub const STATUS_ANNOTATION: &str = "smi.polixy.linkerd.io/import-status";
+
+/// The `ServerAuthorization`s that implement a `TrafficTarget`, and a status describing them.
+#[derive(Clone, Debug)]
+pub struct Translation {
+    pub authorizations: Vec<ServerAuthorization>,
+    pub status: TrafficTargetStatus,
+}
+
+/// Describes how a `pathRegex` may be expressed as a route.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum PathRegex {
+    Any,
+    Exact(String),
+    Prefix(String),
+}
+
+/// Translates a `TrafficTarget` into `ServerAuthorization`s in the target's namespace.
+///
+/// Parts of the target that can't be expressed as authorizations are ignored, rather than
+/// approximated, and reported in the status:
+///
+/// * Only `ServiceAccount` destinations in the target's namespace are supported;
+/// * Only `ServiceAccount` sources are supported;
+/// * `HTTPRouteGroup` matches are ignored if they match headers or if their path regex is not a
+///   literal path or a literal path prefix. A prefix like `/api/.*` also matches `/api`;
+/// * `TCPRoute`s are ignored if they are limited to ports.
+///
+/// A target with `TCPRoute` rules generates an authorization for all of a server's traffic. A
+/// target with `HTTPRouteGroup` rules generates an authorization for the matching routes.
+pub fn translate(
+    target: &TrafficTarget,
+    http_route_groups: &HashMap<String, HttpRouteGroup>,
+    tcp_routes: &HashMap<String, TcpRoute>,
+) -> Translation {
+    let ns = target
+        .namespace()
+        .expect("traffic target must be namespaced");
+    let name = target.name();
+    let mut status = TrafficTargetStatus {
+        observed_generation: target.metadata.generation,
+        ..Default::default()
+    };
+
+    let destination = match mk_destination(&target.spec.destination, &ns) {
+        Ok(sa) => sa,
+        Err(msg) => {
+            status.untranslated.push(msg);
+            return Translation {
+                authorizations: vec![],
+                status,
+            };
+        }
+    };
+    status.translated.push(format!(
+        "destination: servers labeled {}={}",
+        SERVICE_ACCOUNT_LABEL, destination
+    ));
+    if let Some(port) = target.spec.destination.port {
+        status.untranslated.push(format!(
+            "destination port {} is not enforced; only label servers on this port",
+            port
+        ));
+    }
+
+    let mut service_accounts = Vec::new();
+    for src in target.spec.sources.iter() {
+        match mk_source(src, &ns) {
+            Ok(sa) => {
+                status.translated.push(format!(
+                    "source: {}/{}",
+                    sa.namespace.as_deref().unwrap_or(&ns),
+                    sa.name
+                ));
+                service_accounts.push(sa);
+            }
+            Err(msg) => status.untranslated.push(msg),
+        }
+    }
+
+    let mut all_requests = false;
+    let mut routes = Vec::new();
+    for rule in target.spec.rules.iter() {
+        match rule.kind.as_str() {
+            "TCPRoute" => match tcp_routes.get(&rule.name) {
+                None => status
+                    .untranslated
+                    .push(format!("TCPRoute {}: not found", rule.name)),
+                Some(route) => match route.spec.matches {
+                    Some(ref m) if !m.ports.is_empty() => status
+                        .untranslated
+                        .push(format!("TCPRoute {}: ports are not supported", rule.name)),
+                    _ => {
+                        status
+                            .translated
+                            .push(format!("TCPRoute {}: all traffic", rule.name));
+                        all_requests = true;
+                    }
+                },
+            },
+            "HTTPRouteGroup" => match http_route_groups.get(&rule.name) {
+                None => status
+                    .untranslated
+                    .push(format!("HTTPRouteGroup {}: not found", rule.name)),
+                Some(group) => routes.extend(mk_routes(rule, group, &mut status)),
+            },
+            kind => status
+                .untranslated
+                .push(format!("{} {}: unsupported rule kind", kind, rule.name)),
+        }
+    }
+
+    let mut authorizations = Vec::new();
+    if !service_accounts.is_empty() {
+        if all_requests {
+            authorizations.push(mk_authz(
+                target,
+                format!("smi-{}", name),
+                &destination,
+                &service_accounts,
+                None,
+            ));
+        }
+        if !routes.is_empty() {
+            authorizations.push(mk_authz(
+                target,
+                format!("smi-{}-http", name),
+                &destination,
+                &service_accounts,
+                Some(routes),
+            ));
+        }
+    }
+    status.authorizations = authorizations.iter().map(|a| a.name()).collect();
+
+    Translation {
+        authorizations,
+        status,
+    }
+}
+
+fn mk_destination(dst: &IdentityBindingSubject, ns: &str) -> Result<String, String> {
+    if dst.kind != "ServiceAccount" {
+        return Err(format!(
+            "destination {} {}: only ServiceAccount destinations are supported",
+            dst.kind, dst.name
+        ));
+    }
+    match dst.namespace.as_deref() {
+        Some(dst_ns) if dst_ns != ns => Err(format!(
+            "destination {}/{}: destinations must be in the target's namespace",
+            dst_ns, dst.name
+        )),
+        _ => Ok(dst.name.clone()),
+    }
+}
+
+fn mk_source(src: &IdentityBindingSubject, ns: &str) -> Result<ServiceAccountRef, String> {
+    if src.kind != "ServiceAccount" {
+        return Err(format!(
+            "source {} {}: only ServiceAccount sources are supported",
+            src.kind, src.name
+        ));
+    }
+    Ok(ServiceAccountRef {
+        name: src.name.clone(),
+        namespace: Some(src.namespace.clone().unwrap_or_else(|| ns.to_string())),
+        cluster: None,
+    })
+}
+
+fn mk_routes(
+    rule: &TrafficTargetRule,
+    group: &HttpRouteGroup,
+    status: &mut TrafficTargetStatus,
+) -> Vec<HttpRoute> {
+    for name in rule.matches.iter() {
+        if !group.spec.matches.iter().any(|m| &m.name == name) {
+            status.untranslated.push(format!(
+                "HTTPRouteGroup {}: match {} not found",
+                rule.name, name
+            ));
+        }
+    }
+
+    let mut routes = Vec::new();
+    for m in group.spec.matches.iter() {
+        if !rule.matches.is_empty() && !rule.matches.contains(&m.name) {
+            continue;
+        }
+
+        if m.headers.as_ref().map(|h| !h.is_empty()).unwrap_or(false) {
+            status.untranslated.push(format!(
+                "HTTPRouteGroup {}: match {}: headers are not supported",
+                rule.name, m.name
+            ));
+            continue;
+        }
+
+        let path = match m.path_regex.as_deref().map(parse_path_regex) {
+            None => PathRegex::Any,
+            Some(Some(path)) => path,
+            Some(None) => {
+                status.untranslated.push(format!(
+                    "HTTPRouteGroup {}: match {}: unsupported path regex {:?}",
+                    rule.name,
+                    m.name,
+                    m.path_regex.as_deref().unwrap_or_default()
+                ));
+                continue;
+            }
+        };
+
+        let methods = match m.methods {
+            Some(ref methods) if !methods.is_empty() && !methods.iter().any(|m| m == "*") => {
+                Some(methods.clone())
+            }
+            _ => None,
+        };
+
+        let route = match path {
+            PathRegex::Any => HttpRoute {
+                methods,
+                ..Default::default()
+            },
+            PathRegex::Exact(p) => HttpRoute {
+                path: Some(p),
+                methods,
+                ..Default::default()
+            },
+            PathRegex::Prefix(p) => HttpRoute {
+                path_prefix: Some(p),
+                methods,
+                ..Default::default()
+            },
+        };
+        // A route must constrain requests somehow, so a match for all requests is expressed as a
+        // prefix of the root path.
+        let route =
+            if route.path.is_none() && route.path_prefix.is_none() && route.methods.is_none() {
+                HttpRoute {
+                    path_prefix: Some("/".to_string()),
+                    ..route
+                }
+            } else {
+                route
+            };
+
+        status
+            .translated
+            .push(format!("HTTPRouteGroup {}: match {}", rule.name, m.name));
+        routes.push(route);
+    }
+    routes
+}
+
+/// Parses a path regex that is either a literal path or a literal prefix ending with `/.*`.
+///
+/// SMI path regexes must match the entire path, so anchors are ignored.
+fn parse_path_regex(re: &str) -> Option<PathRegex> {
+    let re = re.strip_prefix('^').unwrap_or(re);
+    let re = re.strip_suffix('$').unwrap_or(re);
+    if re.is_empty() || re == ".*" || re == "/.*" {
+        return Some(PathRegex::Any);
+    }
+
+    let (literal, prefix) = match re.strip_suffix(".*") {
+        Some(p) if p.ends_with('/') => (p, true),
+        Some(_) => return None,
+        None => (re, false),
+    };
+
+    let mut path = String::with_capacity(literal.len());
+    let mut chars = literal.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(c) if c.is_ascii_punctuation() => path.push(c),
+                _ => return None,
+            },
+            '.' | '*' | '+' | '?' | '(' | ')' | '[' | ']' | '{' | '}' | '|' | '^' | '$' => {
+                return None
+            }
+            c => path.push(c),
+        }
+    }
+    if !path.starts_with('/') {
+        return None;
+    }
+
+    if prefix {
+        Some(PathRegex::Prefix(path))
+    } else {
+        Some(PathRegex::Exact(path))
+    }
+}
+
+fn mk_authz(
+    target: &TrafficTarget,
+    name: String,
+    destination: &str,
+    service_accounts: &[ServiceAccountRef],
+    routes: Option<Vec<HttpRoute>>,
+) -> ServerAuthorization {
+    let selector = Selector::from_map(
+        Some((SERVICE_ACCOUNT_LABEL.to_string(), destination.to_string()))
+            .into_iter()
+            .collect(),
+    );
+    let spec = ServerAuthorizationSpec {
+        server: Server {
+            name: None,
+            selector: Some(selector),
+        },
+        client: Client {
+            mesh_tls: Some(MeshTls {
+                service_accounts: service_accounts.to_vec(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        },
+        routes,
+        not_before: None,
+        not_after: None,
+        rollout: None,
+    };
+
+    let mut authz = ServerAuthorization::new(&name, spec);
+    authz.metadata = ObjectMeta {
+        name: Some(name),
+        namespace: target.namespace(),
+        labels: Some((TARGET_LABEL.to_string(), target.name()))
+            .into_iter()
+            .collect(),
+        owner_references: target
+            .metadata
+            .uid
+            .iter()
+            .map(|uid| OwnerReference {
+                api_version: "access.smi-spec.io/v1alpha3".to_string(),
+                kind: "TrafficTarget".to_string(),
+                name: target.name(),
+                uid: uid.clone(),
+                controller: Some(true),
+                block_owner_deletion: Some(true),
+            })
+            .collect(),
+        ..Default::default()
+    };
+    authz
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use polixy_controller_k8s_api::smi::{
+        access::TrafficTargetSpec,
+        specs::{HttpMatch, HttpRouteGroupSpec, TcpMatch, TcpRouteSpec},
+    };
+
+    fn sa(name: &str) -> IdentityBindingSubject {
+        IdentityBindingSubject {
+            kind: "ServiceAccount".to_string(),
+            name: name.to_string(),
+            namespace: Some("ns".to_string()),
+            port: None,
+        }
+    }
+
+    fn rule(kind: &str, name: &str, matches: &[&str]) -> TrafficTargetRule {
+        TrafficTargetRule {
+            kind: kind.to_string(),
+            name: name.to_string(),
+            matches: matches.iter().map(|m| m.to_string()).collect(),
+        }
+    }
+
+    fn mk_target(rules: Vec<TrafficTargetRule>) -> TrafficTarget {
+        let mut target = TrafficTarget::new(
+            "target",
+            TrafficTargetSpec {
+                destination: sa("api"),
+                sources: vec![sa("web")],
+                rules,
+            },
+        );
+        target.metadata.namespace = Some("ns".to_string());
+        target.metadata.uid = Some("uid-0".to_string());
+        target
+    }
+
+    fn http_match(name: &str, methods: &[&str], path_regex: Option<&str>) -> HttpMatch {
+        HttpMatch {
+            name: name.to_string(),
+            methods: Some(methods.iter().map(|m| m.to_string()).collect()),
+            path_regex: path_regex.map(Into::into),
+            headers: None,
+        }
+    }
+
+    fn groups(matches: Vec<HttpMatch>) -> HashMap<String, HttpRouteGroup> {
+        let group = HttpRouteGroup::new("routes", HttpRouteGroupSpec { matches });
+        Some(("routes".to_string(), group)).into_iter().collect()
+    }
+
+    #[test]
+    fn http_route_group() {
+        let target = mk_target(vec![rule("HTTPRouteGroup", "routes", &["metrics", "api"])]);
+        let groups = groups(vec![
+            http_match("metrics", &["GET"], Some("/metrics")),
+            http_match("api", &["*"], Some("^/api/.*$")),
+            http_match("admin", &["*"], Some("/admin/.*")),
+        ]);
+        let Translation {
+            authorizations,
+            status,
+        } = translate(&target, &groups, &HashMap::new());
+
+        assert_eq!(authorizations.len(), 1);
+        let authz = &authorizations[0];
+        assert_eq!(authz.name(), "smi-target-http");
+        assert_eq!(authz.namespace().as_deref(), Some("ns"));
+        assert_eq!(
+            authz.labels().get(TARGET_LABEL).map(String::as_str),
+            Some("target")
+        );
+        assert_eq!(authz.metadata.owner_references[0].uid, "uid-0");
+
+        let routes = authz.spec.routes.as_ref().unwrap();
+        assert_eq!(routes.len(), 2);
+        assert_eq!(routes[0].path.as_deref(), Some("/metrics"));
+        assert_eq!(routes[0].methods, Some(vec!["GET".to_string()]));
+        assert_eq!(routes[1].path_prefix.as_deref(), Some("/api/"));
+        assert_eq!(routes[1].methods, None);
+
+        let mtls = authz.spec.client.mesh_tls.as_ref().unwrap();
+        assert_eq!(mtls.service_accounts.len(), 1);
+        assert_eq!(mtls.service_accounts[0].name, "web");
+
+        assert_eq!(status.authorizations, vec!["smi-target-http".to_string()]);
+        assert!(status.untranslated.is_empty(), "{:?}", status.untranslated);
+    }
+
+    #[test]
+    fn tcp_route() {
+        let target = mk_target(vec![
+            rule("TCPRoute", "tcp", &[]),
+            rule("TCPRoute", "tcp-ports", &[]),
+        ]);
+        let tcp_routes = vec![
+            TcpRoute::new("tcp", TcpRouteSpec { matches: None }),
+            TcpRoute::new(
+                "tcp-ports",
+                TcpRouteSpec {
+                    matches: Some(TcpMatch {
+                        name: None,
+                        ports: vec![8080],
+                    }),
+                },
+            ),
+        ]
+        .into_iter()
+        .map(|r| (r.name(), r))
+        .collect();
+        let Translation {
+            authorizations,
+            status,
+        } = translate(&target, &HashMap::new(), &tcp_routes);
+
+        assert_eq!(authorizations.len(), 1);
+        assert_eq!(authorizations[0].name(), "smi-target");
+        assert!(authorizations[0].spec.routes.is_none());
+        assert_eq!(status.untranslated.len(), 1);
+        assert!(status.untranslated[0].contains("tcp-ports"));
+    }
+
+    /// Matches that can't be expressed are omitted rather than broadened.
+    #[test]
+    fn untranslatable_matches_are_omitted() {
+        let target = mk_target(vec![
+            rule("HTTPRouteGroup", "routes", &["regex", "headers", "missing"]),
+            rule("HTTPRouteGroup", "other", &[]),
+        ]);
+        let mut headers = http_match("headers", &["GET"], None);
+        headers.headers = Some(
+            Some(("x-user".to_string(), "admin".to_string()))
+                .into_iter()
+                .collect(),
+        );
+        let groups = groups(vec![
+            http_match("regex", &["GET"], Some("/users/[0-9]+")),
+            headers,
+        ]);
+        let Translation {
+            authorizations,
+            status,
+        } = translate(&target, &groups, &HashMap::new());
+
+        assert!(authorizations.is_empty());
+        assert!(status.authorizations.is_empty());
+        assert_eq!(status.untranslated.len(), 4, "{:?}", status.untranslated);
+    }
+
+    #[test]
+    fn unsupported_subjects() {
+        let mut target = mk_target(vec![rule("HTTPRouteGroup", "routes", &[])]);
+        target.spec.sources.push(IdentityBindingSubject {
+            kind: "Group".to_string(),
+            name: "admins".to_string(),
+            ..Default::default()
+        });
+        let groups = groups(vec![http_match("all", &["*"], None)]);
+        let Translation {
+            authorizations,
+            status,
+        } = translate(&target, &groups, &HashMap::new());
+        assert_eq!(authorizations.len(), 1);
+        let routes = authorizations[0].spec.routes.as_ref().unwrap();
+        assert_eq!(routes[0].path_prefix.as_deref(), Some("/"));
+        assert_eq!(status.untranslated.len(), 1);
+
+        target.spec.destination.namespace = Some("other".to_string());
+        let Translation {
+            authorizations,
+            status,
+        } = translate(&target, &groups, &HashMap::new());
+        assert!(authorizations.is_empty());
+        assert_eq!(status.untranslated.len(), 1);
+    }
+
+    #[test]
+    fn path_regex() {
+        assert_eq!(parse_path_regex(".*"), Some(PathRegex::Any));
+        assert_eq!(
+            parse_path_regex("^/metrics$"),
+            Some(PathRegex::Exact("/metrics".to_string()))
+        );
+        assert_eq!(
+            parse_path_regex(r"/v1\.0/.*"),
+            Some(PathRegex::Prefix("/v1.0/".to_string()))
+        );
+        assert_eq!(parse_path_regex("/api.*"), None);
+        assert_eq!(parse_path_regex("/users/[0-9]+"), None);
+        assert_eq!(parse_path_regex("/a|/b"), None);
+        assert_eq!(parse_path_regex("metrics"), None);
+    }
+}
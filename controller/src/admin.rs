@@ -1,17 +1,40 @@
 use futures::future;
 use hyper::{Body, Request, Response};
+use polixy_controller_core::{PortProtocol, Reconnects};
+use polixy_controller_k8s_api::labels::Selector;
+use polixy_controller_k8s_index::Reader;
+use serde::Serialize;
 use std::net::SocketAddr;
 use tokio::sync::watch;
 use tracing::{info, instrument};
 
-#[instrument(skip(ready))]
-pub async fn serve(addr: SocketAddr, ready: watch::Receiver<bool>) -> Result<(), hyper::Error> {
+/// A pod's discoverable ports, as served by the `/ports` endpoint.
+#[derive(Clone, Debug, Serialize)]
+struct PodPorts {
+    namespace: String,
+    name: String,
+    ports: Vec<u16>,
+}
+
+#[instrument(skip(ready, lookups, reconnects))]
+pub async fn serve(
+    addr: SocketAddr,
+    ready: watch::Receiver<bool>,
+    lookups: Reader,
+    reconnects: Vec<(&'static str, Reconnects)>,
+) -> Result<(), hyper::Error> {
+    let reconnects = std::sync::Arc::new(reconnects);
     let server =
         hyper::server::Server::bind(&addr).serve(hyper::service::make_service_fn(move |_conn| {
             let ready = ready.clone();
+            let lookups = lookups.clone();
+            let reconnects = reconnects.clone();
             future::ok::<_, hyper::Error>(hyper::service::service_fn(
                 move |req: hyper::Request<hyper::Body>| match req.uri().path() {
                     "/ready" => future::ok(handle_ready(&ready, req)),
+                    "/metrics" => future::ok(handle_metrics(&reconnects, req)),
+                    p if p.starts_with("/ports/") => future::ok(handle_ports(&lookups, req)),
+                    "/policy" => future::ok(handle_policy(&lookups, req)),
                     _ => future::ok::<_, hyper::Error>(
                         hyper::Response::builder()
                             .status(hyper::StatusCode::NOT_FOUND)
@@ -49,3 +72,123 @@ fn handle_ready(ready: &watch::Receiver<bool>, req: Request<Body>) -> Response<B
             .unwrap(),
     }
 }
+
+/// Serves Kubernetes watch reconnection counters in the Prometheus text format.
+fn handle_metrics(reconnects: &[(&'static str, Reconnects)], req: Request<Body>) -> Response<Body> {
+    if !matches!(*req.method(), hyper::Method::GET | hyper::Method::HEAD) {
+        return Response::builder()
+            .status(hyper::StatusCode::METHOD_NOT_ALLOWED)
+            .body(Body::default())
+            .unwrap();
+    }
+
+    let mut out = String::new();
+    Reconnects::fmt_prometheus(
+        &mut out,
+        "resource",
+        reconnects.iter().map(|(r, c)| (r.to_string(), c)),
+    )
+    .expect("formatting to a string must not fail");
+    Response::builder()
+        .status(hyper::StatusCode::OK)
+        .header(hyper::header::CONTENT_TYPE, "text/plain; version=0.0.4")
+        .body(out.into())
+        .unwrap()
+}
+
+/// Lists the discoverable ports of indexed pods as JSON.
+///
+/// - `/ports/<ns>/<pod>` describes a single pod;
+/// - `/ports/<ns>?labelSelector=<selector>` describes all pods in a namespace that match a label
+///   selector. If no selector is provided, all pods in the namespace are described.
+///
+/// TCP ports are listed unless another protocol is specified, e.g. `?protocol=UDP`.
+fn handle_ports(lookups: &Reader, req: Request<Body>) -> Response<Body> {
+    if !matches!(*req.method(), hyper::Method::GET | hyper::Method::HEAD) {
+        return Response::builder()
+            .status(hyper::StatusCode::METHOD_NOT_ALLOWED)
+            .body(Body::default())
+            .unwrap();
+    }
+
+    let query = || form_urlencoded::parse(req.uri().query().unwrap_or("").as_bytes());
+    let protocol = query()
+        .find(|(k, _)| k == "protocol")
+        .map(|(_, v)| v.parse::<PortProtocol>())
+        .transpose();
+    let protocol = match protocol {
+        Ok(protocol) => protocol.unwrap_or_default(),
+        Err(error) => return plain(hyper::StatusCode::BAD_REQUEST, format!("{}\n", error)),
+    };
+
+    let path = req.uri().path().trim_start_matches("/ports/");
+    let pods = match path.split('/').collect::<Vec<_>>().as_slice() {
+        [ns, pod] if !ns.is_empty() && !pod.is_empty() => {
+            match lookups.pod_ports(ns, pod, protocol) {
+                Some(ports) => vec![PodPorts {
+                    namespace: ns.to_string(),
+                    name: pod.to_string(),
+                    ports,
+                }],
+                None => return plain(hyper::StatusCode::NOT_FOUND, "unknown pod\n"),
+            }
+        }
+
+        [ns] if !ns.is_empty() => {
+            let selector = query()
+                .find(|(k, _)| k == "labelSelector")
+                .map(|(_, v)| v.parse::<Selector>())
+                .transpose();
+            let selector = match selector {
+                Ok(selector) => selector.unwrap_or_default(),
+                Err(error) => {
+                    return plain(hyper::StatusCode::BAD_REQUEST, format!("{}\n", error));
+                }
+            };
+            lookups
+                .selected_pod_ports(ns, &selector, protocol)
+                .into_iter()
+                .map(|(name, ports)| PodPorts {
+                    namespace: ns.to_string(),
+                    name,
+                    ports,
+                })
+                .collect()
+        }
+
+        _ => return plain(hyper::StatusCode::NOT_FOUND, "invalid path\n"),
+    };
+
+    let bytes = serde_json::to_vec_pretty(&pods).expect("pod ports must serialize");
+    Response::builder()
+        .status(hyper::StatusCode::OK)
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .body(bytes.into())
+        .unwrap()
+}
+
+/// Exports the indexed servers, authorizations, and effective pod port configurations as a JSON data
+/// document, e.g. for `opa/polixy.rego`.
+fn handle_policy(lookups: &Reader, req: Request<Body>) -> Response<Body> {
+    if !matches!(*req.method(), hyper::Method::GET | hyper::Method::HEAD) {
+        return Response::builder()
+            .status(hyper::StatusCode::METHOD_NOT_ALLOWED)
+            .body(Body::default())
+            .unwrap();
+    }
+
+    let bytes = serde_json::to_vec_pretty(&lookups.export()).expect("policy data must serialize");
+    Response::builder()
+        .status(hyper::StatusCode::OK)
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .body(bytes.into())
+        .unwrap()
+}
+
+fn plain(status: hyper::StatusCode, msg: impl Into<Body>) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(hyper::header::CONTENT_TYPE, "text/plain")
+        .body(msg.into())
+        .unwrap()
+}
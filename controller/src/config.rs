@@ -0,0 +1,222 @@
+use crate::k8s::{
+    ClusterNetworks, Config, DefaultAllow, IdentityDomains, KubeletSource, LinkedCluster,
+    UndeclaredPorts,
+};
+use anyhow::{Context, Result};
+use polixy_controller_core::IpNet;
+use serde::Deserialize;
+use std::path::PathBuf;
+use tokio::{sync::watch, time};
+use tracing::{debug, info, warn};
+
+/// Controller settings, as configured by command-line flags.
+#[derive(Clone, Debug)]
+pub struct Settings {
+    pub identity_domain: String,
+    pub linked_clusters: Vec<LinkedCluster>,
+    pub cluster_networks: Vec<IpNet>,
+    pub discover_cluster_networks: bool,
+    pub service_networks: Vec<IpNet>,
+    pub default_allow: DefaultAllow,
+    pub undeclared_ports: UndeclaredPorts,
+    pub kubelet_source: KubeletSource,
+    pub detect_timeout: time::Duration,
+}
+
+/// Overrides settings from a YAML (or JSON) file, e.g. a mounted ConfigMap.
+///
+/// Only the settings that may be changed at runtime can be set in the file.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct File {
+    identity_domain: Option<String>,
+    linked_clusters: Option<Vec<String>>,
+    cluster_networks: Option<Vec<String>>,
+    discover_cluster_networks: Option<bool>,
+    service_networks: Option<Vec<String>>,
+    default_allow: Option<String>,
+    detect_timeout_ms: Option<u64>,
+}
+
+// === impl Settings ===
+
+impl Settings {
+    /// Builds an index configuration from these settings.
+    pub fn to_config(&self) -> Result<Config> {
+        let mut identity_domains = IdentityDomains::new(self.identity_domain.clone());
+        for cluster in self.linked_clusters.iter() {
+            identity_domains.link(cluster.clone())?;
+        }
+
+        let cluster_networks = if self.discover_cluster_networks {
+            ClusterNetworks::Discover {
+                service_networks: self.service_networks.clone(),
+            }
+        } else {
+            ClusterNetworks::Static(self.cluster_networks.clone())
+        };
+
+        Ok(Config {
+            cluster_networks,
+            identity_domains,
+            default_allow: self.default_allow,
+            undeclared_ports: self.undeclared_ports,
+            kubelet_source: self.kubelet_source.clone(),
+            detect_timeout: self.detect_timeout,
+        })
+    }
+
+    /// Builds an index configuration from these settings, overridden by a config file's contents.
+    pub fn to_config_with(&self, yaml: &str) -> Result<Config> {
+        // An empty file (e.g. an empty ConfigMap key) overrides nothing.
+        let file = if yaml.trim().is_empty() {
+            File::default()
+        } else {
+            serde_yaml::from_str(yaml)?
+        };
+        let mut settings = self.clone();
+        if let Some(domain) = file.identity_domain {
+            settings.identity_domain = domain;
+        }
+        if let Some(clusters) = file.linked_clusters {
+            settings.linked_clusters = parse_all(clusters).context("invalid linkedClusters")?;
+        }
+        if let Some(nets) = file.cluster_networks {
+            settings.cluster_networks = parse_all(nets).context("invalid clusterNetworks")?;
+        }
+        if let Some(discover) = file.discover_cluster_networks {
+            settings.discover_cluster_networks = discover;
+        }
+        if let Some(nets) = file.service_networks {
+            settings.service_networks = parse_all(nets).context("invalid serviceNetworks")?;
+        }
+        if let Some(mode) = file.default_allow {
+            settings.default_allow = mode.parse().context("invalid defaultAllow")?;
+        }
+        if let Some(ms) = file.detect_timeout_ms {
+            settings.detect_timeout = time::Duration::from_millis(ms);
+        }
+        settings.to_config()
+    }
+
+    /// Reads the configuration from the file at `path`.
+    pub fn load(&self, path: &std::path::Path) -> Result<Config> {
+        let yaml = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        self.to_config_with(&yaml)
+            .with_context(|| format!("invalid configuration in {}", path.display()))
+    }
+}
+
+/// Polls a config file, publishing the configuration whenever it changes.
+///
+/// Invalid configurations are logged and ignored, so the last valid configuration remains in
+/// effect. Completes when the index stops watching the configuration.
+pub async fn watch(
+    path: PathBuf,
+    settings: Settings,
+    interval: time::Duration,
+    tx: watch::Sender<Config>,
+) {
+    let mut interval = time::interval(interval);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = tx.closed() => return,
+        }
+
+        let config = match settings.load(&path) {
+            Ok(config) => config,
+            Err(error) => {
+                warn!(?error, "Ignoring configuration");
+                continue;
+            }
+        };
+        if *tx.borrow() == config {
+            debug!("Configuration unchanged");
+            continue;
+        }
+        info!(path = %path.display(), "Configuration updated");
+        if tx.send(config).is_err() {
+            return;
+        }
+    }
+}
+
+fn parse_all<T>(values: Vec<String>) -> Result<Vec<T>>
+where
+    T: std::str::FromStr,
+    T::Err: Into<anyhow::Error>,
+{
+    values
+        .into_iter()
+        .map(|v| v.parse().map_err(Into::into))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> Settings {
+        Settings {
+            identity_domain: "cluster.local".into(),
+            linked_clusters: vec![],
+            cluster_networks: vec!["10.42.0.0/16".parse().unwrap()],
+            discover_cluster_networks: false,
+            service_networks: vec![],
+            default_allow: DefaultAllow::AllUnauthenticated,
+            undeclared_ports: UndeclaredPorts::DefaultAllow,
+            kubelet_source: KubeletSource::default(),
+            detect_timeout: time::Duration::from_secs(10),
+        }
+    }
+
+    #[test]
+    fn overrides() {
+        let settings = settings();
+        assert_eq!(
+            settings.to_config_with("").unwrap(),
+            settings.to_config().unwrap()
+        );
+
+        let config = settings
+            .to_config_with(
+                r#"
+identityDomain: example.com
+linkedClusters: [east=east.example.com]
+clusterNetworks: [10.0.0.0/8]
+defaultAllow: deny
+detectTimeoutMs: 500
+"#,
+            )
+            .unwrap();
+        let mut domains = IdentityDomains::new("example.com".into());
+        domains
+            .link("east=east.example.com".parse().unwrap())
+            .unwrap();
+        assert_eq!(config.identity_domains, domains);
+        assert_eq!(
+            config.cluster_networks,
+            ClusterNetworks::Static(vec!["10.0.0.0/8".parse().unwrap()])
+        );
+        assert_eq!(config.default_allow, DefaultAllow::Deny);
+        assert_eq!(config.detect_timeout, time::Duration::from_millis(500));
+        assert_eq!(config.undeclared_ports, UndeclaredPorts::DefaultAllow);
+
+        let config = settings
+            .to_config_with(
+                r#"{"discoverClusterNetworks": true, "serviceNetworks": ["10.43.0.0/16"]}"#,
+            )
+            .unwrap();
+        assert_eq!(
+            config.cluster_networks,
+            ClusterNetworks::Discover {
+                service_networks: vec!["10.43.0.0/16".parse().unwrap()]
+            }
+        );
+
+        assert!(settings.to_config_with("defaultAllow: nope").is_err());
+        assert!(settings.to_config_with("undeclaredPorts: deny").is_err());
+    }
+}
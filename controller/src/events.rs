@@ -0,0 +1,175 @@
+//! Records Kubernetes Events on ServerAuthorizations as their time windows open and close, so that
+//! `kubectl describe` shows when an authorization took effect or expired, and on pods that declare
+//! a port in more than one container.
+
+use kube::api::{Api, PostParams};
+use polixy_controller_k8s_api::{
+    api::core::v1::{Event, EventSource, ObjectReference},
+    ObjectMeta, Time,
+};
+use polixy_controller_k8s_index::{export::AuthorizationState, AuthzTransition, PortConflict};
+use std::time::SystemTime;
+use tokio::sync::mpsc;
+use tracing::{debug, warn};
+
+/// The component that reports events.
+const COMPONENT: &str = "polixy-controller";
+
+/// Records an event for each authorization transition and port conflict until the index stops.
+pub async fn run(
+    client: kube::Client,
+    mut transitions: mpsc::UnboundedReceiver<AuthzTransition>,
+    mut conflicts: mpsc::UnboundedReceiver<PortConflict>,
+) {
+    loop {
+        let now = Time(SystemTime::now().into());
+        let event = tokio::select! {
+            Some(transition) = transitions.recv() => match mk_event(&transition, now) {
+                Some(event) => event,
+                None => continue,
+            },
+            Some(conflict) = conflicts.recv() => mk_conflict_event(&conflict, now),
+            else => return,
+        };
+
+        let ns = event.metadata.namespace.clone().unwrap_or_default();
+        let name = event.involved_object.name.clone().unwrap_or_default();
+        debug!(%ns, %name, ?event.reason, "Recording event");
+        let api = Api::<Event>::namespaced(client.clone(), &ns);
+        if let Err(error) = api.create(&PostParams::default(), &event).await {
+            warn!(%ns, %name, %error, "Failed to record event");
+        }
+    }
+}
+
+/// Describes an authorization's transition as an event. Authorizations that become pending (e.g.
+/// because the clock moved backwards) aren't reported.
+fn mk_event(transition: &AuthzTransition, now: Time) -> Option<Event> {
+    let (reason, message) = match transition.state {
+        AuthorizationState::Active => ("Active", "Authorization took effect"),
+        AuthorizationState::Expired => ("Expired", "Authorization expired"),
+        AuthorizationState::Pending => return None,
+    };
+
+    Some(Event {
+        metadata: ObjectMeta {
+            namespace: Some(transition.namespace.clone()),
+            generate_name: Some(format!("{}.", transition.name)),
+            ..Default::default()
+        },
+        involved_object: ObjectReference {
+            api_version: Some("polixy.linkerd.io/v1alpha1".to_string()),
+            kind: Some("ServerAuthorization".to_string()),
+            namespace: Some(transition.namespace.clone()),
+            name: Some(transition.name.clone()),
+            uid: transition.uid.clone(),
+            ..Default::default()
+        },
+        reason: Some(reason.to_string()),
+        message: Some(message.to_string()),
+        type_: Some("Normal".to_string()),
+        source: Some(EventSource {
+            component: Some(COMPONENT.to_string()),
+            host: None,
+        }),
+        count: Some(1),
+        first_timestamp: Some(now.clone()),
+        last_timestamp: Some(now),
+        ..Default::default()
+    })
+}
+
+/// Describes a port that a pod declares in more than one container as a warning event.
+fn mk_conflict_event(conflict: &PortConflict, now: Time) -> Event {
+    let message = format!(
+        "Port {}/{} is declared by containers {} and {}; only {}'s declaration is used",
+        conflict.port,
+        conflict.protocol,
+        conflict.declared_by,
+        conflict.container,
+        conflict.declared_by,
+    );
+    Event {
+        metadata: ObjectMeta {
+            namespace: Some(conflict.namespace.clone()),
+            generate_name: Some(format!("{}.", conflict.pod)),
+            ..Default::default()
+        },
+        involved_object: ObjectReference {
+            api_version: Some("v1".to_string()),
+            kind: Some("Pod".to_string()),
+            namespace: Some(conflict.namespace.clone()),
+            name: Some(conflict.pod.clone()),
+            uid: conflict.uid.clone(),
+            ..Default::default()
+        },
+        reason: Some("PortConflict".to_string()),
+        message: Some(message),
+        type_: Some("Warning".to_string()),
+        source: Some(EventSource {
+            component: Some(COMPONENT.to_string()),
+            host: None,
+        }),
+        count: Some(1),
+        first_timestamp: Some(now.clone()),
+        last_timestamp: Some(now),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use polixy_controller_core::PortProtocol;
+
+    #[test]
+    fn authz_events() {
+        let now = Time(SystemTime::UNIX_EPOCH.into());
+        let mut transition = AuthzTransition {
+            namespace: "ns-0".to_string(),
+            name: "authz-0".to_string(),
+            uid: Some("uid-0".to_string()),
+            state: AuthorizationState::Active,
+        };
+
+        let event = mk_event(&transition, now.clone()).expect("active authorizations are reported");
+        assert_eq!(event.metadata.namespace.as_deref(), Some("ns-0"));
+        assert_eq!(event.involved_object.name.as_deref(), Some("authz-0"));
+        assert_eq!(event.involved_object.uid.as_deref(), Some("uid-0"));
+        assert_eq!(event.reason.as_deref(), Some("Active"));
+
+        transition.state = AuthorizationState::Expired;
+        let event =
+            mk_event(&transition, now.clone()).expect("expired authorizations are reported");
+        assert_eq!(event.reason.as_deref(), Some("Expired"));
+
+        transition.state = AuthorizationState::Pending;
+        assert!(mk_event(&transition, now).is_none());
+    }
+
+    #[test]
+    fn port_conflict_events() {
+        let conflict = PortConflict {
+            namespace: "ns-0".to_string(),
+            pod: "pod-0".to_string(),
+            uid: Some("uid-0".to_string()),
+            port: 8080,
+            protocol: PortProtocol::Tcp,
+            container: "sidecar".to_string(),
+            declared_by: "app".to_string(),
+        };
+
+        let event = mk_conflict_event(&conflict, Time(SystemTime::UNIX_EPOCH.into()));
+        assert_eq!(event.involved_object.kind.as_deref(), Some("Pod"));
+        assert_eq!(event.involved_object.name.as_deref(), Some("pod-0"));
+        assert_eq!(event.involved_object.uid.as_deref(), Some("uid-0"));
+        assert_eq!(event.type_.as_deref(), Some("Warning"));
+        assert_eq!(
+            event.message.as_deref(),
+            Some(
+                "Port 8080/TCP is declared by containers app and sidecar; \
+                 only app's declaration is used"
+            )
+        );
+    }
+}
@@ -2,6 +2,8 @@
 #![forbid(unsafe_code)]
 
 pub mod admin;
+pub mod config;
+pub mod events;
 
 pub use polixy_controller_grpc as grpc;
 pub use polixy_controller_k8s_index as k8s;
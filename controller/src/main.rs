@@ -1,13 +1,24 @@
 #![deny(warnings, rust_2018_idioms)]
 #![forbid(unsafe_code)]
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use futures::{future, prelude::*};
-use polixy_controller::k8s::DefaultAllow;
-use polixy_controller_core::IpNet;
-use std::net::SocketAddr;
+use polixy_controller::{
+    config::Settings,
+    k8s::{DefaultAllow, KubeletSource, LinkedCluster, UndeclaredPorts},
+};
+use polixy_controller_core::{Backoff, IpNet};
+use polixy_controller_grpc::TlsConfig;
+use polixy_controller_k8s_api::{ClientPodWatches, ResourceWatches, SmiWatches};
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+};
 use structopt::StructOpt;
-use tokio::{sync::watch, time};
+use tokio::{
+    sync::{mpsc, watch},
+    time,
+};
 use tracing::{debug, info, instrument};
 
 #[derive(Debug, StructOpt)]
@@ -19,17 +30,119 @@ struct Args {
     #[structopt(short, long, default_value = "0.0.0.0:8090")]
     grpc_addr: SocketAddr,
 
+    /// A PEM-encoded certificate chain with which the gRPC server serves mTLS.
+    ///
+    /// When set, clients may only discover the policies of pods whose service accounts match their
+    /// identities. Requires `--grpc-tls-key` and `--grpc-tls-client-ca`.
+    #[structopt(long)]
+    grpc_tls_cert: Option<PathBuf>,
+
+    /// The PEM-encoded private key of the gRPC server's certificate.
+    #[structopt(long)]
+    grpc_tls_key: Option<PathBuf>,
+
+    /// PEM-encoded roots that issue gRPC clients' certificates.
+    #[structopt(long)]
+    grpc_tls_client_ca: Option<PathBuf>,
+
+    /// Client identities, e.g. of tooling, that may discover the policies of all pods.
+    #[structopt(long)]
+    grpc_exempt_identities: Vec<String>,
+
     #[structopt(long, default_value = "cluster.local")]
     identity_domain: String,
 
-    /// Network CIDRs of pod IPs.
+    /// Identity domains of linked clusters, in the form `<name>=<identity-domain>`.
+    ///
+    /// Authorizations may reference service accounts in these clusters by name.
+    #[structopt(long)]
+    linked_clusters: Vec<LinkedCluster>,
+
+    /// Network CIDRs of pod IPs. Ignored when cluster networks are discovered.
     ///
     /// The default reflects k3d's default node network.
     #[structopt(long, default_value = "10.42.0.0/16")]
     cluster_networks: Vec<IpNet>,
 
+    /// Discovers cluster networks from the union of all nodes' pod CIDRs and the service networks,
+    /// updating them as nodes change. This supports dual-stack and IPv6 clusters without
+    /// configuring each network.
+    #[structopt(long)]
+    discover_cluster_networks: bool,
+
+    /// Network CIDRs of service IPs, included in discovered cluster networks.
+    #[structopt(long)]
+    service_networks: Vec<IpNet>,
+
     #[structopt(long, default_value = "all-unauthenticated")]
     default_allow: DefaultAllow,
+
+    /// The protocol detection timeout of default-allow policies.
+    #[structopt(long, default_value = "10000")]
+    detect_timeout_ms: u64,
+
+    /// A YAML or JSON file, e.g. a mounted ConfigMap, that overrides the identity domain, linked
+    /// clusters, cluster networks, default-allow policy, and detect timeout.
+    ///
+    /// The file is polled and changes are applied without restarting the controller.
+    #[structopt(long)]
+    config: Option<PathBuf>,
+
+    /// How often the config file is checked for changes.
+    #[structopt(long, default_value = "10")]
+    config_poll_interval_secs: u64,
+
+    /// How to serve ports that pods don't declare: `default-allow` applies the pod's default-allow
+    /// policy and `deny` denies all connections. Servers may select undeclared ports by number.
+    #[structopt(long, default_value = "default-allow")]
+    undeclared_ports: UndeclaredPorts,
+
+    /// How kubelet IPs, which are authorized to send health checks, are determined:
+    /// `pod-cidr` (the first host of each of the node's pod CIDRs), `internal-ip` (the node's
+    /// `InternalIP` addresses), `annotation` (the node's `polixy.linkerd.io/kubelet-ips`
+    /// annotation), or `static=<cidr>,...`.
+    #[structopt(long, default_value = "pod-cidr")]
+    kubelet_source: KubeletSource,
+
+    /// The minimum delay before restarting a failed Kubernetes watch. Defaults to 2s.
+    #[structopt(long)]
+    watch_backoff_min_ms: Option<u64>,
+
+    /// The maximum delay before restarting a failed Kubernetes watch.
+    #[structopt(long, default_value = "30000")]
+    watch_backoff_max_ms: u64,
+
+    /// The fraction by which watch restart delays are randomly reduced.
+    #[structopt(long, default_value = "0.5")]
+    watch_backoff_jitter: f64,
+
+    /// Generates authorizations from SMI TrafficTarget resources.
+    #[structopt(long)]
+    smi_import: bool,
+
+    /// Permits authorizations to select client pods by pod and namespace labels.
+    ///
+    /// The controller then watches all pods and namespaces in the cluster, which requires
+    /// permission to watch namespaces and memory proportional to the number of pods.
+    #[structopt(long)]
+    client_pod_selectors: bool,
+
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Generates NetworkPolicies from Server and ServerAuthorization resources.
+    ///
+    /// Resources are read as YAML, e.g. from `kubectl get servers,serverauthorizations -A -o yaml`,
+    /// and policies are written to stdout. Authorization constraints that NetworkPolicies can't
+    /// express are reported on stderr.
+    NetworkPolicy {
+        /// Files to read resources from. Resources are read from stdin if none are specified.
+        #[structopt(short, long)]
+        files: Vec<PathBuf>,
+    },
 }
 
 #[tokio::main]
@@ -39,32 +152,130 @@ async fn main() -> Result<()> {
     let Args {
         admin_addr,
         grpc_addr,
+        grpc_tls_cert,
+        grpc_tls_key,
+        grpc_tls_client_ca,
+        grpc_exempt_identities,
         identity_domain,
+        linked_clusters,
         cluster_networks,
+        discover_cluster_networks,
+        service_networks,
         default_allow,
+        detect_timeout_ms,
+        config,
+        config_poll_interval_secs,
+        undeclared_ports,
+        kubelet_source,
+        watch_backoff_min_ms,
+        watch_backoff_max_ms,
+        watch_backoff_jitter,
+        smi_import,
+        client_pod_selectors,
+        command,
     } = Args::from_args();
 
+    if let Some(Command::NetworkPolicy { files }) = command {
+        return network_policy(files);
+    }
+
+    let grpc_tls = match (grpc_tls_cert, grpc_tls_key, grpc_tls_client_ca) {
+        (None, None, None) => {
+            if !grpc_exempt_identities.is_empty() {
+                bail!("--grpc-exempt-identities requires --grpc-tls-cert");
+            }
+            None
+        }
+        (Some(cert), Some(key), Some(client_ca)) => Some(TlsConfig {
+            cert_pem: read(&cert)?,
+            key_pem: read(&key)?,
+            client_ca_pem: read(&client_ca)?,
+            exempt_identities: grpc_exempt_identities,
+        }),
+        _ => {
+            bail!("--grpc-tls-cert, --grpc-tls-key, and --grpc-tls-client-ca must be set together")
+        }
+    };
+
+    let backoff = Backoff::new(
+        watch_backoff_min_ms
+            .map(time::Duration::from_millis)
+            .unwrap_or(Backoff::DEFAULT_MIN),
+        time::Duration::from_millis(watch_backoff_max_ms),
+        watch_backoff_jitter,
+    )
+    .context("invalid watch backoff")?;
+
+    let settings = Settings {
+        identity_domain,
+        linked_clusters,
+        cluster_networks,
+        discover_cluster_networks,
+        service_networks,
+        default_allow,
+        undeclared_ports,
+        kubelet_source,
+        detect_timeout: time::Duration::from_millis(detect_timeout_ms),
+    };
+    let (config_tx, config_rx) = watch::channel(match config {
+        None => settings.to_config()?,
+        Some(ref path) => settings.load(path)?,
+    });
+    if let Some(path) = config {
+        let interval = time::Duration::from_secs(config_poll_interval_secs);
+        info!(path = %path.display(), "Watching configuration");
+        tokio::spawn(polixy_controller::config::watch(
+            path, settings, interval, config_tx,
+        ));
+    }
+
     let (drain_tx, drain_rx) = drain::channel();
 
     let client = kube::Client::try_default()
         .await
         .context("failed to initialize kubernetes client")?;
 
+    let mut watches = ResourceWatches::from(client.clone());
+    if client_pod_selectors {
+        info!("Watching client pods");
+        watches = watches.with_client_pods(ClientPodWatches::from(client.clone()));
+    }
+    let watches = watches.with_backoff(backoff);
+    let mut reconnects = watches.reconnects();
+
+    let smi_task = if smi_import {
+        let smi_watches = SmiWatches::from(client.clone()).with_backoff(backoff);
+        reconnects.extend(smi_watches.reconnects());
+        info!("Importing SMI TrafficTargets");
+        tokio::spawn(polixy_controller_k8s_smi::run(client.clone(), smi_watches))
+    } else {
+        tokio::spawn(future::pending())
+    };
+
     let (ready_tx, ready_rx) = watch::channel(false);
-    let admin = tokio::spawn(polixy_controller::admin::serve(admin_addr, ready_rx));
 
-    const DETECT_TIMEOUT: time::Duration = time::Duration::from_secs(10);
-    let (handle, index_task) = polixy_controller::k8s::index(
+    // Record events as authorizations take effect and expire, and as pods' port conflicts are
+    // found.
+    let (transitions_tx, transitions_rx) = mpsc::unbounded_channel();
+    let (conflicts_tx, conflicts_rx) = mpsc::unbounded_channel();
+    tokio::spawn(polixy_controller::events::run(
         client,
-        ready_tx,
-        cluster_networks,
-        identity_domain,
-        default_allow,
-        DETECT_TIMEOUT,
-    );
+        transitions_rx,
+        conflicts_rx,
+    ));
+
+    let (handle, index_task) =
+        polixy_controller::k8s::index(watches, ready_tx, config_rx, transitions_tx, conflicts_tx);
     let index_task = tokio::spawn(index_task);
 
-    let grpc = tokio::spawn(grpc(grpc_addr, handle, drain_rx));
+    let admin = tokio::spawn(polixy_controller::admin::serve(
+        admin_addr,
+        ready_rx,
+        handle.clone(),
+        reconnects,
+    ));
+
+    let grpc = tokio::spawn(grpc(grpc_addr, grpc_tls, handle, drain_rx));
 
     tokio::select! {
        _ = shutdown(drain_tx) => Ok(()),
@@ -78,6 +289,11 @@ async fn main() -> Result<()> {
            Err(e) if e.is_cancelled() => Ok(()),
            Err(e) => Err(e).context("indexer panicked"),
        },
+       res = smi_task => match res {
+           Ok(e) => Err(e).context("SMI importer failed"),
+           Err(e) if e.is_cancelled() => Ok(()),
+           Err(e) => Err(e).context("SMI importer panicked"),
+       },
        res = admin => match res {
            Ok(res) => res.context("admin server failed"),
            Err(e) if e.is_cancelled() => Ok(()),
@@ -86,13 +302,60 @@ async fn main() -> Result<()> {
     }
 }
 
-#[instrument(skip(handle, drain))]
+fn network_policy(files: Vec<PathBuf>) -> Result<()> {
+    use polixy_controller_k8s_netpol as netpol;
+    use std::io::{Read, Write};
+
+    let mut resources = netpol::Resources::default();
+    if files.is_empty() {
+        let mut yaml = String::new();
+        std::io::stdin().read_to_string(&mut yaml)?;
+        resources
+            .read_yaml(&yaml)
+            .context("failed to read resources from stdin")?;
+    }
+    for file in files.iter() {
+        let yaml = std::fs::read_to_string(file)
+            .with_context(|| format!("failed to read {}", file.display()))?;
+        resources
+            .read_yaml(&yaml)
+            .with_context(|| format!("failed to read resources from {}", file.display()))?;
+    }
+
+    let netpol::Generated {
+        policies,
+        unexpressed,
+    } = netpol::generate(&resources);
+    for u in unexpressed.iter() {
+        eprintln!("warning: {}", u);
+    }
+
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
+    for policy in policies.iter() {
+        serde_yaml::to_writer(&mut out, policy)?;
+        writeln!(out)?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn read(path: &Path) -> Result<Vec<u8>> {
+    std::fs::read(path).with_context(|| format!("failed to read {}", path.display()))
+}
+
+#[instrument(skip(tls, handle, drain))]
 async fn grpc(
     addr: SocketAddr,
+    tls: Option<TlsConfig>,
     handle: polixy_controller_k8s_index::Reader,
     drain: drain::Watch,
 ) -> Result<()> {
-    let server = polixy_controller_grpc::Server::new(handle, drain.clone());
+    let mut server = polixy_controller_grpc::Server::new(handle, drain.clone());
+    if let Some(tls) = tls {
+        info!(exempt = ?tls.exempt_identities, "Authorizing gRPC clients by identity");
+        server = server.with_tls(tls);
+    }
     let (close_tx, close_rx) = tokio::sync::oneshot::channel();
     tokio::pin! {
         let srv = server.serve(addr, close_rx.map(|_| {}));
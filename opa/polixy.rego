@@ -0,0 +1,157 @@
+# Authorizes connections to meshed pods from the data document exported by the policy controller
+# (`GET /policy` on its admin server, or `polixy-client export`), loaded at the root of `data`.
+#
+# Input:
+#
+#     {
+#       "namespace": "emojivoto",
+#       "pod": "web-5f86686c4d-58p7k",
+#       "port": 8080,
+#       "client_ip": "10.42.0.15",
+#       "client_id": "vote-bot.emojivoto.serviceaccount.identity.linkerd.cluster.local",
+#       "tls": true
+#     }
+#
+# `client_id` is set when the client presents a mesh identity, which implies `tls`. `tls` alone
+# indicates a mesh TLS connection without a client identity. Ports are TCP unless the input sets a
+# `protocol` of `UDP` or `SCTP`.
+#
+# Authorizations are chosen as the polixy client library does: explicit identities, then identity
+# suffixes or SPIFFE path prefixes (longest first, counting the trust domain as one label), then TLS without an identity, then unauthenticated clients; then the
+# narrowest network; then the first authorization by name. Route-scoped authorizations apply to
+# individual HTTP requests and are not considered here.
+package polixy.authz
+
+default allow = false
+
+allow {
+	authorization
+}
+
+# The decision for the input, with the name of the authorization that permits it, if any.
+decision = {"allow": true, "authorization": name} {
+	name := authorization
+}
+
+decision = {"allow": false, "authorization": null} {
+	not authorization
+}
+
+# The name of the most specific authorization that permits the connection.
+authorization = name {
+	count(candidates) > 0
+	best := max({score | score := candidates[_]})
+	name := min({n | candidates[n] == best})
+}
+
+# Ports that the pod neither declares nor are selected by a server use the pod's undeclared-port
+# server.
+server = srv {
+	srv := data.namespaces[input.namespace].pods[input.pod][ports_key][format_int(input.port, 10)]
+} else = srv {
+	srv := data.namespaces[input.namespace].pods[input.pod].undeclared
+}
+
+ports_key = "ports" {
+	not input.protocol
+}
+
+ports_key = "ports" {
+	input.protocol == "TCP"
+}
+
+ports_key = "udpPorts" {
+	input.protocol == "UDP"
+}
+
+ports_key = "sctpPorts" {
+	input.protocol == "SCTP"
+}
+
+# Scores each authorization that permits the connection. Authentication dominates; network prefix
+# lengths are at most 128.
+candidates[name] = score {
+	authz := server.authorizations[name]
+	prefix_len := network_prefix_len(authz.networks)
+	score := (authn_rank(authz.authentication) * 1000) + prefix_len
+}
+
+network_prefix_len(networks) = len {
+	lens := {l |
+		network := networks[_]
+		net.cidr_contains(network.net, input.client_ip)
+		not excepted(network)
+		l := to_number(split(network.net, "/")[1])
+	}
+	count(lens) > 0
+	len := max(lens)
+}
+
+excepted(network) {
+	net.cidr_contains(network.except[_], input.client_ip)
+}
+
+tls {
+	input.client_id
+}
+
+tls {
+	input.tls == true
+}
+
+authn_rank(authn) = 0 {
+	authn.kind == "unauthenticated"
+}
+
+authn_rank(authn) = 1 {
+	authn.kind == "tlsUnauthenticated"
+	tls
+}
+
+authn_rank(authn) = 10000 {
+	authn.kind == "tlsAuthenticated"
+	identity_matches(authn)
+}
+
+authn_rank(authn) = rank {
+	authn.kind == "tlsAuthenticated"
+	not identity_matches(authn)
+	suffix_depths := {count(suffix) | suffix := authn.suffixes[_]; suffix_matches(suffix)}
+	spiffe_depths := {count(prefix) | prefix := authn.spiffePrefixes[_]; spiffe_prefix_matches(prefix)}
+	depths := suffix_depths | spiffe_depths
+	count(depths) > 0
+	rank := 2 + max(depths)
+}
+
+identity_matches(authn) {
+	authn.identities[_] == input.client_id
+}
+
+# A suffix matches identities with at least one more label.
+suffix_matches(suffix) {
+	labels := split(input.client_id, ".")
+	n := count(labels)
+	k := count(suffix)
+	n > k
+	array.slice(labels, n - k, n) == suffix
+}
+
+# A SPIFFE prefix (a trust domain followed by path segments) matches valid SPIFFE IDs with at least
+# one more path segment.
+spiffe_prefix_matches(prefix) {
+	regex.match(`^spiffe://[a-z0-9._-]+(/[A-Za-z0-9._-]+)+$`, input.client_id)
+	labels := split(trim_prefix(input.client_id, "spiffe://"), "/")
+	not invalid_spiffe_segment(labels)
+	n := count(labels)
+	k := count(prefix)
+	n > k
+	array.slice(labels, 0, k) == prefix
+}
+
+invalid_spiffe_segment(labels) {
+	labels[_] == "."
+}
+
+invalid_spiffe_segment(labels) {
+	labels[_] == ".."
+}
@@ -0,0 +1,19 @@
+# Evaluates the cases in testdata/cases.json, which the index's tests also check against the Rust
+# implementation. Run with `opa test opa/`.
+package polixy.authz_test
+
+import data.polixy.authz
+
+test_cases {
+	count(failed) == 0
+}
+
+failed[c.name] {
+	c := data.testdata.cases[_]
+	not agrees(c)
+}
+
+agrees(c) {
+	d := authz.decision with input as c.input with data.namespaces as data.testdata.data.namespaces
+	d == c.expected
+}